@@ -0,0 +1,168 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::retriever::source_key;
+use crate::vector_db::VectorDB;
+
+/// Retrievals of one chunk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChunkAccess {
+    pub count: u64,
+    /// Unix seconds of the latest one
+    pub last: u64,
+}
+
+/// How often each chunk of an index has been retrieved, kept next to the
+/// index as `<index>.access.json` so read-only and replica processes can
+/// count too
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccessStats {
+    /// Unix seconds when counting began; chunks can only be called unused
+    /// for the time since
+    pub since: u64,
+    /// Chunks retrieved at least once, by document ID
+    pub chunks: BTreeMap<String, ChunkAccess>,
+}
+
+/// A source document none of whose chunks has been retrieved
+#[derive(Debug, PartialEq)]
+pub struct ColdSource {
+    /// `path` or `source` metadata, or the chunk ID without either
+    pub source: String,
+    pub chunk_ids: Vec<String>,
+}
+
+impl AccessStats {
+    /// Where the stats of the index at `index_path` are kept
+    pub fn path_for(index_path: &Path) -> PathBuf {
+        index_path.with_extension("access.json")
+    }
+
+    /// The stats at `path`, or empty ones starting now if there are none yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(AccessStats { since: now(), chunks: BTreeMap::new() });
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Writes via a temp file and rename, so readers never see half a file
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Sources of `db` whose chunks were all never retrieved, by source
+    pub fn cold_sources(&self, db: &VectorDB) -> Vec<ColdSource> {
+        let mut sources: BTreeMap<String, (Vec<String>, bool)> = BTreeMap::new();
+        for doc in db.documents() {
+            let (ids, used) = sources.entry(source_key(&doc.metadata, &doc.id)).or_default();
+            ids.push(doc.id.clone());
+            *used |= self.chunks.contains_key(&doc.id);
+        }
+        sources
+            .into_iter()
+            .filter(|(_, (_, used))| !used)
+            .map(|(source, (mut chunk_ids, _))| {
+                chunk_ids.sort();
+                ColdSource { source, chunk_ids }
+            })
+            .collect()
+    }
+
+    /// Drops the counts of chunks that left the index
+    pub fn forget<'a>(&mut self, ids: impl IntoIterator<Item = &'a String>) {
+        for id in ids {
+            self.chunks.remove(id);
+        }
+    }
+}
+
+/// Counts retrievals in memory and adds them to the stats file on `flush`,
+/// which re-reads it first so several processes serving the same index
+/// add up. Pending counts are flushed on drop.
+pub struct AccessLog {
+    path: PathBuf,
+    pending: Mutex<HashMap<String, ChunkAccess>>,
+}
+
+impl AccessLog {
+    pub fn for_index(index_path: &Path) -> Self {
+        AccessLog { path: AccessStats::path_for(index_path), pending: Mutex::default() }
+    }
+
+    pub fn record<'a>(&self, ids: impl IntoIterator<Item = &'a str>) {
+        let now = now();
+        let mut pending = self.pending.lock().unwrap();
+        for id in ids {
+            let access = pending.entry(id.to_string()).or_default();
+            access.count += 1;
+            access.last = now;
+        }
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let mut stats = AccessStats::load(&self.path)?;
+        for (id, access) in pending {
+            let total = stats.chunks.entry(id).or_default();
+            total.count += access.count;
+            total.last = total.last.max(access.last);
+            // Counting began with the first retrieval, not the first flush
+            stats.since = stats.since.min(access.last);
+        }
+        stats.save(&self.path)
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            tracing::warn!("Couldn't save retrieval counts to {:?}: {}", self.path, e);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_add_up_and_cold_sources_are_found() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let index_path = dir.path().join("index.bin");
+        let mut db = VectorDB::new();
+        let file = |path: &str| BTreeMap::from([("path".to_string(), path.to_string())]);
+        let faq = db.add_documents(vec!["Refunds take thirty days.".to_string(), "Shipping is free.".to_string()], file("faq.md"))?;
+        let old = db.add_documents(vec!["The 2019 office party was fun.".to_string()], file("old.md"))?;
+
+        // Two processes counting the same index
+        for _ in 0..2 {
+            let log = AccessLog::for_index(&index_path);
+            log.record([faq[0].as_str()]);
+        }
+        let mut stats = AccessStats::load(&AccessStats::path_for(&index_path))?;
+        assert_eq!(stats.chunks[&faq[0]].count, 2);
+        assert!(!stats.chunks.contains_key(&faq[1]));
+
+        // One retrieved chunk keeps its whole source warm
+        assert_eq!(stats.cold_sources(&db), vec![ColdSource { source: "old.md".to_string(), chunk_ids: old.clone() }]);
+        stats.forget(&faq);
+        assert_eq!(stats.cold_sources(&db).len(), 2);
+        Ok(())
+    }
+}
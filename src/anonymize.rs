@@ -0,0 +1,273 @@
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use regex::{Captures, Regex, RegexBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::llm::{InferenceOptions, LLMBackend};
+
+lazy_static! {
+    static ref EMAIL: Regex = Regex::new(r"\b[\w.+-]+@[\w-]+(?:\.[\w-]+)+\b").unwrap();
+    static ref PHONE: Regex = Regex::new(r"\+?\d[\d ().-]{7,}\d").unwrap();
+    /// Two or more capitalized words in a row, e.g. a person or company
+    static ref NAME: Regex = Regex::new(r"\b\p{Lu}\p{Ll}+(?:[ \t]+\p{Lu}\p{Ll}+)+\b").unwrap();
+    static ref PLACEHOLDER: Regex = Regex::new(r"\b(?:NAME|EMAIL|PHONE|TERM)_\d+\b").unwrap();
+}
+
+/// Capitalized words that start sentences rather than names
+const SENTENCE_STARTS: &[&str] = &[
+    "A", "An", "And", "Answer", "Are", "As", "Ask", "At", "But", "By", "Call", "Can", "Contact", "Could", "Do", "Does",
+    "Email", "For", "From", "How", "If", "In", "Is", "It", "Of", "On", "Or", "Please", "Question", "So", "Tell", "The",
+    "Then", "There", "These", "This", "Those", "To", "Use", "We", "What", "When", "Where", "Which", "Who", "Why",
+    "With", "You",
+];
+
+/// Fewest digits of a phone number
+const PHONE_DIGITS: usize = 9;
+/// Replaces identifiers stripped from prompts
+const REDACTED: &str = "[redacted]";
+
+/// How identifiers are hidden from a remote backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnonymizeMode {
+    /// Replaced by `[redacted]` for good
+    Strip,
+    /// Replaced by placeholders such as `NAME_1`, which are turned back
+    /// into the original text in the answer
+    Pseudonymize,
+}
+
+impl AnonymizeMode {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "strip" => Ok(AnonymizeMode::Strip),
+            "pseudonymize" => Ok(AnonymizeMode::Pseudonymize),
+            other => Err(anyhow!("Unknown anonymization mode '{}' (strip or pseudonymize)", other)),
+        }
+    }
+}
+
+/// Finds identifiers in prompt text: email addresses, phone numbers,
+/// sequences of capitalized words (names of people, companies and places)
+/// and the terms of a user list, matched ignoring case. Detection is a
+/// heuristic, not a guarantee: lowercase names or unusual formats get
+/// through, so list the identifiers that must never leave as terms.
+pub struct Anonymizer {
+    mode: AnonymizeMode,
+    terms: Option<Regex>,
+}
+
+impl Anonymizer {
+    pub fn new(mode: AnonymizeMode, terms: &[String]) -> Result<Self> {
+        let mut terms: Vec<&str> = terms.iter().map(|term| term.trim()).filter(|term| !term.is_empty()).collect();
+        // Longer terms first, so "Acme Corp" wins over "Acme"
+        terms.sort_by_key(|term| std::cmp::Reverse(term.len()));
+        let terms = match terms.is_empty() {
+            true => None,
+            false => {
+                let alternatives: Vec<String> = terms.iter().map(|term| regex::escape(term)).collect();
+                Some(RegexBuilder::new(&format!(r"\b(?:{})\b", alternatives.join("|"))).case_insensitive(true).build()?)
+            }
+        };
+        Ok(Anonymizer { mode, terms })
+    }
+
+    /// Reads terms from a file, one per line; `#` starts a comment line
+    pub fn load_terms(path: &Path) -> Result<Vec<String>> {
+        let content = fs::read_to_string(path).map_err(|e| anyhow!("Can't read anonymization terms {:?}: {}", path, e))?;
+        Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect())
+    }
+
+    /// `text` with its identifiers replaced, and the mapping that restores
+    /// them. The same identifier always gets the same placeholder.
+    pub fn mask(&self, text: &str) -> (String, Pseudonyms) {
+        let mut pseudonyms = Pseudonyms { strip: self.mode == AnonymizeMode::Strip, ..Pseudonyms::default() };
+        let mut text = text.to_string();
+        if let Some(terms) = &self.terms {
+            text = terms.replace_all(&text, |caps: &Captures| pseudonyms.placeholder("TERM", &caps[0])).into_owned();
+        }
+        text = EMAIL.replace_all(&text, |caps: &Captures| pseudonyms.placeholder("EMAIL", &caps[0])).into_owned();
+        text = PHONE
+            .replace_all(&text, |caps: &Captures| {
+                // Dates and short codes have fewer digits
+                match caps[0].chars().filter(char::is_ascii_digit).count() {
+                    digits if digits >= PHONE_DIGITS => pseudonyms.placeholder("PHONE", &caps[0]),
+                    _ => caps[0].to_string(),
+                }
+            })
+            .into_owned();
+        text = NAME
+            .replace_all(&text, |caps: &Captures| {
+                let matched = &caps[0];
+                let words: Vec<&str> = matched.split_whitespace().collect();
+                let starts = words.iter().take_while(|word| SENTENCE_STARTS.contains(word)).count();
+                if words.len() - starts < 2 {
+                    return matched.to_string();
+                }
+                // Keeps the sentence start and its spacing
+                let skipped = words[..starts]
+                    .iter()
+                    .fold(0, |offset, word| offset + matched[offset..].find(word).unwrap_or(0) + word.len());
+                let name = matched[skipped..].trim_start();
+                format!("{}{}", &matched[..matched.len() - name.len()], pseudonyms.placeholder("NAME", name))
+            })
+            .into_owned();
+        (text, pseudonyms)
+    }
+}
+
+/// Placeholders handed out while masking one prompt, with their originals
+#[derive(Debug, Default)]
+pub struct Pseudonyms {
+    strip: bool,
+    originals: HashMap<String, String>,
+    placeholders: HashMap<String, String>,
+    counts: HashMap<&'static str, usize>,
+}
+
+impl Pseudonyms {
+    /// Number of distinct identifiers replaced
+    pub fn len(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    fn placeholder(&mut self, kind: &'static str, original: &str) -> String {
+        if let Some(placeholder) = self.placeholders.get(original) {
+            return placeholder.clone();
+        }
+        let count = self.counts.entry(kind).or_default();
+        *count += 1;
+        let placeholder = match self.strip {
+            true => REDACTED.to_string(),
+            false => format!("{}_{}", kind, count),
+        };
+        self.placeholders.insert(original.to_string(), placeholder.clone());
+        self.originals.insert(placeholder.clone(), original.to_string());
+        placeholder
+    }
+
+    /// `text` with the placeholders put back; stripped text stays stripped
+    pub fn restore(&self, text: &str) -> String {
+        if self.strip || self.originals.is_empty() {
+            return text.to_string();
+        }
+        PLACEHOLDER
+            .replace_all(text, |caps: &Captures| self.originals.get(&caps[0]).cloned().unwrap_or_else(|| caps[0].to_string()))
+            .into_owned()
+    }
+
+    /// Length of the longest end of `text` a placeholder could start with
+    fn partial_len(&self, text: &str) -> usize {
+        if self.strip {
+            return 0;
+        }
+        text.char_indices()
+            .rev()
+            .take_while(|(i, _)| text.len() - i <= "EMAIL_".len() + 6)
+            .filter(|(i, _)| self.originals.keys().any(|placeholder| placeholder.starts_with(&text[*i..])))
+            .last()
+            .map_or(0, |(i, _)| text.len() - i)
+    }
+}
+
+/// Wraps a remote backend so prompts leave without identifiers. The answer
+/// is streamed with the placeholders restored; text that may be the start
+/// of a placeholder is held back until the next piece shows it isn't.
+pub struct AnonymizingBackend {
+    inner: Arc<dyn LLMBackend>,
+    anonymizer: Anonymizer,
+}
+
+impl AnonymizingBackend {
+    pub fn new(inner: Arc<dyn LLMBackend>, anonymizer: Anonymizer) -> Self {
+        AnonymizingBackend { inner, anonymizer }
+    }
+}
+
+impl LLMBackend for AnonymizingBackend {
+    fn infer(&self, prompt: String, options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+        let (prompt, pseudonyms) = self.anonymizer.mask(&prompt);
+        tracing::debug!(identifiers = pseudonyms.len(), "anonymized prompt");
+        let mut pending = String::new();
+        let mut stopped = false;
+        self.inner.infer(prompt, options, &mut |piece| {
+            pending.push_str(piece);
+            let ready = pending.len() - pseudonyms.partial_len(&pending);
+            if ready == 0 {
+                return ControlFlow::Continue(());
+            }
+            let flow = on_piece(&pseudonyms.restore(&pending[..ready]));
+            pending.drain(..ready);
+            stopped = flow.is_break();
+            flow
+        })?;
+        if !stopped && !pending.is_empty() {
+            let _ = on_piece(&pseudonyms.restore(&pending));
+        }
+        Ok(())
+    }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        self.inner.count_tokens(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records the prompt and answers with canned pieces
+    struct Remote {
+        seen: Mutex<String>,
+        pieces: Vec<&'static str>,
+    }
+
+    impl LLMBackend for Remote {
+        fn infer(&self, prompt: String, _options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+            *self.seen.lock().unwrap() = prompt;
+            for piece in &self.pieces {
+                if on_piece(piece).is_break() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_identifiers_are_hidden_and_restored_in_the_answer() -> Result<()> {
+        let anonymizer = Anonymizer::new(AnonymizeMode::Pseudonymize, &["project falcon".to_string()])?;
+        let prompt = "The Jane Doe account (jane.doe@example.com, +1 555 010 2030) belongs to Acme Corp. \
+                      Ask Jane Doe about Project Falcon.";
+        let (masked, pseudonyms) = anonymizer.mask(prompt);
+        assert_eq!(masked, "The NAME_1 account (EMAIL_1, PHONE_1) belongs to NAME_2. Ask NAME_1 about TERM_1.");
+        assert_eq!(pseudonyms.len(), 5);
+        assert_eq!(pseudonyms.restore(&masked), prompt);
+
+        let (stripped, _) = Anonymizer::new(AnonymizeMode::Strip, &[])?.mask("Call Jane Doe at +1 555 010 2030.");
+        assert_eq!(stripped, "Call [redacted] at [redacted].");
+
+        // Placeholders split across pieces are restored whole
+        let remote = Arc::new(Remote { seen: Mutex::default(), pieces: vec!["Contact NA", "ME_", "1 via EM", "AIL_1", "."] });
+        let backend = AnonymizingBackend::new(remote.clone(), anonymizer);
+        let mut pieces = Vec::new();
+        let options = InferenceOptions { max_tokens: 16, temperature: 0.0, top_p: 1.0, repeat_penalty: 1.0, seed: None };
+        backend.infer(prompt.to_string(), &options, &mut |piece| {
+            pieces.push(piece.to_string());
+            ControlFlow::Continue(())
+        })?;
+        assert_eq!(*remote.seen.lock().unwrap(), masked);
+        assert_eq!(pieces.concat(), "Contact Jane Doe via jane.doe@example.com.");
+        assert!(pieces.iter().all(|piece| !piece.contains("NA") && !piece.contains("EM")));
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::retriever::Retriever;
+
+/// Similarity a question must reach to get a cached answer
+const DEFAULT_THRESHOLD: f32 = 0.95;
+
+/// Answers to earlier questions, served again for the same or a nearly
+/// identical question without retrieval or generation. Each entry records
+/// the revision of every source document its context came from
+/// (`Retriever::source_revisions`); once the content of any of them
+/// changes, exactly the entries built on it are dropped instead of served.
+/// Beyond `capacity` the oldest entry is evicted.
+///
+/// Entries live in a `CacheStore`: process memory by default, or Redis so
+/// that server replicas share them. A store that fails is logged and
+/// treated as a miss; answers are then generated as without a cache.
+/// Entries carry the embedding of their question, and a copy of them is
+/// kept until the store's version changes, so a lookup embeds only the new
+/// question and reads no entries while the cache stays the same.
+pub struct AnswerCache {
+    store: Box<dyn CacheStore>,
+    capacity: usize,
+    threshold: f32,
+    /// The store's entries as of a version
+    snapshot: Mutex<Option<(u64, Arc<Vec<CachedAnswer>>)>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedAnswer {
+    pub query: String,
+    pub answer: String,
+    pub sources: BTreeMap<String, u64>,
+    /// Model `embedding` comes from; other models' entries match only the
+    /// exact question
+    #[serde(default)]
+    pub model_id: String,
+    #[serde(default)]
+    pub embedding: Vec<f32>,
+}
+
+/// Where an `AnswerCache` keeps its entries
+pub trait CacheStore: Send + Sync {
+    /// Every cached answer, oldest first
+    fn entries(&self) -> Result<Vec<CachedAnswer>>;
+    /// Stores `entry` in place of any for the same query, evicting the
+    /// oldest entries beyond `capacity`
+    fn insert(&self, entry: CachedAnswer, capacity: usize) -> Result<()>;
+    /// Drops the entries for `queries`
+    fn remove(&self, queries: &[String]) -> Result<()>;
+    /// Changes whenever entries are inserted or removed
+    fn version(&self) -> Result<u64>;
+}
+
+/// Opens the shared store at `url`, e.g. `redis://cache:6379/0`
+pub fn open_store(url: &str) -> Result<Box<dyn CacheStore>> {
+    #[cfg(feature = "redis")]
+    return Ok(Box::new(RedisStore::open(url)?));
+
+    #[cfg(not(feature = "redis"))]
+    Err(anyhow!("Cannot use answer cache {}: tapssp was built without the `redis` feature", url))
+}
+
+/// Entries in process memory, lost on restart and private to the process
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: Mutex<VecDeque<CachedAnswer>>,
+    version: AtomicU64,
+}
+
+impl CacheStore for MemoryStore {
+    fn entries(&self) -> Result<Vec<CachedAnswer>> {
+        Ok(self.entries.lock().unwrap().iter().cloned().collect())
+    }
+
+    fn insert(&self, entry: CachedAnswer, capacity: usize) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|cached| cached.query != entry.query);
+        while entries.len() >= capacity.max(1) {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+        self.version.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn remove(&self, queries: &[String]) -> Result<()> {
+        self.entries.lock().unwrap().retain(|entry| !queries.contains(&entry.query));
+        self.version.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn version(&self) -> Result<u64> {
+        Ok(self.version.load(Ordering::Relaxed))
+    }
+}
+
+/// Entries in Redis, shared by every server replica pointed at it: a hash
+/// from query to the JSON entry, a list of the queries oldest first for
+/// eviction, and a counter bumped on every change. Revisions are content fingerprints, so a replica drops an
+/// entry built on documents it has since re-ingested, and one whose
+/// documents differ from the replica that cached it never serves it.
+#[cfg(feature = "redis")]
+pub struct RedisStore {
+    client: redis::Client,
+    /// Key of the hash; the list is `{key}:order` and the counter
+    /// `{key}:version`
+    key: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisStore {
+    pub fn open(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url).map_err(|e| anyhow!("Invalid Redis URL '{}': {}", url, e))?;
+        client.get_connection().map_err(|e| anyhow!("Can't connect to Redis at '{}': {}", url, e))?;
+        Ok(RedisStore { client, key: "tapssp:answers".to_string() })
+    }
+
+    fn order_key(&self) -> String {
+        format!("{}:order", self.key)
+    }
+
+    fn version_key(&self) -> String {
+        format!("{}:version", self.key)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl CacheStore for RedisStore {
+    fn entries(&self) -> Result<Vec<CachedAnswer>> {
+        let mut connection = self.client.get_connection()?;
+        let values: Vec<String> = redis::cmd("HVALS").arg(&self.key).query(&mut connection)?;
+        // Entries written by another version are skipped, not fatal
+        Ok(values.iter().filter_map(|value| serde_json::from_str(value).ok()).collect())
+    }
+
+    fn insert(&self, entry: CachedAnswer, capacity: usize) -> Result<()> {
+        let mut connection = self.client.get_connection()?;
+        let order = self.order_key();
+        let value = serde_json::to_string(&entry)?;
+        let (length,): (usize,) = redis::pipe()
+            .atomic()
+            .cmd("HSET")
+            .arg(&self.key)
+            .arg(&entry.query)
+            .arg(value)
+            .ignore()
+            .cmd("LREM")
+            .arg(&order)
+            .arg(0)
+            .arg(&entry.query)
+            .ignore()
+            .cmd("INCR")
+            .arg(self.version_key())
+            .ignore()
+            .cmd("RPUSH")
+            .arg(&order)
+            .arg(&entry.query)
+            .query(&mut connection)?;
+        if length > capacity {
+            let evicted: Vec<String> = redis::cmd("LPOP").arg(&order).arg(length - capacity).query(&mut connection)?;
+            redis::pipe()
+                .atomic()
+                .cmd("HDEL")
+                .arg(&self.key)
+                .arg(evicted)
+                .ignore()
+                .cmd("INCR")
+                .arg(self.version_key())
+                .ignore()
+                .query::<()>(&mut connection)?;
+        }
+        Ok(())
+    }
+
+    fn remove(&self, queries: &[String]) -> Result<()> {
+        if queries.is_empty() {
+            return Ok(());
+        }
+        let mut connection = self.client.get_connection()?;
+        let mut pipe = redis::pipe();
+        pipe.atomic().cmd("HDEL").arg(&self.key).arg(queries).ignore();
+        for query in queries {
+            pipe.cmd("LREM").arg(self.order_key()).arg(0).arg(query).ignore();
+        }
+        pipe.cmd("INCR").arg(self.version_key()).ignore();
+        pipe.query::<()>(&mut connection)?;
+        Ok(())
+    }
+
+    fn version(&self) -> Result<u64> {
+        let mut connection = self.client.get_connection()?;
+        let version: Option<u64> = redis::cmd("GET").arg(self.version_key()).query(&mut connection)?;
+        Ok(version.unwrap_or(0))
+    }
+}
+
+impl AnswerCache {
+    pub fn new(capacity: usize) -> Self {
+        AnswerCache::with_store(Box::new(MemoryStore::default()), capacity)
+    }
+
+    pub fn with_store(store: Box<dyn CacheStore>, capacity: usize) -> Self {
+        AnswerCache { store, capacity, threshold: DEFAULT_THRESHOLD, snapshot: Mutex::new(None) }
+    }
+
+    /// The answer cached for `query`, or for the most similar cached
+    /// question at or above the threshold, after dropping the entries whose
+    /// source documents changed
+    pub fn get(&self, retriever: &Retriever, query: &str) -> Option<String> {
+        let entries = match self.entries() {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::warn!(error = %e, "answer cache unavailable");
+                return None;
+            }
+        };
+        let (fresh, stale): (Vec<&CachedAnswer>, Vec<_>) = entries
+            .iter()
+            .partition(|entry| entry.sources.iter().all(|(key, revision)| retriever.source_revision(key) == *revision));
+        if !stale.is_empty() {
+            let queries: Vec<String> = stale.into_iter().map(|entry| entry.query.clone()).collect();
+            match self.store.remove(&queries) {
+                Ok(()) => tracing::info!(dropped = queries.len(), "dropped cached answers of changed documents"),
+                Err(e) => tracing::warn!(error = %e, "can't drop stale cached answers"),
+            }
+        }
+        if let Some(entry) = fresh.iter().find(|entry| entry.query == query) {
+            return Some(entry.answer.clone());
+        }
+        let embedding = retriever.embed(query).ok()?;
+        fresh
+            .into_iter()
+            .filter(|entry| entry.model_id == retriever.model_id() && !entry.embedding.is_empty())
+            .map(|entry| (retriever.embedding_similarity(&embedding, &entry.embedding), entry))
+            .filter(|(score, _)| *score >= self.threshold)
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, entry)| entry.answer.clone())
+    }
+
+    /// Caches `answer` to `query`, generated from chunks of the `sources`
+    /// documents at the given revisions
+    pub fn insert(&self, retriever: &Retriever, query: &str, answer: String, sources: BTreeMap<String, u64>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entry = CachedAnswer {
+            query: query.to_string(),
+            answer,
+            sources,
+            model_id: retriever.model_id().to_string(),
+            embedding: retriever.embed(query).unwrap_or_default(),
+        };
+        if let Err(e) = self.store.insert(entry, self.capacity) {
+            tracing::warn!(error = %e, "can't cache answer");
+        }
+    }
+
+    /// The store's entries, read again only once its version changed
+    fn entries(&self) -> Result<Arc<Vec<CachedAnswer>>> {
+        let version = self.store.version()?;
+        let mut snapshot = self.snapshot.lock().unwrap();
+        if let Some((read, entries)) = snapshot.as_ref()
+            && *read == version
+        {
+            return Ok(entries.clone());
+        }
+        let entries = Arc::new(self.store.entries()?);
+        *snapshot = Some((version, entries.clone()));
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_changed_documents_invalidate_their_answers_only() -> Result<()> {
+        let file = |path: &str| BTreeMap::from([("path".to_string(), path.to_string())]);
+        let mut retriever = Retriever::new();
+        retriever.add_to_knowledge_base("Refunds take thirty days.".to_string(), file("docs/refunds.md"))?;
+        retriever.add_to_knowledge_base("Shipping is free over fifty euros.".to_string(), file("docs/shipping.md"))?;
+
+        let cache = AnswerCache::new(8);
+        for (query, answer) in [("How long do refunds take?", "Thirty days."), ("Is shipping free?", "Over fifty euros.")] {
+            let context = retriever.retrieve(query, 1);
+            cache.insert(&retriever, query, answer.to_string(), retriever.source_revisions(&context).unwrap());
+        }
+        assert_eq!(cache.get(&retriever, "How long do refunds take?").as_deref(), Some("Thirty days."));
+        assert_eq!(cache.get(&retriever, "how long do refunds take").as_deref(), Some("Thirty days."));
+        assert_eq!(cache.get(&retriever, "What about returns?"), None);
+
+        // Re-ingesting the refunds file drops the refunds answer only
+        retriever.remove_source(Path::new("docs/refunds.md"))?;
+        retriever.add_to_knowledge_base("Refunds take fourteen days.".to_string(), file("docs/refunds.md"))?;
+        assert_eq!(cache.get(&retriever, "How long do refunds take?"), None);
+        assert_eq!(cache.get(&retriever, "Is shipping free?").as_deref(), Some("Over fifty euros."));
+        assert_eq!(cache.entries()?.len(), 1);
+
+        // Revisions depend on content only, so a replica that indexed the
+        // same files agrees and one that re-ingested them unchanged too
+        let mut replica = Retriever::new();
+        replica.add_to_knowledge_base("Shipping is free over fifty euros.".to_string(), file("docs/shipping.md"))?;
+        assert_eq!(cache.get(&replica, "Is shipping free?").as_deref(), Some("Over fifty euros."));
+        retriever.remove_source(Path::new("docs/shipping.md"))?;
+        retriever.add_to_knowledge_base("Shipping is free over fifty euros.".to_string(), file("docs/shipping.md"))?;
+        assert_eq!(cache.get(&retriever, "Is shipping free?").as_deref(), Some("Over fifty euros."));
+
+        // Chunks the index doesn't hold can't be tracked
+        assert!(retriever.source_revisions(&["Unknown text.".to_string()]).is_none());
+        Ok(())
+    }
+
+    /// A `MemoryStore` counting how often all entries are read
+    #[derive(Default)]
+    struct CountingStore {
+        inner: MemoryStore,
+        reads: Arc<AtomicU64>,
+    }
+
+    impl CacheStore for CountingStore {
+        fn entries(&self) -> Result<Vec<CachedAnswer>> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.entries()
+        }
+
+        fn insert(&self, entry: CachedAnswer, capacity: usize) -> Result<()> {
+            self.inner.insert(entry, capacity)
+        }
+
+        fn remove(&self, queries: &[String]) -> Result<()> {
+            self.inner.remove(queries)
+        }
+
+        fn version(&self) -> Result<u64> {
+            self.inner.version()
+        }
+    }
+
+    #[test]
+    fn test_entries_are_read_again_only_after_a_change() -> Result<()> {
+        let mut retriever = Retriever::new();
+        retriever.add_to_knowledge_base("Refunds take thirty days.".to_string(), BTreeMap::new())?;
+        let store = CountingStore::default();
+        let reads = store.reads.clone();
+        let cache = AnswerCache::with_store(Box::new(store), 8);
+        let context = retriever.retrieve("refunds", 1);
+        cache.insert(&retriever, "How long do refunds take?", "Thirty days.".to_string(), retriever.source_revisions(&context).unwrap());
+        let entry = &cache.entries()?[0];
+        assert_eq!(entry.model_id, retriever.model_id());
+        assert!(!entry.embedding.is_empty());
+
+        for query in ["How long do refunds take?", "how long do refunds take", "Is shipping free?"] {
+            cache.get(&retriever, query);
+        }
+        assert_eq!(reads.load(Ordering::Relaxed), 1);
+        cache.insert(&retriever, "Is shipping free?", "No.".to_string(), BTreeMap::new());
+        assert_eq!(cache.get(&retriever, "Is shipping free?").as_deref(), Some("No."));
+        assert_eq!(reads.load(Ordering::Relaxed), 2);
+        Ok(())
+    }
+}
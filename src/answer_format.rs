@@ -0,0 +1,144 @@
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::highlight::sentence_spans;
+use crate::i18n;
+
+lazy_static! {
+    static ref BULLET: Regex = Regex::new(r"^\s*[-*•]\s+\S").unwrap();
+    static ref NUMBERED: Regex = Regex::new(r"^\s*(\d+)[.)]\s+\S").unwrap();
+    static ref TABLE_DIVIDER: Regex = Regex::new(r"^\s*\|?\s*:?-{3,}:?\s*(\|\s*:?-{3,}:?\s*)*\|?\s*$").unwrap();
+    static ref CODE_BLOCK: Regex = Regex::new(r"(?s)```[^\n]*\n.*?```").unwrap();
+}
+
+/// Shape an answer is asked for, with `/format` in the REPL or the `format`
+/// field of API requests. Each adds an instruction to the prompt, and the
+/// answer is checked afterwards and repaired where the model didn't comply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerFormat {
+    /// A short bulleted summary
+    Bullets,
+    /// Numbered instructions
+    Steps,
+    /// A Markdown table
+    Table,
+    /// Only fenced code blocks
+    Code,
+}
+
+impl AnswerFormat {
+    pub const ALL: [AnswerFormat; 4] = [AnswerFormat::Bullets, AnswerFormat::Steps, AnswerFormat::Table, AnswerFormat::Code];
+
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "bullets" | "bullet" | "summary" => Ok(AnswerFormat::Bullets),
+            "steps" | "step-by-step" => Ok(AnswerFormat::Steps),
+            "table" => Ok(AnswerFormat::Table),
+            "code" | "code-only" => Ok(AnswerFormat::Code),
+            _ => Err(anyhow!("Unknown answer format '{}' (bullets, steps, table, code)", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AnswerFormat::Bullets => "bullets",
+            AnswerFormat::Steps => "steps",
+            AnswerFormat::Table => "table",
+            AnswerFormat::Code => "code",
+        }
+    }
+
+    /// Added to the prompt right before the question, in the current locale
+    pub fn instruction(self) -> String {
+        i18n::text(&format!("format-{}", self.name()))
+    }
+
+    /// Whether `answer` already has this shape
+    pub fn validate(self, answer: &str) -> bool {
+        let lines: Vec<&str> = answer.lines().filter(|line| !line.trim().is_empty()).collect();
+        match self {
+            AnswerFormat::Bullets => !lines.is_empty() && lines.iter().all(|line| BULLET.is_match(line)),
+            AnswerFormat::Steps => {
+                let numbers: Vec<usize> = lines.iter().filter_map(|line| NUMBERED.captures(line)?[1].parse().ok()).collect();
+                numbers.len() >= lines.len().min(2) && !numbers.is_empty() && numbers.iter().enumerate().all(|(i, &n)| n == i + 1)
+            }
+            AnswerFormat::Table => lines.windows(2).any(|pair| pair[0].contains('|') && TABLE_DIVIDER.is_match(pair[1])),
+            AnswerFormat::Code => !lines.is_empty() && CODE_BLOCK.replace_all(answer, "").trim().is_empty(),
+        }
+    }
+
+    /// `answer` in this shape: returned as is when valid, otherwise rebuilt
+    /// from its sentences or code. A table can't be made up from prose, so
+    /// a non-table answer is returned unchanged.
+    pub fn enforce(self, answer: String) -> String {
+        if self.validate(&answer) {
+            return answer;
+        }
+        tracing::debug!(format = self.name(), "answer doesn't match the requested format, repairing");
+        match self {
+            AnswerFormat::Bullets => points(&answer).iter().map(|point| format!("- {}", point)).collect::<Vec<_>>().join("\n"),
+            AnswerFormat::Steps => points(&answer)
+                .iter()
+                .enumerate()
+                .map(|(i, point)| format!("{}. {}", i + 1, point))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            AnswerFormat::Table => {
+                tracing::warn!("answer requested as a table has none");
+                answer
+            }
+            AnswerFormat::Code => {
+                let blocks: Vec<&str> = CODE_BLOCK.find_iter(&answer).map(|block| block.as_str()).collect();
+                if blocks.is_empty() {
+                    format!("```\n{}\n```", answer.trim())
+                } else {
+                    blocks.join("\n\n")
+                }
+            }
+        }
+    }
+}
+
+/// The items of a list-like answer: its list entries with their markers
+/// removed, or its sentences when it is prose
+fn points(answer: &str) -> Vec<String> {
+    let listed: Vec<String> = answer
+        .lines()
+        .filter(|line| BULLET.is_match(line) || NUMBERED.is_match(line))
+        .map(|line| line.trim_start().trim_start_matches(|c: char| c.is_ascii_digit() || matches!(c, '-' | '*' | '•' | '.' | ')')).trim().to_string())
+        .collect();
+    if !listed.is_empty() {
+        return listed;
+    }
+    let text = answer.split_whitespace().collect::<Vec<_>>().join(" ");
+    sentence_spans(&text).into_iter().map(|span| text[span].trim().to_string()).filter(|s| !s.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answers_are_checked_and_repaired() {
+        let bullets = AnswerFormat::parse("bullet").unwrap();
+        assert!(bullets.validate("- Thirty days.\n- Original card."));
+        assert_eq!(bullets.enforce("Refunds take thirty days. They go to the original card.".to_string()),
+            "- Refunds take thirty days.\n- They go to the original card.");
+
+        let steps = AnswerFormat::parse("step-by-step").unwrap();
+        assert!(!steps.validate("1. Open settings.\n3. Save."));
+        assert_eq!(steps.enforce("To reset it:\n- Open settings.\n- Click reset.".to_string()),
+            "1. Open settings.\n2. Click reset.");
+
+        let table = "| Plan | Price |\n|---|---|\n| Basic | $5 |";
+        assert!(AnswerFormat::Table.validate(&format!("Prices:\n\n{}", table)));
+        assert_eq!(AnswerFormat::Table.enforce("Basic costs $5.".to_string()), "Basic costs $5.");
+
+        assert_eq!(AnswerFormat::Code.enforce("Run this:\n```sh\nmake\n```\nDone.".to_string()), "```sh\nmake\n```");
+        assert_eq!(AnswerFormat::Code.enforce("make".to_string()), "```\nmake\n```");
+        assert!(AnswerFormat::parse("poem").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::collection::CollectionSettings;
+use crate::utils;
+
+/// Everything that goes into an index, pinned in a `build.toml` so `kb
+/// build` produces the same index file on every machine:
+///
+/// ```toml
+/// tapssp_version = "0.1.0"
+/// content_hash = "..."  # SHA256 of the built index, checked when set
+///
+/// [settings]
+/// chunk-size = 800
+/// chunk-strategy = "recursive"
+///
+/// [embedding]
+/// model = "tfidf"
+///
+/// [[source]]
+/// path = "docs/refunds.txt"
+/// sha256 = "..."
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildManifest {
+    /// Version whose loaders, chunkers and tokenizer the build is pinned to
+    pub tapssp_version: String,
+    pub content_hash: Option<String>,
+    /// Collection settings, as accepted by `kb configure`
+    #[serde(default)]
+    pub settings: BTreeMap<String, toml::Value>,
+    pub embedding: EmbeddingPin,
+    #[serde(rename = "source", default)]
+    pub sources: Vec<SourcePin>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmbeddingPin {
+    /// Model ID recorded in indexes: `tfidf`, or `<dir>@<weights hash>`
+    pub model: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourcePin {
+    /// Relative to the manifest
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+impl BuildManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut manifest = Self::parse(&fs::read_to_string(path)?)
+            .map_err(|e| anyhow!("Invalid build manifest {:?}: {}", path, e))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        for source in &mut manifest.sources {
+            source.path = base.join(&source.path);
+        }
+        Ok(manifest)
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let manifest: BuildManifest = toml::from_str(text)?;
+        if manifest.sources.is_empty() {
+            return Err(anyhow!("no [[source]] entries"));
+        }
+        Ok(manifest)
+    }
+
+    pub fn settings(&self) -> Result<CollectionSettings> {
+        let mut settings = CollectionSettings::default();
+        for (key, value) in &self.settings {
+            let value = match value {
+                toml::Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            settings.set(key, &value)?;
+        }
+        Ok(settings)
+    }
+
+    /// Checks the running version and every source file against their
+    /// pins, listing all mismatches at once
+    pub fn verify(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        let version = env!("CARGO_PKG_VERSION");
+        if self.tapssp_version != version {
+            problems.push(format!("pinned to tapssp {}, this is {}", self.tapssp_version, version));
+        }
+        for source in &self.sources {
+            match utils::sha256_file(&source.path) {
+                Ok(hash) if hash == source.sha256.to_lowercase() => {}
+                Ok(hash) => problems.push(format!("{:?} has sha256 {}, pinned {}", source.path, hash, source.sha256)),
+                Err(e) => problems.push(format!("{:?}: {}", source.path, e)),
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Build manifest doesn't match:\n  {}", problems.join("\n  ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::ChunkStrategy;
+    use crate::vector_db::VectorDB;
+
+    #[test]
+    fn test_manifest_pins_settings_sources_and_output() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("refunds.txt"), "abc")?;
+        let manifest_path = dir.path().join("build.toml");
+        fs::write(&manifest_path, format!(r#"
+            tapssp_version = "{}"
+
+            [settings]
+            chunk-size = 800
+            chunk-strategy = "recursive"
+            stop-words = false
+
+            [embedding]
+            model = "tfidf"
+
+            [[source]]
+            path = "refunds.txt"
+            sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        "#, env!("CARGO_PKG_VERSION")))?;
+        let manifest = BuildManifest::load(&manifest_path)?;
+        let settings = manifest.settings()?;
+        assert_eq!((settings.chunk_size, settings.chunk_strategy, settings.stop_words), (Some(800), ChunkStrategy::Recursive, false));
+        manifest.verify()?;
+
+        fs::write(dir.path().join("refunds.txt"), "abd")?;
+        assert!(manifest.verify().is_err());
+        assert!(BuildManifest::parse("tapssp_version = \"0.1.0\"\n[embedding]\nmodel = \"tfidf\"").is_err());
+
+        // The same inputs give byte-identical index files
+        let build = |path: &Path| -> Result<String> {
+            let mut db = VectorDB::new();
+            db.use_deterministic_ids();
+            for text in ["Refunds take thirty days", "Orders ship on Monday", "Support answers email"] {
+                db.add_document(text.to_string())?;
+            }
+            db.rebuild();
+            db.save(path)?;
+            utils::sha256_file(path)
+        };
+        assert_eq!(build(&dir.path().join("a.bin"))?, build(&dir.path().join("b.bin"))?);
+        Ok(())
+    }
+}
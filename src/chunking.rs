@@ -0,0 +1,326 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::utils::{Block, split_blocks, split_into_chunks};
+
+/// Separators the recursive strategy tries in order, coarsest first
+const RECURSIVE_SEPARATORS: &[&str] = &["\n\n", "\n", ". ", " "];
+
+/// How text files are split into documents. Every strategy keeps fenced
+/// code and LaTeX display blocks whole and byte-for-byte intact, splitting
+/// only the prose between them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ChunkStrategy {
+    /// Whole sentences, keeping code and LaTeX blocks intact
+    #[default]
+    Sentence,
+    /// Whole paragraphs (separated by blank lines); long ones by sentence
+    Paragraph,
+    /// Windows of a fixed number of whitespace-separated tokens
+    FixedToken,
+    /// Paragraphs, then lines, sentences and words, as far as needed to fit
+    Recursive,
+}
+
+impl ChunkStrategy {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "sentence" => Ok(ChunkStrategy::Sentence),
+            "paragraph" => Ok(ChunkStrategy::Paragraph),
+            "fixed-token" | "token" => Ok(ChunkStrategy::FixedToken),
+            "recursive" => Ok(ChunkStrategy::Recursive),
+            _ => Err(anyhow!("Unknown chunk strategy '{}' (sentence, paragraph, fixed-token, recursive)", s)),
+        }
+    }
+}
+
+impl fmt::Display for ChunkStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChunkStrategy::Sentence => "sentence",
+            ChunkStrategy::Paragraph => "paragraph",
+            ChunkStrategy::FixedToken => "fixed-token",
+            ChunkStrategy::Recursive => "recursive",
+        })
+    }
+}
+
+/// Splits a text file into the documents to index
+pub trait Chunker {
+    fn chunk(&self, text: &str) -> Vec<String>;
+}
+
+/// The chunker for `strategy`. `size` and `overlap` count tokens for
+/// `FixedToken` and characters otherwise.
+pub fn chunker(strategy: ChunkStrategy, size: usize, overlap: usize) -> Box<dyn Chunker> {
+    match strategy {
+        ChunkStrategy::Sentence => Box::new(SentenceChunker { max_chars: size, overlap }),
+        ChunkStrategy::Paragraph => Box::new(ParagraphChunker { max_chars: size, overlap }),
+        ChunkStrategy::FixedToken => Box::new(FixedTokenChunker { tokens: size, overlap }),
+        ChunkStrategy::Recursive => Box::new(RecursiveChunker { max_chars: size, overlap }),
+    }
+}
+
+pub struct SentenceChunker {
+    max_chars: usize,
+    overlap: usize,
+}
+
+impl Chunker for SentenceChunker {
+    fn chunk(&self, text: &str) -> Vec<String> {
+        with_overlap(split_into_chunks(text, self.max_chars), self.overlap)
+    }
+}
+
+pub struct ParagraphChunker {
+    max_chars: usize,
+    overlap: usize,
+}
+
+impl Chunker for ParagraphChunker {
+    fn chunk(&self, text: &str) -> Vec<String> {
+        let pieces = around_verbatim(text, |prose| {
+            let mut pieces = Vec::new();
+            for paragraph in paragraphs(prose) {
+                if paragraph.chars().count() > self.max_chars {
+                    pieces.extend(split_into_chunks(paragraph, self.max_chars));
+                } else {
+                    pieces.push(paragraph.to_string());
+                }
+            }
+            pieces
+        });
+        with_overlap(merge(pieces, "\n\n", self.max_chars), self.overlap)
+    }
+}
+
+pub struct FixedTokenChunker {
+    tokens: usize,
+    overlap: usize,
+}
+
+impl Chunker for FixedTokenChunker {
+    /// Verbatim blocks are chunks of their own, however many tokens they
+    /// have; windows don't span them
+    fn chunk(&self, text: &str) -> Vec<String> {
+        around_verbatim(text, |prose| {
+            let words: Vec<&str> = prose.split_whitespace().collect();
+            let step = self.tokens.saturating_sub(self.overlap).max(1);
+            let mut chunks = Vec::new();
+            let mut start = 0;
+            while start < words.len() {
+                let end = (start + self.tokens).min(words.len());
+                chunks.push(words[start..end].join(" "));
+                if end == words.len() {
+                    break;
+                }
+                start += step;
+            }
+            chunks
+        })
+    }
+}
+
+pub struct RecursiveChunker {
+    max_chars: usize,
+    overlap: usize,
+}
+
+impl Chunker for RecursiveChunker {
+    fn chunk(&self, text: &str) -> Vec<String> {
+        let pieces = around_verbatim(text, |prose| split_recursive(prose.trim(), RECURSIVE_SEPARATORS, self.max_chars));
+        with_overlap(merge(pieces, "\n", self.max_chars), self.overlap)
+    }
+}
+
+/// The verbatim blocks of `text` as they are, with the prose between them
+/// split by `split_prose`, in order
+fn around_verbatim(text: &str, split_prose: impl Fn(&str) -> Vec<String>) -> Vec<String> {
+    let mut pieces = Vec::new();
+    for block in split_blocks(text) {
+        match block {
+            Block::Verbatim(block) => pieces.push(block.to_string()),
+            Block::Prose(prose) => pieces.extend(split_prose(prose)),
+        }
+    }
+    pieces
+}
+
+/// Whether `chunk` starts (`first`) or ends with a verbatim block
+fn verbatim_at(chunk: &str, first: bool) -> bool {
+    let blocks = split_blocks(chunk);
+    let block = if first { blocks.first() } else { blocks.last() };
+    matches!(block, Some(Block::Verbatim(_)))
+}
+
+/// Splits on the first separator, splitting pieces that are still too long
+/// on the next ones (and finally by character), then packs neighbouring
+/// pieces back together up to `max_chars`
+fn split_recursive(text: &str, separators: &[&str], max_chars: usize) -> Vec<String> {
+    if text.chars().count() <= max_chars {
+        return if text.trim().is_empty() { Vec::new() } else { vec![text.to_string()] };
+    }
+    let Some((separator, rest)) = separators.split_first() else {
+        let chars: Vec<char> = text.chars().collect();
+        return chars.chunks(max_chars).map(|chunk| chunk.iter().collect()).collect();
+    };
+    // Pieces keep the punctuation of their separator; whitespace is restored
+    // when they are packed again
+    let mut pieces = Vec::new();
+    for piece in text.split_inclusive(separator).map(str::trim).filter(|piece| !piece.is_empty()) {
+        pieces.extend(split_recursive(piece, rest, max_chars));
+    }
+    let joiner = if separator.trim().is_empty() { separator } else { " " };
+    merge(pieces, joiner, max_chars)
+}
+
+/// Paragraphs of `text`, separated by lines that are empty or only whitespace
+fn paragraphs(text: &str) -> Vec<&str> {
+    let mut paragraphs = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        match (line.trim().is_empty(), start) {
+            (true, Some(begin)) => {
+                paragraphs.push(text[begin..offset].trim());
+                start = None;
+            }
+            (false, None) => start = Some(offset),
+            _ => {}
+        }
+        offset += line.len();
+    }
+    if let Some(begin) = start {
+        paragraphs.push(text[begin..].trim());
+    }
+    paragraphs
+}
+
+/// Joins consecutive pieces with `separator` while they fit in `max_chars`
+fn merge(pieces: Vec<String>, separator: &str, max_chars: usize) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    let mut length = 0;
+    for piece in pieces {
+        let piece_len = piece.chars().count();
+        match merged.last_mut() {
+            Some(last) if length + separator.chars().count() + piece_len <= max_chars => {
+                last.push_str(separator);
+                last.push_str(&piece);
+                length += separator.chars().count() + piece_len;
+            }
+            _ => {
+                merged.push(piece);
+                length = piece_len;
+            }
+        }
+    }
+    merged
+}
+
+/// Repeats the last `overlap` characters of each chunk, from a word
+/// boundary, at the start of the next one. Nothing is repeated from a chunk
+/// ending in a verbatim block, which would be cut, and a chunk starting
+/// with one gets the repeated text on a line of its own.
+fn with_overlap(chunks: Vec<String>, overlap: usize) -> Vec<String> {
+    if overlap == 0 {
+        return chunks;
+    }
+    let mut overlapped = Vec::with_capacity(chunks.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        let tail = i
+            .checked_sub(1)
+            .filter(|&previous| !verbatim_at(&chunks[previous], false))
+            .map(|previous| tail(&chunks[previous], overlap));
+        match tail {
+            Some(tail) if !tail.is_empty() => {
+                let separator = if verbatim_at(chunk, true) { "\n" } else { " " };
+                overlapped.push(format!("{}{}{}", tail, separator, chunk));
+            }
+            _ => overlapped.push(chunk.clone()),
+        }
+    }
+    overlapped
+}
+
+/// The last `chars` characters of `text`, starting at a word boundary
+fn tail(text: &str, chars: usize) -> &str {
+    let start = text.char_indices().rev().nth(chars.saturating_sub(1)).map_or(0, |(i, _)| i);
+    if start == 0 || text[..start].ends_with(char::is_whitespace) {
+        return &text[start..];
+    }
+    match text[start..].find(char::is_whitespace) {
+        Some(space) => text[start + space..].trim_start(),
+        None => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strategies_split_at_their_boundaries() {
+        let text = "Refunds take thirty days.\nReturns need a receipt.\n\nOrders ship on Monday. Tracking follows by email.";
+
+        let paragraphs = chunker(ChunkStrategy::Paragraph, 60, 0).chunk(text);
+        assert_eq!(paragraphs, [
+            "Refunds take thirty days.\nReturns need a receipt.",
+            "Orders ship on Monday. Tracking follows by email.",
+        ]);
+
+        let recursive = chunker(ChunkStrategy::Recursive, 30, 0).chunk(text);
+        assert_eq!(recursive, [
+            "Refunds take thirty days.",
+            "Returns need a receipt.",
+            "Orders ship on Monday.",
+            "Tracking follows by email.",
+        ]);
+        assert!(recursive.iter().all(|chunk| chunk.chars().count() <= 30));
+
+        let tokens = chunker(ChunkStrategy::FixedToken, 4, 1).chunk("a b c d e f g");
+        assert_eq!(tokens, ["a b c d", "d e f g"]);
+
+        assert_eq!(ChunkStrategy::parse("fixed-token").unwrap().to_string(), "fixed-token");
+    }
+
+    /// A code block with a blank line and indentation between two
+    /// paragraphs, and the block as it must appear in some chunk
+    fn text_with_code() -> (String, &'static str) {
+        let code = "```python\ndef area(r):\n\n    return 3.14 * r ** 2  # pi.r^2!\n```";
+        let text = format!("Refunds take thirty days. Returns need a receipt.\n\n{}\n\nOrders ship on Monday. Tracking follows.", code);
+        (text, code)
+    }
+
+    fn assert_kept_whole(chunks: &[String], code: &str) {
+        assert!(chunks.iter().any(|chunk| chunk.contains(code)), "{:?}", chunks);
+        assert!(chunks.iter().any(|chunk| chunk.contains("Tracking follows")));
+    }
+
+    #[test]
+    fn test_paragraph_strategy_keeps_code_blocks_verbatim() {
+        let (text, code) = text_with_code();
+        assert_kept_whole(&chunker(ChunkStrategy::Paragraph, 30, 0).chunk(&text), code);
+        assert_kept_whole(&chunker(ChunkStrategy::Paragraph, 200, 0).chunk(&text), code);
+        assert_kept_whole(&chunker(ChunkStrategy::Paragraph, 30, 10).chunk(&text), code);
+    }
+
+    #[test]
+    fn test_recursive_strategy_keeps_code_blocks_verbatim() {
+        let (text, code) = text_with_code();
+        assert_kept_whole(&chunker(ChunkStrategy::Recursive, 30, 0).chunk(&text), code);
+        let overlapped = chunker(ChunkStrategy::Recursive, 30, 10).chunk(&text);
+        assert_kept_whole(&overlapped, code);
+        // The repeated text goes on its own line, so the fence still opens one
+        assert!(overlapped.iter().any(|chunk| chunk.ends_with(&format!("receipt.\n{}", code))));
+    }
+
+    #[test]
+    fn test_fixed_token_strategy_keeps_code_blocks_verbatim() {
+        let (text, code) = text_with_code();
+        let chunks = chunker(ChunkStrategy::FixedToken, 4, 1).chunk(&text);
+        assert!(chunks.contains(&code.to_string()), "{:?}", chunks);
+        assert_eq!(chunks[0], "Refunds take thirty days.");
+        assert_eq!(chunks.last().unwrap(), "Monday. Tracking follows.");
+    }
+}
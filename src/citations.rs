@@ -0,0 +1,109 @@
+use std::fmt;
+
+use crate::retriever::ScoredChunk;
+
+/// Characters of a chunk shown for sources without a file name, e.g.
+/// chunks from federated peers
+const UNNAMED_PREVIEW_CHARS: usize = 60;
+
+/// The context documents an answer was generated from, numbered like the
+/// `<document index="N">` blocks of the prompt the answer cites them by
+#[derive(Debug, Clone)]
+pub struct Sources {
+    pub sources: Vec<Source>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    /// The file the chunk came from plus its table rows, or the start of
+    /// the chunk when its origin isn't known
+    pub label: String,
+    /// Similarity to the query
+    pub score: f32,
+    /// Whether the answer cites the document as `[N]`
+    pub cited: bool,
+}
+
+impl Sources {
+    /// The sources of the `context` chunks as retrieved, with the scores
+    /// and metadata they were retrieved with
+    pub fn new(context: &[ScoredChunk], answer: &str) -> Self {
+        let cited = cited_numbers(answer);
+        let sources = context
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| Source { label: label(chunk), score: chunk.score, cited: cited.contains(&(i + 1)) })
+            .collect();
+        Sources { sources }
+    }
+}
+
+fn label(chunk: &ScoredChunk) -> String {
+    match (chunk.metadata.get("source"), chunk.metadata.get("rows")) {
+        (Some(source), Some(rows)) => format!("{}, rows {}", source, rows),
+        (Some(source), None) => source.clone(),
+        _ => {
+            let preview: String = chunk.content.chars().take(UNNAMED_PREVIEW_CHARS).collect();
+            let ellipsis = if chunk.content.chars().count() > UNNAMED_PREVIEW_CHARS { "..." } else { "" };
+            format!("\"{}{}\"", preview.split_whitespace().collect::<Vec<_>>().join(" "), ellipsis)
+        }
+    }
+}
+
+/// Document numbers cited in `answer` as `[1]`, `[2, 3]` or `[1][3]`
+pub fn cited_numbers(answer: &str) -> Vec<usize> {
+    let mut numbers = Vec::new();
+    for (start, _) in answer.match_indices('[') {
+        let Some(end) = answer[start..].find(']') else {
+            break;
+        };
+        let inside = &answer[start + 1..start + end];
+        let parsed: Option<Vec<usize>> = inside.split(',').map(|n| n.trim().parse().ok()).collect();
+        for number in parsed.unwrap_or_default() {
+            if !numbers.contains(&number) {
+                numbers.push(number);
+            }
+        }
+    }
+    numbers
+}
+
+impl fmt::Display for Sources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Sources:")?;
+        for (i, source) in self.sources.iter().enumerate() {
+            let marker = if source.cited { "*" } else { " " };
+            write!(f, "\n {}[{}] {} ({:.3})", marker, i + 1, source.label, source.score)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retriever::Retriever;
+    use crate::timings::Timings;
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_sources_are_numbered_like_the_prompt() -> Result<()> {
+        assert_eq!(cited_numbers("Refunds take 30 days [2]. Orders ship Monday [1, 3][2]. See [note]."), vec![2, 1, 3]);
+
+        let mut retriever = Retriever::new();
+        let metadata = BTreeMap::from([("source".to_string(), "refunds.txt".to_string())]);
+        retriever.add_to_knowledge_base("Refunds take thirty days".to_string(), metadata)?;
+        retriever.rebuild();
+        let mut context = retriever.retrieve_timed("refunds", 1, &mut Timings::new());
+        context.push(ScoredChunk { content: "Peer chunk about shipping".to_string(), score: 0.5, ..ScoredChunk::default() });
+        let sources = Sources::new(&context, "Thirty days [1].");
+        assert_eq!(sources.sources[0].label, "refunds.txt");
+        assert_eq!(sources.sources[0].score, context[0].score);
+        assert_eq!(sources.sources[1].score, 0.5);
+        assert!(sources.sources[0].cited && !sources.sources[1].cited);
+        assert_eq!(sources.sources[1].label, "\"Peer chunk about shipping\"");
+        assert!(sources.to_string().starts_with("Sources:\n *[1] refunds.txt ("));
+        Ok(())
+    }
+}
@@ -0,0 +1,2091 @@
+use anyhow::{Result, anyhow};
+use crate::{
+    access_log, anonymize, answer_cache, answer_format, build, collection, completions, config, corpus_diff, device, discovery, embeddings, escalation,
+    faq, federation, fixtures, highlight, i18n, import, ingest, ingest_preview, ingest_queue, intent, llm, maintenance,
+    metadata, migration, moderation, object_store, pipeline, profile, prompt_template, rerank, retriever,
+    runtime, search, sessions, setup, snapshots, spelling, status_line, synonyms, telemetry, templates, timings, training,
+    transforms, utils, vector_db, watch, webhooks,
+};
+#[cfg(feature = "ollama")]
+use crate::ollama::{self, OllamaBackend};
+#[cfg(feature = "openai")]
+use crate::openai_client::{self, OpenAiBackend};
+#[cfg(feature = "server")]
+use crate::server;
+use access_log::{AccessLog, AccessStats};
+use anonymize::{AnonymizeMode, AnonymizingBackend, Anonymizer};
+use answer_cache::AnswerCache;
+use answer_format::AnswerFormat;
+use build::BuildManifest;
+use collection::{Collections, CollectionSettings};
+use config::RuntimeConfig;
+use corpus_diff::CorpusDiff;
+use device::Device;
+use embeddings::Embedder;
+use escalation::{Escalation, Outcome};
+use faq::Faq;
+use federation::Federation;
+use fixtures::{Fixture, FixtureRecorder};
+use highlight::Highlight;
+use i18n::Locale;
+use ingest::{TABLE_CHUNK_CHARS, add_file, is_ingestible, load_documents, load_file, load_files, read_file};
+use ingest_queue::IngestQueue;
+use ingest_preview::IngestPreview;
+use intent::IntentClassifier;
+use llm::{Backend, Conversation, GenerationOverrides, LLM, LLMBackend, LLMConfig};
+use maintenance::MaintenanceWorker;
+use migration::ReembedWorker;
+use moderation::Moderator;
+use object_store::ObjectUrl;
+use pipeline::RagPipeline;
+use profile::UserProfile;
+use prompt_template::PromptTemplate;
+use rerank::Reranker;
+use retriever::{Fusion, Retriever, ScoredChunk};
+use search::SearchResults;
+use sessions::SessionLog;
+use setup::SetupChoices;
+use snapshots::{SnapshotStore, SnapshotWorker};
+use spelling::IndexSpellCorrector;
+use status_line::StatusLine;
+use synonyms::Synonyms;
+use vector_db::{IndexDelta, SearchMode, VectorDB};
+use watch::WatchWorker;
+use webhooks::{WebhookEvent, Webhooks};
+use templates::{OutputFormat, SavedQuery};
+use timings::Timings;
+use transforms::Transforms;
+use std::{env, fs};
+use std::io::{IsTerminal, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long an ingestion task may stay claimed before other workers assume
+/// its worker died and put it back in the queue
+const STALE_CLAIM_AFTER: Duration = Duration::from_secs(3600);
+/// How often an idle ingestion worker checks the queue
+const WORKER_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often a worker refreshes the claim on the task it is running
+const CLAIM_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// Sampling seed of `tapssp replay`, so replayed answers are reproducible
+const REPLAY_SEED: u64 = 42;
+
+/// The generator configured by `config`, with `model` (a GGUF file, or a
+/// model name with the Ollama backend) in place of the configured one
+fn load_llm(config: &RuntimeConfig, model: Option<PathBuf>, status: &dyn Fn(String)) -> Result<LLM> {
+    let defaults = LLMConfig::default();
+    let mut profiles = defaults.profiles.clone();
+    profiles.answer.temperature = config.temperature.unwrap_or(profiles.answer.temperature);
+    profiles.answer.top_p = config.top_p.unwrap_or(profiles.answer.top_p);
+    let llm_config = LLMConfig {
+        model_path: model.clone(),
+        models_dir: config.models_dir().ok(),
+        model_sha256: config.model_sha256.clone(),
+        max_tokens: config.max_tokens.unwrap_or(defaults.max_tokens),
+        limits: config.limits.clone(),
+        context_tokens: config.context_size,
+        profiles,
+        repeat_penalty: config.repeat_penalty.unwrap_or(defaults.repeat_penalty),
+        n_gpu_layers: config.gpu_layers,
+        backend: config.gpu_backend.as_deref().map(Backend::parse).transpose()?.unwrap_or_default(),
+        main_gpu: config.main_gpu,
+        tensor_split: config.tensor_split.clone(),
+        memory_reserve: config.memory_reserve_mb.map_or(defaults.memory_reserve, |mb| mb * 1024 * 1024),
+        prompt_template: config.prompt_template.clone().unwrap_or_default(),
+        ..defaults
+    };
+    match config.backend.as_deref() {
+        None | Some("llama") => {
+            if config.anonymize.is_some() {
+                return Err(anyhow!("--anonymize applies to remote backends; the llama backend keeps prompts on this machine"));
+            }
+            // Ask before a first launch downloads gigabytes; scripts and
+            // servers keep downloading the default model unattended
+            if model.is_none() && !config.non_interactive && std::io::stdin().is_terminal() {
+                let cached = config.models_dir()?.join(llm::DEFAULT_MODEL_FILE);
+                if !cached.exists() && !ask_yes_no(
+                    "No model is configured (run `tapssp init` to choose one). Download the default model, about 4.4 GB, now?",
+                    false,
+                )? {
+                    return Err(anyhow!("No model configured; run `tapssp init` or pass --model PATH"));
+                }
+            }
+            status("Initializing LLM...".to_string());
+            LLM::new(llm_config)
+        }
+        Some(name @ ("ollama" | "openai")) => {
+            if config.prompt_template.is_some() {
+                return Err(anyhow!(
+                    "--prompt-template applies to the llama backend; remote backends format prompts with the model's own template"
+                ));
+            }
+            let (remote, model) = connect_remote(config, name, model, status)?;
+            let backend: Arc<dyn LLMBackend> = match config.anonymize {
+                Some(mode) => {
+                    let terms = config.anonymize_terms.as_deref().map(Anonymizer::load_terms).transpose()?.unwrap_or_default();
+                    Arc::new(AnonymizingBackend::new(remote, Anonymizer::new(mode, &terms)?))
+                }
+                None => remote,
+            };
+            Ok(LLM::with_backend(backend, LLMConfig { model_path: Some(PathBuf::from(model)), ..llm_config }))
+        }
+        Some(other) => Err(anyhow!("Unknown backend '{}' (llama, ollama, openai)", other)),
+    }
+}
+
+/// The remote backend `name` serving `model` (or its default model), with
+/// the name of that model
+#[cfg_attr(not(any(feature = "ollama", feature = "openai")), allow(unused_variables))]
+fn connect_remote(config: &RuntimeConfig, name: &str, model: Option<PathBuf>, status: &dyn Fn(String)) -> Result<(Arc<dyn LLMBackend>, String)> {
+    let model = model.map(|model| model.to_string_lossy().into_owned());
+    match name {
+        #[cfg(feature = "ollama")]
+        "ollama" => {
+            let model = model.unwrap_or_else(|| ollama::DEFAULT_MODEL.to_string());
+            let ollama = OllamaBackend::connect(&config.ollama_url, &model, config.context_size)?;
+            status(format!("Generating with Ollama model '{}' at {}", ollama.model(), config.ollama_url));
+            Ok((Arc::new(ollama), model))
+        }
+        #[cfg(feature = "openai")]
+        "openai" => {
+            let model = model.unwrap_or_else(|| openai_client::DEFAULT_MODEL.to_string());
+            let openai = OpenAiBackend::connect(&config.openai_url, config.openai_api_key.as_deref(), &model)?;
+            status(format!("Generating with model '{}' at {}", openai.model(), config.openai_url));
+            Ok((Arc::new(openai), model))
+        }
+        _ => Err(anyhow!("tapssp was built without the `{}` feature", name)),
+    }
+}
+
+/// The model `/model switch NAME` loads: with the built-in backend a GGUF
+/// file, given as a path or by its name in the models directory
+fn resolve_model(config: &RuntimeConfig, name: &str) -> Result<PathBuf> {
+    if matches!(config.backend.as_deref(), Some("ollama" | "openai")) || Path::new(name).is_file() {
+        return Ok(PathBuf::from(name));
+    }
+    let dir = config.models_dir()?;
+    [dir.join(name), dir.join(format!("{}.gguf", name))]
+        .into_iter()
+        .find(|path| path.is_file())
+        .ok_or_else(|| anyhow!("No model file '{}' (give a path to a GGUF file or the name of one in {:?})", name, dir))
+}
+
+/// Cross-encoder reranking from `--reranker`, if configured
+fn load_reranker(config: &RuntimeConfig) -> Result<Option<Reranker>> {
+    let Some(dir) = &config.reranker else {
+        return Ok(None);
+    };
+    Ok(Some(Reranker::new(rerank::load_cross_encoder(dir, config.embedding_device)?, config.rerank_candidates)))
+}
+
+/// Opens the indexes of `--collection`, searched with the main index's
+/// embedding model and retrieval settings
+fn open_collections(config: &RuntimeConfig, embedder: Option<Arc<dyn Embedder>>, read_only: bool) -> Result<Collections> {
+    let mut collections = Collections::default();
+    for (name, path) in &config.collections {
+        let db = match read_only {
+            true => VectorDB::open_read_only(path),
+            false => VectorDB::load(path),
+        };
+        let db = db.map_err(|e| anyhow!("Can't open collection '{}' at {:?}: {}", name, path, e))?;
+        let mut retriever = Retriever::with_vector_db(db);
+        retriever.use_embedding_model(embedder.clone()).map_err(|e| anyhow!("Collection '{}': {}", name, e))?;
+        retriever.set_ann_params(config.ann);
+        retriever.set_hybrid(config.hybrid.as_deref().map(Fusion::parse).transpose()?);
+        retriever.set_min_score(config.min_score);
+        retriever.set_mmr(config.mmr_lambda)?;
+        retriever.set_access_log(Some(AccessLog::for_index(path)));
+        let top_k = config.top_k.or(retriever.settings().top_k);
+        collections.insert(name, retriever, path.clone(), top_k)?;
+    }
+    Ok(collections)
+}
+
+/// Transforms applied to loaded files, from `--transforms`
+fn load_transforms(config: &RuntimeConfig) -> Result<Arc<Transforms>> {
+    Ok(Arc::new(config.transforms_path.as_deref().map(Transforms::load).transpose()?.unwrap_or_default()))
+}
+
+/// The first `max_chars` characters of `chunk` on one line, with
+/// highlights rendered when stdout is a terminal
+fn preview(chunk: &str, highlights: &[Highlight], max_chars: usize) -> String {
+    let (end, ellipsis) = match chunk.char_indices().nth(max_chars) {
+        Some((end, _)) => (end, "..."),
+        None => (chunk.len(), ""),
+    };
+    let text = if std::io::stdout().is_terminal() {
+        highlight::render_ansi(&chunk[..end], highlights)
+    } else {
+        chunk[..end].to_string()
+    };
+    format!("{}{}", text.replace('\n', " "), ellipsis)
+}
+
+/// Lists retrieved chunks and lets the user drop irrelevant ones before generation
+fn review_chunks(chunks: Vec<ScoredChunk>, highlights: &[Vec<Highlight>]) -> Result<Vec<ScoredChunk>> {
+    if chunks.is_empty() {
+        return Ok(chunks);
+    }
+
+    println!("\nRetrieved context:");
+    for (i, (chunk, highlights)) in chunks.iter().zip(highlights).enumerate() {
+        println!("  [{}] {}", i + 1, preview(&chunk.content, highlights, 200));
+    }
+
+    loop {
+        print!("Chunks to drop (e.g. \"1 3\"), Enter to keep all: ");
+        std::io::stdout().flush()?;
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+
+        let dropped: Result<Vec<usize>, _> = input
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<usize>())
+            .collect();
+
+        match dropped {
+            Ok(dropped) if dropped.iter().all(|i| (1..=chunks.len()).contains(i)) => {
+                return Ok(chunks
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(i, _)| !dropped.contains(&(i + 1)))
+                    .map(|(_, chunk)| chunk)
+                    .collect());
+            }
+            _ => eprintln!("Please enter chunk numbers between 1 and {}", chunks.len()),
+        }
+    }
+}
+
+/// Arguments that are neither flags nor flag values
+fn positional_args(args: &[String]) -> Vec<&str> {
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if completions::takes_value(arg) {
+            iter.next();
+        } else if !arg.starts_with("--") {
+            positional.push(arg.as_str());
+        }
+    }
+    positional
+}
+
+/// Values following every occurrence of `flag`, e.g. all `--var k=v` pairs
+fn flag_values<'a>(args: &'a [String], flag: &str) -> Vec<&'a str> {
+    args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].as_str())
+        .collect()
+}
+
+/// Snapshots of the index at `index_path`
+fn snapshot_store(config: &RuntimeConfig, index_path: &Path) -> Result<SnapshotStore> {
+    Ok(SnapshotStore::new(config.snapshots_dir(index_path)?))
+}
+
+/// An index file, or the snapshot of the configured index taken at or
+/// before a time given like `kb restore --at`
+fn resolve_index(config: &RuntimeConfig, arg: &str) -> Result<PathBuf> {
+    if Path::new(arg).exists() {
+        return Ok(PathBuf::from(arg));
+    }
+    let at = snapshots::parse_time(arg).map_err(|_| anyhow!("{} is neither an index file nor a snapshot time", arg))?;
+    let index_path = config.index_path().ok_or_else(|| anyhow!("Snapshot times require --index PATH"))?;
+    Ok(snapshot_store(config, &index_path)?.at(at)?.index_path())
+}
+
+/// Snapshots an existing index and the sessions before a command
+/// overwrites the index, so a bad ingest can be undone with `kb restore`
+fn snapshot_before_write(config: &RuntimeConfig, index_path: &Path) -> Result<()> {
+    if !index_path.exists() {
+        return Ok(());
+    }
+    let store = snapshot_store(config, index_path)?;
+    let snapshot = store.take(index_path, config.sessions_dir().ok().as_deref())?;
+    store.prune(config.snapshot_retention)?;
+    tracing::info!(path = ?snapshot.path, "took index snapshot");
+    Ok(())
+}
+
+/// Applies `--chunk-size`, `--chunk-overlap` and `--chunk-strategy` to the
+/// settings of an index about to be built
+fn apply_chunking_flags(settings: &mut CollectionSettings, args: &[String]) -> Result<()> {
+    for (flag, key) in [("--chunk-size", "chunk-size"), ("--chunk-overlap", "chunk-overlap"), ("--chunk-strategy", "chunk-strategy")] {
+        if let Some(value) = flag_values(args, flag).last() {
+            settings.set(key, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// `index [DIR] --index PATH`: rebuilds the index at PATH from the files in
+/// DIR (or the docs directory), keeping its collection settings apart from
+/// any chunking flags. The model
+/// isn't loaded, so this is cheap to run from cron or CI.
+fn index_command(config: &RuntimeConfig, args: &[String]) -> Result<()> {
+    let usage = || anyhow!("Usage: tapssp index [DIR] --index PATH [--chunk-size N] [--chunk-overlap N] [--chunk-strategy S]");
+    let docs_dir = match positional_args(args).as_slice() {
+        [] => config.docs_dir(),
+        [dir] => PathBuf::from(dir),
+        _ => return Err(usage()),
+    };
+    let index_path = config.index_path().ok_or_else(usage)?;
+
+    let mut settings = config.collection.clone();
+    let mut db = VectorDB::new();
+    if index_path.exists() {
+        let previous = VectorDB::load(&index_path)?;
+        settings = previous.settings().clone();
+        // Unchanged chunks keep their IDs, so deltas and access logs follow them
+        db.reuse_ids_from(&previous);
+        snapshot_before_write(config, &index_path)?;
+    }
+    apply_chunking_flags(&mut settings, args)?;
+    db.set_settings(settings)?;
+    let mut retriever = Retriever::with_vector_db(db);
+    retriever.use_embedding_model(config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?)?;
+    for dir in &config.embedding_variants {
+        retriever.add_embedding_variant(embeddings::load_embedder(dir, config.embedding_device)?)?;
+    }
+    if let Some(path) = &config.synonyms_path {
+        retriever.set_synonyms(Synonyms::load(path)?)?;
+    }
+
+    let webhooks = Webhooks::new(config.webhooks.clone(), config.webhook_secret.clone());
+    let result = runtime::block_on(load_documents(&mut retriever, &docs_dir, &config.file_filter(), load_transforms(config)?)).and_then(|_| {
+        retriever.rebuild();
+        retriever.save(&index_path)
+    });
+    if let Err(e) = result {
+        let _ = webhooks.send(&WebhookEvent::IndexError { message: e.to_string() });
+        return Err(e);
+    }
+    let _ = webhooks.send(&WebhookEvent::IngestCompleted {
+        documents: retriever.len(),
+        index_path: Some(index_path.display().to_string()),
+    });
+    println!("Indexed {} documents from {:?} into {:?} ({})", retriever.len(), docs_dir, index_path, retriever.take_ingest_report());
+    Ok(())
+}
+
+/// Reads an answer to `question` from stdin; an empty line (or end of
+/// input) picks `default`
+fn ask(question: &str, default: &str) -> Result<String> {
+    if default.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [{}]: ", question, default);
+    }
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+    Ok(if input.is_empty() { default.to_string() } else { input.to_string() })
+}
+
+fn ask_yes_no(question: &str, default: bool) -> Result<bool> {
+    let question = format!("{} [{}]", question, if default { "Y/n" } else { "y/N" });
+    loop {
+        match ask(&question, "")?.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please answer y or n"),
+        }
+    }
+}
+
+/// `init`: asks for the backend and model, docs directory, index location
+/// and chunking, writes them to the config file (`--config` or
+/// `./tapssp.toml`) and offers to download the model and build the index
+fn init_command(config: &RuntimeConfig, args: &[String]) -> Result<()> {
+    let config_path = flag_values(args, "--config").last().map_or(PathBuf::from(config::CONFIG_FILE), PathBuf::from);
+    if config_path.exists() && !ask_yes_no(&format!("{:?} already exists. Overwrite it?", config_path), false)? {
+        return Ok(());
+    }
+
+    let compiled = [("llama", cfg!(feature = "llama")), ("ollama", cfg!(feature = "ollama")), ("openai", cfg!(feature = "openai"))];
+    let backends: Vec<&str> = compiled
+        .into_iter()
+        .filter_map(|(name, compiled)| compiled.then_some(name))
+        .collect();
+    let backend = match backends.as_slice() {
+        [] => None,
+        [only] => Some(only.to_string()),
+        _ => Some(loop {
+            let backend = ask(
+                "Generate answers with a local GGUF model (llama), an Ollama server (ollama) or an OpenAI-compatible API (openai)?",
+                config.backend.as_deref().unwrap_or("llama"),
+            )?;
+            if backends.contains(&backend.as_str()) {
+                break backend;
+            }
+            println!("Choose one of: {}", backends.join(", "));
+        }),
+    };
+
+    let mut download_model = false;
+    let (model, url) = match backend.as_deref() {
+        Some("llama") => (loop {
+            let path = ask("GGUF model file (Enter for the default, Mistral 7B Instruct)", "")?;
+            if path.is_empty() {
+                let cached = config.models_dir()?.join(llm::DEFAULT_MODEL_FILE);
+                download_model = !cached.exists()
+                    && ask_yes_no("The default model is a download of about 4.4 GB. Download it now?", true)?;
+                break None;
+            }
+            if Path::new(&path).is_file() {
+                break Some(PathBuf::from(path));
+            }
+            println!("{:?} is not a file", path);
+        }, None),
+        #[cfg(feature = "ollama")]
+        Some("ollama") => {
+            let url = ask("Ollama server URL", &config.ollama_url)?;
+            let default = config.model_path.as_ref().map_or(ollama::DEFAULT_MODEL.into(), |model| model.to_string_lossy());
+            (Some(PathBuf::from(ask("Ollama model", &default)?)), Some(url))
+        }
+        #[cfg(feature = "openai")]
+        Some("openai") => {
+            let url = ask("API base URL", &config.openai_url)?;
+            let default = config.model_path.as_ref().map_or(openai_client::DEFAULT_MODEL.into(), |model| model.to_string_lossy());
+            let model = ask("Model", &default)?;
+            if config.openai_api_key.is_none() {
+                println!("Set OPENAI_API_KEY in the environment if the API needs a key; it isn't stored in the config file");
+            }
+            (Some(PathBuf::from(model)), Some(url))
+        }
+        _ => (None, None),
+    };
+
+    let docs = PathBuf::from(ask("Documents directory", &config.docs_dir().to_string_lossy())?);
+    if !docs.is_dir() && ask_yes_no(&format!("{:?} doesn't exist. Create it?", docs), true)? {
+        fs::create_dir_all(&docs)?;
+    }
+    let default_index = config.index_path().unwrap_or_else(|| PathBuf::from("index.bin"));
+    let index = PathBuf::from(ask("Index file", &default_index.to_string_lossy())?);
+
+    let mut chunking = config.collection.clone();
+    for (key, question) in [
+        ("chunk-strategy", "Chunking strategy (sentence, paragraph, fixed-token, recursive)"),
+        ("chunk-size", "Chunk size in characters ('none' indexes each file whole)"),
+        ("chunk-overlap", "Characters repeated between chunks"),
+    ] {
+        if key == "chunk-overlap" && chunking.chunk_size.is_none() {
+            continue;
+        }
+        let current = match key {
+            "chunk-strategy" => chunking.chunk_strategy.to_string(),
+            "chunk-size" => chunking.chunk_size.map_or("none".to_string(), |size| size.to_string()),
+            _ => chunking.chunk_overlap.to_string(),
+        };
+        while let Err(e) = chunking.set(key, &ask(question, &current)?) {
+            println!("{}", e);
+        }
+    }
+
+    let choices = SetupChoices { backend, model, url, docs, index, chunking };
+    fs::write(&config_path, choices.to_toml())?;
+    println!("Wrote {:?}", config_path);
+
+    if download_model {
+        #[cfg(feature = "llama")]
+        crate::llama::get_default_model(Some(config.models_dir()?), config.model_sha256.as_deref())?;
+    }
+    let config = RuntimeConfig::load(Some(&config_path), |key| env::var(key).ok())?;
+    if choices.docs.is_dir() && ask_yes_no(&format!("Index the documents in {:?} now?", choices.docs), true)? {
+        index_command(&config, &[])?;
+    }
+    println!("Setup done. Run `tapssp` to start asking questions.");
+    Ok(())
+}
+
+/// `ingest FILE... --index PATH [--preview]`: adds text and table files to
+/// an index, created with any chunking flags if it doesn't exist yet. With
+/// `--preview`, only prints how each file would be chunked, so chunking
+/// flags can be tried out on a sample before a long ingest.
+fn ingest_command(config: &RuntimeConfig, args: &[String]) -> Result<()> {
+    let usage = || anyhow!("Usage: tapssp ingest FILE... --index PATH [--preview] [--chunk-size N] [--chunk-overlap N] [--chunk-strategy S]");
+    let files = positional_args(args);
+    if files.is_empty() {
+        return Err(usage());
+    }
+    let index_path = config.index_path();
+    let existing = match &index_path {
+        Some(path) if path.exists() => Some(VectorDB::load(path)?),
+        _ => None,
+    };
+    let mut settings = existing.as_ref().map_or_else(|| config.collection.clone(), |db| db.settings().clone());
+    let chunking_flags = ["--chunk-size", "--chunk-overlap", "--chunk-strategy"].iter().any(|flag| args.iter().any(|arg| arg == flag));
+    apply_chunking_flags(&mut settings, args)?;
+    let transforms = load_transforms(config)?;
+
+    if args.iter().any(|arg| arg == "--preview") {
+        for file in &files {
+            println!("{}\n", IngestPreview::of_file(Path::new(file), &settings, &transforms, TABLE_CHUNK_CHARS)?);
+        }
+        return Ok(());
+    }
+
+    let index_path = index_path.ok_or_else(usage)?;
+    let db = match existing {
+        // Chunks of one index are all cut the same way
+        Some(_) if chunking_flags => {
+            return Err(anyhow!("{:?} already has chunking settings; change them with `tapssp kb configure` and rebuild with `tapssp index`", index_path));
+        }
+        Some(db) => db,
+        None => {
+            let mut db = VectorDB::new();
+            db.set_settings(settings)?;
+            db
+        }
+    };
+    let mut retriever = Retriever::with_vector_db(db);
+    retriever.use_embedding_model(config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?)?;
+    for dir in &config.embedding_variants {
+        retriever.add_embedding_variant(embeddings::load_embedder(dir, config.embedding_device)?)?;
+    }
+    let before = retriever.len();
+    runtime::block_on(load_files(&mut retriever, files.iter().map(PathBuf::from).collect(), transforms))?;
+    retriever.rebuild();
+    snapshot_before_write(config, &index_path)?;
+    retriever.save(&index_path)?;
+    let report = retriever.take_ingest_report();
+    println!("Added {} documents from {} files to {:?} ({})", retriever.len() - before, files.len(), index_path, report);
+    Ok(())
+}
+
+/// `ingest-enqueue PATH... --queue DIR`: queues the text and table files
+/// among PATH, or under PATH when it is a directory, for ingestion workers
+fn ingest_enqueue(config: &RuntimeConfig, args: &[String]) -> Result<()> {
+    let usage = || anyhow!("Usage: tapssp ingest-enqueue PATH... --queue DIR");
+    let queue = IngestQueue::open(flag_values(args, "--queue").last().ok_or_else(usage)?)?;
+    let paths = positional_args(args);
+    if paths.is_empty() {
+        return Err(usage());
+    }
+    let mut queued = 0;
+    for path in paths.iter().map(Path::new) {
+        let files = match path.is_dir() {
+            true => discovery::find_files(path, &config.file_filter(), |_| true)?,
+            false => vec![path.to_path_buf()],
+        };
+        for file in files.iter().filter(|file| is_ingestible(file)) {
+            queue.enqueue(file)?;
+            queued += 1;
+        }
+    }
+    println!("Queued {} files ({} pending)", queued, queue.pending()?);
+    Ok(())
+}
+
+/// `ingest-worker --queue DIR --index PATH [--exit-when-empty]`: chunks and
+/// embeds queued files one at a time, logs each result to the queue's
+/// write-ahead log and merges the log into the shared index whenever no
+/// other worker is. Any number of workers, on any machine that shares the
+/// queue directory and index, can run at once.
+fn ingest_worker(config: &RuntimeConfig, args: &[String]) -> Result<()> {
+    let usage = || anyhow!("Usage: tapssp ingest-worker --queue DIR --index PATH [--exit-when-empty]");
+    let queue = IngestQueue::open(flag_values(args, "--queue").last().ok_or_else(usage)?)?;
+    let index_path = config.index_path().ok_or_else(usage)?;
+    let exit_when_empty = args.iter().any(|arg| arg == "--exit-when-empty");
+    if !index_path.exists() {
+        return Err(anyhow!("No index at {:?}; create it first, e.g. with `tapssp kb configure`", index_path));
+    }
+    // Workers chunk like the shared index, and must embed with its model
+    let settings = VectorDB::load(&index_path)?.settings().clone();
+    let embedder = config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?;
+    let variants = config.embedding_variants.iter().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).collect::<Result<Vec<_>>>()?;
+    let transforms = load_transforms(config)?;
+
+    let (mut files, mut documents) = (0, 0);
+    loop {
+        let requeued = queue.requeue_stale(STALE_CLAIM_AFTER)?;
+        if requeued > 0 {
+            tracing::warn!(requeued, "requeued ingestion tasks of unresponsive workers");
+        }
+        let Some(task) = queue.claim()? else {
+            if exit_when_empty {
+                queue.merge_into(&index_path, true)?;
+                break;
+            }
+            merge_log(&queue, &index_path, true);
+            std::thread::sleep(WORKER_POLL_INTERVAL);
+            continue;
+        };
+
+        let mut db = VectorDB::new();
+        db.set_settings(settings.clone())?;
+        let mut retriever = Retriever::with_vector_db(db);
+        retriever.use_embedding_model(embedder.clone())?;
+        for variant in &variants {
+            retriever.add_embedding_variant(variant.clone())?;
+        }
+        let heartbeat = task.heartbeat(CLAIM_HEARTBEAT_INTERVAL);
+        let result = load_file(&mut retriever, &task.path, &transforms).and_then(|()| {
+            let segment = retriever.to_segment();
+            queue.append(&segment)?;
+            Ok(segment.document_count())
+        });
+        drop(heartbeat);
+        match result {
+            Ok(count) => {
+                tracing::info!(path = ?task.path, documents = count, "ingested file");
+                files += 1;
+                documents += count;
+                queue.complete(task)?;
+            }
+            Err(e) => {
+                eprintln!("Failed to ingest {:?}: {}", task.path, e);
+                queue.fail(task, &e.to_string())?;
+            }
+        }
+        merge_log(&queue, &index_path, false);
+    }
+    println!("Ingested {} files ({} documents) into {:?}", files, documents, index_path);
+    Ok(())
+}
+
+/// Merges the queue's write-ahead log into the index; a failed merge is
+/// retried by the next one, so the worker keeps going
+fn merge_log(queue: &IngestQueue, index_path: &Path, wait: bool) {
+    if let Err(e) = queue.merge_into(index_path, wait) {
+        tracing::warn!(error = %e, "merging the write-ahead log failed; retrying later");
+    }
+}
+
+/// `save-query <name> "<template>" [--top-k N] [--contains TEXT] [--format FORMAT]`
+fn save_query(config: &RuntimeConfig, args: &[String]) -> Result<()> {
+    let (name, template) = match args {
+        [name, template, ..] => (name, template),
+        _ => return Err(anyhow!(
+            "Usage: tapssp save-query <name> \"<template>\" [--top-k N] [--contains TEXT] [--format text|markdown|json]"
+        )),
+    };
+
+    let mut query = SavedQuery::new(name, template)?;
+    if let Some(top_k) = flag_values(args, "--top-k").last() {
+        query.top_k = top_k.parse()?;
+    }
+    query.must_contain = flag_values(args, "--contains").last().map(|s| s.to_string());
+    if let Some(format) = flag_values(args, "--format").last() {
+        query.format = OutputFormat::parse(format)?;
+    }
+
+    let path = query.save(&config.queries_dir()?)?;
+    println!("Saved query '{}' to {:?}", query.name, path);
+    Ok(())
+}
+
+/// Renders a saved query with its `--var` values and prints the answer in its format
+fn run_saved_query(llm: &LLM, retriever: &Retriever, saved: &SavedQuery, args: &[String]) -> Result<()> {
+    let vars = templates::parse_vars(flag_values(args, "--var"))?;
+    let query = saved.render(&vars)?;
+    let chunks = retriever.retrieve_filtered(&query, saved.top_k, |chunk| saved.accepts(chunk));
+    let answer = llm.generate_response(&query, chunks.clone())?;
+    println!("{}", saved.format.render(&saved.name, &query, &answer, &chunks)?);
+    Ok(())
+}
+
+const SEARCH_USAGE: &str = "Usage: tapssp search \"QUERY\" [-k N] [--where EXPR] [--json] [--index PATH]";
+
+/// `search QUERY` (also `kb search QUERY`): the best-matching chunks of the
+/// index with their scores and metadata, without loading the generator.
+/// `--json` prints them as `POST /search` returns them.
+fn search_command(config: &RuntimeConfig, query: &str, args: &[String]) -> Result<()> {
+    let index_path = config.index_path().ok_or_else(|| anyhow!(SEARCH_USAGE))?;
+    let mut retriever = Retriever::with_vector_db(VectorDB::load(index_path)?);
+    retriever.use_embedding_model(config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?)?;
+    retriever.set_hybrid(config.hybrid.as_deref().map(Fusion::parse).transpose()?);
+    retriever.set_routing(config.route_documents);
+    retriever.set_reranker(load_reranker(config)?);
+    retriever.set_min_score(config.min_score);
+    retriever.set_mmr(config.mmr_lambda)?;
+    let top_k = match flag_values(args, "-k").last() {
+        Some(n) => n.parse().map_err(|_| anyhow!("-k must be a number"))?,
+        None => config.top_k.or(retriever.settings().top_k).unwrap_or(5),
+    };
+    let filter = flag_values(args, "--where").last().map(|expression| metadata::Filter::parse(expression)).transpose()?;
+    let chunks = retriever.retrieve_with_scores(query, top_k, filter.as_ref());
+
+    if args.iter().any(|arg| arg == "--json") {
+        println!("{}", serde_json::to_string_pretty(&SearchResults::new(query.to_string(), chunks))?);
+        return Ok(());
+    }
+    print_chunks(&chunks);
+    Ok(())
+}
+
+/// Numbered previews of `chunks` with their scores, IDs and metadata
+fn print_chunks(chunks: &[ScoredChunk]) {
+    for (i, chunk) in chunks.iter().enumerate() {
+        println!("[{}] ({:.3}) {}\n    id: {}", i + 1, chunk.score, preview(&chunk.content, &chunk.highlights, 300), chunk.id);
+        if !chunk.metadata.is_empty() {
+            let fields: Vec<String> = chunk.metadata.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+            println!("    {}", fields.join(" "));
+        }
+    }
+}
+
+/// `kb push|pull s3://bucket/prefix --index PATH`, `kb build --manifest FILE`,
+/// `kb delta OLD NEW --out FILE`, `kb apply FILE --index PATH`, `kb diff OLD NEW`,
+/// `kb search QUERY`, `kb export`, `kb import FILE`, `kb remove ID...`, `kb cold`,
+/// `kb update ID FILE`, `kb configure`, `kb snapshot`, `kb snapshots`,
+/// `kb restore --at TIME`, `kb reembed` and `kb mine-negatives`
+fn kb_command(config: &RuntimeConfig, args: &[String]) -> Result<()> {
+    let usage = || anyhow!(concat!(
+        "Usage: tapssp kb push|pull s3://bucket/prefix --index PATH\n",
+        "       tapssp kb build --manifest build.toml --index PATH\n",
+        "       tapssp kb delta OLD_INDEX NEW_INDEX --out FILE\n",
+        "       tapssp kb apply FILE --index PATH\n",
+        "       tapssp kb diff OLD NEW [--index PATH]   (index files or snapshot times)\n",
+        "       tapssp kb search QUERY [--index PATH] [-k N] [--where EXPR] [--json]\n",
+        "       tapssp kb export [--out FILE] [--index PATH]\n",
+        "       tapssp kb import FILE [--format langchain|llamaindex|tapssp] [--index PATH]\n",
+        "       tapssp kb remove ID... [--index PATH]\n",
+        "       tapssp kb cold [--archive] [--index PATH]\n",
+        "       tapssp kb update ID FILE [--index PATH]\n",
+        "       tapssp kb configure [chunk-size=N] [chunk-overlap=N] [chunk-strategy=S] [stop-words=on|off] [top-k=N] [--index PATH]\n",
+        "       tapssp kb snapshot|snapshots [--index PATH]\n",
+        "       tapssp kb restore --at YYYY-MM-DD[THH:MM[:SS]] [--index PATH]\n",
+        "       tapssp kb mine-negatives --session NAME --out FILE [--index PATH] [--top-k N]\n",
+        "       tapssp kb reembed --model MODEL_DIR [--index PATH]",
+    ));
+    let index_path = || config.index_path().ok_or_else(usage);
+
+    match positional_args(args).as_slice() {
+        ["push", url] => {
+            let (url, index_path) = (ObjectUrl::parse(url)?, index_path()?);
+            object_store::push_index(&index_path, &url)?;
+            println!("Pushed {:?} to s3://{}/{}", index_path, url.bucket, url.prefix);
+        }
+        ["pull", url] => {
+            let (url, index_path) = (ObjectUrl::parse(url)?, index_path()?);
+            object_store::pull_index(&url, &index_path)?;
+            println!("Pulled s3://{}/{} to {:?}", url.bucket, url.prefix, index_path);
+        }
+        ["build"] => {
+            let manifest = BuildManifest::load(Path::new(flag_values(args, "--manifest").last().ok_or_else(usage)?))?;
+            manifest.verify()?;
+            let index_path = index_path()?;
+            let mut db = VectorDB::new();
+            db.set_settings(manifest.settings()?)?;
+            db.use_deterministic_ids();
+            let mut retriever = Retriever::with_vector_db(db);
+            retriever.use_embedding_model(config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?)?;
+            if retriever.model_id() != manifest.embedding.model {
+                return Err(anyhow!("Manifest pins embedding model '{}' but '{}' is configured",
+                    manifest.embedding.model, retriever.model_id()));
+            }
+            let transforms = load_transforms(config)?;
+            for source in &manifest.sources {
+                if !is_ingestible(&source.path) {
+                    return Err(anyhow!("{:?} is not a text or table file", source.path));
+                }
+            }
+            let paths = manifest.sources.iter().map(|source| source.path.clone()).collect();
+            runtime::block_on(load_files(&mut retriever, paths, transforms))?;
+            retriever.rebuild();
+
+            // Built next to the index, which is only replaced if the build matches
+            let staging = index_path.with_extension("build");
+            retriever.save(&staging)?;
+            let content_hash = utils::sha256_file(&staging)?;
+            if let Some(expected) = &manifest.content_hash
+                && *expected != content_hash
+            {
+                fs::remove_file(&staging)?;
+                return Err(anyhow!("Build is not reproducible: content hash {}, manifest pins {}", content_hash, expected));
+            }
+            snapshot_before_write(config, &index_path)?;
+            fs::rename(&staging, &index_path)?;
+            let file_name = index_path.file_name().unwrap_or_default().to_string_lossy();
+            fs::write(index_path.with_extension("sha256"), format!("{}  {}\n", content_hash, file_name))?;
+            println!("Built {:?} from {} sources ({} documents)\ncontent_hash = \"{}\"",
+                index_path, manifest.sources.len(), retriever.len(), content_hash);
+        }
+        ["delta", old, new] => {
+            let out = flag_values(args, "--out").last().copied().ok_or_else(usage)?;
+            let delta = VectorDB::load(new)?.diff(&VectorDB::load(old)?);
+            delta.save(out)?;
+            println!("Wrote delta to {} ({} documents changed, {} removed)",
+                out, delta.upserted_count(), delta.removed_count());
+        }
+        ["apply", delta_path] => {
+            let index_path = index_path()?;
+            let delta = IndexDelta::load(delta_path)?;
+            let (upserted, removed) = (delta.upserted_count(), delta.removed_count());
+            let mut db = VectorDB::load(&index_path)?;
+            db.apply_delta(delta)?;
+            snapshot_before_write(config, &index_path)?;
+            db.save(&index_path)?;
+            println!("Updated {:?} ({} documents changed, {} removed)", index_path, upserted, removed);
+        }
+        ["diff", old, new] => {
+            let old = VectorDB::load(resolve_index(config, old)?)?;
+            let new = VectorDB::load(resolve_index(config, new)?)?;
+            println!("{}", CorpusDiff::between(&old, &new));
+        }
+        ["search", query] => search_command(config, query, args)?,
+        ["export"] => {
+            let db = VectorDB::load(index_path()?)?;
+            match flag_values(args, "--out").last() {
+                Some(out) => {
+                    let count = db.export_jsonl(std::io::BufWriter::new(fs::File::create(out)?))?;
+                    println!("Exported {} documents to {}", count, out);
+                }
+                None => {
+                    db.export_jsonl(std::io::stdout().lock())?;
+                }
+            }
+        }
+        ["import", file] => {
+            // Imports add to an existing index, or create one with its settings
+            let index_path = index_path()?;
+            let format = flag_values(args, "--format").last().map(|f| import::ImportFormat::parse(f)).transpose()?;
+            if import::ImportFormat::detect(Path::new(file), format)? == import::ImportFormat::Tapssp {
+                let dump = VectorDB::import_jsonl(std::io::BufReader::new(fs::File::open(file)?))?;
+                let count = dump.len();
+                let db = match index_path.exists() {
+                    true => {
+                        let mut db = VectorDB::load(&index_path)?;
+                        db.apply_segment(dump.to_segment())?;
+                        db
+                    }
+                    false => dump,
+                };
+                snapshot_before_write(config, &index_path)?;
+                db.save(&index_path)?;
+                println!("Imported {} documents from {} into {:?}", count, file, index_path);
+                return Ok(());
+            }
+            let documents = import::load(Path::new(file), format)?;
+            let db = if index_path.exists() { VectorDB::load(&index_path)? } else { VectorDB::new() };
+            let mut retriever = Retriever::with_vector_db(db);
+            retriever.use_embedding_model(config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?)?;
+            for dir in &config.embedding_variants {
+                retriever.add_embedding_variant(embeddings::load_embedder(dir, config.embedding_device)?)?;
+            }
+            let mut chunks = 0;
+            for document in &documents {
+                chunks += retriever.add_to_knowledge_base(document.text.clone(), document.metadata.clone())?.len();
+            }
+            retriever.rebuild();
+            snapshot_before_write(config, &index_path)?;
+            retriever.save(&index_path)?;
+            println!("Imported {} documents ({} chunks) from {} into {:?}", documents.len(), chunks, file, index_path);
+        }
+        ["remove", ids @ ..] if !ids.is_empty() => {
+            let index_path = index_path()?;
+            let mut retriever = Retriever::with_vector_db(VectorDB::load(&index_path)?);
+            retriever.use_embedding_model(config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?)?;
+            for id in ids.iter().filter(|id| !retriever.contains(id)) {
+                eprintln!("No document with ID {}", id);
+            }
+            let removed = retriever.remove_documents(&ids.iter().map(|id| id.to_string()).collect::<Vec<_>>())?;
+            if removed > 0 {
+                retriever.rebuild();
+                snapshot_before_write(config, &index_path)?;
+                retriever.save(&index_path)?;
+            }
+            println!("Removed {} documents from {:?}", removed, index_path);
+        }
+        ["cold"] => {
+            let index_path = index_path()?;
+            let stats_path = AccessStats::path_for(&index_path);
+            if !stats_path.exists() {
+                return Err(anyhow!("No retrievals counted for {:?} yet; they are counted while chatting or serving", index_path));
+            }
+            let mut stats = AccessStats::load(&stats_path)?;
+            let db = VectorDB::load(&index_path)?;
+            let cold = stats.cold_sources(&db);
+            println!("{} sources never retrieved since {}", cold.len(), snapshots::format_time(stats.since));
+            for source in &cold {
+                println!("  {} ({} chunks)", source.source, source.chunk_ids.len());
+            }
+            if !args.iter().any(|arg| arg == "--archive") || cold.is_empty() {
+                return Ok(());
+            }
+
+            let ids: Vec<String> = cold.iter().flat_map(|source| source.chunk_ids.iter().cloned()).collect();
+            let mut retriever = Retriever::with_vector_db(db);
+            retriever.use_embedding_model(config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?)?;
+            let archived = retriever.split_off(&ids)?;
+            // The cold tier is written first, so a failure never loses chunks
+            let cold_path = index_path.with_extension("cold.bin");
+            let cold_tier = match cold_path.exists() {
+                true => {
+                    let mut cold_tier = VectorDB::load(&cold_path)?;
+                    cold_tier.apply_segment(archived.to_segment())?;
+                    cold_tier
+                }
+                false => archived,
+            };
+            cold_tier.save(&cold_path)?;
+            retriever.rebuild();
+            snapshot_before_write(config, &index_path)?;
+            retriever.save(&index_path)?;
+            stats.forget(&ids);
+            stats.save(&stats_path)?;
+            println!("Archived {} chunks to {:?} ({} documents left in {:?})", ids.len(), cold_path, retriever.len(), index_path);
+        }
+        ["update", id, file] => {
+            let index_path = index_path()?;
+            let content = fs::read_to_string(file)?;
+            let mut retriever = Retriever::with_vector_db(VectorDB::load(&index_path)?);
+            retriever.use_embedding_model(config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?)?;
+            for dir in &config.embedding_variants {
+                retriever.add_embedding_variant(embeddings::load_embedder(dir, config.embedding_device)?)?;
+            }
+            if !retriever.update_document(id, content)? {
+                return Err(anyhow!("No document with ID {} in {:?}", id, index_path));
+            }
+            retriever.rebuild();
+            snapshot_before_write(config, &index_path)?;
+            retriever.save(&index_path)?;
+            println!("Updated document {} in {:?}", id, index_path);
+        }
+        ["configure", changes @ ..] => {
+            // Configuring a new index creates it empty, so the first
+            // ingestion already uses the settings
+            let index_path = index_path()?;
+            let mut db = if index_path.exists() { VectorDB::load(&index_path)? } else { VectorDB::new() };
+            let mut settings = db.settings().clone();
+            for change in changes {
+                let (key, value) = change.split_once('=').ok_or_else(usage)?;
+                settings.set(key, value)?;
+            }
+            if !changes.is_empty() {
+                db.set_settings(settings)?;
+                snapshot_before_write(config, &index_path)?;
+                db.save(&index_path)?;
+            }
+            println!("{}", db.settings());
+        }
+        ["snapshot"] => {
+            let index_path = index_path()?;
+            let store = snapshot_store(config, &index_path)?;
+            let snapshot = store.take(&index_path, config.sessions_dir().ok().as_deref())?;
+            let pruned = store.prune(config.snapshot_retention)?;
+            println!("Took snapshot {} of {:?} ({} old snapshots pruned)",
+                snapshots::format_time(snapshot.timestamp), index_path, pruned);
+        }
+        ["snapshots"] => {
+            for snapshot in snapshot_store(config, &index_path()?)?.list()? {
+                println!("{}  {}", snapshots::format_time(snapshot.timestamp), snapshot.path.display());
+            }
+        }
+        ["restore"] => {
+            let at = flag_values(args, "--at").last().copied().ok_or_else(usage)?;
+            let index_path = index_path()?;
+            let store = snapshot_store(config, &index_path)?;
+            let snapshot = store.restore(snapshots::parse_time(at)?, &index_path, config.sessions_dir().ok().as_deref())?;
+            println!("Restored {:?} and sessions from the snapshot taken at {}",
+                index_path, snapshots::format_time(snapshot.timestamp));
+        }
+        ["reembed"] => {
+            let model_dir = flag_values(args, "--model").last().map(PathBuf::from).ok_or_else(usage)?;
+            let index_path = index_path()?;
+            let embedder = embeddings::load_embedder(&model_dir, config.embedding_device)?;
+            let model_id = embedder.model_id().to_string();
+            let worker = ReembedWorker::spawn(index_path.clone(), embedder);
+            while !worker.is_finished() {
+                let (done, total) = worker.progress();
+                eprint!("\rRe-embedding: {}/{} documents", done, total);
+                std::thread::sleep(Duration::from_millis(200));
+            }
+            let (done, total) = worker.progress();
+            eprintln!("\rRe-embedding: {}/{} documents", done, total);
+            worker.join()?;
+            println!("Swapped {:?} to embedding model {}", index_path, model_id);
+        }
+        ["mine-negatives"] => {
+            let name = flag_values(args, "--session").last().copied().ok_or_else(usage)?;
+            let out = flag_values(args, "--out").last().copied().ok_or_else(usage)?;
+            let per_query = match flag_values(args, "--top-k").last() {
+                Some(n) => n.parse().map_err(|_| anyhow!("--top-k must be a number"))?,
+                None => 3,
+            };
+            let entries = SessionLog::new(&config.sessions_dir()?, name)?.entries()?;
+            let mut retriever = Retriever::with_vector_db(VectorDB::load(index_path()?)?);
+            retriever.use_embedding_model(config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?)?;
+            let triples = training::mine_hard_negatives(&retriever, &entries, per_query);
+            training::write_triples(Path::new(out), &triples)?;
+            let rated = entries.iter().filter(|entry| entry.helpful == Some(true)).count();
+            println!("Wrote {} triples from {} helpful answers to {}", triples.len(), rated, out);
+        }
+        _ => return Err(usage()),
+    }
+    Ok(())
+}
+
+/// Entry point of the `tapssp` binary
+pub fn main() -> Result<()> {
+    telemetry::init()?;
+    let result = run();
+    telemetry::shutdown();
+    result
+}
+
+/// Applies CLI flags on top of the environment-derived config
+fn apply_cli_overrides(config: &mut RuntimeConfig, args: &[String]) -> Result<()> {
+    let last = |flag| flag_values(args, flag).last().map(PathBuf::from);
+    if let Some(dir) = last("--data-dir") {
+        config.data_dir = Some(dir);
+    }
+    if let Some(path) = last("--model") {
+        config.model_path = Some(path);
+    }
+    if let Some(name) = flag_values(args, "--small-model").last() {
+        config.small_model = Some(name.to_string());
+    }
+    if let Some(mode) = flag_values(args, "--anonymize").last() {
+        config.anonymize = Some(AnonymizeMode::parse(mode)?);
+    }
+    if let Some(path) = last("--anonymize-terms") {
+        config.anonymize_terms = Some(path);
+    }
+    if let Some(template) = flag_values(args, "--prompt-template").last() {
+        config.prompt_template = Some(PromptTemplate::parse(template)?);
+    }
+    if let Some(reserve) = flag_values(args, "--memory-reserve-mb").last() {
+        config.memory_reserve_mb = Some(reserve.parse().map_err(|_| anyhow!("--memory-reserve-mb must be a number"))?);
+    }
+    if let Some(backend) = flag_values(args, "--backend").last() {
+        config.backend = Some(backend.to_string());
+    }
+    if let Some(layers) = flag_values(args, "--gpu-layers").last() {
+        config.gpu_layers = layers.parse().map_err(|_| anyhow!("--gpu-layers must be a number"))?;
+    }
+    if let Some(backend) = flag_values(args, "--gpu-backend").last() {
+        config.gpu_backend = Some(backend.to_string());
+    }
+    if let Some(gpu) = flag_values(args, "--main-gpu").last() {
+        config.main_gpu = gpu.parse().map_err(|_| anyhow!("--main-gpu must be a GPU number"))?;
+    }
+    if let Some(split) = flag_values(args, "--tensor-split").last() {
+        config.tensor_split = device::parse_tensor_split(split)?;
+    }
+    if let Some(name) = flag_values(args, "--embedding-device").last() {
+        config.embedding_device = Device::parse(name)?;
+    }
+    if let Some(size) = flag_values(args, "--context-size").last() {
+        config.context_size = size.parse().ok().filter(|n| *n > 0)
+            .ok_or_else(|| anyhow!("--context-size must be a positive number"))?;
+    }
+    if let Some(path) = last("--index") {
+        config.index_path = Some(path);
+    }
+    for spec in flag_values(args, "--collection") {
+        config.add_collection_spec(spec)?;
+    }
+    if let Some(dir) = last("--embedding-model") {
+        config.embedding_model = Some(dir);
+    }
+    config.embedding_variants.extend(flag_values(args, "--embedding-variant").into_iter().map(PathBuf::from));
+    config.include.extend(flag_values(args, "--include").into_iter().map(String::from));
+    config.exclude.extend(flag_values(args, "--exclude").into_iter().map(String::from));
+    if args.iter().any(|arg| arg == "--follow-symlinks") {
+        config.follow_symlinks = true;
+    }
+    if let Some(mode) = flag_values(args, "--search-mode").last() {
+        config.search_mode = Some(mode.to_string());
+    }
+    if let Some(fusion) = flag_values(args, "--hybrid").last() {
+        config.hybrid = Some(fusion.to_string());
+    }
+    if let Some(path) = last("--synonyms") {
+        config.synonyms_path = Some(path);
+    }
+    if let Some(path) = last("--transforms") {
+        config.transforms_path = Some(path);
+    }
+    if let Some(dir) = last("--docs") {
+        config.docs_dir = Some(dir);
+    }
+    if let Some(path) = last("--faq") {
+        config.faq_path = Some(path);
+    }
+    if let Some(path) = last("--moderation") {
+        config.moderation_path = Some(path);
+    }
+    if let Some(target) = flag_values(args, "--escalate").last() {
+        config.escalate = Some(target.to_string());
+    }
+    config.webhooks.extend(flag_values(args, "--webhook").into_iter().map(String::from));
+    config.federation.extend(flag_values(args, "--federate").into_iter().map(String::from));
+    if let Some(host) = flag_values(args, "--host").last() {
+        config.host = host.to_string();
+    }
+    if let Some(port) = flag_values(args, "--port").last() {
+        config.port = port.parse().map_err(|_| anyhow!("--port must be a port number"))?;
+    }
+    if let Some(n) = flag_values(args, "--route-documents").last() {
+        config.route_documents = Some(n.parse().ok().filter(|n| *n > 0)
+            .ok_or_else(|| anyhow!("--route-documents must be a positive number"))?);
+    }
+    if let Some(dir) = last("--reranker") {
+        config.reranker = Some(dir);
+    }
+    if let Some(n) = flag_values(args, "--rerank-candidates").last() {
+        config.rerank_candidates = n.parse().ok().filter(|n| *n > 0)
+            .ok_or_else(|| anyhow!("--rerank-candidates must be a positive number"))?;
+    }
+    if let Some(top_k) = flag_values(args, "--top-k").last() {
+        config.top_k = Some(top_k.parse().map_err(|_| anyhow!("--top-k must be a number"))?);
+    }
+    if let Some(score) = flag_values(args, "--min-score").last() {
+        config.min_score = Some(score.parse().map_err(|_| anyhow!("--min-score must be a number"))?);
+    }
+    if let Some(lambda) = flag_values(args, "--mmr-lambda").last() {
+        config.mmr_lambda = Some(lambda.parse().map_err(|_| anyhow!("--mmr-lambda must be a number"))?);
+    }
+    if let Some(n) = flag_values(args, "--answer-cache").last() {
+        config.answer_cache = Some(n.parse().ok().filter(|n| *n > 0)
+            .ok_or_else(|| anyhow!("--answer-cache must be a positive number"))?);
+    }
+    if let Some(url) = flag_values(args, "--answer-cache-url").last() {
+        config.answer_cache_url = Some(url.to_string());
+    }
+    if let Some(max_tokens) = flag_values(args, "--max-tokens").last() {
+        config.max_tokens = Some(max_tokens.parse().map_err(|_| anyhow!("--max-tokens must be a number"))?);
+    }
+    if let Some(temperature) = flag_values(args, "--temperature").last() {
+        config.temperature = Some(temperature.parse().map_err(|_| anyhow!("--temperature must be a number"))?);
+    }
+    if let Some(language) = flag_values(args, "--lang").last() {
+        config.language = Some(language.to_string());
+    }
+    if args.iter().any(|arg| arg == "--intent-llm") {
+        config.intent_llm = true;
+    }
+    if args.iter().any(|arg| arg == "--non-interactive") {
+        config.non_interactive = true;
+    }
+    Ok(())
+}
+
+struct ChatOptions {
+    review_context: bool,
+    show_timings: bool,
+    interactive: bool,
+    /// Where `/profile set` persists the user profile; unsaved when `None`
+    profile_path: Option<PathBuf>,
+    /// Records every answered query for `tapssp replay`
+    session: Option<SessionLog>,
+    /// Writes every answered query as a fixture for `tapssp replay --fixtures`
+    recorder: Option<FixtureRecorder>,
+    /// Answer questions asked again with their earlier answer
+    detect_repeats: bool,
+    /// Collection named in the status line shown above each prompt;
+    /// `None` hides the line
+    status_collection: Option<String>,
+    /// Index file written by /save and read by /load
+    index_path: Option<PathBuf>,
+    /// Opened with --read-only, so /add, /save and /load are refused
+    read_only: bool,
+}
+
+fn run() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // Anything but a command name, such as a documents directory, starts a chat
+    let named = args.first().map(String::as_str).filter(|name| completions::COMMANDS.iter().any(|command| command.name == *name));
+    completions::check_flags(named.unwrap_or("chat"), &args)?;
+    let config_path = flag_values(&args, "--config").last().map(PathBuf::from);
+    let mut config = RuntimeConfig::load(config_path.as_deref(), |key| env::var(key).ok())?;
+    apply_cli_overrides(&mut config, &args)?;
+    i18n::init(match &config.language {
+        Some(language) => Locale::new(language)?,
+        None => Locale::detect(|key| env::var(key).ok()),
+    });
+    tracing::debug!(language = i18n::current().code(), "selected locale");
+
+    let command = args.first().map(String::as_str);
+    match command {
+        Some("save-query") => return save_query(&config, &args[1..]),
+        Some("kb") => return kb_command(&config, &args[1..]),
+        Some("search") => {
+            let query = positional_args(&args).get(1).copied().ok_or_else(|| anyhow!(SEARCH_USAGE))?;
+            return search_command(&config, query, &args[1..]);
+        }
+        Some("init") => return init_command(&config, &args[1..]),
+        Some("completions") => {
+            let shell = positional_args(&args).get(1).copied().ok_or_else(|| anyhow!("Usage: tapssp completions bash|zsh|fish"))?;
+            print!("{}", completions::script(shell)?);
+            return Ok(());
+        }
+        Some("man") => {
+            print!("{}", completions::man_page());
+            return Ok(());
+        }
+        Some("index") => return index_command(&config, &args[1..]),
+        Some("ingest") => return ingest_command(&config, &args[1..]),
+        Some("ingest-enqueue") => return ingest_enqueue(&config, &args[1..]),
+        Some("ingest-worker") => return ingest_worker(&config, &args[1..]),
+        _ => {}
+    }
+    let one_shot = match command {
+        Some("query") => Some(positional_args(&args).get(1).copied()
+            .ok_or_else(|| anyhow!("Usage: tapssp query \"QUESTION\" [DOCS_DIR] [--index PATH]"))?),
+        _ => None,
+    };
+
+    let fixtures_dir = flag_values(&args, "--fixtures").last().map(PathBuf::from);
+    let session = match flag_values(&args, "--session").last() {
+        Some(name) => Some(SessionLog::new(&config.sessions_dir()?, name)?),
+        None if command == Some("replay") && fixtures_dir.is_none() => {
+            return Err(anyhow!("Usage: tapssp replay --session NAME | --fixtures DIR [--index PATH]"));
+        }
+        None => None,
+    };
+    // Replays compare against a given index; one built on the spot from the
+    // docs directory would only compare the documents with themselves
+    if command == Some("replay") {
+        match config.index_path() {
+            Some(path) if path.exists() => {}
+            Some(path) => return Err(anyhow!("No index at {:?}; replay needs an existing --index", path)),
+            None => return Err(anyhow!("Usage: tapssp replay --session NAME | --fixtures DIR [--index PATH]")),
+        }
+    }
+
+    let saved_query = match command {
+        Some("run") => {
+            let name = args.get(1)
+                .ok_or_else(|| anyhow!("Usage: tapssp run <name> [--var key=value]... [--docs DIR]"))?;
+            Some(SavedQuery::load(&config.queries_dir()?, name)?)
+        }
+        _ => None,
+    };
+    let serve = command == Some("serve");
+    if serve && !cfg!(feature = "server") {
+        return Err(anyhow!("tapssp was built without the `server` feature"));
+    }
+    if serve || one_shot.is_some() {
+        // Servers never own a terminal, and one-shot answers go to stdout alone
+        config.non_interactive = true;
+    }
+    // Status messages go to stderr when stdout is meant for answers only
+    let status = |message: String| {
+        if config.non_interactive {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    };
+
+    // Initialize LLM (downloads the default model if no path is configured,
+    // after asking in an interactive session)
+    let llm = match load_llm(&config, config.model_path.clone(), &status) {
+        Ok(llm) => llm,
+        // Saved queries and replays are about the generated answers
+        Err(e) if saved_query.is_none() && command != Some("replay") => {
+            eprintln!("Warning: Failed to load the model: {}", e);
+            eprintln!("Continuing in search-only mode: questions get the best matching chunks instead of answers");
+            LLM::unavailable(e.to_string())
+        }
+        Err(e) => return Err(e),
+    };
+    let current_model = llm.model_path().map_or_else(String::new, |path| path.display().to_string());
+    
+    let webhooks = Webhooks::new(config.webhooks.clone(), config.webhook_secret.clone());
+    // Reports index failures to the webhooks before propagating them
+    let index_error = |e: anyhow::Error| {
+        let _ = webhooks.send(&WebhookEvent::IndexError { message: e.to_string() });
+        e
+    };
+
+    let index_path = config.index_path();
+    let read_only = args.iter().any(|arg| arg == "--read-only");
+    // Workers can fetch a centrally built index before starting
+    if let Some(url) = flag_values(&args, "--pull").last() {
+        let path = index_path.as_ref().ok_or_else(|| anyhow!("--pull requires --index PATH"))?;
+        status(format!("Fetching index from {}...", url));
+        object_store::pull_index(&ObjectUrl::parse(url)?, path).map_err(index_error)?;
+    }
+    let mut retriever = match &index_path {
+        Some(path) if read_only => Retriever::with_vector_db(VectorDB::open_read_only(path).map_err(index_error)?),
+        Some(path) if path.exists() => Retriever::with_vector_db(VectorDB::load(path).map_err(index_error)?),
+        None if read_only => return Err(anyhow!("--read-only requires --index PATH")),
+        _ => {
+            let mut retriever = Retriever::new();
+            retriever.set_settings(config.collection.clone())?;
+            retriever
+        }
+    };
+    let embedder = config.embedding_model.as_deref().map(|dir| embeddings::load_embedder(dir, config.embedding_device)).transpose()?;
+    retriever.use_embedding_model(embedder.clone()).map_err(index_error)?;
+    retriever.set_ann_params(config.ann);
+    for dir in &config.embedding_variants {
+        let variant = embeddings::load_embedder(dir, config.embedding_device)?;
+        let model_id = variant.model_id().to_string();
+        retriever.add_embedding_variant(variant)?;
+        status(format!("Embedding variant {} covers {} of {} documents",
+            model_id, retriever.variant_coverage(&model_id), retriever.len()));
+    }
+    if let Some(mode) = &config.search_mode {
+        retriever.set_search_mode(SearchMode::parse(mode))?;
+    }
+    retriever.set_hybrid(config.hybrid.as_deref().map(Fusion::parse).transpose()?);
+    if let Some(path) = &config.synonyms_path {
+        // Re-tokenizes a loaded index if the map was edited since it was built
+        let changed = retriever.set_synonyms(Synonyms::load(path)?).map_err(index_error)?;
+        if changed && !retriever.is_empty() && let Some(path) = &index_path {
+            retriever.save(path).map_err(index_error)?;
+            status(format!("Re-indexed {} documents with the updated synonyms", retriever.len()));
+        }
+    }
+
+    // Load documents from a directory
+    if config.docs_dir.is_none() {
+        let positional = positional_args(&args);
+        let dir = match command {
+            Some("serve" | "chat") => positional.get(1),
+            Some("query") => positional.get(2),
+            Some("run" | "replay") => None,
+            _ => positional.first(),
+        };
+        config.docs_dir = dir.map(PathBuf::from);
+    }
+    let docs_dir = config.docs_dir();
+
+    if retriever.is_empty() {
+        let mut settings = retriever.settings().clone();
+        apply_chunking_flags(&mut settings, &args)?;
+        retriever.set_settings(settings)?;
+        status(format!("Loading documents from {:?}...", docs_dir));
+        if let Err(e) = runtime::block_on(load_documents(&mut retriever, &docs_dir, &config.file_filter(), load_transforms(&config)?)) {
+            eprintln!("Warning: Failed to load documents: {}", e);
+            let _ = webhooks.send(&WebhookEvent::IndexError { message: format!("Failed to load documents: {}", e) });
+        }
+        status(format!("Loaded documents: {}", retriever.take_ingest_report()));
+        if let Some(path) = &index_path {
+            retriever.save(path).map_err(index_error)?;
+            status(format!("Saved index with {} documents to {:?}", retriever.len(), path));
+        }
+        let _ = webhooks.send(&WebhookEvent::IngestCompleted {
+            documents: retriever.len(),
+            index_path: index_path.as_ref().map(|path| path.display().to_string()),
+        });
+    } else {
+        let mode = if read_only { " (read-only)" } else { "" };
+        let search = if retriever.uses_ann() { ", HNSW search" } else { "" };
+        status(format!("Loaded {} documents from index{} (embeddings: {}{})",
+            retriever.len(), mode, retriever.model_id(), search));
+    }
+
+    if let Some(saved) = &saved_query {
+        return run_saved_query(&llm, &retriever, saved, &args[2..]);
+    }
+
+    // Summarizes the documents as loaded, including any just ingested
+    retriever.set_routing(config.route_documents);
+    retriever.set_reranker(load_reranker(&config)?);
+    retriever.set_min_score(config.min_score);
+    retriever.set_mmr(config.mmr_lambda)?;
+    if let Some(path) = &index_path {
+        retriever.set_access_log(Some(AccessLog::for_index(path)));
+    }
+    let top_k = config.top_k.or(retriever.settings().top_k);
+    let mut pipeline = RagPipeline::new(retriever, llm);
+    if let Some(top_k) = top_k {
+        pipeline = pipeline.with_top_k(top_k);
+    }
+    let collections = open_collections(&config, embedder, read_only)?;
+    if !collections.is_empty() {
+        status(format!("Serving collections: {}", collections.names().join(", ")));
+        pipeline = pipeline.with_collections(collections);
+    }
+    pipeline = pipeline.with_intent(IntentClassifier::new(config.intent_llm));
+    let model_config = config.clone();
+    pipeline = pipeline.with_model_switching(
+        current_model,
+        Box::new(move |name| load_llm(&model_config, Some(resolve_model(&model_config, name)?), &|message| tracing::info!("{}", message))),
+    );
+    if let Some(path) = config.faq_path() {
+        let faq = Faq::load(&path)?;
+        status(format!("Loaded {} FAQ entries from {:?}", faq.len(), path));
+        pipeline = pipeline.with_faq(faq);
+    }
+    if let Some(path) = &config.moderation_path {
+        let moderator = Moderator::load(path)?;
+        status(format!("Loaded {} moderation categories from {:?}", moderator.len(), path));
+        pipeline = pipeline.with_moderation(moderator);
+    }
+    if !config.federation.is_empty() {
+        let federation = Federation::new(config.federation.clone());
+        status(format!("Federating retrieval with {} remote instances", federation.len()));
+        pipeline = pipeline.with_federation(federation);
+    }
+    if let Some(capacity) = config.answer_cache {
+        let cache = match &config.answer_cache_url {
+            Some(url) => AnswerCache::with_store(answer_cache::open_store(url)?, capacity),
+            None => AnswerCache::new(capacity),
+        };
+        pipeline = pipeline.with_answer_cache(cache);
+    }
+    if let Some(name) = &config.small_model {
+        let small = load_llm(&config, Some(resolve_model(&config, name)?), &status)?;
+        pipeline = pipeline.with_small_model(small);
+    }
+    if !webhooks.is_empty() {
+        pipeline = pipeline.with_webhooks(webhooks);
+    }
+    if let Some(target) = &config.escalate {
+        pipeline = pipeline.with_escalation(Escalation::parse(target)?);
+    }
+    // Typos would otherwise match nothing in the TF-IDF vocabulary
+    let corrector = IndexSpellCorrector::new(pipeline.retriever(), 2);
+    if !corrector.corrector().is_empty() && !args.iter().any(|arg| arg == "--no-spell-correction") {
+        pipeline.on_pre_retrieval(move |query| {
+            let corrected = corrector.correct_query(query);
+            if corrected != *query {
+                tracing::debug!(original = %query, corrected = %corrected, "corrected query spelling");
+                *query = corrected;
+            }
+        });
+    }
+    if command == Some("replay") {
+        return match &fixtures_dir {
+            Some(dir) => replay_fixtures(&pipeline, dir),
+            None => replay(&pipeline, session.as_ref().expect("checked above")),
+        };
+    }
+    if let Some(query) = one_shot {
+        if !pipeline.llm().is_available() {
+            print_chunks(&pipeline.retrieve_local(query, pipeline.top_k(), None));
+            return Ok(());
+        }
+        let (_, answer) = pipeline.answer(query)?;
+        println!("{}", answer);
+        return Ok(());
+    }
+    // Rebuilds the index in the background between queries
+    let _maintenance = MaintenanceWorker::spawn(
+        pipeline.retriever(),
+        pipeline.activity(),
+        Duration::from_secs(5),
+    );
+    let _collection_maintenance: Vec<_> = pipeline.collections().iter()
+        .map(|(_, collection)| MaintenanceWorker::spawn(Arc::clone(&collection.retriever), pipeline.activity(), Duration::from_secs(5)))
+        .collect();
+    let _snapshots = match (config.snapshot_interval, &index_path) {
+        (Some(interval), Some(path)) if !read_only => Some(SnapshotWorker::spawn(
+            snapshot_store(&config, path)?,
+            path.clone(),
+            config.sessions_dir().ok(),
+            interval,
+            config.snapshot_retention,
+        )),
+        _ => None,
+    };
+
+    let _watch = if args.iter().any(|arg| arg == "--watch") {
+        if read_only {
+            return Err(anyhow!("--watch can't update a --read-only index"));
+        }
+        let transforms = load_transforms(&config)?;
+        let (filter, watched) = (config.file_filter(), docs_dir.clone());
+        status(format!("Watching {:?} for changes", docs_dir));
+        Some(WatchWorker::spawn(&docs_dir, pipeline.retriever(), index_path.clone(), move |retriever, path| {
+            if is_ingestible(path) && filter.accepts(&watched, path) { load_file(retriever, path, &transforms) } else { Ok(()) }
+        })?)
+    } else {
+        None
+    };
+
+    #[cfg(feature = "server")]
+    if serve {
+        return runtime::block_on(server::serve(pipeline, config.listen_addr()?, config.session_ttl));
+    }
+
+    let show_status = !config.non_interactive && std::io::stdout().is_terminal()
+        && !args.iter().any(|arg| arg == "--no-status-line");
+    let options = ChatOptions {
+        review_context: args.iter().any(|arg| arg == "--review-context") && !config.non_interactive,
+        show_timings: args.iter().any(|arg| arg == "--timings"),
+        interactive: !config.non_interactive,
+        profile_path: config.profile_path().ok(),
+        session,
+        recorder: flag_values(&args, "--record").last().map(|dir| FixtureRecorder::new(Path::new(dir))).transpose()?,
+        detect_repeats: !args.iter().any(|arg| arg == "--no-repeat-detection"),
+        status_collection: show_status.then(|| StatusLine::collection_name(index_path.as_deref(), &docs_dir)),
+        index_path,
+        read_only,
+    };
+    chat(&config, &pipeline, &options)
+}
+
+/// Re-runs the queries recorded in `session` against the current index and
+/// config, printing a word diff for every answer that changed. Sampling is
+/// pinned, so the diffs come from the index and config rather than chance.
+fn replay(pipeline: &RagPipeline, session: &SessionLog) -> Result<()> {
+    let overrides = GenerationOverrides { temperature: Some(0.0), seed: Some(REPLAY_SEED), ..GenerationOverrides::default() };
+    let entries = session.entries()?;
+    let mut changed = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let (_, answer) = pipeline.answer_with(&entry.query, &overrides)?;
+        println!("[{}] {}", i + 1, entry.query);
+        match sessions::word_diff(&entry.answer, &answer) {
+            Some(diff) => {
+                changed += 1;
+                println!("{}\n", diff);
+            }
+            None => println!("(unchanged)\n"),
+        }
+    }
+    println!("{} of {} answers changed", changed, entries.len());
+    Ok(())
+}
+
+/// Re-runs retrieval and prompt building for the fixtures recorded in
+/// `dir` with --record, failing when any retrieval changed
+fn replay_fixtures(pipeline: &RagPipeline, dir: &Path) -> Result<()> {
+    let fixtures = fixtures::load_fixtures(dir)?;
+    let (mut retrieval, mut prompts) = (0, 0);
+    for (path, fixture) in &fixtures {
+        let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+        match fixture.replay(pipeline)? {
+            None => println!("ok      {} {}", name, fixture.query),
+            Some(drift) => {
+                match drift {
+                    fixtures::Drift::Retrieval { .. } => retrieval += 1,
+                    fixtures::Drift::Prompt { .. } => prompts += 1,
+                }
+                println!("CHANGED {} {}
+  {}", name, fixture.query, drift.to_string().replace('\n', "\n  "));
+            }
+        }
+    }
+    println!("{} fixtures: {} retrieval changes, {} prompt changes", fixtures.len(), retrieval, prompts);
+    if retrieval > 0 {
+        return Err(anyhow!("Retrieval changed for {} of {} fixtures", retrieval, fixtures.len()));
+    }
+    Ok(())
+}
+
+/// The REPL when no model could be loaded: each question gets the best
+/// matching chunks with their highlights, like `tapssp search`
+fn search_repl(pipeline: &RagPipeline, options: &ChatOptions) -> Result<()> {
+    if options.interactive {
+        println!("{}", i18n::text("repl-search-only"));
+    }
+    loop {
+        let mut query = String::new();
+        if options.interactive {
+            print!("> ");
+            std::io::stdout().flush()?;
+        }
+        if std::io::stdin().read_line(&mut query)? == 0 {
+            return Ok(());
+        }
+        let query = query.trim();
+        if query.is_empty() {
+            continue;
+        }
+        print_chunks(&pipeline.retrieve_local(query, pipeline.top_k(), None));
+        println!();
+    }
+}
+
+/// The slash-commands of the REPL, for the welcome message and /help
+fn print_commands() {
+    println!("{}", i18n::text("repl-commands"));
+    for command in ["retry", "set", "format", "profile", "feedback", "why", "reset", "model", "add", "sources", "topk", "collection", "stats"] {
+        println!("  {}", i18n::text(&format!("repl-command-{}", command)));
+    }
+}
+
+/// The REPL. Without a terminal it reads one query per line and prints
+/// only the answers.
+fn chat(config: &RuntimeConfig, pipeline: &RagPipeline, options: &ChatOptions) -> Result<()> {
+    if !pipeline.llm().is_available() {
+        return search_repl(pipeline, options);
+    }
+    if options.interactive {
+        println!("{}", i18n::text("repl-welcome"));
+        println!("{}", i18n::text("repl-model"));
+        print_commands();
+    }
+
+    let mut profile = match &options.profile_path {
+        Some(path) => UserProfile::load(path)?,
+        None => UserProfile::default(),
+    };
+
+    // Last question and its context, kept so /retry and /variants can reuse them
+    let mut last_turn: Option<(String, Vec<ScoredChunk>)> = None;
+    let mut retry_attempt = 0;
+    // Newest answer to that question with the parameters and retry attempt
+    // it was generated with, for /why
+    let mut last_answer: Option<(String, GenerationOverrides, usize)> = None;
+    // Earlier turns given to the model; older ones are summarized as it grows
+    let mut conversation = Conversation::default();
+    // Whether the last question's answer is the newest turn in `conversation`
+    let mut last_answered = false;
+    // Sampling parameters changed with /set for the rest of the session
+    let mut overrides = GenerationOverrides::default();
+    // Questions answered since the last /reset, with their answers and
+    // context, offered again when asked again
+    let mut answered: Vec<(String, String, Vec<ScoredChunk>)> = Vec::new();
+    // Decoding speed of the last generated answer, for the status line
+    let mut tokens_per_second = None;
+    // Chunks retrieved per question and whether sources are listed below
+    // answers, changed with /topk and /sources
+    let mut top_k = pipeline.top_k();
+    let mut show_sources = true;
+    // Collection questions are answered from, with the index file /save
+    // and /load use and the name in the status line, changed with /collection
+    let mut pipeline = pipeline.clone();
+    let mut index_path = options.index_path.clone();
+    let mut status_collection = options.status_collection.clone();
+
+    // Interactive query loop
+    loop {
+        let mut query = String::new();
+        if let Some(collection) = &status_collection {
+            // Read again before every prompt, so it follows --watch
+            // ingestion, model switches and the growing conversation
+            let llm = pipeline.llm();
+            let status = StatusLine {
+                documents: pipeline.retriever().try_read().ok().map(|retriever| retriever.len()),
+                collection: collection.clone(),
+                model: StatusLine::model_name(&match pipeline.model_switcher() {
+                    Some(switcher) => switcher.status().model,
+                    None => llm.model_path().map_or_else(String::new, |path| path.display().to_string()),
+                }),
+                context_used: llm.count_tokens(&conversation.render()),
+                context_size: llm.context_tokens(),
+                tokens_per_second,
+            };
+            println!("\x1b[2m{}\x1b[0m", status);
+        }
+        if options.interactive {
+            print!("> ");
+            std::io::stdout().flush()?;
+        }
+        
+        if std::io::stdin().read_line(&mut query)? == 0 {
+            break; // EOF (Ctrl+D)
+        }
+
+        let query = query.trim();
+        if query.is_empty() {
+            continue;
+        }
+
+        if let Some(command) = query.strip_prefix('/') {
+            let mut parts = command.split_whitespace();
+            match (parts.next(), &last_turn) {
+                (Some("retry"), Some((last_query, context))) => {
+                    retry_attempt += 1;
+                    print!("\n{}", i18n::text("repl-regenerating"));
+                    std::io::stdout().flush()?;
+                    if last_answered {
+                        conversation.pop();
+                    }
+                    let applied = profile.apply(&overrides);
+                    let result = pipeline.regenerate(
+                        last_query, retriever::contents(context), Some(&conversation), retry_attempt, &applied);
+                    last_answered = result.is_ok();
+                    match result {
+                        Ok((outcome, response)) => {
+                            println!("\r{}\n", response);
+                            if show_sources && outcome == Outcome::Answered && !context.is_empty() {
+                                println!("{}\n", pipeline.sources(context, &response));
+                            }
+                            conversation.push(last_query, &response);
+                            // The new answer is the one offered when the question comes again
+                            answered.retain(|(question, _, _)| question != last_query);
+                            if outcome == Outcome::Answered {
+                                answered.push((last_query.clone(), response.clone(), context.clone()));
+                            }
+                            last_answer = Some((response, applied, retry_attempt));
+                        }
+                        Err(e) => eprintln!("\r{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                    }
+                }
+                (Some("variants"), Some((last_query, context))) => {
+                    let n = match parts.next().map(str::parse::<usize>) {
+                        None => 3,
+                        Some(Ok(n)) if n > 0 => n,
+                        _ => {
+                            eprintln!("Usage: /variants [N]\n");
+                            continue;
+                        }
+                    };
+                    print!("\n{}", i18n::format("repl-generating-variants", &[("count", n.into())]));
+                    std::io::stdout().flush()?;
+                    // Variants answer the same question, so they must not see its current answer
+                    let previous = if last_answered { conversation.pop() } else { None };
+                    let result = pipeline.variants(
+                        last_query, retriever::contents(context), Some(&conversation), n, &profile.apply(&overrides));
+                    if let Some(turn) = previous {
+                        conversation.push(&turn.question, &turn.answer);
+                    }
+                    match result {
+                        Ok(variants) => {
+                            println!();
+                            for (i, (_, variant)) in variants.iter().enumerate() {
+                                println!("{}\n{}\n", i18n::format("repl-variant", &[("number", (i + 1).into())]), variant);
+                            }
+                        }
+                        Err(e) => eprintln!("\r{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                    }
+                }
+                (Some("set"), _) => {
+                    let (key, value) = match command["set".len()..].trim().split_once(' ') {
+                        Some((key, value)) => (key, value),
+                        None => (command["set".len()..].trim(), ""),
+                    };
+                    if key.is_empty() {
+                        println!("{}\n", overrides);
+                        continue;
+                    }
+                    let mut updated = overrides.clone();
+                    match updated.set(key, value).and_then(|_| pipeline.llm().validate_overrides(&profile.apply(&updated))) {
+                        Ok(()) => overrides = updated,
+                        Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                    }
+                }
+                (Some("format"), _) => match parts.next() {
+                    None => {
+                        let current = overrides.format.map_or("off", AnswerFormat::name);
+                        let available: Vec<&str> = AnswerFormat::ALL.iter().map(|format| format.name()).collect();
+                        let available = format!("{}, off", available.join(", "));
+                        println!("{}\n", i18n::format("repl-answer-format", &[("current", current.into()), ("available", available.into())]));
+                    }
+                    Some("off" | "none") => overrides.format = None,
+                    Some(name) => match AnswerFormat::parse(name) {
+                        Ok(format) => overrides.format = Some(format),
+                        Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                    },
+                },
+                (Some("profile"), _) => match (parts.next(), parts.next()) {
+                    (None, _) => println!("{}\n", profile),
+                    (Some("clear"), _) => {
+                        profile = UserProfile::default();
+                        if let Some(path) = &options.profile_path {
+                            profile.save(path)?;
+                        }
+                    }
+                    (Some("set"), Some(key)) => {
+                        let value = command.splitn(4, char::is_whitespace).nth(3).unwrap_or("");
+                        let mut updated = profile.clone();
+                        let result = updated.set(key, value)
+                            .and_then(|_| pipeline.llm().validate_overrides(&updated.apply(&overrides)));
+                        match result {
+                            Ok(()) => {
+                                profile = updated;
+                                if let Some(path) = &options.profile_path {
+                                    profile.save(path)?;
+                                }
+                            }
+                            Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                        }
+                    }
+                    _ => eprintln!("Usage: /profile [set role|expertise|style VALUE | clear]\n"),
+                },
+                (Some("feedback"), _) => {
+                    let helpful = match parts.next() {
+                        Some("good") => true,
+                        Some("bad") => false,
+                        _ => {
+                            eprintln!("Usage: /feedback good|bad\n");
+                            continue;
+                        }
+                    };
+                    match &options.session {
+                        Some(session) => match session.rate_last(helpful) {
+                            Ok(()) => println!("{}\n", i18n::text("repl-feedback-recorded")),
+                            Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                        },
+                        None => eprintln!("{}\n", i18n::text("repl-feedback-needs-session")),
+                    }
+                }
+                (Some("why"), Some((last_query, context))) => {
+                    let Some((answer, applied, attempt)) = &last_answer else {
+                        eprintln!("{}\n", i18n::text("repl-nothing-to-explain"));
+                        continue;
+                    };
+                    match pipeline.explain(last_query, &retriever::contents(context), answer, applied, *attempt) {
+                        Ok(explanation) => println!("\n{}\n", explanation),
+                        Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                    }
+                }
+                (Some("model"), _) => {
+                    let Some(switcher) = pipeline.model_switcher() else {
+                        eprintln!("{}\n", i18n::format("repl-unknown-command", &[("command", command.into())]));
+                        continue;
+                    };
+                    match (parts.next(), parts.next()) {
+                        (None, _) => {
+                            let status = switcher.status();
+                            println!("{}", i18n::format("repl-model-status", &[("model", status.model.into())]));
+                            if let Some(model) = status.loading {
+                                println!("{}", i18n::format("repl-model-loading", &[("model", model.into())]));
+                            }
+                            if let Some(message) = status.error {
+                                println!("{}", i18n::format("repl-model-switch-failed", &[("message", message.into())]));
+                            }
+                            println!();
+                        }
+                        (Some("switch"), Some(model)) => match switcher.switch(model) {
+                            Ok(()) => println!("{}\n", i18n::format("repl-model-loading", &[("model", model.into())])),
+                            Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                        },
+                        _ => eprintln!("Usage: /model [switch NAME]\n"),
+                    }
+                }
+                (Some("help"), _) => {
+                    print_commands();
+                    println!();
+                }
+                (Some("sources"), _) => match parts.next() {
+                    Some("on") => show_sources = true,
+                    Some("off") => show_sources = false,
+                    _ => eprintln!("Usage: /sources on|off\n"),
+                },
+                (Some("topk"), _) => match parts.next().map(str::parse::<usize>) {
+                    None => println!("{}\n", i18n::format("repl-stats-top-k", &[("top_k", top_k.into())])),
+                    Some(Ok(n)) if n > 0 => top_k = n,
+                    _ => eprintln!("Usage: /topk N\n"),
+                },
+                (Some("collection"), _) => {
+                    let Some(name) = parts.next() else {
+                        let names = pipeline.collections().names().join(", ");
+                        println!("{}\n", i18n::format("repl-collections", &[("current", pipeline.collection_name().into()), ("names", names.into())]));
+                        continue;
+                    };
+                    match pipeline.collection(name) {
+                        Ok(selected) => {
+                            let served = selected.collections().get(name);
+                            index_path = served.map_or_else(|| options.index_path.clone(), |collection| Some(collection.path.clone()));
+                            status_collection = options.status_collection.as_ref()
+                                .map(|main| served.map_or_else(|| main.clone(), |_| name.to_string()));
+                            // Earlier answers came from the other collection's documents
+                            answered.clear();
+                            last_turn = None;
+                            last_answer = None;
+                            last_answered = false;
+                            retry_attempt = 0;
+                            top_k = selected.top_k();
+                            pipeline = selected;
+                            let count = pipeline.retriever().read().expect("retriever lock poisoned").len();
+                            println!("{}\n", i18n::format("repl-collection-selected", &[("name", name.into()), ("count", count.into())]));
+                        }
+                        Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                    }
+                }
+                (Some("temp"), _) => {
+                    let Some(value) = parts.next() else {
+                        eprintln!("Usage: /temp X\n");
+                        continue;
+                    };
+                    let mut updated = overrides.clone();
+                    match updated.set("temperature", value).and_then(|_| pipeline.llm().validate_overrides(&profile.apply(&updated))) {
+                        Ok(()) => overrides = updated,
+                        Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                    }
+                }
+                (Some("stats"), _) => {
+                    let retriever = pipeline.retriever();
+                    let retriever = retriever.read().expect("retriever lock poisoned");
+                    println!("{}", i18n::format("repl-stats-documents", &[
+                        ("count", retriever.len().into()),
+                        ("model", retriever.model_id().to_string().into()),
+                    ]));
+                    println!("{}", i18n::format("repl-stats-settings", &[("settings", retriever.settings().to_string().into())]));
+                    println!("{}\n", i18n::format("repl-stats-top-k", &[("top_k", top_k.into())]));
+                }
+                (Some("add"), _) => {
+                    let file = command["add".len()..].trim();
+                    if file.is_empty() {
+                        eprintln!("Usage: /add FILE\n");
+                        continue;
+                    }
+                    if options.read_only {
+                        eprintln!("{}\n", i18n::text("repl-index-read-only"));
+                        continue;
+                    }
+                    let path = Path::new(file);
+                    if !is_ingestible(path) {
+                        eprintln!("{}\n", i18n::format("repl-not-ingestible", &[("file", file.into())]));
+                        continue;
+                    }
+                    // Read and chunked before taking the lock, so only embedding holds up other readers
+                    let result = load_transforms(config).and_then(|transforms| read_file(path, &transforms)).and_then(|loaded| {
+                        let retriever = pipeline.retriever();
+                        let mut retriever = retriever.write().expect("retriever lock poisoned");
+                        let before = retriever.len();
+                        add_file(&mut retriever, loaded)?;
+                        Ok(retriever.len() - before)
+                    });
+                    match result {
+                        Ok(count) => println!("{}\n", i18n::format("repl-added", &[("file", file.into()), ("count", count.into())])),
+                        Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                    }
+                }
+                (Some(action @ ("save" | "load")), _) => {
+                    if options.read_only {
+                        eprintln!("{}\n", i18n::text("repl-index-read-only"));
+                        continue;
+                    }
+                    let Some(index_path) = &index_path else {
+                        eprintln!("{}\n", i18n::text("repl-no-index-file"));
+                        continue;
+                    };
+                    let retriever = pipeline.retriever();
+                    let (message, result) = match action {
+                        "save" => ("repl-saved", snapshot_before_write(config, index_path).and_then(|_| {
+                            let retriever = retriever.read().expect("retriever lock poisoned");
+                            retriever.save(index_path)?;
+                            Ok(retriever.len())
+                        })),
+                        _ => ("repl-loaded", VectorDB::load(index_path).and_then(|db| {
+                            let mut retriever = retriever.write().expect("retriever lock poisoned");
+                            retriever.reload(db)?;
+                            Ok(retriever.len())
+                        })),
+                    };
+                    let path = index_path.display().to_string();
+                    match result {
+                        Ok(count) => println!("{}\n", i18n::format(message, &[("count", count.into()), ("path", path.into())])),
+                        Err(e) => eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())])),
+                    }
+                }
+                (Some("reset"), _) => {
+                    // Settings from /set and /profile outlive the conversation
+                    conversation.clear();
+                    answered.clear();
+                    last_turn = None;
+                    last_answer = None;
+                    last_answered = false;
+                    retry_attempt = 0;
+                    println!("{}\n", i18n::text("repl-conversation-cleared"));
+                }
+                (Some("retry" | "variants" | "why"), None) => {
+                    eprintln!("{}\n", i18n::text("repl-nothing-to-regenerate"));
+                }
+                _ => eprintln!("{}\n", i18n::format("repl-unknown-command", &[("command", command.into())])),
+            }
+            continue;
+        }
+
+        // Approved answers to common questions skip retrieval and generation
+        if let Some(entry) = pipeline.faq_answer(query) {
+            println!("\n{}\n", entry.answer);
+            conversation.push(query, &entry.answer);
+            if let Some(session) = &options.session {
+                session.record(query, &entry.answer, &[])?;
+            }
+            last_turn = None;
+            last_answer = None;
+            last_answered = false;
+            continue;
+        }
+
+        // A question asked again gets its earlier answer without generating;
+        // /retry generates a new one from the same context
+        let earlier: Vec<&str> = answered.iter().map(|(question, _, _)| question.as_str()).collect();
+        if options.detect_repeats && let Some(i) = pipeline.find_repeat(query, &earlier) {
+            let (question, answer, context) = answered[i].clone();
+            if options.interactive {
+                println!("\n{}", i18n::format("repl-repeated", &[("question", question.into())]));
+            }
+            println!("\n{}\n", answer);
+            last_turn = Some((query.to_string(), context));
+            last_answer = None;
+            last_answered = false;
+            retry_attempt = 0;
+            continue;
+        }
+
+        // Retrieve relevant context
+        let mut timings = Timings::new();
+        let mut relevant_chunks = pipeline.retrieve_top_k(query, top_k, &mut timings);
+        // Fixtures record retrieval as it was, before any review
+        let retrieved = options.recorder.as_ref().map(|_| retriever::contents(&relevant_chunks));
+        if options.review_context {
+            let highlights = pipeline.highlight(query, &retriever::contents(&relevant_chunks));
+            relevant_chunks = review_chunks(relevant_chunks, &highlights)?;
+        }
+        let context = retriever::contents(&relevant_chunks);
+        
+        // Generate and print response; interactive sessions see the answer
+        // as it is decoded
+        let thinking = i18n::text("repl-thinking");
+        if options.interactive {
+            print!("\n{}", thinking);
+            std::io::stdout().flush()?;
+        }
+        let applied = profile.apply(&overrides);
+        let mut streamed = String::new();
+        let result = pipeline
+            .generate_streaming(query, context.clone(), Some(&conversation), &applied, &mut timings, |text| {
+                if !options.interactive {
+                    return ControlFlow::Continue(());
+                }
+                if streamed.is_empty() {
+                    print!("\r{:1$}\r", "", thinking.chars().count());
+                }
+                streamed.push_str(text);
+                print!("{}", text);
+                let _ = std::io::stdout().flush();
+                ControlFlow::Continue(())
+            })
+            .map(|answer| pipeline.review_answer(query, &context, answer));
+        last_answered = result.is_ok();
+        last_answer = None;
+        match result {
+            Ok((outcome, response)) => {
+                if streamed.is_empty() {
+                    println!("\r{}\n", response);
+                } else if response == streamed {
+                    println!("\n");
+                } else {
+                    // Hooks or the abstain policy replaced what was streamed
+                    println!("\n\n{}\n", response);
+                }
+                if show_sources && outcome == Outcome::Answered && !relevant_chunks.is_empty() {
+                    println!("{}\n", pipeline.sources(&relevant_chunks, &response));
+                }
+                conversation.push(query, &response);
+                if outcome == Outcome::Answered {
+                    answered.push((query.to_string(), response.clone(), relevant_chunks.clone()));
+                }
+                last_answer = Some((response.clone(), applied, 0));
+                if let Some(decode) = timings.stage("decode") {
+                    tokens_per_second = StatusLine::speed(pipeline.llm().count_tokens(&response), decode);
+                }
+                if let Some(session) = &options.session {
+                    session.record(query, &response, &context)?;
+                }
+                if let (Some(recorder), Some(retrieved)) = (&options.recorder, &retrieved) {
+                    recorder.record(&Fixture::capture(&pipeline, query, retrieved, &response)?)?;
+                }
+            }
+            Err(e) => eprintln!("{}{}\n", if streamed.is_empty() { "\r" } else { "\n" }, i18n::format("repl-error", &[("message", e.to_string().into())])),
+        }
+        if options.show_timings {
+            println!("[timings] {}\n", timings);
+        }
+        last_turn = Some((query.to_string(), relevant_chunks));
+        retry_attempt = 0;
+
+        if conversation.needs_summary() {
+            if options.interactive {
+                println!("{}\n", i18n::text("repl-summarizing"));
+            }
+            // The newest turn is kept verbatim, so /retry can still replace it
+            if let Err(e) = pipeline.llm().summarize_conversation(&mut conversation) {
+                eprintln!("{}\n", i18n::format("repl-error", &[("message", e.to_string().into())]));
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use crate::chunking::{self, ChunkStrategy};
+use crate::retriever::Retriever;
+
+/// Name the main index is selected by among the served collections
+pub const DEFAULT_COLLECTION: &str = "default";
+
+/// How an index (a collection of documents) is chunked and queried. Stored
+/// in the index, so every process using it behaves the same; a code
+/// collection can keep stop words and larger chunks while a prose
+/// collection drops them. The embedding model is recorded separately, see
+/// `VectorDB::model_id`.
+#[derive(Debug, Clone, PartialEq, Hash, Serialize, Deserialize)]
+pub struct CollectionSettings {
+    /// Text files are split into chunks of about this many characters
+    /// (tokens for `ChunkStrategy::FixedToken`); `None` indexes each file as
+    /// one document
+    pub chunk_size: Option<usize>,
+    /// Characters (or tokens) from the end of a chunk repeated at the start
+    /// of the next, so statements spanning a boundary can still be found
+    pub chunk_overlap: usize,
+    pub chunk_strategy: ChunkStrategy,
+    /// Drop common English words when tokenizing. Off suits code, where
+    /// words like `is` and `for` carry meaning.
+    pub stop_words: bool,
+    /// Chunks retrieved per query unless overridden
+    pub top_k: Option<usize>,
+}
+
+impl Default for CollectionSettings {
+    fn default() -> Self {
+        CollectionSettings {
+            chunk_size: None,
+            chunk_overlap: 0,
+            chunk_strategy: ChunkStrategy::default(),
+            stop_words: true,
+            top_k: None,
+        }
+    }
+}
+
+impl CollectionSettings {
+    /// Applies `key=value` style changes as given to `kb configure`:
+    /// `chunk-size` (a number or `none`; `chunk-chars` also works),
+    /// `chunk-overlap`, `chunk-strategy` (`sentence`, `paragraph`,
+    /// `fixed-token` or `recursive`), `stop-words` (`on`/`off`) and `top-k`
+    /// (a number or `none`). Invalid changes leave
+    /// the settings untouched.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let mut updated = self.clone();
+        let number = |value: &str| -> Result<Option<usize>> {
+            match value {
+                "none" => Ok(None),
+                n => n.parse().ok().filter(|n| *n > 0).map(Some)
+                    .ok_or_else(|| anyhow!("{} must be a positive number or 'none', got '{}'", key, value)),
+            }
+        };
+        match key {
+            "chunk-size" | "chunk-chars" => updated.chunk_size = number(value)?,
+            "chunk-overlap" => {
+                updated.chunk_overlap = value.parse().map_err(|_| anyhow!("chunk-overlap must be a number, got '{}'", value))?
+            }
+            "chunk-strategy" => updated.chunk_strategy = ChunkStrategy::parse(value)?,
+            "stop-words" => {
+                updated.stop_words = match value {
+                    "on" | "true" | "yes" => true,
+                    "off" | "false" | "no" => false,
+                    _ => return Err(anyhow!("stop-words must be 'on' or 'off', got '{}'", value)),
+                }
+            }
+            "top-k" => updated.top_k = number(value)?,
+            _ => return Err(anyhow!("Unknown collection setting '{}'", key)),
+        }
+        if let Some(size) = updated.chunk_size
+            && updated.chunk_overlap >= size
+        {
+            return Err(anyhow!("chunk-overlap must be smaller than chunk-size"));
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    /// Splits a text file into the documents to index
+    pub fn chunk(&self, text: &str) -> Vec<String> {
+        match self.chunk_size {
+            Some(size) => chunking::chunker(self.chunk_strategy, size, self.chunk_overlap).chunk(text),
+            None => vec![text.to_string()],
+        }
+    }
+}
+
+impl fmt::Display for CollectionSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_none = |n: Option<usize>| n.map_or("none".to_string(), |n| n.to_string());
+        write!(
+            f,
+            "chunk-size={} chunk-overlap={} chunk-strategy={} stop-words={} top-k={}",
+            or_none(self.chunk_size),
+            self.chunk_overlap,
+            self.chunk_strategy,
+            if self.stop_words { "on" } else { "off" },
+            or_none(self.top_k)
+        )
+    }
+}
+
+/// A further index served next to the main one
+#[derive(Clone)]
+pub struct Collection {
+    pub retriever: Arc<RwLock<Retriever>>,
+    /// Index file the collection was loaded from, and /save writes to
+    pub path: PathBuf,
+    /// Chunks retrieved per query; the pipeline's when unset
+    pub top_k: Option<usize>,
+}
+
+/// The collections one process serves besides its main index, by name.
+/// Server requests and the REPL pick one; see `RagPipeline::collection`.
+#[derive(Clone, Default)]
+pub struct Collections {
+    entries: BTreeMap<String, Collection>,
+}
+
+impl Collections {
+    pub fn insert(&mut self, name: &str, retriever: Retriever, path: PathBuf, top_k: Option<usize>) -> Result<()> {
+        if name.is_empty() || name == DEFAULT_COLLECTION {
+            return Err(anyhow!("'{}' can't name a collection; the main index is '{}'", name, DEFAULT_COLLECTION));
+        }
+        if self.entries.contains_key(name) {
+            return Err(anyhow!("Collection '{}' is given twice", name));
+        }
+        let retriever = Arc::new(RwLock::new(retriever));
+        self.entries.insert(name.to_string(), Collection { retriever, path, top_k });
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Collection> {
+        self.entries.get(name)
+    }
+
+    /// Names of the collections in order, the main index first
+    pub fn names(&self) -> Vec<&str> {
+        std::iter::once(DEFAULT_COLLECTION).chain(self.entries.keys().map(String::as_str)).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Collection)> {
+        self.entries.iter().map(|(name, collection)| (name.as_str(), collection))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_and_overlapping_chunks() -> Result<()> {
+        let mut settings = CollectionSettings::default();
+        assert_eq!(settings.chunk("One. Two."), ["One. Two."]);
+
+        settings.set("chunk-chars", "30")?;
+        settings.set("chunk-overlap", "12")?;
+        settings.set("stop-words", "off")?;
+        assert_eq!(settings.to_string(), "chunk-size=30 chunk-overlap=12 chunk-strategy=sentence stop-words=off top-k=none");
+        assert!(settings.set("chunk-overlap", "30").is_err());
+        assert!(settings.set("top-k", "0").is_err());
+        assert!(settings.set("chunk-strategy", "words").is_err());
+
+        let chunks = settings.chunk("Refunds take thirty days. Orders ship on Monday.");
+        assert_eq!(chunks, ["Refunds take thirty days.", "thirty days. Orders ship on Monday."]);
+        Ok(())
+    }
+}
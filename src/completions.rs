@@ -0,0 +1,351 @@
+use anyhow::{Result, anyhow};
+
+/// A subcommand of the `tapssp` binary
+pub struct Command {
+    pub name: &'static str,
+    /// Arguments after the name, as in the usage message
+    pub synopsis: &'static str,
+    pub about: &'static str,
+}
+
+/// A command line flag
+pub struct Flag {
+    pub name: &'static str,
+    /// Placeholder of the value the flag takes; `None` for switches
+    pub value: Option<&'static str>,
+    /// Values to complete; paths are completed for `PATH`, `DIR` and
+    /// `FILE` placeholders
+    pub choices: &'static [&'static str],
+    pub about: &'static str,
+    /// Commands that take the flag; empty for settings every command reads
+    pub commands: &'static [&'static str],
+}
+
+const fn switch(name: &'static str, about: &'static str) -> Flag {
+    Flag { name, value: None, choices: &[], about, commands: &[] }
+}
+
+const fn value(name: &'static str, value: &'static str, about: &'static str) -> Flag {
+    Flag { name, value: Some(value), choices: &[], about, commands: &[] }
+}
+
+const fn choice(name: &'static str, value: &'static str, choices: &'static [&'static str], about: &'static str) -> Flag {
+    Flag { name, value: Some(value), choices, about, commands: &[] }
+}
+
+impl Flag {
+    /// The flag, taken by `commands` alone
+    const fn only(self, commands: &'static [&'static str]) -> Flag {
+        Flag { commands, ..self }
+    }
+}
+
+/// Commands that load the index and model and answer questions
+const ANSWERING: &[&str] = &["chat", "query", "serve", "run", "replay"];
+/// Commands that chunk documents into an index
+const CHUNKING: &[&str] = &["chat", "query", "serve", "run", "replay", "index", "ingest"];
+
+pub const COMMANDS: &[Command] = &[
+    Command { name: "chat", synopsis: "[DOCS_DIR]", about: "Ask questions interactively (the default without a command)" },
+    Command { name: "query", synopsis: "\"QUESTION\" [DOCS_DIR]", about: "Answer one question and exit" },
+    Command { name: "search", synopsis: "\"QUERY\" [-k N] [--where EXPR] [--json]", about: "Print the best matching chunks without generating an answer" },
+    Command { name: "serve", synopsis: "[DOCS_DIR]", about: "Serve the HTTP and OpenAI-compatible API" },
+    Command { name: "init", synopsis: "[--config FILE]", about: "Set up the model, documents, index and chunking and write the config file" },
+    Command { name: "index", synopsis: "[DIR] --index PATH", about: "Rebuild an index from the files in a directory" },
+    Command { name: "ingest", synopsis: "FILE... --index PATH [--preview]", about: "Add files to an index" },
+    Command { name: "ingest-enqueue", synopsis: "PATH... --queue DIR", about: "Queue files for an ingest worker" },
+    Command { name: "ingest-worker", synopsis: "--queue DIR --index PATH [--exit-when-empty]", about: "Ingest queued files" },
+    Command { name: "kb", synopsis: "SUBCOMMAND ...", about: "Manage indexes: push, pull, build, diff, snapshots and more" },
+    Command { name: "save-query", synopsis: "NAME \"TEMPLATE\"", about: "Save a query template" },
+    Command { name: "run", synopsis: "NAME [--var KEY=VALUE]...", about: "Answer a saved query" },
+    Command { name: "replay", synopsis: "--session NAME | --fixtures DIR", about: "Answer recorded questions again and compare" },
+    Command { name: "completions", synopsis: "bash|zsh|fish", about: "Print a shell completion script" },
+    Command { name: "man", synopsis: "", about: "Print the man page" },
+];
+
+/// Subcommands of `tapssp kb`
+pub const KB_COMMANDS: &[&str] = &[
+    "push", "pull", "build", "delta", "apply", "diff", "search", "export", "import", "remove", "cold", "update", "configure",
+    "snapshot", "snapshots", "restore", "mine-negatives", "reembed",
+];
+
+pub const FLAGS: &[Flag] = &[
+    value("--config", "FILE", "Config file instead of ./tapssp.toml"),
+    value("--data-dir", "DIR", "Root for models, saved queries and the default index"),
+    value("--docs", "DIR", "Documents directory"),
+    value("--index", "PATH", "Index file"),
+    value("--collection", "NAME=PATH", "Serve another index as collection NAME (repeatable)"),
+    value("--include", "GLOB", "Only ingest files matching GLOB (repeatable)"),
+    value("--exclude", "GLOB", "Skip files and directories matching GLOB (repeatable)"),
+    switch("--follow-symlinks", "Descend into symlinked directories while ingesting"),
+    switch("--read-only", "Open the index without writing to it").only(ANSWERING),
+    switch("--watch", "Re-ingest files in the documents directory when they change").only(&["chat", "serve"]),
+    value("--pull", "URL", "Fetch the index from s3://bucket/prefix first").only(ANSWERING),
+    value("--out", "FILE", "Output file").only(&["kb"]),
+    choice("--backend", "BACKEND", &["llama", "ollama", "openai"], "Generation backend"),
+    value("--model", "PATH", "GGUF model file, or model name with a remote backend"),
+    value("--small-model", "MODEL", "Faster model for queries with a tight budget"),
+    choice("--prompt-template", "TEMPLATE", &["mistral", "llama3", "chatml"], "Prompt format preset or template file"),
+    value("--max-tokens", "N", "Longest answer in tokens"),
+    value("--temperature", "T", "Sampling temperature"),
+    value("--context-size", "N", "Context window of the model in tokens"),
+    value("--memory-reserve-mb", "MB", "Memory to leave free when loading the model"),
+    value("--gpu-layers", "N", "Model layers offloaded to the GPU"),
+    choice("--gpu-backend", "BACKEND", &["cpu", "cuda", "metal", "vulkan"], "GPU backend"),
+    value("--main-gpu", "N", "GPU holding the model's scratch buffers"),
+    value("--tensor-split", "SHARES", "Comma-separated share of the model per GPU"),
+    choice("--anonymize", "MODE", &["strip", "pseudonymize"], "Hide identifiers from remote backends"),
+    value("--anonymize-terms", "FILE", "Extra terms to hide, one per line"),
+    value("--top-k", "N", "Chunks retrieved per question"),
+    value("-k", "N", "Chunks to print").only(&["search"]),
+    value("--min-score", "S", "Least similarity of a retrieved chunk"),
+    value("--mmr-lambda", "L", "Select chunks by maximal marginal relevance"),
+    value("--hybrid", "FUSION", "Fuse BM25 keyword ranking: rrf or a keyword weight"),
+    value("--search-mode", "MODE", "Embedding space to rank in: primary, fused or a variant's model ID"),
+    value("--route-documents", "N", "Search the chunks of the N best documents only"),
+    value("--reranker", "DIR", "Cross-encoder checkpoint for reranking"),
+    value("--rerank-candidates", "N", "Chunks the reranker scores"),
+    value("--embedding-model", "DIR", "Sentence-transformers checkpoint to embed with"),
+    value("--embedding-variant", "DIR", "Additional embedding model (repeatable)"),
+    value("--embedding-device", "DEVICE", "Device for embedding models, e.g. cpu or cuda:0"),
+    value("--chunk-size", "N", "Characters per chunk").only(CHUNKING),
+    value("--chunk-overlap", "N", "Characters repeated between chunks").only(CHUNKING),
+    choice("--chunk-strategy", "STRATEGY", &["sentence", "paragraph", "fixed-token", "recursive"], "Where chunks break").only(CHUNKING),
+    value("--where", "EXPR", "Only chunks whose metadata matches EXPR").only(&["search"]),
+    value("--contains", "TEXT", "Only chunks containing TEXT").only(&["save-query"]),
+    choice("--format", "FORMAT", &["text", "markdown", "json", "langchain", "llamaindex", "tapssp"], "Output or import format").only(&["save-query", "kb"]),
+    switch("--json", "Print JSON").only(&["search"]),
+    switch("--archive", "Move never-retrieved sources to the cold tier").only(&["kb"]),
+    switch("--preview", "Show how files would be chunked without ingesting").only(&["ingest"]),
+    value("--synonyms", "FILE", "Query synonyms"),
+    value("--transforms", "FILE", "Text transforms applied while ingesting"),
+    value("--faq", "FILE", "Curated answers"),
+    value("--moderation", "FILE", "Moderation rules"),
+    value("--escalate", "TARGET", "URL or command notified of unanswered questions"),
+    value("--webhook", "URL", "Receives ingest and error events (repeatable)"),
+    value("--federate", "URL", "Also retrieve from another tapssp server (repeatable)"),
+    value("--host", "HOST", "Address the server listens on"),
+    value("--port", "PORT", "Port the server listens on"),
+    value("--answer-cache", "N", "Answers kept for repeated questions"),
+    value("--answer-cache-url", "URL", "Redis server shared by replicas' answer caches"),
+    value("--session", "NAME", "Record the chat session, or the session to replay").only(&["chat", "replay", "kb"]),
+    value("--record", "DIR", "Write each answered question as a fixture").only(&["chat"]),
+    value("--fixtures", "DIR", "Fixtures to replay").only(&["replay"]),
+    value("--var", "KEY=VALUE", "Saved query variable (repeatable)").only(&["run"]),
+    value("--queue", "DIR", "Ingest queue directory").only(&["ingest-enqueue", "ingest-worker"]),
+    value("--manifest", "FILE", "Build manifest").only(&["kb"]),
+    value("--at", "TIME", "Restore the snapshot taken at or before TIME").only(&["kb"]),
+    value("--lang", "LANG", "Interface language"),
+    switch("--review-context", "Review the retrieved chunks before answering").only(&["chat"]),
+    switch("--timings", "Print stage timings").only(&["chat"]),
+    switch("--intent-llm", "Ask the model whether short questions need retrieval"),
+    switch("--no-spell-correction", "Search for the words as typed").only(ANSWERING),
+    switch("--no-repeat-detection", "Answer repeated questions again").only(&["chat"]),
+    switch("--no-status-line", "Hide the status line above the chat prompt").only(&["chat"]),
+    switch("--non-interactive", "Never prompt; status goes to stderr"),
+    switch("--exit-when-empty", "Stop the worker once the queue is empty").only(&["ingest-worker"]),
+];
+
+/// Whether `flag` consumes the following argument as its value
+pub fn takes_value(flag: &str) -> bool {
+    FLAGS.iter().any(|known| known.name == flag && known.value.is_some())
+}
+
+/// Rejects flags `command` doesn't take, including unknown ones. Like
+/// `positional_args` in the CLI, arguments starting with a single dash other
+/// than `-k` count as positional, so a question may start with one.
+pub fn check_flags(command: &str, args: &[String]) -> Result<()> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") && arg != "-k" {
+            continue;
+        }
+        let flag = FLAGS.iter().find(|flag| flag.name == arg)
+            .ok_or_else(|| anyhow!("Unknown option '{}'; see `tapssp man`", arg))?;
+        if !flag.commands.is_empty() && !flag.commands.contains(&command) {
+            return Err(anyhow!("'{}' is not an option of `tapssp {}` (only of {})", arg, command, flag.commands.join(", ")));
+        }
+        if flag.value.is_some() {
+            iter.next();
+        }
+    }
+    Ok(())
+}
+
+fn completes_paths(flag: &Flag) -> bool {
+    matches!(flag.value, Some("PATH" | "DIR" | "FILE"))
+}
+
+/// The completion script for `shell`
+pub fn script(shell: &str) -> Result<String> {
+    match shell {
+        "bash" => Ok(bash()),
+        "zsh" => Ok(zsh()),
+        "fish" => Ok(fish()),
+        _ => Err(anyhow!("Unknown shell '{}' (bash, zsh, fish)", shell)),
+    }
+}
+
+fn names<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    names.collect::<Vec<_>>().join(" ")
+}
+
+fn bash() -> String {
+    let flags = names(FLAGS.iter().map(|flag| flag.name));
+    let mut cases = String::new();
+    for flag in FLAGS.iter().filter(|flag| flag.value.is_some()) {
+        let reply = if !flag.choices.is_empty() {
+            format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", flag.choices.join(" "))
+        } else if completes_paths(flag) {
+            "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string()
+        } else {
+            "COMPREPLY=()".to_string()
+        };
+        cases += &format!("        {}) {}; return ;;\n", flag.name, reply);
+    }
+    format!(
+        r#"# bash completion for tapssp
+_tapssp() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+{cases}    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{flags}" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "{commands}" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 2 && "${{COMP_WORDS[1]}}" == kb ]]; then
+        COMPREPLY=($(compgen -W "{kb}" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 2 && "${{COMP_WORDS[1]}}" == completions ]]; then
+        COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}}
+complete -F _tapssp tapssp
+"#,
+        commands = names(COMMANDS.iter().map(|command| command.name)),
+        kb = KB_COMMANDS.join(" "),
+    )
+}
+
+fn zsh() -> String {
+    // Brackets and colons are special in `_arguments` specs
+    let escape = |text: &str| text.replace('[', "\\[").replace(']', "\\]").replace(':', "\\:").replace('\'', "'\\''");
+    let mut specs = String::new();
+    for flag in FLAGS {
+        let action = match flag.value {
+            None => String::new(),
+            Some(_) if !flag.choices.is_empty() => format!(":{}:({})", escape(flag.value.unwrap_or_default()), flag.choices.join(" ")),
+            Some(value) if completes_paths(flag) => format!(":{}:_files", escape(value)),
+            Some(value) => format!(":{}: ", escape(value)),
+        };
+        specs += &format!("    '*{}[{}]{}' \\\n", flag.name, escape(flag.about), action);
+    }
+    let commands: Vec<String> = COMMANDS.iter().map(|command| format!("{}\\:\"{}\"", command.name, escape(command.about).replace('"', "\\\""))).collect();
+    format!(
+        "#compdef tapssp\n\n_arguments -s \\\n{}    '1:command:(({}))' \\\n    '*:file:_files'\n",
+        specs,
+        commands.join(" ")
+    )
+}
+
+fn fish() -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"));
+    let mut out = String::from("# fish completion for tapssp\n");
+    for command in COMMANDS {
+        out += &format!("complete -c tapssp -n __fish_use_subcommand -f -a {} -d {}\n", command.name, quote(command.about));
+    }
+    out += &format!("complete -c tapssp -n '__fish_seen_subcommand_from kb' -f -a {}\n", quote(&KB_COMMANDS.join(" ")));
+    out += "complete -c tapssp -n '__fish_seen_subcommand_from completions' -f -a 'bash zsh fish'\n";
+    for flag in FLAGS {
+        let name = match flag.name.strip_prefix("--") {
+            Some(long) => format!("-l {}", long),
+            None => format!("-s {}", flag.name.trim_start_matches('-')),
+        };
+        let value = match flag.value {
+            None => String::new(),
+            Some(_) if !flag.choices.is_empty() => format!(" -x -a {}", quote(&flag.choices.join(" "))),
+            Some(_) if completes_paths(flag) => " -r -F".to_string(),
+            Some(_) => " -x".to_string(),
+        };
+        out += &format!("complete -c tapssp {}{} -d {}\n", name, value, quote(flag.about));
+    }
+    out
+}
+
+/// The `tapssp(1)` man page in roff
+pub fn man_page() -> String {
+    let roff = |text: &str| {
+        let text = text.replace('\\', "\\e").replace('-', "\\-");
+        // A leading dot or quote would be read as a request
+        if text.starts_with(['.', '\'']) { format!("\\&{}", text) } else { text }
+    };
+    let mut out = format!(
+        ".TH TAPSSP 1 \"\" \"tapssp {}\" \"User Commands\"\n\
+         .SH NAME\ntapssp \\- answer questions from local documents with a language model\n\
+         .SH SYNOPSIS\n.B tapssp\n[\\fICOMMAND\\fR] [\\fIOPTIONS\\fR]\n\
+         .SH DESCRIPTION\n\
+         tapssp indexes text, Markdown, HTML and table files, retrieves the chunks most relevant to a question \
+         and has a local or remote model answer from them. Settings are read from \\fItapssp.toml\\fR, \
+         \\fBTAPSSP_*\\fR environment variables and the options below, each overriding the previous.\n\
+         .SH COMMANDS\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    for command in COMMANDS {
+        out += &format!(".TP\n\\fBtapssp {}\\fR {}\n{}\n", roff(command.name), roff(command.synopsis), roff(command.about));
+    }
+    out += &format!(".PP\n\\fBkb\\fR subcommands: {}.\n.SH OPTIONS\n", roff(&KB_COMMANDS.join(", ")));
+    for flag in FLAGS {
+        let value = flag.value.map_or(String::new(), |value| format!(" \\fI{}\\fR", roff(value)));
+        let choices = match flag.choices {
+            [] => String::new(),
+            choices => format!(" ({})", roff(&choices.join(", "))),
+        };
+        let commands = match flag.commands {
+            [] => String::new(),
+            commands => format!("; only for {}", roff(&commands.join(", "))),
+        };
+        out += &format!(".TP\n\\fB{}\\fR{}\n{}{}{}\n", roff(flag.name), value, roff(flag.about), choices, commands);
+    }
+    out += ".SH FILES\n.TP\n\\fItapssp.toml\\fR\nConfig file in the working directory, written by \\fBtapssp init\\fR\n";
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripts_and_man_page_cover_commands_and_flags() -> Result<()> {
+        let bash = script("bash")?;
+        assert!(bash.contains("complete -F _tapssp tapssp"));
+        assert!(bash.contains("--backend) COMPREPLY=($(compgen -W \"llama ollama openai\" -- \"$cur\")); return ;;"));
+        assert!(bash.contains("--index) COMPREPLY=($(compgen -f -- \"$cur\")); return ;;"));
+        assert!(script("zsh")?.contains("'*--index[Index file]:PATH:_files'"));
+        assert!(script("fish")?.contains("complete -c tapssp -l chunk-strategy -x -a 'sentence paragraph fixed-token recursive'"));
+        assert!(script("powershell").is_err());
+
+        let man = man_page();
+        for command in COMMANDS {
+            assert!(man.contains(&format!("\\fBtapssp {}\\fR", roff_name(command.name))), "{}", command.name);
+        }
+        assert!(man.contains(".TP\n\\fB\\-\\-mmr\\-lambda\\fR \\fIL\\fR\n"));
+
+        assert!(takes_value("--index") && !takes_value("--watch") && !takes_value("QUESTION"));
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(check_flags("search", &args(&["search", "refunds", "-k", "3", "--json", "--index", "kb.bin"])).is_ok());
+        assert!(check_flags("chat", &args(&["--json"])).is_err());
+        assert!(check_flags("chat", &args(&["--indx", "kb.bin"])).is_err());
+        // A value is never taken for a flag
+        assert!(check_flags("run", &args(&["run", "notes", "--var", "--json"])).is_ok());
+        let mut names: Vec<&str> = FLAGS.iter().map(|flag| flag.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), FLAGS.len(), "duplicate flag");
+        Ok(())
+    }
+
+    fn roff_name(name: &str) -> String {
+        name.replace('-', "\\-")
+    }
+}
@@ -0,0 +1,789 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::anonymize::AnonymizeMode;
+use crate::prompt_template::PromptTemplate;
+use crate::collection::{CollectionSettings, DEFAULT_COLLECTION};
+use crate::device::{self, Device};
+use crate::discovery::FileFilter;
+use crate::llm::GenerationLimits;
+use crate::snapshots::Retention;
+use crate::vector_db::HnswParams;
+
+/// Default config file, read from the working directory when present
+pub const CONFIG_FILE: &str = "tapssp.toml";
+/// Where `ollama serve` listens by default
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+/// API used by the OpenAI backend unless another base URL is configured
+pub const DEFAULT_OPENAI_URL: &str = "https://api.openai.com/v1";
+
+/// Process-level settings. Values come from a `tapssp.toml` file (see
+/// `load`), are overridden by `TAPSSP_*` environment variables (see
+/// `with_env`) and then by CLI flags, so a container can be configured
+/// entirely through its environment.
+#[derive(Debug, Clone)]
+pub struct RuntimeConfig {
+    /// Root for models, saved queries and the default index. Without it the
+    /// user cache/config directories are used, which requires a home directory.
+    pub data_dir: Option<PathBuf>,
+    /// GGUF model file; the model name with the Ollama and OpenAI backends
+    pub model_path: Option<PathBuf>,
+    /// Faster model for queries whose budget the main one can't meet: a
+    /// GGUF path or name in the models directory, or an Ollama model name
+    pub small_model: Option<String>,
+    /// SHA256 the downloaded default model must have; without it the hash
+    /// Hugging Face publishes for the file is used
+    pub model_sha256: Option<String>,
+    /// `llama` (in-process GGUF model, the default), `ollama` or `openai`
+    pub backend: Option<String>,
+    /// Server used by the Ollama backend
+    pub ollama_url: String,
+    /// Base URL of the OpenAI-compatible API used by the OpenAI backend
+    pub openai_url: String,
+    /// Sent as a bearer token to the OpenAI-compatible API. Only read from
+    /// the environment, so it doesn't end up in config files.
+    pub openai_api_key: Option<String>,
+    /// Hide identifiers in prompts sent to a remote backend
+    pub anonymize: Option<AnonymizeMode>,
+    /// Terms always hidden when anonymizing, one per line
+    pub anonymize_terms: Option<PathBuf>,
+    /// Chat format of the model's prompts; Mistral's when `None`
+    pub prompt_template: Option<PromptTemplate>,
+    /// Model layers offloaded to the GPU; 0 keeps inference on the CPU
+    pub gpu_layers: usize,
+    /// `cpu`, `cuda`, `metal` or `vulkan`; the first one compiled in when unset
+    pub gpu_backend: Option<String>,
+    /// GPU of the KV cache, and of all offloaded layers without a split
+    pub main_gpu: usize,
+    /// Shares of the offloaded layers per GPU; empty keeps them on `main_gpu`
+    pub tensor_split: Vec<f32>,
+    /// Device of the embedding and reranking models
+    pub embedding_device: Device,
+    /// Model context window in tokens
+    pub context_size: usize,
+    /// MiB of memory kept free while loading the model and generating
+    pub memory_reserve_mb: Option<u64>,
+    /// Answer length cap; `LLMConfig`'s default when unset
+    pub max_tokens: Option<usize>,
+    /// Bounds on the generation overrides of API requests and `/set`
+    pub limits: GenerationLimits,
+    /// Sampling of answers, overriding the answer profile
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub repeat_penalty: Option<f32>,
+    /// Chunks retrieved per query, overriding the index's `top-k`
+    pub top_k: Option<usize>,
+    /// Chunking and other collection settings of indexes created by this
+    /// process; existing indexes keep their own
+    pub collection: CollectionSettings,
+    pub index_path: Option<PathBuf>,
+    /// Further indexes served next to the main one, by name
+    pub collections: BTreeMap<String, PathBuf>,
+    pub docs_dir: Option<PathBuf>,
+    /// Fine-tuned embedding checkpoint used instead of TF-IDF
+    pub embedding_model: Option<PathBuf>,
+    /// Checkpoints whose embeddings are stored next to the primary ones
+    pub embedding_variants: Vec<PathBuf>,
+    /// Which files under the docs directory are ingested
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub follow_symlinks: bool,
+    /// `primary`, `fused` or a variant's model ID
+    pub search_mode: Option<String>,
+    /// `rrf` or a BM25 weight, to fuse keyword scores into retrieval
+    pub hybrid: Option<String>,
+    /// Cross-encoder checkpoint reranking retrieved chunks
+    pub reranker: Option<PathBuf>,
+    /// Chunks the reranker scores per query
+    pub rerank_candidates: usize,
+    /// Source documents whose chunks are searched, ranked by their
+    /// summaries first; all chunks are searched when unset
+    pub route_documents: Option<usize>,
+    /// Similarity below which chunks aren't retrieved; top-k is always
+    /// returned when unset
+    pub min_score: Option<f32>,
+    /// Lambda for maximal marginal relevance selection of chunks; `None`
+    /// ranks by relevance alone
+    pub mmr_lambda: Option<f32>,
+    /// Ask the model whether short questions the small-talk rules don't
+    /// recognize need retrieval
+    pub intent_llm: bool,
+    /// `term = replacement` lines applied when tokenizing
+    pub synonyms_path: Option<PathBuf>,
+    /// Per-source clean-up rules applied to files before chunking
+    pub transforms_path: Option<PathBuf>,
+    /// HNSW graph parameters for large indexes
+    pub ann: HnswParams,
+    /// Curated FAQ answered before the RAG pipeline
+    pub faq_path: Option<PathBuf>,
+    /// Sensitive answer categories checked before API responses
+    pub moderation_path: Option<PathBuf>,
+    /// Base URLs of tapssp servers whose chunks are merged into retrieval
+    pub federation: Vec<String>,
+    /// Webhook URL or shell command receiving unanswered questions
+    pub escalate: Option<String>,
+    /// Endpoints notified of ingest, index error and low-confidence events
+    pub webhooks: Vec<String>,
+    /// Key for the HMAC-SHA256 signature on webhook payloads
+    pub webhook_secret: Option<String>,
+    pub host: String,
+    pub port: u16,
+    /// Idle time after which `/sessions` conversations are dropped
+    pub session_ttl: Duration,
+    /// Answers kept for repeated questions; no caching when `None`
+    pub answer_cache: Option<usize>,
+    /// Redis URL shared answer caches are kept at; process memory when `None`
+    pub answer_cache_url: Option<String>,
+    /// How often the index and sessions are snapshotted; off when `None`
+    pub snapshot_interval: Option<Duration>,
+    /// Which snapshots are kept when old ones are pruned
+    pub snapshot_retention: Retention,
+    /// Never prompt or print REPL decorations; queries are read line by line
+    pub non_interactive: bool,
+    /// Language of REPL messages and prompt instructions; taken from the
+    /// system locale when unset
+    pub language: Option<String>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            data_dir: None,
+            model_path: None,
+            small_model: None,
+            model_sha256: None,
+            backend: None,
+            ollama_url: DEFAULT_OLLAMA_URL.to_string(),
+            openai_url: DEFAULT_OPENAI_URL.to_string(),
+            openai_api_key: None,
+            anonymize: None,
+            anonymize_terms: None,
+            prompt_template: None,
+            gpu_layers: 0,
+            gpu_backend: None,
+            main_gpu: 0,
+            tensor_split: Vec::new(),
+            embedding_device: Device::Cpu,
+            context_size: 2048,
+            memory_reserve_mb: None,
+            max_tokens: None,
+            limits: GenerationLimits::default(),
+            temperature: None,
+            top_p: None,
+            repeat_penalty: None,
+            top_k: None,
+            collection: CollectionSettings::default(),
+            index_path: None,
+            collections: BTreeMap::new(),
+            docs_dir: None,
+            embedding_model: None,
+            embedding_variants: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            follow_symlinks: false,
+            search_mode: None,
+            hybrid: None,
+            route_documents: None,
+            min_score: None,
+            mmr_lambda: None,
+            reranker: None,
+            rerank_candidates: 20,
+            intent_llm: false,
+            synonyms_path: None,
+            transforms_path: None,
+            ann: HnswParams::default(),
+            faq_path: None,
+            moderation_path: None,
+            federation: Vec::new(),
+            escalate: None,
+            webhooks: Vec::new(),
+            webhook_secret: None,
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            session_ttl: Duration::from_secs(30 * 60),
+            answer_cache: None,
+            answer_cache_url: None,
+            snapshot_interval: None,
+            snapshot_retention: Retention::default(),
+            non_interactive: !std::io::stdin().is_terminal(),
+            language: None,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    /// Settings from the config file at `path`, `TAPSSP_CONFIG` or
+    /// `./tapssp.toml` (if present, in that order) with the environment
+    /// applied on top
+    pub fn load(path: Option<&Path>, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let path = path.map(Path::to_path_buf).or_else(|| var("TAPSSP_CONFIG").filter(|v| !v.is_empty()).map(PathBuf::from));
+        let base = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(CONFIG_FILE).exists() => Self::from_file(Path::new(CONFIG_FILE))?,
+            None => RuntimeConfig::default(),
+        };
+        base.with_env(var)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).map_err(|e| anyhow!("Can't read config file {:?}: {}", path, e))?;
+        Self::parse_file(&text).map_err(|e| anyhow!("Invalid config file {:?}: {}", path, e))
+    }
+
+    fn parse_file(text: &str) -> Result<Self> {
+        let file: ConfigFile = toml::from_str(text)?;
+        let mut config = RuntimeConfig::default();
+        let ConfigFile { language, paths, ingest, collections, llm, limits, retriever, chunking, server } = file;
+        config.language = language;
+        config.data_dir = paths.data_dir;
+        config.model_path = paths.model;
+        config.index_path = paths.index;
+        config.docs_dir = paths.docs;
+        config.embedding_model = paths.embedding_model;
+        config.synonyms_path = paths.synonyms;
+        config.transforms_path = paths.transforms;
+        config.faq_path = paths.faq;
+        config.moderation_path = paths.moderation;
+        config.reranker = paths.reranker;
+        config.anonymize_terms = paths.anonymize_terms;
+
+        config.include = ingest.include;
+        config.exclude = ingest.exclude;
+        config.follow_symlinks = ingest.follow_symlinks.unwrap_or(config.follow_symlinks);
+        for (name, path) in collections {
+            config.add_collection(&name, path)?;
+        }
+
+        config.backend = llm.backend;
+        config.small_model = llm.small_model;
+        config.model_sha256 = llm.model_sha256.as_deref().map(parse_sha256).transpose()?;
+        config.ollama_url = llm.ollama_url.unwrap_or(config.ollama_url);
+        config.openai_url = llm.openai_url.unwrap_or(config.openai_url);
+        config.anonymize = llm.anonymize.as_deref().map(AnonymizeMode::parse).transpose()?;
+        config.prompt_template = llm.prompt_template.as_deref().map(PromptTemplate::parse).transpose()?;
+        config.max_tokens = llm.max_tokens;
+        config.temperature = llm.temperature;
+        config.top_p = llm.top_p;
+        config.repeat_penalty = llm.repeat_penalty;
+        config.context_size = llm.context_size.unwrap_or(config.context_size);
+        config.memory_reserve_mb = llm.memory_reserve_mb;
+        config.gpu_layers = llm.gpu_layers.unwrap_or(config.gpu_layers);
+        config.gpu_backend = llm.gpu_backend;
+        config.main_gpu = llm.main_gpu.unwrap_or(config.main_gpu);
+        if let Some(split) = &llm.tensor_split {
+            config.tensor_split = device::parse_tensor_split(split)?;
+        }
+
+        let defaults = &config.limits;
+        config.limits = GenerationLimits {
+            max_tokens: limits.max_tokens.unwrap_or(defaults.max_tokens),
+            max_temperature: limits.max_temperature.unwrap_or(defaults.max_temperature),
+            max_stop_sequences: limits.max_stop_sequences.unwrap_or(defaults.max_stop_sequences),
+            max_system_prompt_chars: limits.max_system_prompt_chars.unwrap_or(defaults.max_system_prompt_chars),
+        };
+
+        config.top_k = retriever.top_k;
+        config.search_mode = retriever.search_mode;
+        config.hybrid = retriever.hybrid;
+        config.route_documents = retriever.route_documents;
+        config.min_score = retriever.min_score;
+        config.mmr_lambda = retriever.mmr_lambda;
+        config.rerank_candidates = retriever.rerank_candidates.unwrap_or(config.rerank_candidates);
+        config.intent_llm = retriever.intent_llm.unwrap_or(config.intent_llm);
+        if let Some(name) = &retriever.embedding_device {
+            config.embedding_device = Device::parse(name)?;
+        }
+
+        let collection = &mut config.collection;
+        if let Some(size) = chunking.size {
+            collection.set("chunk-size", &size.to_string())?;
+        }
+        if let Some(overlap) = chunking.overlap {
+            collection.set("chunk-overlap", &overlap.to_string())?;
+        }
+        if let Some(strategy) = &chunking.strategy {
+            collection.set("chunk-strategy", strategy)?;
+        }
+        if let Some(stop_words) = chunking.stop_words {
+            collection.stop_words = stop_words;
+        }
+
+        config.host = server.host.unwrap_or(config.host);
+        config.port = server.port.unwrap_or(config.port);
+        config.answer_cache = server.answer_cache;
+        config.answer_cache_url = server.answer_cache_url;
+        Ok(config)
+    }
+
+    /// Reads `TAPSSP_DATA_DIR`, `TAPSSP_MODEL_PATH`, `TAPSSP_SMALL_MODEL`, `TAPSSP_MODEL_SHA256`, `TAPSSP_BACKEND`,
+    /// `OLLAMA_HOST` (as the Ollama CLI does), `OPENAI_BASE_URL` and `OPENAI_API_KEY` (as the OpenAI SDKs do), `TAPSSP_ANONYMIZE`, `TAPSSP_ANONYMIZE_TERMS`, `TAPSSP_PROMPT_TEMPLATE`, `TAPSSP_GPU_LAYERS`,
+    /// `TAPSSP_GPU_BACKEND`, `TAPSSP_MAIN_GPU`, `TAPSSP_TENSOR_SPLIT`, `TAPSSP_EMBEDDING_DEVICE`, `TAPSSP_CONTEXT_SIZE`, `TAPSSP_MEMORY_RESERVE_MB`, `TAPSSP_INDEX_PATH`,
+    /// `TAPSSP_COLLECTIONS` (comma-separated `NAME=PATH`), `TAPSSP_DOCS_DIR`, `TAPSSP_INCLUDE` and `TAPSSP_EXCLUDE` (comma-separated), `TAPSSP_FOLLOW_SYMLINKS`, `TAPSSP_EMBEDDING_MODEL`, `TAPSSP_EMBEDDING_VARIANTS`
+    /// (comma-separated), `TAPSSP_SEARCH_MODE`, `TAPSSP_HYBRID`, `TAPSSP_ROUTE_DOCUMENTS`, `TAPSSP_MIN_SCORE`, `TAPSSP_MMR_LAMBDA`, `TAPSSP_RERANKER`, `TAPSSP_RERANK_CANDIDATES`, `TAPSSP_INTENT_LLM`, `TAPSSP_SYNONYMS`, `TAPSSP_TRANSFORMS`, `TAPSSP_HNSW_M`,
+    /// `TAPSSP_HNSW_EF_CONSTRUCTION`, `TAPSSP_HNSW_EF_SEARCH`, `TAPSSP_FAQ_PATH`,
+    /// `TAPSSP_MODERATION`, `TAPSSP_FEDERATION` (comma-separated), `TAPSSP_ESCALATE`, `TAPSSP_WEBHOOKS` (comma-separated), `TAPSSP_WEBHOOK_SECRET`,
+    /// `TAPSSP_HOST`, `TAPSSP_PORT`, `TAPSSP_SESSION_TTL` (seconds), `TAPSSP_ANSWER_CACHE`, `TAPSSP_ANSWER_CACHE_URL`,
+    /// `TAPSSP_SNAPSHOT_INTERVAL` (seconds), `TAPSSP_SNAPSHOT_KEEP_LAST`,
+    /// `TAPSSP_SNAPSHOT_KEEP_DAILY`, `TAPSSP_SNAPSHOT_KEEP_WEEKLY` and
+    /// `TAPSSP_NON_INTERACTIVE`, `TAPSSP_MAX_TOKENS`, `TAPSSP_TEMPERATURE`,
+    /// `TAPSSP_TOP_P`, `TAPSSP_TOP_K`, the `TAPSSP_LIMIT_*` bounds of
+    /// `GenerationLimits` and `TAPSSP_LANG` through `var`, e.g.
+    /// `|k| std::env::var(k).ok()`, over the settings in `self`
+    fn with_env(self, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut config = self;
+        let path = |key: &str| var(key).filter(|v| !v.is_empty()).map(PathBuf::from);
+        let count = |key: &str, default: usize| match var(key).filter(|v| !v.is_empty()) {
+            Some(value) => value.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| anyhow!("{} must be a positive number, got '{}'", key, value)),
+            None => Ok(default),
+        };
+
+        config.data_dir = path("TAPSSP_DATA_DIR").or(config.data_dir);
+        config.model_path = path("TAPSSP_MODEL_PATH").or(config.model_path);
+        config.backend = var("TAPSSP_BACKEND").filter(|v| !v.is_empty()).or(config.backend);
+        config.small_model = var("TAPSSP_SMALL_MODEL").filter(|v| !v.is_empty()).or(config.small_model);
+        if let Some(hash) = var("TAPSSP_MODEL_SHA256").filter(|v| !v.is_empty()) {
+            config.model_sha256 = Some(parse_sha256(&hash)?);
+        }
+        if let Some(url) = var("OLLAMA_HOST").filter(|v| !v.is_empty()) {
+            config.ollama_url = url;
+        }
+        if let Some(url) = var("OPENAI_BASE_URL").filter(|v| !v.is_empty()) {
+            config.openai_url = url;
+        }
+        config.openai_api_key = var("OPENAI_API_KEY").filter(|v| !v.is_empty()).or(config.openai_api_key);
+        if let Some(mode) = var("TAPSSP_ANONYMIZE").filter(|v| !v.is_empty()) {
+            config.anonymize = Some(AnonymizeMode::parse(&mode)?);
+        }
+        config.anonymize_terms = path("TAPSSP_ANONYMIZE_TERMS").or(config.anonymize_terms);
+        if let Some(template) = var("TAPSSP_PROMPT_TEMPLATE").filter(|v| !v.is_empty()) {
+            config.prompt_template = Some(PromptTemplate::parse(&template)?);
+        }
+        config.index_path = path("TAPSSP_INDEX_PATH").or(config.index_path);
+        config.docs_dir = path("TAPSSP_DOCS_DIR").or(config.docs_dir);
+        if let Some(patterns) = var("TAPSSP_INCLUDE") {
+            config.include = patterns.split(',').map(str::trim).filter(|p| !p.is_empty()).map(String::from).collect();
+        }
+        if let Some(patterns) = var("TAPSSP_EXCLUDE") {
+            config.exclude = patterns.split(',').map(str::trim).filter(|p| !p.is_empty()).map(String::from).collect();
+        }
+        if let Some(flag) = var("TAPSSP_FOLLOW_SYMLINKS") {
+            config.follow_symlinks = matches!(flag.as_str(), "1" | "true" | "yes");
+        }
+        config.embedding_model = path("TAPSSP_EMBEDDING_MODEL").or(config.embedding_model);
+        if let Some(specs) = var("TAPSSP_COLLECTIONS") {
+            for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                config.add_collection_spec(spec)?;
+            }
+        }
+        if let Some(dirs) = var("TAPSSP_EMBEDDING_VARIANTS") {
+            config.embedding_variants = dirs.split(',').map(str::trim).filter(|d| !d.is_empty()).map(PathBuf::from).collect();
+        }
+        if let Some(layers) = var("TAPSSP_GPU_LAYERS").filter(|v| !v.is_empty()) {
+            config.gpu_layers = layers.parse()
+                .map_err(|_| anyhow!("TAPSSP_GPU_LAYERS must be a number, got '{}'", layers))?;
+        }
+        config.gpu_backend = var("TAPSSP_GPU_BACKEND").filter(|v| !v.is_empty()).or(config.gpu_backend);
+        if let Some(gpu) = var("TAPSSP_MAIN_GPU").filter(|v| !v.is_empty()) {
+            config.main_gpu = gpu.parse()
+                .map_err(|_| anyhow!("TAPSSP_MAIN_GPU must be a GPU number, got '{}'", gpu))?;
+        }
+        if let Some(split) = var("TAPSSP_TENSOR_SPLIT").filter(|v| !v.is_empty()) {
+            config.tensor_split = device::parse_tensor_split(&split)?;
+        }
+        if let Some(name) = var("TAPSSP_EMBEDDING_DEVICE").filter(|v| !v.is_empty()) {
+            config.embedding_device = Device::parse(&name)?;
+        }
+        config.context_size = count("TAPSSP_CONTEXT_SIZE", config.context_size)?;
+        if let Some(reserve) = var("TAPSSP_MEMORY_RESERVE_MB").filter(|v| !v.is_empty()) {
+            config.memory_reserve_mb = Some(reserve.parse()
+                .map_err(|_| anyhow!("TAPSSP_MEMORY_RESERVE_MB must be a number, got '{}'", reserve))?);
+        }
+        config.search_mode = var("TAPSSP_SEARCH_MODE").filter(|v| !v.is_empty()).or(config.search_mode);
+        config.hybrid = var("TAPSSP_HYBRID").filter(|v| !v.is_empty()).or(config.hybrid);
+        config.reranker = path("TAPSSP_RERANKER").or(config.reranker);
+        config.rerank_candidates = count("TAPSSP_RERANK_CANDIDATES", config.rerank_candidates)?;
+        config.synonyms_path = path("TAPSSP_SYNONYMS").or(config.synonyms_path);
+        config.transforms_path = path("TAPSSP_TRANSFORMS").or(config.transforms_path);
+        config.ann = HnswParams {
+            m: count("TAPSSP_HNSW_M", config.ann.m)?,
+            ef_construction: count("TAPSSP_HNSW_EF_CONSTRUCTION", config.ann.ef_construction)?,
+            ef_search: count("TAPSSP_HNSW_EF_SEARCH", config.ann.ef_search)?,
+        };
+        config.faq_path = path("TAPSSP_FAQ_PATH").or(config.faq_path);
+        config.moderation_path = path("TAPSSP_MODERATION").or(config.moderation_path);
+        if let Some(urls) = var("TAPSSP_FEDERATION") {
+            config.federation = urls.split(',').map(str::trim).filter(|u| !u.is_empty()).map(String::from).collect();
+        }
+        config.escalate = var("TAPSSP_ESCALATE").filter(|v| !v.is_empty()).or(config.escalate);
+        if let Some(urls) = var("TAPSSP_WEBHOOKS") {
+            config.webhooks = urls.split(',').map(str::trim).filter(|u| !u.is_empty()).map(String::from).collect();
+        }
+        config.webhook_secret = var("TAPSSP_WEBHOOK_SECRET").filter(|v| !v.is_empty()).or(config.webhook_secret);
+        if let Some(host) = var("TAPSSP_HOST") {
+            config.host = host;
+        }
+        if let Some(port) = var("TAPSSP_PORT") {
+            config.port = port.parse()
+                .map_err(|_| anyhow!("TAPSSP_PORT must be a port number, got '{}'", port))?;
+        }
+        config.session_ttl = Duration::from_secs(count("TAPSSP_SESSION_TTL", config.session_ttl.as_secs() as usize)? as u64);
+        if var("TAPSSP_ANSWER_CACHE").is_some_and(|v| !v.is_empty()) {
+            config.answer_cache = Some(count("TAPSSP_ANSWER_CACHE", 0)?);
+        }
+        config.answer_cache_url = var("TAPSSP_ANSWER_CACHE_URL").filter(|v| !v.is_empty()).or(config.answer_cache_url);
+        if var("TAPSSP_SNAPSHOT_INTERVAL").is_some_and(|v| !v.is_empty()) {
+            config.snapshot_interval = Some(Duration::from_secs(count("TAPSSP_SNAPSHOT_INTERVAL", 0)? as u64));
+        }
+        let retention = config.snapshot_retention;
+        config.snapshot_retention = Retention {
+            last: count("TAPSSP_SNAPSHOT_KEEP_LAST", retention.last)?,
+            daily: count("TAPSSP_SNAPSHOT_KEEP_DAILY", retention.daily)?,
+            weekly: count("TAPSSP_SNAPSHOT_KEEP_WEEKLY", retention.weekly)?,
+        };
+        if let Some(flag) = var("TAPSSP_INTENT_LLM") {
+            config.intent_llm = matches!(flag.as_str(), "1" | "true" | "yes");
+        }
+        if let Some(flag) = var("TAPSSP_NON_INTERACTIVE") {
+            config.non_interactive = matches!(flag.as_str(), "1" | "true" | "yes");
+        }
+        if var("TAPSSP_MAX_TOKENS").is_some_and(|v| !v.is_empty()) {
+            config.max_tokens = Some(count("TAPSSP_MAX_TOKENS", 0)?);
+        }
+        if var("TAPSSP_ROUTE_DOCUMENTS").is_some_and(|v| !v.is_empty()) {
+            config.route_documents = Some(count("TAPSSP_ROUTE_DOCUMENTS", 0)?);
+        }
+        if var("TAPSSP_TOP_K").is_some_and(|v| !v.is_empty()) {
+            config.top_k = Some(count("TAPSSP_TOP_K", 0)?);
+        }
+        let float = |key: &str| var(key).filter(|v| !v.is_empty()).map(|value| {
+            value.parse::<f32>().map_err(|_| anyhow!("{} must be a number, got '{}'", key, value))
+        }).transpose();
+        config.temperature = float("TAPSSP_TEMPERATURE")?.or(config.temperature);
+        config.limits = GenerationLimits {
+            max_tokens: count("TAPSSP_LIMIT_MAX_TOKENS", config.limits.max_tokens)?,
+            max_temperature: float("TAPSSP_LIMIT_MAX_TEMPERATURE")?.unwrap_or(config.limits.max_temperature),
+            max_stop_sequences: count("TAPSSP_LIMIT_MAX_STOP_SEQUENCES", config.limits.max_stop_sequences)?,
+            max_system_prompt_chars: count("TAPSSP_LIMIT_MAX_SYSTEM_PROMPT_CHARS", config.limits.max_system_prompt_chars)?,
+        };
+        config.top_p = float("TAPSSP_TOP_P")?.or(config.top_p);
+        config.min_score = float("TAPSSP_MIN_SCORE")?.or(config.min_score);
+        config.mmr_lambda = float("TAPSSP_MMR_LAMBDA")?.or(config.mmr_lambda);
+        config.language = var("TAPSSP_LANG").filter(|v| !v.is_empty()).or(config.language);
+        Ok(config)
+    }
+
+    /// Serves the index at `path` as collection `name`
+    pub fn add_collection(&mut self, name: &str, path: PathBuf) -> Result<()> {
+        if name.is_empty() || name == DEFAULT_COLLECTION {
+            return Err(anyhow!("'{}' can't name a collection; the main index is '{}'", name, DEFAULT_COLLECTION));
+        }
+        self.collections.insert(name.to_string(), path);
+        Ok(())
+    }
+
+    /// `add_collection` from `NAME=PATH`, as given to `--collection`
+    pub fn add_collection_spec(&mut self, spec: &str) -> Result<()> {
+        let (name, path) = spec.split_once('=')
+            .ok_or_else(|| anyhow!("Collections are given as NAME=PATH, got '{}'", spec))?;
+        self.add_collection(name.trim(), PathBuf::from(path.trim()))
+    }
+
+    /// Where the default model is downloaded to
+    pub fn models_dir(&self) -> Result<PathBuf> {
+        self.app_dir(dirs::cache_dir()).map(|dir| dir.join("models"))
+    }
+
+    /// Where saved queries (`save-query`) are stored
+    pub fn queries_dir(&self) -> Result<PathBuf> {
+        self.app_dir(dirs::config_dir()).map(|dir| dir.join("queries"))
+    }
+
+    /// Where chat sessions recorded with `--session` are kept
+    pub fn sessions_dir(&self) -> Result<PathBuf> {
+        self.app_dir(dirs::data_dir()).map(|dir| dir.join("sessions"))
+    }
+
+    /// Where snapshots of the index at `index_path` and the sessions are kept
+    pub fn snapshots_dir(&self, index_path: &std::path::Path) -> Result<PathBuf> {
+        let name = index_path.file_name().map_or("index".into(), |name| name.to_string_lossy());
+        self.app_dir(dirs::data_dir()).map(|dir| dir.join("snapshots").join(name.as_ref()))
+    }
+
+    /// Where the REPL user profile (`/profile set`) is kept
+    pub fn profile_path(&self) -> Result<PathBuf> {
+        self.app_dir(dirs::config_dir()).map(|dir| dir.join("profile.json"))
+    }
+
+    /// Explicit index path, or `index.bin` under the data directory
+    pub fn index_path(&self) -> Option<PathBuf> {
+        self.index_path
+            .clone()
+            .or_else(|| self.data_dir.as_ref().map(|dir| dir.join("index.bin")))
+    }
+
+    /// Include and exclude patterns for the docs directory
+    pub fn file_filter(&self) -> FileFilter {
+        FileFilter { include: self.include.clone(), exclude: self.exclude.clone(), follow_symlinks: self.follow_symlinks }
+    }
+
+    /// Explicit docs directory, `docs` under the data directory, or `./docs`
+    pub fn docs_dir(&self) -> PathBuf {
+        self.docs_dir
+            .clone()
+            .or_else(|| self.data_dir.as_ref().map(|dir| dir.join("docs")))
+            .unwrap_or_else(|| PathBuf::from("docs"))
+    }
+
+    /// Explicit FAQ file, or `faq.json` under the data directory if present
+    pub fn faq_path(&self) -> Option<PathBuf> {
+        self.faq_path.clone().or_else(|| {
+            self.data_dir
+                .as_ref()
+                .map(|dir| dir.join("faq.json"))
+                .filter(|path| path.exists())
+        })
+    }
+
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    pub fn listen_addr(&self) -> Result<SocketAddr> {
+        format!("{}:{}", self.host, self.port)
+            .parse()
+            .map_err(|_| anyhow!("Invalid listen address {}:{}", self.host, self.port))
+    }
+
+    fn app_dir(&self, user_dir: Option<PathBuf>) -> Result<PathBuf> {
+        match &self.data_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => user_dir
+                .map(|dir| dir.join("tapssp-project"))
+                .ok_or_else(|| anyhow!("No home directory available; set --data-dir or TAPSSP_DATA_DIR")),
+        }
+    }
+}
+
+/// A pinned model hash: 64 hex digits, compared in lowercase
+fn parse_sha256(text: &str) -> Result<String> {
+    let hash = text.trim().to_lowercase();
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("model_sha256 must be 64 hex digits, got '{}'", text));
+    }
+    Ok(hash)
+}
+
+/// Layout of `tapssp.toml`. Every key is optional:
+///
+/// ```toml
+/// language = "de"
+///
+/// [paths]
+/// data_dir = "/data"
+/// model = "models/mistral-7b.gguf"
+/// index = "index.bin"
+///
+/// [ingest]
+/// include = ["**/*.md"]
+/// exclude = ["drafts"]
+///
+/// [collections]
+/// handbook = "handbook.bin"
+///
+/// [llm]
+/// max_tokens = 512
+/// temperature = 0.3
+/// gpu_layers = 35
+///
+/// [limits]
+/// max_tokens = 2048
+/// max_temperature = 1.5
+///
+/// [retriever]
+/// top_k = 5
+/// hybrid = "rrf"
+/// route_documents = 3
+///
+/// [chunking]
+/// size = 800
+/// overlap = 100
+/// strategy = "recursive"
+///
+/// [server]
+/// port = 9000
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct ConfigFile {
+    language: Option<String>,
+    paths: PathsSection,
+    ingest: IngestSection,
+    collections: BTreeMap<String, PathBuf>,
+    llm: LlmSection,
+    limits: LimitsSection,
+    retriever: RetrieverSection,
+    chunking: ChunkingSection,
+    server: ServerSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct PathsSection {
+    data_dir: Option<PathBuf>,
+    model: Option<PathBuf>,
+    index: Option<PathBuf>,
+    docs: Option<PathBuf>,
+    embedding_model: Option<PathBuf>,
+    synonyms: Option<PathBuf>,
+    transforms: Option<PathBuf>,
+    faq: Option<PathBuf>,
+    moderation: Option<PathBuf>,
+    reranker: Option<PathBuf>,
+    anonymize_terms: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct LlmSection {
+    backend: Option<String>,
+    small_model: Option<String>,
+    /// Pinned hash of the downloaded default model
+    model_sha256: Option<String>,
+    /// `strip` or `pseudonymize`
+    anonymize: Option<String>,
+    /// A preset name or the path of a template file
+    prompt_template: Option<String>,
+    ollama_url: Option<String>,
+    openai_url: Option<String>,
+    max_tokens: Option<usize>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    repeat_penalty: Option<f32>,
+    context_size: Option<usize>,
+    memory_reserve_mb: Option<u64>,
+    gpu_layers: Option<usize>,
+    gpu_backend: Option<String>,
+    main_gpu: Option<usize>,
+    /// Comma-separated shares, as with `--tensor-split`
+    tensor_split: Option<String>,
+}
+
+/// Bounds on per-request generation overrides, see `GenerationLimits`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct LimitsSection {
+    max_tokens: Option<usize>,
+    max_temperature: Option<f32>,
+    max_stop_sequences: Option<usize>,
+    max_system_prompt_chars: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct RetrieverSection {
+    top_k: Option<usize>,
+    search_mode: Option<String>,
+    hybrid: Option<String>,
+    route_documents: Option<usize>,
+    min_score: Option<f32>,
+    mmr_lambda: Option<f32>,
+    rerank_candidates: Option<usize>,
+    intent_llm: Option<bool>,
+    embedding_device: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct IngestSection {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    follow_symlinks: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct ChunkingSection {
+    size: Option<usize>,
+    overlap: Option<usize>,
+    strategy: Option<String>,
+    stop_words: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+struct ServerSection {
+    host: Option<String>,
+    port: Option<u16>,
+    answer_cache: Option<usize>,
+    answer_cache_url: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_from_env_with_data_dir() -> Result<()> {
+        let env: HashMap<&str, &str> = [
+            ("TAPSSP_DATA_DIR", "/data"),
+            ("TAPSSP_PORT", "9000"),
+            ("TAPSSP_HOST", "0.0.0.0"),
+            ("TAPSSP_NON_INTERACTIVE", "1"),
+            ("TAPSSP_GPU_LAYERS", "35"),
+        ].into_iter().collect();
+        let config = RuntimeConfig::default().with_env(|key| env.get(key).map(|v| v.to_string()))?;
+
+        assert_eq!(config.models_dir()?, PathBuf::from("/data/models"));
+        assert_eq!(config.index_path(), Some(PathBuf::from("/data/index.bin")));
+        assert_eq!(config.docs_dir(), PathBuf::from("/data/docs"));
+        assert_eq!(config.listen_addr()?.port(), 9000);
+        assert!(config.non_interactive);
+        assert_eq!((config.gpu_layers, config.context_size), (35, 2048));
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_then_env() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(CONFIG_FILE);
+        fs::write(&path, "[paths]\nmodel = \"m.gguf\"\n\n[llm]\ntemperature = 0.3\n\n[retriever]\ntop_k = 5\n\n\
+            [chunking]\nsize = 800\nstrategy = \"recursive\"\n\n[server]\nport = 9000\n\n[collections]\nhandbook = \"handbook.bin\"\n")?;
+        let env: HashMap<&str, &str> = [("TAPSSP_PORT", "9100"), ("TAPSSP_TOP_K", "7"), ("TAPSSP_COLLECTIONS", "code=code.bin")].into_iter().collect();
+        let config = RuntimeConfig::load(Some(&path), |key| env.get(key).map(|v| v.to_string()))?;
+
+        assert_eq!(config.model_path, Some(PathBuf::from("m.gguf")));
+        assert_eq!((config.temperature, config.top_k, config.port), (Some(0.3), Some(7), 9100));
+        assert_eq!(config.collection.chunk_size, Some(800));
+        assert_eq!(config.collections.keys().collect::<Vec<_>>(), ["code", "handbook"]);
+        assert_eq!(config.collection.to_string(), {
+            let mut expected = CollectionSettings::default();
+            expected.set("chunk-size", "800")?;
+            expected.set("chunk-strategy", "recursive")?;
+            expected.to_string()
+        });
+
+        assert!(RuntimeConfig::parse_file("[llm]\ntemprature = 0.3\n").is_err());
+        assert!(RuntimeConfig::parse_file("[chunking]\nsize = 100\noverlap = 200\n").is_err());
+        assert!(RuntimeConfig::parse_file("[collections]\ndefault = \"other.bin\"\n").is_err());
+        assert!(RuntimeConfig::parse_file("[llm]\nmodel_sha256 = \"abc\"\n").is_err());
+        let pinned = RuntimeConfig::parse_file(&format!("[llm]\nmodel_sha256 = \"{}\"\n", "AB".repeat(32)))?;
+        assert_eq!(pinned.model_sha256, Some("ab".repeat(32)));
+
+        let limited = RuntimeConfig::parse_file("[limits]\nmax_tokens = 1024\n")?
+            .with_env(|key| (key == "TAPSSP_LIMIT_MAX_TEMPERATURE").then(|| "1.5".to_string()))?;
+        assert_eq!((limited.limits.max_tokens, limited.limits.max_temperature), (1024, 1.5));
+        assert_eq!(limited.limits.max_stop_sequences, GenerationLimits::default().max_stop_sequences);
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_env_rejects_bad_port() {
+        let result = RuntimeConfig::default().with_env(|key| (key == "TAPSSP_PORT").then(|| "http".to_string()));
+        assert!(result.is_err());
+    }
+}
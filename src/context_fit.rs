@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+
+use crate::highlight::sentence_spans;
+
+/// Prompt tokens taken by the `<document>` tags around each chunk
+const DOCUMENT_TAG_TOKENS: usize = 8;
+/// A chunk is only cut down to a region of at least this many tokens; with
+/// less budget left, it and any later chunks are left out
+const MIN_REGION_TOKENS: usize = 32;
+/// Marks where a chunk was cut
+const ELLIPSIS: &str = "...";
+
+/// Fits ranked `chunks` into `budget` prompt tokens as counted by `count`.
+/// Chunks are kept whole while they fit; one too large for the budget
+/// left, such as a huge chunk from a legacy index, is cut down to the run of
+/// sentences that mentions the most query terms instead of overflowing the
+/// context window. Once too little budget is left, the remaining, lowest
+/// ranked chunks are dropped.
+pub fn fit(query: &str, chunks: Vec<String>, budget: usize, count: &dyn Fn(&str) -> usize) -> Vec<String> {
+    let terms = terms(query);
+    let mut remaining = budget;
+    let mut fitted = Vec::with_capacity(chunks.len());
+    for chunk in chunks {
+        let available = remaining.saturating_sub(DOCUMENT_TAG_TOKENS);
+        let tokens = count(&chunk);
+        let chunk = if tokens <= available {
+            chunk
+        } else if available >= MIN_REGION_TOKENS {
+            tracing::debug!(tokens, available, "re-splitting oversized chunk");
+            best_region(&terms, &chunk, available, count)
+        } else {
+            break;
+        };
+        // Cutting by sentence can't always get under a tokenizer's count
+        let Some(left) = available.checked_sub(count(&chunk)) else {
+            break;
+        };
+        remaining = left;
+        fitted.push(chunk);
+    }
+    fitted
+}
+
+/// The contiguous sentences of `chunk` within `max_tokens` that contain the
+/// most query terms, earliest first on ties
+fn best_region(terms: &HashSet<String>, chunk: &str, max_tokens: usize, count: &dyn Fn(&str) -> usize) -> String {
+    let spans = sentence_spans(chunk);
+    let scores: Vec<usize> = spans.iter().map(|span| self::terms(&chunk[span.clone()]).intersection(terms).count()).collect();
+    // Room for an ellipsis at each end
+    let max_tokens = max_tokens.saturating_sub(2 * count(ELLIPSIS));
+
+    let mut best: Option<(usize, std::ops::Range<usize>)> = None;
+    let (mut end, mut score) = (0, 0);
+    for start in 0..spans.len() {
+        while end < spans.len() && count(&chunk[spans[start].start..spans[end].end]) <= max_tokens {
+            score += scores[end];
+            end += 1;
+        }
+        if end > start && best.as_ref().is_none_or(|(best_score, _)| score > *best_score) {
+            best = Some((score, start..end));
+        }
+        if end > start {
+            score -= scores[start];
+        } else {
+            end = start + 1;
+        }
+    }
+
+    let (text, start, end) = match best {
+        Some((_, sentences)) => {
+            let (start, end) = (spans[sentences.start].start, spans[sentences.end - 1].end);
+            (chunk[start..end].to_string(), start, end)
+        }
+        // Not even one sentence fits: keep the start of the best one
+        None => {
+            let span = (0..spans.len()).max_by_key(|&i| (scores[i], std::cmp::Reverse(i))).map_or(0..chunk.len(), |i| spans[i].clone());
+            let text: String = chunk[span.clone()].chars().take(max_tokens * 4).collect();
+            let end = span.start + text.len();
+            (text, span.start, end)
+        }
+    };
+    let prefix = if chunk[..start].trim().is_empty() { "" } else { "... " };
+    let suffix = if chunk[end..].trim().is_empty() { "" } else { " ..." };
+    format!("{}{}{}", prefix, text, suffix)
+}
+
+fn terms(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 3)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::estimate_tokens;
+
+    #[test]
+    fn test_oversized_chunk_is_cut_around_matching_sentences() {
+        let filler = "Our offices are closed on public holidays. ".repeat(40);
+        let legacy = format!("{}Refunds are paid within thirty days of the return. {}", filler, filler);
+        let small = "Shipping takes two days.".to_string();
+
+        // Everything fits: nothing changes
+        let chunks = vec![small.clone(), legacy.clone()];
+        assert_eq!(fit("refund days", chunks.clone(), 2000, &estimate_tokens), chunks);
+
+        let fitted = fit("How many days until refunds are paid?", vec![small.clone(), legacy.clone()], 200, &estimate_tokens);
+        assert_eq!(fitted[0], small);
+        assert!(fitted[1].contains("Refunds are paid within thirty days"));
+        assert!(fitted[1].starts_with("... ") && fitted[1].ends_with(" ..."));
+        let used: usize = fitted.iter().map(|chunk| estimate_tokens(chunk) + DOCUMENT_TAG_TOKENS).sum();
+        assert!(used <= 200);
+
+        // Too little budget left for a useful region
+        assert_eq!(fit("refunds", vec![legacy.clone(), small.clone()], 20, &estimate_tokens), Vec::<String>::new());
+
+        // A tokenizer that counts more tokens than the estimate drops the
+        // lowest ranked chunks first
+        let words = |text: &str| text.split_whitespace().count() * 2;
+        let fitted = fit("refunds", vec![small.clone(), "Returns need a receipt.".to_string(), legacy], 40, &words);
+        assert_eq!(fitted, vec![small, "Returns need a receipt.".to_string()]);
+    }
+}
@@ -0,0 +1,141 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::escalation::Outcome;
+use crate::llm::Conversation;
+
+/// One question and answer of a server-side conversation
+#[derive(Debug, Clone, Serialize)]
+pub struct Exchange {
+    pub question: String,
+    pub answer: String,
+    pub outcome: Outcome,
+}
+
+/// Why a conversation couldn't be checked out
+#[derive(Debug, PartialEq)]
+pub enum CheckoutError {
+    /// Unknown, deleted or expired
+    NotFound,
+    /// Another message of the conversation is still being answered
+    Busy,
+}
+
+struct Entry {
+    /// History as given to the model; older turns get summarized
+    conversation: Conversation,
+    /// Every exchange verbatim, for clients reading the history back
+    exchanges: Vec<Exchange>,
+    last_used: Instant,
+    busy: bool,
+}
+
+/// Conversations of HTTP clients, kept in memory and dropped after `ttl`
+/// without activity. A message is answered by checking the conversation
+/// out, generating without holding the lock, and checking it back in.
+pub struct ConversationStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl ConversationStore {
+    pub fn new(ttl: Duration) -> Self {
+        ConversationStore { ttl, entries: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Starts an empty conversation and returns its ID
+    pub fn create(&self) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let entry = Entry {
+            conversation: Conversation::default(),
+            exchanges: Vec::new(),
+            last_used: Instant::now(),
+            busy: false,
+        };
+        self.lock().insert(id.clone(), entry);
+        id
+    }
+
+    pub fn history(&self, id: &str) -> Option<Vec<Exchange>> {
+        let mut entries = self.lock();
+        let entry = entries.get_mut(id)?;
+        entry.last_used = Instant::now();
+        Some(entry.exchanges.clone())
+    }
+
+    /// Whether the conversation existed
+    pub fn delete(&self, id: &str) -> bool {
+        self.lock().remove(id).is_some()
+    }
+
+    /// The model history of conversation `id`, marking it busy until
+    /// `check_in` or `release`
+    pub fn check_out(&self, id: &str) -> Result<Conversation, CheckoutError> {
+        let mut entries = self.lock();
+        let entry = entries.get_mut(id).ok_or(CheckoutError::NotFound)?;
+        if entry.busy {
+            return Err(CheckoutError::Busy);
+        }
+        entry.busy = true;
+        entry.last_used = Instant::now();
+        Ok(entry.conversation.clone())
+    }
+
+    /// Stores the updated history after a new exchange
+    pub fn check_in(&self, id: &str, conversation: Conversation, exchange: Exchange) {
+        if let Some(entry) = self.lock().get_mut(id) {
+            entry.conversation = conversation;
+            entry.exchanges.push(exchange);
+            entry.last_used = Instant::now();
+            entry.busy = false;
+        }
+    }
+
+    /// Ends a checkout that produced no answer
+    pub fn release(&self, id: &str) {
+        if let Some(entry) = self.lock().get_mut(id) {
+            entry.busy = false;
+        }
+    }
+
+    /// Locks the map after dropping idle conversations. Busy ones are kept
+    /// even when past their TTL, since an answer is about to arrive.
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        let mut entries = self.entries.lock().expect("conversation store lock poisoned");
+        let now = Instant::now();
+        entries.retain(|_, entry| entry.busy || now.duration_since(entry.last_used) < self.ttl);
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(question: &str) -> Exchange {
+        Exchange { question: question.to_string(), answer: "Yes.".to_string(), outcome: Outcome::Answered }
+    }
+
+    #[test]
+    fn test_checkout_and_expiry() {
+        let store = ConversationStore::new(Duration::from_millis(50));
+        let id = store.create();
+
+        let mut conversation = store.check_out(&id).unwrap();
+        assert_eq!(store.check_out(&id).unwrap_err(), CheckoutError::Busy);
+        conversation.push("Is it open?", "Yes.");
+        store.check_in(&id, conversation, exchange("Is it open?"));
+        assert_eq!(store.history(&id).unwrap().len(), 1);
+
+        std::thread::sleep(Duration::from_millis(80));
+        assert!(store.history(&id).is_none());
+        assert_eq!(store.check_out(&id).unwrap_err(), CheckoutError::NotFound);
+        assert!(!store.delete(&id));
+    }
+}
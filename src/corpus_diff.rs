@@ -0,0 +1,174 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::vector_db::VectorDB;
+
+/// Terms listed per side of the vocabulary drift
+const MAX_TERMS_SHOWN: usize = 20;
+/// Characters of each document shown
+const PREVIEW_CHARS: usize = 100;
+
+/// What changed between two versions of an index, for auditing sync jobs.
+/// Documents are matched by content, since a rebuild gives every chunk a new
+/// ID; a document whose ID survived with new text counts as changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct CorpusDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// ID, old content, new content
+    pub changed: Vec<(String, String, String)>,
+    /// Index terms only the new version has
+    pub new_terms: Vec<String>,
+    /// Index terms only the old version has
+    pub dropped_terms: Vec<String>,
+    pub old_vocabulary: usize,
+    pub new_vocabulary: usize,
+}
+
+impl CorpusDiff {
+    pub fn between(old: &VectorDB, new: &VectorDB) -> Self {
+        let old_docs: HashMap<&str, &str> =
+            old.documents().map(|doc| (doc.id.as_str(), doc.content.as_str())).collect();
+        let mut changed: Vec<(String, String, String)> = new
+            .documents()
+            .filter_map(|doc| {
+                let old_content = old_docs.get(doc.id.as_str())?;
+                (*old_content != doc.content)
+                    .then(|| (doc.id.clone(), old_content.to_string(), doc.content.clone()))
+            })
+            .collect();
+        changed.sort();
+
+        // Remaining documents are compared as a multiset of contents
+        let is_changed = |id: &str| changed.iter().any(|(changed_id, _, _)| changed_id == id);
+        let mut counts: HashMap<&str, isize> = HashMap::new();
+        for doc in new.documents().filter(|doc| !is_changed(&doc.id)) {
+            *counts.entry(&doc.content).or_default() += 1;
+        }
+        for doc in old.documents().filter(|doc| !is_changed(&doc.id)) {
+            *counts.entry(&doc.content).or_default() -= 1;
+        }
+        let (mut added, mut removed) = (Vec::new(), Vec::new());
+        for (content, count) in counts {
+            let side = if count > 0 { &mut added } else { &mut removed };
+            side.extend(std::iter::repeat_n(content.to_string(), count.unsigned_abs()));
+        }
+        added.sort();
+        removed.sort();
+
+        let (old_terms, new_terms) = (terms(old), terms(new));
+        CorpusDiff {
+            added,
+            removed,
+            changed,
+            new_terms: new_terms.difference(&old_terms).cloned().collect(),
+            dropped_terms: old_terms.difference(&new_terms).cloned().collect(),
+            old_vocabulary: old_terms.len(),
+            new_vocabulary: new_terms.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Distinct index terms of all documents, as `db` tokenizes them
+fn terms(db: &VectorDB) -> BTreeSet<String> {
+    db.documents()
+        .flat_map(|doc| db.tokenize_with_spans(&doc.content))
+        .map(|token| token.term)
+        .collect()
+}
+
+fn preview(text: &str) -> String {
+    let line = text.replace('\n', " ");
+    match line.char_indices().nth(PREVIEW_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line,
+    }
+}
+
+fn term_list(terms: &[String]) -> String {
+    let mut list = terms.iter().take(MAX_TERMS_SHOWN).cloned().collect::<Vec<_>>().join(", ");
+    if terms.len() > MAX_TERMS_SHOWN {
+        list.push_str(&format!(", ... ({} more)", terms.len() - MAX_TERMS_SHOWN));
+    }
+    list
+}
+
+impl fmt::Display for CorpusDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            writeln!(f, "No documents added, removed or changed")?;
+        } else {
+            writeln!(
+                f,
+                "{} added, {} removed, {} changed documents",
+                self.added.len(),
+                self.removed.len(),
+                self.changed.len()
+            )?;
+        }
+        for content in &self.added {
+            writeln!(f, "+ {}", preview(content))?;
+        }
+        for content in &self.removed {
+            writeln!(f, "- {}", preview(content))?;
+        }
+        for (id, old, new) in &self.changed {
+            writeln!(f, "~ {}\n    was: {}\n    now: {}", id, preview(old), preview(new))?;
+        }
+        write!(
+            f,
+            "Vocabulary: {} -> {} terms, {} new, {} dropped",
+            self.old_vocabulary,
+            self.new_vocabulary,
+            self.new_terms.len(),
+            self.dropped_terms.len()
+        )?;
+        if !self.new_terms.is_empty() {
+            write!(f, "\n  new: {}", term_list(&self.new_terms))?;
+        }
+        if !self.dropped_terms.is_empty() {
+            write!(f, "\n  dropped: {}", term_list(&self.dropped_terms))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_diff_matches_documents_by_content() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("old.bin");
+        let mut old = VectorDB::new();
+        old.add_document("Refunds take thirty days".to_string())?;
+        old.add_document("Orders ship on Monday".to_string())?;
+        let id = old.add_document("Support answers email".to_string())?;
+        old.save(&path)?;
+
+        // A rebuild gives unchanged documents new IDs
+        let mut rebuilt = VectorDB::new();
+        rebuilt.add_document("Refunds take thirty days".to_string())?;
+        rebuilt.add_document("Warehouse closes Friday".to_string())?;
+        let diff = CorpusDiff::between(&old, &rebuilt);
+        assert_eq!(diff.added, ["Warehouse closes Friday"]);
+        assert_eq!(diff.removed, ["Orders ship on Monday", "Support answers email"]);
+        assert!(diff.changed.is_empty());
+        assert_eq!(diff.new_terms, ["closes", "friday", "warehouse"]);
+        assert!(diff.dropped_terms.contains(&"monday".to_string()));
+
+        let mut edited = VectorDB::load(&path)?;
+        edited.update_document(&id, "Support answers chat".to_string())?;
+        let diff = CorpusDiff::between(&old, &edited);
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(diff.changed, [(id, "Support answers email".to_string(), "Support answers chat".to_string())]);
+        assert_eq!((diff.new_terms, diff.dropped_terms), (vec!["chat".to_string()], vec!["email".to_string()]));
+        Ok(())
+    }
+}
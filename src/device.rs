@@ -0,0 +1,93 @@
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+/// Where the embedding and reranking models run: `cpu`, `cuda:N` or
+/// `metal:N` (`cuda` and `metal` alone mean device 0). The generator is
+/// placed separately, with `--main-gpu` and `--tensor-split`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Device {
+    #[default]
+    Cpu,
+    Cuda(usize),
+    Metal(usize),
+}
+
+impl Device {
+    pub fn parse(name: &str) -> Result<Self> {
+        let name = name.trim().to_lowercase();
+        let (kind, ordinal) = match name.split_once(':') {
+            Some((kind, ordinal)) => {
+                let ordinal = ordinal.parse().map_err(|_| anyhow!("Invalid device number in '{}'", name))?;
+                (kind, ordinal)
+            }
+            None => (name.as_str(), 0),
+        };
+        match kind {
+            "cpu" if ordinal == 0 => Ok(Device::Cpu),
+            "cuda" => Ok(Device::Cuda(ordinal)),
+            "metal" => Ok(Device::Metal(ordinal)),
+            _ => Err(anyhow!("Unknown device '{}' (cpu, cuda:N or metal:N)", name)),
+        }
+    }
+
+    /// The candle device, which fails when its backend isn't compiled in
+    /// (the `cuda` or `metal` feature together with `candle`) or the GPU
+    /// doesn't exist
+    #[cfg(feature = "candle")]
+    pub fn candle(self) -> Result<candle_core::Device> {
+        let device = match self {
+            Device::Cpu => Ok(candle_core::Device::Cpu),
+            Device::Cuda(ordinal) => candle_core::Device::new_cuda(ordinal),
+            Device::Metal(ordinal) => candle_core::Device::new_metal(ordinal),
+        };
+        device.map_err(|e| anyhow!("Can't use device {}: {}", self, e))
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Device::Cpu => write!(f, "cpu"),
+            Device::Cuda(ordinal) => write!(f, "cuda:{}", ordinal),
+            Device::Metal(ordinal) => write!(f, "metal:{}", ordinal),
+        }
+    }
+}
+
+/// Proportions of the offloaded layers each GPU takes, from a list such as
+/// `3,1` (three quarters on GPU 0, a quarter on GPU 1), normalized to sum
+/// to 1
+pub fn parse_tensor_split(text: &str) -> Result<Vec<f32>> {
+    let shares = text
+        .split(',')
+        .map(|share| share.trim().parse::<f32>().ok().filter(|share| share.is_finite() && *share >= 0.0))
+        .collect::<Option<Vec<f32>>>()
+        .ok_or_else(|| anyhow!("Invalid tensor split '{}': expected non-negative numbers such as 3,1", text))?;
+    let total: f32 = shares.iter().sum();
+    if total <= 0.0 {
+        return Err(anyhow!("Invalid tensor split '{}': at least one GPU needs a share", text));
+    }
+    Ok(shares.into_iter().map(|share| share / total).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_devices_and_splits_parse() -> Result<()> {
+        assert_eq!(Device::parse("CUDA:1")?, Device::Cuda(1));
+        assert_eq!(Device::parse("metal")?, Device::Metal(0));
+        assert_eq!(Device::parse("cpu")?.to_string(), "cpu");
+        assert!(Device::parse("cpu:1").is_err());
+        assert!(Device::parse("cuda:x").is_err());
+        assert!(Device::parse("tpu").is_err());
+
+        assert_eq!(parse_tensor_split("3, 1")?, vec![0.75, 0.25]);
+        assert_eq!(parse_tensor_split("0,2")?, vec![0.0, 1.0]);
+        assert!(parse_tensor_split("0,0").is_err());
+        assert!(parse_tensor_split("1,-1").is_err());
+        assert!(parse_tensor_split("half").is_err());
+        Ok(())
+    }
+}
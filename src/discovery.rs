@@ -0,0 +1,170 @@
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Which files under a documents directory are ingested. Patterns are
+/// globs relative to the directory: `*` and `?` match within one path
+/// segment, `**` matches any number of segments, and a pattern without `/`
+/// matches file and directory names at any depth.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    /// Files must match one of these; all files do when empty
+    pub include: Vec<String>,
+    /// Files, and directories whose contents are skipped, matching any
+    pub exclude: Vec<String>,
+    /// Descend into symlinked directories; symlinked files are always read
+    pub follow_symlinks: bool,
+}
+
+impl FileFilter {
+    /// Whether the file at `path` under `dir` is ingested: it's included,
+    /// and neither it nor a directory on the way is excluded or hidden
+    pub fn accepts(&self, dir: &Path, path: &Path) -> bool {
+        let Some(segments) = relative_segments(dir, path) else {
+            return false;
+        };
+        let Some((_, parents)) = segments.split_last() else {
+            return false;
+        };
+        if (1..=parents.len()).any(|depth| self.skips_dir(&segments[..depth])) {
+            return false;
+        }
+        let included = self.include.is_empty() || self.include.iter().any(|pattern| glob_match(pattern, &segments));
+        included && !self.excluded(&segments)
+    }
+
+    /// Whether the directory at `segments` and everything in it is left out
+    fn skips_dir(&self, segments: &[String]) -> bool {
+        segments.last().is_some_and(|name| name.starts_with('.')) || self.excluded(segments)
+    }
+
+    fn excluded(&self, segments: &[String]) -> bool {
+        self.exclude.iter().any(|pattern| glob_match(pattern, segments))
+    }
+}
+
+/// Files under `dir` that `filter` and `wanted` accept, through all
+/// subdirectories and in path order. Hidden directories such as `.git` are
+/// skipped, and each directory is visited once even when symlinks loop.
+pub fn find_files(dir: &Path, filter: &FileFilter, wanted: impl Fn(&Path) -> bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::from([dir.canonicalize()?]);
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            let is_dir = if file_type.is_symlink() {
+                match fs::metadata(&path) {
+                    Ok(target) if target.is_dir() && !filter.follow_symlinks => continue,
+                    Ok(target) => target.is_dir(),
+                    Err(e) => {
+                        tracing::warn!(path = %path.display(), error = %e, "skipping broken symlink");
+                        continue;
+                    }
+                }
+            } else {
+                file_type.is_dir()
+            };
+
+            if is_dir {
+                let skipped = relative_segments(dir, &path).is_none_or(|segments| filter.skips_dir(&segments));
+                if !skipped && visited.insert(path.canonicalize()?) {
+                    pending.push(path);
+                }
+            } else if filter.accepts(dir, &path) && wanted(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The names on the way from `dir` to `path`
+fn relative_segments(dir: &Path, path: &Path) -> Option<Vec<String>> {
+    let relative = path.strip_prefix(dir).ok()
+        .or_else(|| path.strip_prefix(dir.canonicalize().ok()?).ok())?;
+    relative
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether the relative path `segments` matches the glob `pattern`
+fn glob_match(pattern: &str, segments: &[String]) -> bool {
+    let pattern = pattern.trim_start_matches("./");
+    if !pattern.contains('/') {
+        return segments.last().is_some_and(|name| segment_match(pattern.as_bytes(), name.as_bytes()));
+    }
+    let parts: Vec<&str> = pattern.split('/').filter(|part| !part.is_empty()).collect();
+    path_match(&parts, segments)
+}
+
+fn path_match(parts: &[&str], segments: &[String]) -> bool {
+    match parts.split_first() {
+        None => segments.is_empty(),
+        Some((&"**", rest)) => (0..=segments.len()).any(|skip| path_match(rest, &segments[skip..])),
+        Some((part, rest)) => segments
+            .split_first()
+            .is_some_and(|(name, names)| segment_match(part.as_bytes(), name.as_bytes()) && path_match(rest, names)),
+    }
+}
+
+/// Matches one path segment against a pattern of `*`, `?` and literals
+fn segment_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => (0..=name.len()).any(|skip| segment_match(rest, &name[skip..])),
+        (Some((b'?', rest)), Some((_, names))) => segment_match(rest, names),
+        (Some((p, rest)), Some((n, names))) => p == n && segment_match(rest, names),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_files_recurses_and_filters() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        for file in ["a.md", "notes.txt", "guides/setup.md", "guides/drafts/wip.md", ".git/HEAD.md", "archive/old.md"] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, "text")?;
+        }
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files.iter().map(|f| f.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/")).collect()
+        };
+
+        let all = find_files(root, &FileFilter::default(), |_| true)?;
+        assert_eq!(names(all), ["a.md", "archive/old.md", "guides/drafts/wip.md", "guides/setup.md", "notes.txt"]);
+
+        let filter = FileFilter {
+            include: vec!["**/*.md".to_string()],
+            exclude: vec!["drafts".to_string(), "archive/**".to_string()],
+            follow_symlinks: false,
+        };
+        assert_eq!(names(find_files(root, &filter, |_| true)?), ["a.md", "guides/setup.md"]);
+        assert!(filter.accepts(root, &root.join("guides/new.md")));
+        assert!(!filter.accepts(root, &root.join("guides/drafts/new.md")));
+        assert!(!filter.accepts(root, &root.join("b.txt")));
+
+        #[cfg(unix)]
+        {
+            // A symlink back to the root is followed at most once
+            std::os::unix::fs::symlink(root, root.join("guides/loop"))?;
+            assert_eq!(find_files(root, &filter, |_| true)?.len(), 2);
+            let following = FileFilter { follow_symlinks: true, ..filter };
+            assert_eq!(find_files(root, &following, |_| true)?.len(), 2);
+        }
+        Ok(())
+    }
+}
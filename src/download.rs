@@ -0,0 +1,148 @@
+use anyhow::{Result, anyhow};
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use reqwest::{Client, Response, StatusCode};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::utils;
+
+/// Header in which Hugging Face reports the SHA256 of LFS files, on the
+/// redirect from a `resolve` URL
+const LINKED_ETAG: &str = "x-linked-etag";
+
+/// Downloads `url` to `dest`, showing a progress bar on a terminal.
+///
+/// Bytes go to `<dest>.part` first, so an interrupted download is resumed
+/// with an HTTP range request on the next run instead of leaving a
+/// truncated model behind. The file is only moved to `dest` once its
+/// SHA256 matches `sha256`, or else the one the server publishes; without
+/// either the download fails. A mismatching file is deleted.
+pub async fn download(url: &str, dest: &Path, sha256: Option<&str>) -> Result<()> {
+    let client = Client::builder().build()?;
+    let expected = match sha256 {
+        Some(hash) => hash.to_lowercase(),
+        None => published_sha256(url)
+            .await?
+            .ok_or_else(|| anyhow!("{} publishes no SHA256 to verify the download against", url))?,
+    };
+
+    let partial = partial_path(dest);
+    let mut offset = fs::metadata(&partial).await.map_or(0, |meta| meta.len());
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let mut response = request.send().await?;
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT => eprintln!("Resuming download at {} bytes", offset),
+        // The server ignored the range; start over
+        StatusCode::OK => offset = 0,
+        // Everything was downloaded already, but not yet verified
+        StatusCode::RANGE_NOT_SATISFIABLE => return verify_and_move(&partial, dest, &expected).await,
+        status => return Err(anyhow!("Downloading {} failed: HTTP {}", url, status)),
+    }
+    let total = total_length(&response, offset);
+
+    let mut file = OpenOptions::new().create(true).append(offset > 0).write(true).truncate(offset == 0).open(&partial).await?;
+    let progress = match total {
+        Some(total) => ProgressBar::new(total),
+        None => ProgressBar::no_length(),
+    };
+    progress.set_style(
+        ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} {bytes_per_sec} eta {eta}")?
+            .progress_chars("=> "),
+    );
+    progress.set_position(offset);
+    while let Some(bytes) = response.chunk().await? {
+        file.write_all(&bytes).await?;
+        progress.inc(bytes.len() as u64);
+    }
+    file.flush().await?;
+    progress.finish();
+
+    if let Some(total) = total {
+        let written = fs::metadata(&partial).await?.len();
+        if written != total {
+            return Err(anyhow!("Download stopped at {} of {} bytes; run again to resume", written, total));
+        }
+    }
+    verify_and_move(&partial, dest, &expected).await
+}
+
+/// The SHA256 Hugging Face reports for an LFS file, if the server is one
+async fn published_sha256(url: &str) -> Result<Option<String>> {
+    // The header is on the redirect to the CDN, so don't follow it
+    let client = Client::builder().redirect(reqwest::redirect::Policy::none()).build()?;
+    let response = client.head(url).send().await?;
+    let etag = response
+        .headers()
+        .get(LINKED_ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim_matches('"').to_lowercase());
+    Ok(etag.filter(|etag| is_sha256(etag)))
+}
+
+/// Length of the whole file: the body plus the bytes already on disk
+fn total_length(response: &Response, offset: u64) -> Option<u64> {
+    let header = |name| response.headers().get(name).and_then(|value| value.to_str().ok());
+    // `Content-Range: bytes 100-199/200`
+    if let Some(total) = header(CONTENT_RANGE).and_then(|range| range.rsplit_once('/')) {
+        return total.1.parse().ok();
+    }
+    header(CONTENT_LENGTH).and_then(|length| length.parse::<u64>().ok()).map(|length| length + offset)
+}
+
+async fn verify_and_move(partial: &Path, dest: &Path, expected: &str) -> Result<()> {
+    eprintln!("Verifying checksum...");
+    // Hashing a model reads gigabytes; keep it off the async workers
+    let path = partial.to_path_buf();
+    let actual = tokio::task::spawn_blocking(move || utils::sha256_file(path)).await??;
+    if actual != expected {
+        fs::remove_file(partial).await?;
+        return Err(anyhow!(
+            "Checksum mismatch for {:?}: expected {}, got {}; the file was deleted, run again to re-download",
+            dest, expected, actual
+        ));
+    }
+    fs::rename(partial, dest).await?;
+    Ok(())
+}
+
+fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    dest.with_file_name(name)
+}
+
+fn is_sha256(text: &str) -> bool {
+    text.len() == 64 && text.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_verify_moves_only_matching_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dest = dir.path().join("model.gguf");
+        let partial = partial_path(&dest);
+        assert_eq!(partial, dir.path().join("model.gguf.part"));
+
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        fs::write(&partial, "abd")?;
+        assert!(verify_and_move(&partial, &dest, abc).await.is_err());
+        assert!(!partial.exists() && !dest.exists());
+
+        fs::write(&partial, "abc")?;
+        verify_and_move(&partial, &dest, abc).await?;
+        assert!(!partial.exists());
+        assert_eq!(fs::read_to_string(&dest)?, "abc");
+        assert!(is_sha256(abc) && !is_sha256("\"abc\""));
+        Ok(())
+    }
+}
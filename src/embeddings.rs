@@ -0,0 +1,111 @@
+use anyhow::{Result, anyhow};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::device::Device;
+
+/// Model ID recorded in indexes embedded with the built-in TF-IDF scheme
+pub const TFIDF_MODEL_ID: &str = "tfidf";
+
+/// Turns text into dense vectors. Indexes record the `model_id` they were
+/// built with, and queries must be embedded by the same model.
+pub trait Embedder: Send + Sync {
+    /// Identifies the exact weights, so a re-trained checkpoint gets a new ID
+    fn model_id(&self) -> &str;
+    fn embed(&self, text: &str) -> Result<Vec<f32>>;
+}
+
+/// Loads a fine-tuned BERT-style checkpoint (sentence-transformers export
+/// with `config.json`, `tokenizer.json` and `model.safetensors`) onto
+/// `device`
+pub fn load_embedder(dir: &Path, device: Device) -> Result<Arc<dyn Embedder>> {
+    #[cfg(feature = "candle")]
+    return Ok(Arc::new(candle::CandleEmbedder::load(dir, device)?));
+
+    #[cfg(not(feature = "candle"))]
+    Err(anyhow!("Cannot load embedding model {:?} onto {}: tapssp was built without the `candle` feature", dir, device))
+}
+
+/// `<directory name>@<hash of the weights>`
+#[cfg_attr(not(feature = "candle"), allow(dead_code))]
+fn checkpoint_id(dir: &Path, weights: &Path) -> Result<String> {
+    use rustc_hash::FxHasher;
+    use std::hash::Hasher;
+    use std::io::Read;
+
+    let mut hasher = FxHasher::default();
+    let mut file = std::fs::File::open(weights)?;
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+    }
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow!("Invalid model directory {:?}", dir))?;
+    Ok(format!("{}@{:016x}", name, hasher.finish()))
+}
+
+#[cfg(feature = "candle")]
+mod candle {
+    use anyhow::{Result, anyhow};
+    use candle_core::{DType, Device, Tensor};
+    use candle_nn::VarBuilder;
+    use candle_transformers::models::bert::{BertModel, Config};
+    use std::fs;
+    use std::path::Path;
+    use tokenizers::{Tokenizer, TruncationParams};
+
+    use super::{Embedder, checkpoint_id};
+    use crate::device;
+
+    /// Mean-pooled, L2-normalized BERT embeddings
+    pub struct CandleEmbedder {
+        id: String,
+        model: BertModel,
+        tokenizer: Tokenizer,
+        device: Device,
+    }
+
+    impl CandleEmbedder {
+        pub fn load(dir: &Path, placement: device::Device) -> Result<Self> {
+            let device: Device = placement.candle()?;
+            let config: Config = serde_json::from_str(&fs::read_to_string(dir.join("config.json"))?)?;
+            let mut tokenizer = Tokenizer::from_file(dir.join("tokenizer.json"))
+                .map_err(|e| anyhow!("Invalid tokenizer in {:?}: {}", dir, e))?;
+            tokenizer
+                .with_truncation(Some(TruncationParams { max_length: 512, ..Default::default() }))
+                .map_err(|e| anyhow!("{}", e))?;
+
+            let weights = dir.join("model.safetensors");
+            // SAFETY: the checkpoint isn't modified while tapssp runs
+            let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[&weights], DType::F32, &device)? };
+            let model = BertModel::load(vb, &config)?;
+
+            Ok(CandleEmbedder { id: checkpoint_id(dir, &weights)?, model, tokenizer, device })
+        }
+    }
+
+    impl Embedder for CandleEmbedder {
+        fn model_id(&self) -> &str {
+            &self.id
+        }
+
+        fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            let encoding = self.tokenizer.encode(text, true).map_err(|e| anyhow!("{}", e))?;
+            let ids = Tensor::new(encoding.get_ids(), &self.device)?.unsqueeze(0)?;
+            let type_ids = ids.zeros_like()?;
+            let mask = Tensor::new(encoding.get_attention_mask(), &self.device)?.unsqueeze(0)?;
+
+            let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+            let (_, tokens, _) = hidden.dims3()?;
+            let pooled = (hidden.sum(1)? / tokens as f64)?;
+            let normalized = pooled.broadcast_div(&pooled.sqr()?.sum_keepdim(1)?.sqrt()?)?;
+            Ok(normalized.squeeze(0)?.to_vec1::<f32>()?)
+        }
+    }
+}
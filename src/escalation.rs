@@ -0,0 +1,147 @@
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+lazy_static! {
+    /// Phrases the model uses when the context doesn't contain the answer
+    static ref UNCERTAINTY: Regex = Regex::new(
+        r"(?i)\b(i don'?t know|i do not know|i'?m not sure|i am not sure|(?:cannot|can'?t|could not|couldn'?t) (?:find|determine|answer)|no information|not (?:mentioned|provided|specified) in the (?:context|documents?))"
+    ).unwrap();
+}
+
+/// Retrieval similarity below which an uncertain answer counts as "no answer"
+const DEFAULT_MIN_CONFIDENCE: f32 = 0.2;
+
+/// How a query was resolved
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Answered,
+    Faq,
+    /// An earlier answer to the same or a very similar question
+    Cached,
+    /// The answer given earlier in the session to the same question
+    Repeated,
+    NoAnswer,
+    /// Withheld by output moderation
+    Blocked,
+}
+
+/// Where unanswered questions are sent for human follow-up
+#[derive(Debug, Clone, PartialEq)]
+pub enum Escalation {
+    /// POSTs the event as JSON
+    Webhook(String),
+    /// Runs the command with `sh -c`, passing the event as JSON on stdin
+    Command(String),
+}
+
+impl Escalation {
+    /// `http(s)://...` is a webhook, anything else a shell command
+    pub fn parse(target: &str) -> Result<Self> {
+        let target = target.trim();
+        if target.is_empty() {
+            return Err(anyhow!("Escalation target cannot be empty"));
+        }
+        if target.starts_with("http://") || target.starts_with("https://") {
+            Ok(Escalation::Webhook(target.to_string()))
+        } else {
+            Ok(Escalation::Command(target.to_string()))
+        }
+    }
+
+    pub fn send(&self, event: &EscalationEvent) -> Result<()> {
+        match self {
+            Escalation::Webhook(url) => {
+                let response = reqwest::blocking::Client::new().post(url).json(event).send()?;
+                if !response.status().is_success() {
+                    return Err(anyhow!("Escalation webhook returned {}", response.status()));
+                }
+            }
+            Escalation::Command(command) => {
+                let mut child = Command::new("sh").arg("-c").arg(command).stdin(Stdio::piped()).spawn()?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(serde_json::to_string(event)?.as_bytes())?;
+                }
+                let status = child.wait()?;
+                if !status.success() {
+                    return Err(anyhow!("Escalation command exited with {}", status));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends `event` on a background thread so the reply isn't delayed;
+    /// failures are logged
+    pub fn send_in_background(&self, event: EscalationEvent) {
+        let escalation = self.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = escalation.send(&event) {
+                tracing::warn!(error = %e, "escalation failed");
+            }
+        });
+    }
+}
+
+/// Payload describing a question the system couldn't answer
+#[derive(Debug, Clone, Serialize)]
+pub struct EscalationEvent {
+    pub query: String,
+    /// The model's own (uncertain) answer
+    pub answer: String,
+    /// Best retrieval similarity for the query
+    pub confidence: f32,
+    pub context: Vec<String>,
+}
+
+/// Decides when an answer should be replaced by a "no answer" outcome:
+/// only when retrieval was weak and the model itself signals uncertainty
+#[derive(Debug, Clone)]
+pub struct AbstainPolicy {
+    pub min_confidence: f32,
+}
+
+impl Default for AbstainPolicy {
+    fn default() -> Self {
+        AbstainPolicy { min_confidence: DEFAULT_MIN_CONFIDENCE }
+    }
+}
+
+impl AbstainPolicy {
+    pub fn should_abstain(&self, confidence: f32, answer: &str) -> bool {
+        confidence < self.min_confidence && signals_uncertainty(answer)
+    }
+}
+
+pub fn signals_uncertainty(answer: &str) -> bool {
+    UNCERTAINTY.is_match(answer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_abstain_requires_low_confidence_and_uncertainty() {
+        let policy = AbstainPolicy::default();
+        let unsure = "I'm not sure; the refund window is not mentioned in the context.";
+        assert!(policy.should_abstain(0.05, unsure));
+        assert!(!policy.should_abstain(0.6, unsure));
+        assert!(!policy.should_abstain(0.05, "Refunds are accepted within 30 days."));
+    }
+
+    #[test]
+    fn test_parse_escalation_target() -> Result<()> {
+        assert_eq!(
+            Escalation::parse("https://hooks.example.com/support")?,
+            Escalation::Webhook("https://hooks.example.com/support".to_string())
+        );
+        assert_eq!(Escalation::parse("./notify.sh")?, Escalation::Command("./notify.sh".to_string()));
+        assert!(Escalation::parse("  ").is_err());
+        Ok(())
+    }
+}
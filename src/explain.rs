@@ -0,0 +1,101 @@
+use std::fmt;
+
+use crate::highlight;
+use crate::retriever::Retriever;
+
+/// Answer sentences less similar than this to every chunk are reported as
+/// unsupported by the context
+const MIN_ALIGNMENT: f32 = 0.1;
+
+/// How an answer relates to the context it was generated from, for `/why`
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    /// Each context chunk with its similarity to the query
+    pub chunks: Vec<(f32, String)>,
+    pub alignments: Vec<Alignment>,
+    /// Sampling parameters the answer was generated with
+    pub sampling: String,
+}
+
+/// An answer sentence and the chunk most similar to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alignment {
+    pub sentence: String,
+    /// Index into `Explanation::chunks`, `None` if no chunk is similar enough
+    pub chunk: Option<usize>,
+    pub similarity: f32,
+}
+
+/// Scores `context` against `query` and aligns every sentence of `answer`
+/// with the chunk closest to it in embedding space
+pub fn explain(retriever: &Retriever, query: &str, context: &[String], answer: &str, sampling: String) -> Explanation {
+    let chunks = context.iter().map(|chunk| (retriever.similarity(query, chunk), chunk.clone())).collect();
+    let alignments = highlight::sentence_spans(answer)
+        .into_iter()
+        .map(|span| {
+            let sentence = answer[span].to_string();
+            let best = context
+                .iter()
+                .enumerate()
+                .map(|(i, chunk)| (i, retriever.similarity(&sentence, chunk)))
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            match best {
+                Some((i, similarity)) if similarity >= MIN_ALIGNMENT => Alignment { sentence, chunk: Some(i), similarity },
+                best => Alignment { sentence, chunk: None, similarity: best.map_or(0.0, |(_, s)| s) },
+            }
+        })
+        .collect();
+    Explanation { chunks, alignments, sampling }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Context used:")?;
+        if self.chunks.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for (i, (score, chunk)) in self.chunks.iter().enumerate() {
+            let preview: String = chunk.chars().take(120).collect();
+            let ellipsis = if chunk.chars().count() > 120 { "..." } else { "" };
+            writeln!(f, "  [{}] score {:.3}  {}{}", i + 1, score, preview.replace('\n', " "), ellipsis)?;
+        }
+
+        writeln!(f, "Answer sentences:")?;
+        for alignment in &self.alignments {
+            match alignment.chunk {
+                Some(i) => write!(f, "  [{}] {:.3}", i + 1, alignment.similarity)?,
+                None => write!(f, "  [-] {:.3}", alignment.similarity)?,
+            }
+            writeln!(f, "  {}", alignment.sentence.replace('\n', " "))?;
+        }
+        write!(f, "Sampling: {}", self.sampling)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_aligns_answer_sentences_with_chunks() -> Result<()> {
+        let mut retriever = Retriever::new();
+        let context = [
+            "Refunds are issued within 30 days of purchase.",
+            "Purchase orders require manager approval.",
+        ];
+        for chunk in context.iter().chain(&["The office is closed on public holidays."]) {
+            retriever.add_to_knowledge_base(chunk.to_string(), Default::default())?;
+        }
+        retriever.rebuild();
+
+        let context: Vec<String> = context.iter().map(|c| c.to_string()).collect();
+        let answer = "Your manager must approve purchase orders. Weather tomorrow is sunny.";
+        let explanation = explain(&retriever, "who approves purchase orders?", &context, answer, String::new());
+
+        assert!(explanation.chunks[1].0 > explanation.chunks[0].0);
+        let aligned: Vec<Option<usize>> = explanation.alignments.iter().map(|a| a.chunk).collect();
+        assert_eq!(aligned, [Some(1), None]);
+        Ok(())
+    }
+}
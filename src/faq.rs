@@ -0,0 +1,99 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::vector_db::VectorDB;
+
+/// Similarity a question must reach to be answered from the FAQ
+const DEFAULT_THRESHOLD: f32 = 0.8;
+
+/// An approved answer to a common question
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaqEntry {
+    pub question: String,
+    pub answer: String,
+}
+
+/// Curated question/answer pairs checked before the RAG pipeline. A close
+/// enough match returns the approved answer verbatim, without generation.
+pub struct Faq {
+    index: VectorDB,
+    /// Answers keyed by their normalized question
+    answers: HashMap<String, FaqEntry>,
+    threshold: f32,
+}
+
+impl Faq {
+    pub fn new(entries: Vec<FaqEntry>) -> Result<Self> {
+        let mut index = VectorDB::new();
+        let mut answers = HashMap::new();
+        for entry in entries {
+            index.add_document(entry.question.clone())?;
+            answers.insert(normalize(&entry.question), entry);
+        }
+        // Entries added early were embedded with an incomplete vocabulary
+        index.rebuild();
+        Ok(Faq { index, answers, threshold: DEFAULT_THRESHOLD })
+    }
+
+    /// Reads a JSON array of `{"question": ..., "answer": ...}` objects
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let entries = serde_json::from_str(&content).map_err(|e| anyhow!("Invalid FAQ file {:?}: {}", path, e))?;
+        Self::new(entries)
+    }
+
+    pub fn len(&self) -> usize {
+        self.answers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.answers.is_empty()
+    }
+
+    /// The entry whose question matches `query` exactly (ignoring case and
+    /// punctuation) or with similarity at or above the threshold
+    pub fn find(&self, query: &str) -> Option<&FaqEntry> {
+        if let Some(entry) = self.answers.get(&normalize(query)) {
+            return Some(entry);
+        }
+        self.index
+            .search_scored(query, 1)
+            .into_iter()
+            .find(|(score, _)| *score >= self.threshold)
+            .and_then(|(_, doc)| self.answers.get(&normalize(&doc.content)))
+    }
+}
+
+fn normalize(question: &str) -> String {
+    question
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(question: &str, answer: &str) -> FaqEntry {
+        FaqEntry { question: question.to_string(), answer: answer.to_string() }
+    }
+
+    #[test]
+    fn test_find_matches_close_questions_only() -> Result<()> {
+        let faq = Faq::new(vec![
+            entry("How do I reset my password?", "Use the 'Forgot password' link on the login page."),
+            entry("What are the support hours?", "Monday to Friday, 9:00-17:00 CET."),
+        ])?;
+
+        assert!(faq.find("how do i reset my password").unwrap().answer.starts_with("Use the"));
+        assert!(faq.find("reset my password how").unwrap().answer.starts_with("Use the"));
+        assert!(faq.find("Which regions does the product support?").is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use crate::retriever::ScoredChunk;
+
+/// Rank offset in reciprocal rank fusion, as in `VectorDB`'s fused search
+const RRF_K: f32 = 60.0;
+/// How long a peer may take before its results are left out
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Body of `POST /query/raw`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RawQuery {
+    pub query: String,
+    pub top_k: Option<usize>,
+}
+
+/// Response of `POST /query/raw`: local chunks only, best first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RawResults {
+    pub chunks: Vec<RawChunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RawChunk {
+    pub content: String,
+    pub score: f32,
+    /// The chunk's metadata on the peer, so answers can cite its source;
+    /// absent from peers running an older version
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Other tapssp servers whose knowledge bases are searched along with the
+/// local one. Only retrieved chunks cross the network; each team keeps its
+/// documents and index on its own instance.
+#[derive(Debug, Clone, Default)]
+pub struct Federation {
+    /// Base URLs, e.g. `http://hr-kb:8080`
+    peers: Vec<String>,
+}
+
+impl Federation {
+    pub fn new(peers: Vec<String>) -> Self {
+        Federation { peers: peers.into_iter().map(|url| url.trim_end_matches('/').to_string()).collect() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Each peer's ranking for `query`, queried in parallel. Peers that fail
+    /// or time out are logged and skipped, so one unreachable instance
+    /// doesn't stop answers.
+    pub fn fetch(&self, query: &str, top_k: usize) -> Vec<Vec<ScoredChunk>> {
+        let client = match reqwest::blocking::Client::builder().timeout(PEER_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::warn!(error = %e, "federation client unavailable");
+                return Vec::new();
+            }
+        };
+        let body = RawQuery { query: query.to_string(), top_k: Some(top_k) };
+        std::thread::scope(|scope| {
+            let requests: Vec<_> = self
+                .peers
+                .iter()
+                .map(|peer| (peer, scope.spawn(|| fetch_peer(&client, peer, &body))))
+                .collect();
+            requests
+                .into_iter()
+                .filter_map(|(peer, request)| match request.join() {
+                    Ok(Ok(chunks)) => Some(chunks),
+                    Ok(Err(e)) => {
+                        tracing::warn!(peer = %peer, error = %e, "federated retrieval failed");
+                        None
+                    }
+                    Err(_) => None,
+                })
+                .collect()
+        })
+    }
+}
+
+fn fetch_peer(client: &reqwest::blocking::Client, peer: &str, body: &RawQuery) -> Result<Vec<ScoredChunk>> {
+    let response = client.post(format!("{}/query/raw", peer)).json(body).send()?;
+    if !response.status().is_success() {
+        return Err(anyhow!("{} returned {}", peer, response.status()));
+    }
+    let results: RawResults = response.json()?;
+    Ok(results
+        .chunks
+        .into_iter()
+        .map(|chunk| ScoredChunk { content: chunk.content, score: chunk.score, metadata: chunk.metadata, ..ScoredChunk::default() })
+        .collect())
+}
+
+/// Merges rankings with reciprocal rank fusion, since scores from different
+/// indexes aren't comparable. A chunk found by several instances counts
+/// once, with the fused weight of all its ranks.
+pub fn merge(rankings: Vec<Vec<ScoredChunk>>, top_k: usize) -> Vec<ScoredChunk> {
+    // By content: fused weight, order first seen, and the chunk as first seen
+    let mut fused: HashMap<String, (f32, usize, ScoredChunk)> = HashMap::new();
+    let mut order = 0;
+    for ranking in rankings {
+        for (rank, chunk) in ranking.into_iter().enumerate() {
+            let entry = fused.entry(chunk.content.clone()).or_insert_with(|| {
+                order += 1;
+                (0.0, order, chunk)
+            });
+            entry.0 += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+    }
+    let mut merged: Vec<(f32, usize, ScoredChunk)> = fused.into_values().collect();
+    // Ties keep the order chunks were first seen in, local results first
+    merged.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    merged.into_iter().take(top_k).map(|(_, _, chunk)| chunk).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_interleaves_and_deduplicates() {
+        let chunks = |names: &[&str]| {
+            names.iter().map(|name| ScoredChunk { content: name.to_string(), ..ScoredChunk::default() }).collect::<Vec<_>>()
+        };
+        let local = chunks(&["local-1", "shared", "local-2"]);
+        let remote = chunks(&["remote-1", "shared"]);
+
+        let merged = merge(vec![local, remote], 4);
+        let contents: Vec<&str> = merged.iter().map(|chunk| chunk.content.as_str()).collect();
+        assert_eq!(contents, ["shared", "local-1", "remote-1", "local-2"]);
+        assert_eq!(Federation::new(vec!["http://kb:8080/".to_string()]).peers, ["http://kb:8080"]);
+    }
+}
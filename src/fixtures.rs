@@ -0,0 +1,196 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::pipeline::RagPipeline;
+use crate::retriever;
+use crate::utils::ensure_dir;
+
+/// Source shown for chunks the index doesn't hold, e.g. from federated peers
+const UNKNOWN_SOURCE: &str = "?";
+
+/// A question answered during a recorded session, with what retrieval and
+/// prompt building produced for it. `tapssp replay --fixtures DIR` runs the
+/// question through the current code again and reports any difference, so
+/// changes to tokenizing, chunking or scoring show up as failing fixtures.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    pub query: String,
+    /// Chunks retrieved, best first, see `chunk_key`
+    pub retrieved: Vec<String>,
+    /// Prompt for the question on its own, without conversation history,
+    /// so replays compare like with like
+    pub prompt: String,
+    /// Answer given during the session; replays don't generate
+    pub answer: String,
+}
+
+/// How a replayed fixture differs from its recording
+#[derive(Debug, Clone, PartialEq)]
+pub enum Drift {
+    /// Other chunks, or the same ones in another order
+    Retrieval { recorded: Vec<String>, replayed: Vec<String> },
+    /// Same chunks, but the prompt changed from this line (from 1) on
+    Prompt { line: usize },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Retrieval { recorded, replayed } => {
+                write!(f, "retrieval changed\n  recorded: {}\n  replayed: {}", recorded.join(", "), replayed.join(", "))
+            }
+            Drift::Prompt { line } => write!(f, "prompt changed from line {}", line),
+        }
+    }
+}
+
+/// Stable name of a retrieved chunk, `<source>#<content hash>`. Document IDs
+/// are random unless the index is built deterministically, so they can't be
+/// compared across rebuilds; a re-chunked document gets new hashes.
+pub fn chunk_key(source: Option<&str>, content: &str) -> String {
+    let digest = Sha256::digest(content.as_bytes());
+    let hash: String = digest[..6].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}#{}", source.unwrap_or(UNKNOWN_SOURCE), hash)
+}
+
+impl Fixture {
+    /// Records `query`, answered with `answer` from the `retrieved` chunks
+    pub fn capture(pipeline: &RagPipeline, query: &str, retrieved: &[String], answer: &str) -> Result<Self> {
+        Ok(Fixture {
+            query: query.to_string(),
+            retrieved: chunk_keys(pipeline, retrieved),
+            prompt: pipeline.prompt(query, retrieved.to_vec())?,
+            answer: answer.to_string(),
+        })
+    }
+
+    /// Retrieves and builds the prompt for the recorded question again;
+    /// None when both match the recording
+    pub fn replay(&self, pipeline: &RagPipeline) -> Result<Option<Drift>> {
+        let chunks = retriever::contents(&pipeline.retrieve(&self.query));
+        let replayed = chunk_keys(pipeline, &chunks);
+        if replayed != self.retrieved {
+            return Ok(Some(Drift::Retrieval { recorded: self.retrieved.clone(), replayed }));
+        }
+        let prompt = pipeline.prompt(&self.query, chunks)?;
+        if prompt == self.prompt {
+            return Ok(None);
+        }
+        let line = prompt.lines().zip(self.prompt.lines()).take_while(|(a, b)| a == b).count() + 1;
+        Ok(Some(Drift::Prompt { line }))
+    }
+}
+
+fn chunk_keys(pipeline: &RagPipeline, chunks: &[String]) -> Vec<String> {
+    let retriever = pipeline.retriever();
+    let retriever = retriever.read().expect("retriever lock poisoned");
+    chunks.iter().map(|chunk| chunk_key(retriever.chunk_source(chunk).as_deref(), chunk)).collect()
+}
+
+/// Writes fixtures as numbered, pretty-printed JSON files into a directory,
+/// after any already there, so they can be reviewed and committed
+pub struct FixtureRecorder {
+    dir: PathBuf,
+    next: Mutex<usize>,
+}
+
+impl FixtureRecorder {
+    pub fn new(dir: &Path) -> Result<Self> {
+        ensure_dir(dir)?;
+        let next = load_fixtures(dir)?.len() + 1;
+        Ok(FixtureRecorder { dir: dir.to_path_buf(), next: Mutex::new(next) })
+    }
+
+    /// Writes `fixture` to the next free `NNNN.json`, returning its path
+    pub fn record(&self, fixture: &Fixture) -> Result<PathBuf> {
+        let mut next = self.next.lock().unwrap();
+        let path = loop {
+            let path = self.dir.join(format!("{:04}.json", *next));
+            *next += 1;
+            if !path.exists() {
+                break path;
+            }
+        };
+        fs::write(&path, serde_json::to_string_pretty(fixture)? + "\n")?;
+        Ok(path)
+    }
+}
+
+/// The fixtures in `dir`, by file name
+pub fn load_fixtures(dir: &Path) -> Result<Vec<(PathBuf, Fixture)>> {
+    let entries = fs::read_dir(dir).map_err(|e| anyhow!("Can't read fixtures {:?}: {}", dir, e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| {
+            let content = fs::read_to_string(&path)?;
+            let fixture = serde_json::from_str(&content).map_err(|e| anyhow!("Invalid fixture {:?}: {}", path, e))?;
+            Ok((path, fixture))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{InferenceOptions, LLM, LLMBackend, LLMConfig};
+    use std::collections::BTreeMap;
+    use std::ops::ControlFlow;
+    use std::sync::Arc;
+
+    struct Silent;
+
+    impl LLMBackend for Silent {
+        fn infer(&self, _prompt: String, _options: &InferenceOptions, _on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn pipeline(refunds: &str) -> Result<RagPipeline> {
+        let file = |path: &str| BTreeMap::from([("path".to_string(), path.to_string())]);
+        RagPipeline::builder()
+            .document_with_metadata(refunds, file("docs/refunds.md"))
+            .document_with_metadata("Shipping is free over fifty euros.", file("docs/shipping.md"))
+            .llm(LLM::with_backend(Arc::new(Silent), LLMConfig::default()))
+            .top_k(1)
+            .build()
+    }
+
+    #[test]
+    fn test_recorded_fixtures_replay_until_retrieval_changes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let recorder = FixtureRecorder::new(dir.path())?;
+        let recorded = pipeline("Refunds take thirty days.")?;
+        let query = "How long do refunds take?";
+        let retrieved = retriever::contents(&recorded.retrieve(query));
+        recorder.record(&Fixture::capture(&recorded, query, &retrieved, "Thirty days.")?)?;
+
+        let fixtures = load_fixtures(dir.path())?;
+        assert_eq!(fixtures.len(), 1);
+        let (path, fixture) = &fixtures[0];
+        assert!(path.ends_with("0001.json"));
+        assert_eq!(fixture.retrieved, vec![chunk_key(Some("docs/refunds.md"), "Refunds take thirty days.")]);
+        assert!(fixture.prompt.contains("Refunds take thirty days."));
+
+        // An index rebuilt from the same documents gets new IDs but replays
+        assert_eq!(fixture.replay(&pipeline("Refunds take thirty days.")?)?, None);
+        match fixture.replay(&pipeline("Refunds take fourteen days.")?)? {
+            Some(Drift::Retrieval { recorded, replayed }) => {
+                assert_eq!(recorded, fixture.retrieved);
+                assert!(replayed[0].starts_with("docs/refunds.md#") && replayed != recorded);
+            }
+            other => panic!("expected a retrieval change, got {:?}", other),
+        }
+        assert!(FixtureRecorder::new(dir.path())?.record(fixture)?.ends_with("0002.json"));
+        Ok(())
+    }
+}
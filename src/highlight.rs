@@ -0,0 +1,143 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use rustc_hash::FxHashSet;
+use serde::Serialize;
+use std::ops::Range;
+
+use crate::vector_db::VectorDB;
+
+lazy_static! {
+    static ref SENTENCE_END: Regex = Regex::new(r"[.!?]+\s+|\n\s*").unwrap();
+}
+
+/// Passages less similar to the query than this aren't marked
+const PASSAGE_MIN_SIMILARITY: f32 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HighlightKind {
+    /// A word matching a query term (after normalization and synonyms)
+    Term,
+    /// The sentence closest in meaning to the query
+    Passage,
+}
+
+/// A marked span of a retrieved chunk, as byte offsets into its text
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Highlight {
+    pub start: usize,
+    pub end: usize,
+    pub kind: HighlightKind,
+}
+
+/// Marks why `text` was retrieved for `query`: the words sharing an index
+/// term with the query, and the sentence whose embedding is most similar
+/// to the query's
+pub fn highlight(db: &VectorDB, query: &str, text: &str) -> Vec<Highlight> {
+    let query_terms: FxHashSet<String> = db.tokenize_with_spans(query).into_iter().map(|token| token.term).collect();
+    let mut terms: Vec<Range<usize>> = db
+        .tokenize_with_spans(text)
+        .into_iter()
+        .filter(|token| query_terms.contains(&token.term))
+        .map(|token| token.span)
+        .collect();
+    // Synonym expansions repeat the span of the word they replace
+    terms.dedup();
+
+    let mut highlights: Vec<Highlight> = terms
+        .into_iter()
+        .map(|span| Highlight { start: span.start, end: span.end, kind: HighlightKind::Term })
+        .collect();
+    if let Some(passage) = best_passage(db, query, text) {
+        highlights.push(Highlight { start: passage.start, end: passage.end, kind: HighlightKind::Passage });
+    }
+    highlights.sort_by_key(|h| (h.start, h.end));
+    highlights
+}
+
+fn best_passage(db: &VectorDB, query: &str, text: &str) -> Option<Range<usize>> {
+    let sentences = sentence_spans(text);
+    if sentences.len() < 2 {
+        return None;
+    }
+    let query_embedding = db.embed_text(query).ok()?;
+    sentences
+        .into_iter()
+        .filter_map(|span| {
+            let embedding = db.embed_text(&text[span.clone()]).ok()?;
+            Some((db.cosine_similarity(&query_embedding, &embedding), span))
+        })
+        .filter(|(similarity, _)| *similarity >= PASSAGE_MIN_SIMILARITY)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, span)| span)
+}
+
+/// Spans of the non-empty sentences of `text`, without trailing whitespace
+pub(crate) fn sentence_spans(text: &str) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for end in SENTENCE_END.find_iter(text) {
+        spans.push(start..end.start() + end.as_str().trim_end().len());
+        start = end.end();
+    }
+    spans.push(start..text.trim_end().len().max(start));
+    spans.retain(|span| !text[span.clone()].trim().is_empty());
+    spans
+}
+
+/// `text` with terms in bold yellow and the passage underlined, for
+/// terminals. Highlights past the end of `text` are clipped, so a prefix of
+/// a chunk can be rendered with the chunk's highlights.
+pub fn render_ansi(text: &str, highlights: &[Highlight]) -> String {
+    let clip = |offset: usize| offset.min(text.len());
+    let mut boundaries: Vec<usize> = highlights.iter().flat_map(|h| [clip(h.start), clip(h.end)]).collect();
+    boundaries.extend([0, text.len()]);
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut out = String::with_capacity(text.len());
+    for window in boundaries.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        let within = |kind| highlights.iter().any(|h| h.kind == kind && h.start <= start && end <= h.end);
+        let style = match (within(HighlightKind::Term), within(HighlightKind::Passage)) {
+            (true, true) => "\x1b[1;4;33m",
+            (true, false) => "\x1b[1;33m",
+            (false, true) => "\x1b[4m",
+            (false, false) => "",
+        };
+        if style.is_empty() {
+            out.push_str(&text[start..end]);
+        } else {
+            out.push_str(&format!("{}{}\x1b[0m", style, &text[start..end]));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_highlights_keep_original_text() -> Result<()> {
+        let mut db = VectorDB::new();
+        db.add_document("The office opens at nine. Refunds take 30 days.".to_string())?;
+        db.add_document("Parking is free for visitors.".to_string())?;
+        db.rebuild();
+
+        let text = "The office opens at nine. Refunds take 30 days.";
+        let highlights = highlight(&db, "how long do REFUNDS take", text);
+        let marked: Vec<(&str, HighlightKind)> =
+            highlights.iter().map(|h| (&text[h.start..h.end], h.kind)).collect();
+        assert_eq!(marked, [
+            ("Refunds", HighlightKind::Term),
+            ("Refunds take 30 days.", HighlightKind::Passage),
+            ("take", HighlightKind::Term),
+        ]);
+
+        let rendered = render_ansi(&text[..38], &highlights);
+        assert!(rendered.ends_with("\x1b[1;4;33mRefunds\x1b[0m\x1b[4m \x1b[0m\x1b[1;4;33mtake\x1b[0m"));
+        Ok(())
+    }
+}
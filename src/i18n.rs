@@ -0,0 +1,155 @@
+use anyhow::{Result, anyhow};
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue};
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+/// Bundled Fluent translations of the REPL messages and prompt
+/// instructions. English comes first and fills in messages a translation
+/// lacks.
+const LOCALES: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+    ("fr", include_str!("../locales/fr.ftl")),
+    ("es", include_str!("../locales/es.ftl")),
+];
+
+static CURRENT: OnceLock<Locale> = OnceLock::new();
+
+/// The messages of one bundled language
+pub struct Locale {
+    code: &'static str,
+    /// The language's bundle, then English
+    bundles: Vec<FluentBundle<FluentResource>>,
+}
+
+impl Locale {
+    /// The bundled locale for a language tag such as `de`, `de-AT` or the
+    /// `de_AT.UTF-8` form of `LANG`
+    pub fn new(tag: &str) -> Result<Self> {
+        let language = tag.split(['_', '-', '.', '@']).next().unwrap_or_default().to_lowercase();
+        let code = LOCALES
+            .iter()
+            .map(|(code, _)| *code)
+            .find(|code| *code == language)
+            .ok_or_else(|| anyhow!("Unsupported language '{}' ({})", tag, Self::available().join(", ")))?;
+        let mut bundles = vec![bundle(code)];
+        if code != "en" {
+            bundles.push(bundle("en"));
+        }
+        Ok(Locale { code, bundles })
+    }
+
+    /// The language picked from `LC_ALL`, `LC_MESSAGES` or `LANG` through
+    /// `var`, like other command-line tools; English when none of them names
+    /// a bundled language
+    pub fn detect(var: impl Fn(&str) -> Option<String>) -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|key| var(key).filter(|value| !value.is_empty()))
+            .find_map(|tag| Locale::new(&tag).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn available() -> Vec<&'static str> {
+        LOCALES.iter().map(|(code, _)| *code).collect()
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Message `id` with `args` filled in, from the first bundle that has
+    /// it; the ID itself when none has
+    pub fn format(&self, id: &str, args: &[(&str, FluentValue)]) -> String {
+        let mut fluent_args = FluentArgs::new();
+        for (name, value) in args {
+            fluent_args.set(*name, value.clone());
+        }
+        for bundle in &self.bundles {
+            let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) else {
+                continue;
+            };
+            let mut errors = Vec::new();
+            let text = bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
+            if !errors.is_empty() {
+                tracing::warn!(id, locale = self.code, ?errors, "incomplete message");
+            }
+            return text.into_owned();
+        }
+        tracing::warn!(id, locale = self.code, "missing message");
+        id.to_string()
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::new("en").expect("English is bundled")
+    }
+}
+
+fn bundle(code: &str) -> FluentBundle<FluentResource> {
+    let source = LOCALES.iter().find(|(c, _)| *c == code).map(|(_, source)| *source).expect("bundled locale");
+    let language: LanguageIdentifier = code.parse().expect("valid language code");
+    let mut bundle = FluentBundle::new_concurrent(vec![language]);
+    // Isolation marks would end up in prompts and terminals
+    bundle.set_use_isolating(false);
+    let resource = FluentResource::try_new(source.to_string())
+        .unwrap_or_else(|(_, errors)| panic!("Invalid bundled locale {}: {:?}", code, errors));
+    bundle.add_resource(resource).expect("bundled locales have unique messages");
+    bundle
+}
+
+/// Sets the locale of this process; only the first call has an effect
+pub fn init(locale: Locale) {
+    if CURRENT.set(locale).is_err() {
+        tracing::debug!("locale already set");
+    }
+}
+
+/// The locale set with `init`, English before that
+pub fn current() -> &'static Locale {
+    CURRENT.get_or_init(Locale::default)
+}
+
+/// Message `id` in the current locale
+pub fn text(id: &str) -> String {
+    current().format(id, &[])
+}
+
+/// Message `id` in the current locale with `args` filled in
+pub fn format(id: &str, args: &[(&str, FluentValue)]) -> String {
+    current().format(id, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locales_are_complete_and_selectable() -> Result<()> {
+        let english = LOCALES[0].1;
+        let ids: Vec<&str> = english
+            .lines()
+            .filter_map(|line| line.split_once(" = ").map(|(id, _)| id))
+            .filter(|id| !id.starts_with('#') && !id.contains(' '))
+            .collect();
+        assert!(ids.contains(&"prompt-answer-language"));
+        for (code, _) in LOCALES {
+            let bundle = bundle(code);
+            let missing: Vec<&&str> = ids.iter().filter(|id| !bundle.has_message(id)).collect();
+            assert!(missing.is_empty(), "{} lacks {:?}", code, missing);
+        }
+
+        let german = Locale::new("de_AT.UTF-8")?;
+        assert_eq!(german.code(), "de");
+        assert_eq!(german.format("repl-generating-variants", &[("count", 3.into())]), "Erzeuge 3 Varianten...");
+        assert_eq!(german.format("no-such-message", &[]), "no-such-message");
+        assert!(Locale::new("tlh").is_err());
+
+        let env = |key: &str| (key == "LANG").then(|| "fr_FR.UTF-8".to_string());
+        assert_eq!(Locale::detect(env).code(), "fr");
+        assert_eq!(Locale::detect(|_| Some("C".to_string())).code(), "en");
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::vector_db::is_export_header;
+
+/// Export formats of Python RAG stacks that `kb import` reads
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// One LangChain `Document` per line:
+    /// `{"page_content": "...", "metadata": {...}}`
+    LangChain,
+    /// A LlamaIndex `docstore.json` from `storage_context.persist()`
+    LlamaIndex,
+    /// A dump of a tapssp index from `kb export`, imported with its IDs and
+    /// embeddings by `VectorDB::import_jsonl` rather than by `load`
+    Tapssp,
+}
+
+impl ImportFormat {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "langchain" => Ok(ImportFormat::LangChain),
+            "llamaindex" | "llama-index" => Ok(ImportFormat::LlamaIndex),
+            "tapssp" => Ok(ImportFormat::Tapssp),
+            _ => Err(anyhow!("Unknown import format '{}' (langchain, llamaindex, tapssp)", s)),
+        }
+    }
+
+    /// The given format, or else the one guessed for `path`: `.jsonl` files
+    /// are tapssp dumps if they start with an export header and LangChain
+    /// exports otherwise, anything else LlamaIndex
+    pub fn detect(path: &Path, format: Option<Self>) -> Result<Self> {
+        if let Some(format) = format {
+            return Ok(format);
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") => {
+                let mut first_line = String::new();
+                BufReader::new(File::open(path)?).read_line(&mut first_line)?;
+                match is_export_header(first_line.trim()) {
+                    true => Ok(ImportFormat::Tapssp),
+                    false => Ok(ImportFormat::LangChain),
+                }
+            }
+            _ => Ok(ImportFormat::LlamaIndex),
+        }
+    }
+}
+
+/// Text and metadata of one imported document
+#[derive(Debug, PartialEq)]
+pub struct ImportedDocument {
+    pub text: String,
+    pub metadata: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct LangChainDocument {
+    page_content: String,
+    #[serde(default)]
+    metadata: Map<String, Value>,
+}
+
+/// Reads the documents in `path`, guessing the format from the extension
+/// unless one is given. Documents without text are skipped.
+pub fn load(path: &Path, format: Option<ImportFormat>) -> Result<Vec<ImportedDocument>> {
+    let format = ImportFormat::detect(path, format)?;
+    let content = fs::read_to_string(path)?;
+    let documents = match format {
+        ImportFormat::LangChain => parse_langchain(&content),
+        ImportFormat::LlamaIndex => parse_llamaindex(&content),
+        ImportFormat::Tapssp => return Err(anyhow!("{:?} is a tapssp index dump, not a document export", path)),
+    }
+    .map_err(|e| anyhow!("Invalid export {:?}: {}", path, e))?;
+    Ok(documents.into_iter().filter(|doc| !doc.text.trim().is_empty()).collect())
+}
+
+fn parse_langchain(content: &str) -> Result<Vec<ImportedDocument>> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let doc: LangChainDocument =
+                serde_json::from_str(line).map_err(|e| anyhow!("line {}: {}", i + 1, e))?;
+            Ok(ImportedDocument { text: doc.page_content, metadata: flatten(doc.metadata) })
+        })
+        .collect()
+}
+
+/// Reads nodes from `{"docstore/data": {id: {"__data__": {...}}}}`, as
+/// written by current LlamaIndex, or the older `{"docstore": {"docs": ...}}`
+fn parse_llamaindex(content: &str) -> Result<Vec<ImportedDocument>> {
+    let store: Value = serde_json::from_str(content)?;
+    let nodes = store
+        .get("docstore/data")
+        .or_else(|| store.pointer("/docstore/docs"))
+        .and_then(Value::as_object)
+        .ok_or_else(|| anyhow!("no \"docstore/data\" or \"docstore.docs\" object"))?;
+
+    let mut documents = Vec::new();
+    // Sorted by node ID, so repeated imports index the same order
+    let mut ids: Vec<&String> = nodes.keys().collect();
+    ids.sort();
+    for id in ids {
+        let node = nodes[id].get("__data__").unwrap_or(&nodes[id]);
+        let Some(text) = node.get("text").and_then(Value::as_str) else {
+            continue;
+        };
+        let mut metadata = node
+            .get("metadata")
+            .or_else(|| node.get("extra_info"))
+            .and_then(Value::as_object)
+            .cloned()
+            .map(flatten)
+            .unwrap_or_default();
+        metadata.entry("node_id".to_string()).or_insert_with(|| id.clone());
+        documents.push(ImportedDocument { text: text.to_string(), metadata });
+    }
+    Ok(documents)
+}
+
+/// Metadata values as strings; non-string values keep their JSON form
+fn flatten(metadata: Map<String, Value>) -> BTreeMap<String, String> {
+    metadata
+        .into_iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(key, value)| match value {
+            Value::String(text) => (key, text),
+            other => (key, other.to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_langchain_and_llamaindex_exports() -> Result<()> {
+        let langchain = concat!(
+            r#"{"page_content": "Refunds take 30 days.", "metadata": {"source": "faq.md", "page": 2, "draft": null}, "type": "Document"}"#,
+            "\n\n",
+            r#"{"page_content": "Orders ship on Monday."}"#,
+        );
+        let docs = parse_langchain(langchain)?;
+        assert_eq!(docs[0].text, "Refunds take 30 days.");
+        assert_eq!(docs[0].metadata, BTreeMap::from([
+            ("page".to_string(), "2".to_string()),
+            ("source".to_string(), "faq.md".to_string()),
+        ]));
+        assert!(docs[1].metadata.is_empty());
+        assert!(parse_langchain("{\"text\": \"x\"}").is_err());
+
+        let llamaindex = r#"{"docstore/data": {
+            "b-node": {"__type__": "1", "__data__": {"id_": "b-node", "text": "Orders ship on Monday.", "metadata": {"file_name": "shipping.txt"}}},
+            "a-node": {"__type__": "1", "__data__": {"id_": "a-node", "text": "Refunds take 30 days."}}
+        }}"#;
+        let docs = parse_llamaindex(llamaindex)?;
+        assert_eq!(docs.iter().map(|doc| doc.text.as_str()).collect::<Vec<_>>(), ["Refunds take 30 days.", "Orders ship on Monday."]);
+        assert_eq!(docs[1].metadata["file_name"], "shipping.txt");
+        assert_eq!(docs[0].metadata["node_id"], "a-node");
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::discovery::{self, FileFilter};
+use crate::loaders;
+use crate::metadata;
+use crate::retriever::Retriever;
+use crate::tables;
+use crate::transforms::Transforms;
+
+/// Largest table chunk indexed from CSV and spreadsheet files
+pub const TABLE_CHUNK_CHARS: usize = 1500;
+
+/// Loads the text and table files under `docs_dir` that `filter` accepts,
+/// in path order
+pub async fn load_documents(retriever: &mut Retriever, docs_dir: &Path, filter: &FileFilter, transforms: Arc<Transforms>) -> Result<()> {
+    let paths = discovery::find_files(docs_dir, filter, has_ingestible_extension)?;
+    load_files(retriever, paths, transforms).await
+}
+
+/// Reads and chunks all `paths` at once on blocking threads, then adds
+/// them to the knowledge base in order. Embedding needs the retriever, so
+/// only reading and parsing overlap.
+pub async fn load_files(retriever: &mut Retriever, paths: Vec<PathBuf>, transforms: Arc<Transforms>) -> Result<()> {
+    let reads: Vec<_> = paths
+        .into_iter()
+        .map(|path| {
+            let transforms = transforms.clone();
+            tokio::task::spawn_blocking(move || read_file(&path, &transforms))
+        })
+        .collect();
+    for read in reads {
+        add_file(retriever, read.await??)?;
+    }
+    Ok(())
+}
+
+/// Whether `path` is a text or table file that `load_file` reads
+pub fn is_ingestible(path: &Path) -> bool {
+    path.is_file() && has_ingestible_extension(path)
+}
+
+pub fn has_ingestible_extension(path: &Path) -> bool {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    loaders::TEXT_EXTENSIONS.contains(&extension) || tables::TABLE_EXTENSIONS.contains(&extension)
+}
+
+/// A file's chunks or tables, read but not yet embedded, with the
+/// metadata of the file
+pub enum LoadedFile {
+    Text(Vec<(String, BTreeMap<String, String>)>, BTreeMap<String, String>),
+    Tables(Vec<tables::Table>, BTreeMap<String, String>),
+}
+
+pub fn read_file(path: &Path, transforms: &Transforms) -> Result<LoadedFile> {
+    let mut metadata = metadata::of_file(path)?;
+    transforms.add_metadata(path, &mut metadata);
+    if is_text_file(path) {
+        Ok(LoadedFile::Text(loaders::load_text(path, transforms, &metadata)?, metadata))
+    } else {
+        Ok(LoadedFile::Tables(tables::load_tables(path)?, metadata))
+    }
+}
+
+/// Adds the chunks of a file. Chunks it already had are kept as they are,
+/// and those of an earlier version it no longer has are removed, so
+/// re-ingesting an edited file replaces it in place.
+pub fn add_file(retriever: &mut Retriever, file: LoadedFile) -> Result<()> {
+    let added_before = retriever.ingest_report().added;
+    let mut ids = Vec::new();
+    let metadata = match file {
+        LoadedFile::Text(chunks, metadata) => {
+            for (text, metadata) in chunks {
+                ids.extend(retriever.add_to_knowledge_base(text, metadata)?);
+            }
+            metadata
+        }
+        LoadedFile::Tables(tables, metadata) => {
+            for table in tables {
+                ids.extend(retriever.add_table(&table, TABLE_CHUNK_CHARS, metadata.clone())?);
+            }
+            metadata
+        }
+    };
+    retriever.number_chunks(&ids)?;
+    let added = retriever.ingest_report().added - added_before;
+    retriever.remove_stale_documents(&metadata, &ids, added)?;
+    Ok(())
+}
+
+pub fn load_file(retriever: &mut Retriever, path: &Path, transforms: &Transforms) -> Result<()> {
+    add_file(retriever, read_file(path, transforms)?)
+}
+
+pub fn is_text_file(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| loaders::TEXT_EXTENSIONS.contains(&ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection::CollectionSettings;
+    use crate::runtime;
+    use crate::utils;
+    use crate::vector_db::IngestReport;
+    use crate::vector_db::VectorDB;
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_deterministic_builds_of_loaded_files_are_identical() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("refunds.md");
+        fs::write(&source, "# Refunds\n\nRefunds take thirty days. Orders ship on Monday.\n")?;
+        let build = |name: &str, modified: SystemTime| -> Result<String> {
+            // A fresh checkout gives the same file another modification time
+            File::options().write(true).open(&source)?.set_modified(modified)?;
+            let mut db = VectorDB::new();
+            db.use_deterministic_ids();
+            let mut retriever = Retriever::with_vector_db(db);
+            runtime::block_on(load_files(&mut retriever, vec![source.clone()], Arc::new(Transforms::default())))?;
+            retriever.rebuild();
+            let path = dir.path().join(name);
+            retriever.save(&path)?;
+            utils::sha256_file(&path)
+        };
+        let first = build("a.bin", SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))?;
+        let second = build("b.bin", SystemTime::now())?;
+        assert_eq!(first, second);
+
+        let db = VectorDB::load(dir.path().join("a.bin"))?;
+        let metadata = &db.documents().next().unwrap().metadata;
+        assert_eq!(metadata.get("source").map(String::as_str), Some("refunds.md"));
+        assert!(!metadata.contains_key("modified") && !metadata.contains_key("ingested"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reingesting_an_edited_file_replaces_its_chunks() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("faq.txt");
+        let mut settings = CollectionSettings::default();
+        settings.set("chunk-size", "40")?;
+        let mut db = VectorDB::new();
+        db.set_settings(settings)?;
+        let mut retriever = Retriever::with_vector_db(db);
+        let mut load = |path: PathBuf, text: &str, modified: u64| -> Result<IngestReport> {
+            fs::write(&source, text)?;
+            File::options().write(true).open(&source)?.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))?;
+            runtime::block_on(load_files(&mut retriever, vec![path], Arc::new(Transforms::default())))?;
+            Ok(retriever.take_ingest_report())
+        };
+
+        let report = load(source.clone(), "Refunds take thirty days. Shipping is free.", 1_700_000_000)?;
+        assert_eq!(report, IngestReport { added: 2, ..IngestReport::default() });
+        // Another spelling of the same path is the same file
+        let report = load(dir.path().join(".").join("faq.txt"), "Refunds take thirty days. Shipping is free.", 1_700_000_000)?;
+        assert_eq!(report.added, 0);
+        // One sentence edited: its old chunk is replaced, not kept
+        let report = load(source.clone(), "Refunds take thirty days. Shipping costs five euros.", 1_800_000_000)?;
+        assert_eq!(report, IngestReport { updated: 2, ..IngestReport::default() });
+        assert_eq!(retriever.len(), 2);
+        assert!(retriever.retrieve("shipping", 2).iter().all(|chunk| !chunk.contains("free")));
+        Ok(())
+    }
+}
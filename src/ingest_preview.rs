@@ -0,0 +1,134 @@
+use anyhow::Result;
+use std::fmt;
+use std::path::Path;
+
+use crate::collection::CollectionSettings;
+use crate::loaders;
+use crate::metadata;
+use crate::tables;
+use crate::transforms::Transforms;
+
+/// Characters shown of each end of a chunk
+const EDGE_CHARS: usize = 60;
+
+/// How a file would be split into documents, for tuning the chunking
+/// settings before a long ingest
+#[derive(Debug)]
+pub struct IngestPreview {
+    pub source: String,
+    /// The settings the chunks come from, as `kb configure` shows them
+    pub settings: String,
+    pub chunks: Vec<ChunkPreview>,
+}
+
+#[derive(Debug)]
+pub struct ChunkPreview {
+    pub text: String,
+    /// Metadata indexed with the chunk, plus a table's columns and rows
+    pub metadata: Vec<(String, String)>,
+}
+
+impl IngestPreview {
+    /// Chunks `path` like `load_file` would, transforms included, without
+    /// embedding or indexing anything. Tables are split into chunks of at
+    /// most `table_chunk_chars`.
+    pub fn of_file(path: &Path, settings: &CollectionSettings, transforms: &Transforms, table_chunk_chars: usize) -> Result<Self> {
+        let source = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+        let mut file_metadata = metadata::of_file(path)?;
+        transforms.add_metadata(path, &mut file_metadata);
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        let chunks = if loaders::TEXT_EXTENSIONS.contains(&extension) {
+            let mut chunks = Vec::new();
+            for (text, metadata) in loaders::load_text(path, transforms, &file_metadata)? {
+                let metadata: Vec<(String, String)> = metadata.into_iter().collect();
+                chunks.extend(settings.chunk(&text).into_iter().map(|text| ChunkPreview { text, metadata: metadata.clone() }));
+            }
+            chunks
+        } else {
+            let file_metadata: Vec<(String, String)> = file_metadata.into_iter().collect();
+            let mut chunks = Vec::new();
+            for table in tables::load_tables(path)? {
+                chunks.extend(table.chunks(table_chunk_chars).into_iter().map(|chunk| ChunkPreview {
+                    text: chunk.markdown,
+                    metadata: file_metadata.iter().cloned().chain([
+                        ("table".to_string(), chunk.info.source),
+                        ("columns".to_string(), chunk.info.columns.join(", ")),
+                        ("rows".to_string(), format!("{}-{}", chunk.info.first_row + 1, chunk.info.first_row + chunk.info.row_count)),
+                    ]).collect(),
+                }));
+            }
+            chunks
+        };
+        Ok(IngestPreview { source, settings: settings.to_string(), chunks })
+    }
+
+    /// Smallest, mean and largest chunk in characters
+    pub fn sizes(&self) -> Option<(usize, usize, usize)> {
+        let sizes: Vec<usize> = self.chunks.iter().map(|chunk| chunk.text.chars().count()).collect();
+        let min = *sizes.iter().min()?;
+        let max = *sizes.iter().max()?;
+        Some((min, sizes.iter().sum::<usize>() / sizes.len(), max))
+    }
+}
+
+impl fmt::Display for IngestPreview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {} chunks with {}", self.source, self.chunks.len(), self.settings)?;
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            let chars = chunk.text.chars().count();
+            writeln!(f, "[{}] {} chars, {} words", i + 1, chars, chunk.text.split_whitespace().count())?;
+            if chars <= EDGE_CHARS * 2 {
+                writeln!(f, "    text: {}", one_line(&chunk.text))?;
+            } else {
+                let start: String = chunk.text.chars().take(EDGE_CHARS).collect();
+                let end: String = chunk.text.chars().skip(chars - EDGE_CHARS).collect();
+                writeln!(f, "    starts: {}...", one_line(&start))?;
+                writeln!(f, "    ends:   ...{}", one_line(&end))?;
+            }
+            for (key, value) in &chunk.metadata {
+                writeln!(f, "    {}: {}", key, value)?;
+            }
+        }
+        match self.sizes() {
+            Some((min, mean, max)) => write!(f, "Chunk sizes: min {}, mean {}, max {} chars; nothing was indexed", min, mean, max),
+            None => write!(f, "Nothing to index"),
+        }
+    }
+}
+
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_preview_matches_ingested_chunks() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("refunds.txt");
+        let text = "Refunds take thirty days. ".repeat(20);
+        fs::write(&path, &text)?;
+
+        let mut settings = CollectionSettings::default();
+        let whole = IngestPreview::of_file(&path, &settings, &Transforms::default(), 1500)?;
+        assert_eq!(whole.chunks.len(), 1);
+
+        settings.set("chunk-size", "100")?;
+        let preview = IngestPreview::of_file(&path, &settings, &Transforms::default(), 1500)?;
+        assert_eq!(preview.chunks.iter().map(|chunk| chunk.text.clone()).collect::<Vec<_>>(), settings.chunk(&text));
+        let (min, _, max) = preview.sizes().expect("chunks");
+        assert!(min > 0 && max <= 100);
+        let shown = preview.to_string();
+        assert!(shown.starts_with(&format!("refunds.txt: {} chunks with chunk-size=100", preview.chunks.len())));
+
+        let table = dir.path().join("prices.csv");
+        fs::write(&table, "plan,price\nbasic,5\npro,20\n")?;
+        let preview = IngestPreview::of_file(&table, &settings, &Transforms::default(), 1500)?;
+        assert_eq!(preview.chunks[0].metadata.last(), Some(&("rows".to_string(), "1-2".to_string())));
+        assert!(preview.chunks[0].metadata.contains(&("file_type".to_string(), "csv".to_string())));
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::vector_db::{IngestSegment, VectorDB};
+
+/// A file waiting to be chunked, embedded and indexed by a worker
+#[derive(Debug, Serialize, Deserialize)]
+struct TaskFile {
+    path: PathBuf,
+}
+
+/// A task a worker has claimed
+#[derive(Debug)]
+pub struct Task {
+    pub path: PathBuf,
+    /// The task's file under `claimed/`
+    claimed: PathBuf,
+}
+
+impl Task {
+    /// Refreshes the claim time every `interval` until the returned guard
+    /// is dropped, so `requeue_stale` leaves a slow task to its worker
+    pub fn heartbeat(&self, interval: Duration) -> Heartbeat {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let claimed = self.claimed.clone();
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // Gone when another worker took the task back; it then
+                // stays with that worker
+                let _ = touch(&claimed);
+            }
+        });
+        Heartbeat { stop: Some(stop), handle: Some(handle) }
+    }
+}
+
+/// Keeps a task's claim fresh; see `Task::heartbeat`. The thread is
+/// stopped and joined when dropped.
+pub struct Heartbeat {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// A queue of ingestion tasks in a directory shared by every worker, e.g.
+/// on NFS. Tasks are JSON files moved between `pending/`, `claimed/`,
+/// `done/` and `failed/` by rename, which is atomic, so each task is
+/// claimed by exactly one worker without any coordinator. Workers write
+/// what they embedded to `wal/` as segments, which are applied to the
+/// shared index under `index.lock`.
+pub struct IngestQueue {
+    dir: PathBuf,
+}
+
+impl IngestQueue {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let queue = IngestQueue { dir: dir.into() };
+        for sub in ["pending", "claimed", "done", "failed", "wal"] {
+            fs::create_dir_all(queue.dir.join(sub))?;
+        }
+        Ok(queue)
+    }
+
+    /// Adds a task for `path`, which must be readable by every worker
+    pub fn enqueue(&self, path: &Path) -> Result<()> {
+        let task = TaskFile { path: path.canonicalize()? };
+        // Names sort by enqueue time, so tasks are claimed in order
+        let name = format!("{}.json", unique_name());
+        let tmp = self.dir.join("pending").join(format!(".{}.tmp", name));
+        fs::write(&tmp, serde_json::to_vec(&task)?)?;
+        fs::rename(&tmp, self.dir.join("pending").join(name))?;
+        Ok(())
+    }
+
+    /// Claims the oldest pending task, or returns None when there is none.
+    /// Losing a race for a task to another worker moves on to the next one.
+    pub fn claim(&self) -> Result<Option<Task>> {
+        for name in sorted_names(&self.dir.join("pending"), ".json")? {
+            let claimed = self.dir.join("claimed").join(&name);
+            match fs::rename(self.dir.join("pending").join(&name), &claimed) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+            // The claim time, for `requeue_stale`
+            touch(&claimed)?;
+            let task: TaskFile = serde_json::from_slice(&fs::read(&claimed)?)?;
+            return Ok(Some(Task { path: task.path, claimed }));
+        }
+        Ok(None)
+    }
+
+    pub fn complete(&self, task: Task) -> Result<()> {
+        self.finish(&task, "done")
+    }
+
+    /// Moves a task to `failed/`, with the error next to it in a `.err` file
+    pub fn fail(&self, task: Task, error: &str) -> Result<()> {
+        self.finish(&task, "failed")?;
+        let name = task.claimed.file_name().ok_or_else(|| anyhow!("Invalid task file {:?}", task.claimed))?;
+        fs::write(self.dir.join("failed").join(name).with_extension("err"), error)?;
+        Ok(())
+    }
+
+    /// A claim that is gone was requeued as stale and is now another
+    /// worker's to finish, so that is no error
+    fn finish(&self, task: &Task, to: &str) -> Result<()> {
+        let name = task.claimed.file_name().ok_or_else(|| anyhow!("Invalid task file {:?}", task.claimed))?;
+        match fs::rename(&task.claimed, self.dir.join(to).join(name)) {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                tracing::warn!(path = ?task.path, "task was requeued while it ran");
+                Ok(())
+            }
+            result => Ok(result?),
+        }
+    }
+
+    /// Puts back tasks claimed longer than `max_age` ago, whose worker most
+    /// likely died, returning how many
+    pub fn requeue_stale(&self, max_age: Duration) -> Result<usize> {
+        let mut requeued = 0;
+        for name in sorted_names(&self.dir.join("claimed"), ".json")? {
+            let claimed = self.dir.join("claimed").join(&name);
+            let age = match fs::metadata(&claimed).and_then(|meta| meta.modified()) {
+                Ok(modified) => modified.elapsed().unwrap_or_default(),
+                Err(_) => continue,
+            };
+            if age > max_age && fs::rename(&claimed, self.dir.join("pending").join(&name)).is_ok() {
+                requeued += 1;
+            }
+        }
+        Ok(requeued)
+    }
+
+    /// Number of tasks not yet claimed
+    pub fn pending(&self) -> Result<usize> {
+        Ok(sorted_names(&self.dir.join("pending"), ".json")?.len())
+    }
+
+    /// Writes a segment to the write-ahead log. Once this returns, the
+    /// documents survive the worker crashing before they reach the index.
+    pub fn append(&self, segment: &IngestSegment) -> Result<()> {
+        segment.save(self.dir.join("wal").join(format!("{}.seg", unique_name())))
+    }
+
+    /// Applies every segment in the write-ahead log to the index at
+    /// `index_path`, oldest first, rebuilding it once, and deletes them once
+    /// the index is saved. Segments that can't be applied, such as those
+    /// embedded with another model, are moved to `failed/` with the error
+    /// in a `.err` file, so they don't hold up the others.
+    /// With `wait` false, returns Ok(None) if another worker is merging.
+    pub fn merge_into(&self, index_path: &Path, wait: bool) -> Result<Option<usize>> {
+        let lock = File::create(self.dir.join("index.lock"))?;
+        if wait {
+            lock.lock()?;
+        } else if lock.try_lock().is_err() {
+            return Ok(None);
+        }
+
+        let segments = sorted_names(&self.dir.join("wal"), ".seg")?;
+        if segments.is_empty() {
+            return Ok(Some(0));
+        }
+        let mut db = VectorDB::load(index_path)?;
+        let (mut batch, mut applied) = (Vec::new(), Vec::new());
+        for name in segments {
+            let path = self.dir.join("wal").join(&name);
+            let segment = IngestSegment::load(&path).and_then(|segment| db.check_segment(&segment).map(|_| segment));
+            match segment {
+                Ok(segment) => {
+                    batch.push(segment);
+                    applied.push(path);
+                }
+                // Reading may work next time; a bad segment never will
+                Err(e) if e.downcast_ref::<io::Error>().is_some() => return Err(e),
+                Err(e) => {
+                    tracing::warn!(segment = %name, error = %e, "moving unusable segment to failed/");
+                    fs::write(self.dir.join("failed").join(&name).with_extension("err"), e.to_string())?;
+                    fs::rename(&path, self.dir.join("failed").join(&name))?;
+                }
+            }
+        }
+        if batch.is_empty() {
+            return Ok(Some(0));
+        }
+        let documents = db.apply_segments(batch)?;
+        db.save(index_path)?;
+        // A crash before this point replays the segments, which keeps the
+        // same document IDs
+        for path in &applied {
+            fs::remove_file(path)?;
+        }
+        Ok(Some(documents))
+    }
+}
+
+/// Sets the modification time of the file at `path` to now
+fn touch(path: &Path) -> io::Result<()> {
+    File::options().write(true).open(path)?.set_modified(SystemTime::now())
+}
+
+/// Time-ordered unique file name: nanoseconds since the epoch and a UUID
+fn unique_name() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    format!("{:020}-{}", nanos, uuid::Uuid::new_v4().simple())
+}
+
+/// Names of the files in `dir` ending in `suffix`, skipping in-progress
+/// `.tmp` files, in sorted order
+fn sorted_names(dir: &Path, suffix: &str) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.ends_with(suffix) && !name.starts_with('.') {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tasks_are_claimed_once_and_segments_merged() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let queue = IngestQueue::open(dir.path().join("queue"))?;
+        let (first, second) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        fs::write(&first, "Refunds take thirty days")?;
+        fs::write(&second, "Orders ship on Monday")?;
+        queue.enqueue(&first)?;
+        queue.enqueue(&second)?;
+
+        let task = queue.claim()?.expect("pending task");
+        assert_eq!(task.path, first.canonicalize()?);
+        assert_eq!(queue.pending()?, 1);
+        queue.complete(task)?;
+        let task = queue.claim()?.expect("pending task");
+        assert_eq!(queue.requeue_stale(Duration::from_secs(3600))?, 0);
+        queue.fail(task, "unreadable")?;
+        assert!(queue.claim()?.is_none());
+
+        let index_path = dir.path().join("kb.bin");
+        let mut shared = VectorDB::new();
+        shared.add_document("Support answers email".to_string())?;
+        shared.save(&index_path)?;
+        let mut worker = VectorDB::new();
+        worker.add_document(fs::read_to_string(&first)?)?;
+        queue.append(&worker.to_segment())?;
+        // A segment that can never be applied is set aside, not retried
+        fs::write(dir.path().join("queue/wal/00000000000000000000-bad.seg"), "not a segment")?;
+        assert_eq!(queue.merge_into(&index_path, true)?, Some(1));
+        assert_eq!(queue.merge_into(&index_path, true)?, Some(0));
+        assert!(dir.path().join("queue/failed/00000000000000000000-bad.err").exists());
+
+        let merged = VectorDB::load(&index_path)?;
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged.search_similar("refunds", 1)[0].content, "Refunds take thirty days");
+        Ok(())
+    }
+
+    #[test]
+    fn test_heartbeat_keeps_slow_tasks_claimed() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let queue = IngestQueue::open(dir.path().join("queue"))?;
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "Refunds take thirty days")?;
+        queue.enqueue(&file)?;
+        queue.enqueue(&file)?;
+
+        let task = queue.claim()?.expect("pending task");
+        File::options().write(true).open(&task.claimed)?.set_modified(SystemTime::now() - Duration::from_secs(7200))?;
+        let heartbeat = task.heartbeat(Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(100));
+        drop(heartbeat);
+        assert_eq!(queue.requeue_stale(Duration::from_secs(3600))?, 0);
+        queue.complete(task)?;
+
+        // A task requeued while it ran is the next worker's to finish
+        let task = queue.claim()?.expect("pending task");
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(queue.requeue_stale(Duration::ZERO)?, 1);
+        queue.complete(task)?;
+        assert_eq!(queue.pending()?, 1);
+        Ok(())
+    }
+}
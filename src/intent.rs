@@ -0,0 +1,114 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::llm::LLM;
+
+/// Longest query, in words, sent to the LLM check; longer ones are assumed
+/// to be questions about the documents
+const LLM_CHECK_MAX_WORDS: usize = 12;
+
+lazy_static! {
+    /// Clauses that are small talk or questions about the assistant itself
+    static ref CHIT_CHAT: Regex = Regex::new(
+        r"(?x)^(
+            hi|hello|hey|hiya|howdy|yo|greetings|good\ (morning|afternoon|evening|day)
+            |(hi|hello|hey)\ there
+            |thanks?(\ you)?(\ (so|very)\ much)?|thx|cheers|ok(ay)?|cool|great|nice|perfect
+            |bye|goodbye|see\ you|good\ ?night
+            |how\ are\ you(\ doing)?(\ today)?|how's\ it\ going|what's\ up|sup
+            |who\ are\ you|what\ are\ you|what('s|\ is)\ your\ name
+            |what\ can\ you\ do|what\ do\ you\ do|how\ (can|do)\ you\ help(\ me)?|how\ do\ you\ work
+            |what\ (can|should)\ i\ ask(\ you)?|help
+        )$"
+    ).unwrap();
+}
+
+/// Whether a question needs context from the knowledge base
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Intent {
+    Retrieval,
+    /// Greetings, thanks and questions about the assistant, answered
+    /// without retrieved context
+    ChitChat,
+}
+
+/// Decides which questions skip retrieval, so "hello, what can you do?"
+/// isn't answered from whatever chunks happen to be closest to it. Rules
+/// catch the common phrasings; the optional LLM check covers short queries
+/// they don't.
+#[derive(Debug, Default)]
+pub struct IntentClassifier {
+    llm_check: bool,
+}
+
+impl IntentClassifier {
+    pub fn new(llm_check: bool) -> Self {
+        IntentClassifier { llm_check }
+    }
+
+    /// The intent of `query`. A failed LLM check is logged and the query
+    /// is treated as needing retrieval.
+    pub fn classify(&self, query: &str, llm: &LLM) -> Intent {
+        if let Some(intent) = rules(query) {
+            return intent;
+        }
+        if !self.llm_check || query.split_whitespace().count() > LLM_CHECK_MAX_WORDS {
+            return Intent::Retrieval;
+        }
+        match llm.classify(llm_prompt(query)) {
+            Ok(reply) => parse_reply(&reply),
+            Err(e) => {
+                tracing::warn!(error = %e, "intent check failed");
+                Intent::Retrieval
+            }
+        }
+    }
+}
+
+/// `ChitChat` when every clause of `query` is small talk, `None` when the
+/// rules can't tell
+fn rules(query: &str) -> Option<Intent> {
+    let query = query.to_lowercase().replace('’', "'");
+    let mut clauses = query
+        .split([',', '.', '!', '?', ';', ':'])
+        .map(|clause| clause.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|clause| !clause.is_empty())
+        .peekable();
+    clauses.peek()?;
+    clauses.all(|clause| CHIT_CHAT.is_match(&clause)).then_some(Intent::ChitChat)
+}
+
+fn llm_prompt(query: &str) -> String {
+    format!(
+        "A user sent the message below to an assistant that answers questions from a document collection. \
+         Reply CHAT if it is a greeting, thanks, small talk or a question about the assistant itself, \
+         or DOCS if answering it could need the documents.\n\nMessage:\n{}",
+        query
+    )
+}
+
+fn parse_reply(reply: &str) -> Intent {
+    if reply.to_uppercase().contains("CHAT") && !reply.to_uppercase().contains("DOCS") {
+        Intent::ChitChat
+    } else {
+        Intent::Retrieval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_only_skip_small_talk() {
+        for query in ["Hello!", "hey there, what can you do?", "Thanks so much.", "Who are you", "good morning. how are you?"] {
+            assert_eq!(rules(query), Some(Intent::ChitChat), "{}", query);
+        }
+        for query in ["hi, how do refunds work?", "What can you tell me about shipping?", "help with my invoice", "", "?!"] {
+            assert_eq!(rules(query), None, "{}", query);
+        }
+        assert_eq!(parse_reply("CHAT"), Intent::ChitChat);
+        assert_eq!(parse_reply("docs"), Intent::Retrieval);
+        assert_eq!(parse_reply("I'm not sure"), Intent::Retrieval);
+    }
+}
@@ -0,0 +1,134 @@
+//! Retrieval-augmented generation over local documents with a local model.
+//!
+//! The `tapssp` binary is a thin CLI over this library; other programs can
+//! embed the same pipeline:
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use tapssp::{LLMConfig, RagPipeline};
+//!
+//! let pipeline = RagPipeline::builder()
+//!     .llm_config(LLMConfig { max_tokens: 256, ..LLMConfig::default() })
+//!     .model("models/mistral-7b-instruct-v0.1.Q4_K_M.gguf")
+//!     .document("Refunds are paid within thirty days of the return.")
+//!     .top_k(5)
+//!     .build()?;
+//! let (_, answer) = pipeline.answer("How long do refunds take?")?;
+//! println!("{}", answer);
+//! # Ok(())
+//! # }
+//! ```
+
+pub(crate) mod retriever;
+pub(crate) mod vector_db;
+pub(crate) mod llm;
+#[cfg(feature = "llama")]
+pub(crate) mod llama;
+pub(crate) mod utils;
+pub(crate) mod templates;
+pub(crate) mod pipeline;
+pub(crate) mod timings;
+pub(crate) mod telemetry;
+pub(crate) mod maintenance;
+pub(crate) mod object_store;
+pub(crate) mod config;
+#[cfg(feature = "server")]
+pub(crate) mod server;
+#[cfg(feature = "server")]
+pub(crate) mod systemd;
+pub(crate) mod profile;
+pub(crate) mod faq;
+pub(crate) mod escalation;
+pub(crate) mod webhooks;
+pub(crate) mod tables;
+pub(crate) mod sessions;
+pub(crate) mod training;
+pub(crate) mod embeddings;
+pub(crate) mod migration;
+pub(crate) mod spelling;
+pub(crate) mod synonyms;
+pub(crate) mod normalize;
+pub(crate) mod highlight;
+pub(crate) mod explain;
+#[cfg(feature = "server")]
+pub(crate) mod conversations;
+pub(crate) mod injection;
+pub(crate) mod moderation;
+pub(crate) mod collection;
+pub(crate) mod chunking;
+pub(crate) mod federation;
+pub(crate) mod snapshots;
+pub(crate) mod corpus_diff;
+#[cfg(feature = "server")]
+pub(crate) mod openai;
+pub(crate) mod import;
+#[cfg(feature = "server")]
+pub(crate) mod remote_retriever;
+#[cfg(feature = "llama")]
+pub(crate) mod download;
+pub(crate) mod ingest_queue;
+pub(crate) mod build;
+pub(crate) mod ingest_preview;
+pub(crate) mod metadata;
+pub(crate) mod citations;
+pub(crate) mod rerank;
+pub(crate) mod loaders;
+pub(crate) mod routing;
+pub(crate) mod transforms;
+pub(crate) mod context_fit;
+pub(crate) mod intent;
+pub(crate) mod answer_format;
+pub(crate) mod i18n;
+#[cfg(feature = "ollama")]
+pub(crate) mod ollama;
+#[cfg(feature = "openai")]
+pub(crate) mod openai_client;
+pub(crate) mod resources;
+pub(crate) mod device;
+pub(crate) mod watch;
+pub(crate) mod model_swap;
+pub(crate) mod search;
+pub(crate) mod answer_cache;
+pub(crate) mod planner;
+pub(crate) mod anonymize;
+pub(crate) mod prompt_template;
+pub(crate) mod fixtures;
+pub(crate) mod repeats;
+pub(crate) mod setup;
+pub(crate) mod completions;
+pub(crate) mod status_line;
+pub(crate) mod access_log;
+pub(crate) mod discovery;
+pub(crate) mod ingest;
+
+/// The `tapssp` command line, run by the binary
+#[doc(hidden)]
+pub mod cli;
+
+pub use answer_format::AnswerFormat;
+pub use chunking::ChunkStrategy;
+pub use citations::{Source, Sources};
+pub use collection::CollectionSettings;
+pub use embeddings::Embedder;
+pub use escalation::Outcome;
+pub use faq::{Faq, FaqEntry};
+pub use highlight::{Highlight, HighlightKind};
+pub use llm::{
+    Backend, Conversation, GenerationLimits, GenerationOverrides, InferenceOptions, LLM, LLMBackend, LLMConfig,
+    SamplingProfile, Stage, StageProfiles, Turn,
+};
+pub use metadata::{Filter, Op};
+pub use moderation::{Moderator, Verdict};
+#[cfg(feature = "ollama")]
+pub use ollama::OllamaBackend;
+#[cfg(feature = "openai")]
+pub use openai_client::OpenAiBackend;
+pub use pipeline::{PipelineBuilder, RagPipeline};
+pub use planner::{Budget, ModelChoice, Plan};
+pub use prompt_template::PromptTemplate;
+pub use retriever::{Fusion, Retriever, ScoredChunk};
+pub use synonyms::Synonyms;
+pub use tables::TableInfo;
+pub use timings::Timings;
+pub use vector_db::{Document, HnswParams, IndexDelta, IngestReport, SearchMode, VectorDB};
+pub(crate) mod runtime;
@@ -0,0 +1,144 @@
+use anyhow::{Result, anyhow};
+use llama_rs::{
+    Model, ModelParams, InferenceParams, InferenceSession,
+    InferenceRequest, InferenceResponse, InferenceError, TokenId
+};
+use std::ops::ControlFlow;
+use std::{path::PathBuf, sync::Arc};
+
+use crate::download;
+use crate::llm::{Backend, DEFAULT_MODEL_FILE, DEFAULT_MODEL_URL, InferenceOptions, LLM, LLMBackend, LLMConfig};
+use crate::resources::{self, MemoryUsage};
+use crate::runtime;
+
+/// A GGUF model run in-process with llama-rs
+struct LlamaBackend {
+    model: Arc<Model>,
+}
+
+/// Loads the configured GGUF model, downloading the default one when no path
+/// is set. The context window may be shrunk to fit the memory available.
+pub fn load(mut config: LLMConfig) -> Result<LLM> {
+    // If model path not provided, download and use default model
+    if config.model_path.is_none() {
+        config.model_path = Some(get_default_model(config.models_dir.clone(), config.model_sha256.as_deref())?);
+    }
+
+    let model_path = config.model_path.as_ref()
+        .ok_or_else(|| anyhow!("Model path not set"))?;
+
+    if !model_path.exists() {
+        return Err(anyhow!("Model file not found at {:?}", model_path));
+    }
+
+    let n_gpu_layers = match config.backend {
+        Backend::Cpu => 0,
+        _ => config.n_gpu_layers,
+    };
+    if n_gpu_layers > 0 && !config.backend.is_compiled() {
+        return Err(anyhow!(
+            "GPU backend {:?} is not compiled in; rebuild with `--features {}`",
+            config.backend,
+            format!("{:?}", config.backend).to_lowercase()
+        ));
+    }
+    if n_gpu_layers > 0 && config.tensor_split.len() > 1 && config.backend == Backend::Metal {
+        return Err(anyhow!("Metal drives a single GPU; --tensor-split needs the cuda or vulkan backend"));
+    }
+    if !config.tensor_split.is_empty() && config.main_gpu >= config.tensor_split.len() {
+        return Err(anyhow!(
+            "--main-gpu {} is not among the {} GPUs of --tensor-split",
+            config.main_gpu,
+            config.tensor_split.len()
+        ));
+    }
+    // Weights offloaded to a GPU aren't in RAM, so only a CPU-only load
+    // can be checked against the memory available
+    if n_gpu_layers == 0 && config.memory_reserve > 0 && let Some(usage) = MemoryUsage::sample() {
+        let model_bytes = std::fs::metadata(model_path)?.len();
+        config.context_tokens = resources::plan_context(model_bytes, config.context_tokens, usage.available, config.memory_reserve)?;
+    }
+    let model_params = ModelParams {
+        n_ctx: config.context_tokens,
+        n_gpu_layers,
+        main_gpu: config.main_gpu,
+        tensor_split: config.tensor_split.clone(),
+        ..ModelParams::default()
+    };
+    let model = Model::load(model_path, model_params)?;
+    let rss_mib = MemoryUsage::sample().map(|usage| usage.rss / (1024 * 1024));
+    tracing::info!(backend = ?config.backend, n_gpu_layers, main_gpu = config.main_gpu, tensor_split = ?config.tensor_split, n_ctx = config.context_tokens, ?rss_mib, "loaded model");
+
+    Ok(LLM::with_backend(Arc::new(LlamaBackend { model: Arc::new(model) }), config))
+}
+
+impl LLMBackend for LlamaBackend {
+    fn infer(&self, prompt: String, options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+        let inference_params = InferenceParams {
+            n_threads: num_cpus::get(),  // Use all available CPU cores
+            n_tokens: options.max_tokens,
+            temperature: options.temperature,
+            top_p: options.top_p,
+            repeat_penalty: options.repeat_penalty,
+            seed: options.seed,
+            ..InferenceParams::default()
+        };
+        let mut session = InferenceSession::new(self.model.clone(), inference_params)?;
+        let result = session.infer::<StopGeneration>(
+            InferenceRequest::from_prompt(prompt),
+            |r| match r {
+                InferenceResponse::InferredToken(token) => match on_piece(&token) {
+                    ControlFlow::Continue(()) => Ok(()),
+                    ControlFlow::Break(()) => Err(StopGeneration),
+                },
+                InferenceResponse::EotToken => Ok(()),
+            },
+        );
+        match result {
+            Ok(_) | Err(InferenceError::UserCallback(_)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        let tokens: Vec<TokenId> = self.model.tokenize(text, false).ok()?;
+        Some(tokens.len())
+    }
+}
+
+/// The default model in `models_dir` (the cache directory if unset),
+/// downloaded first if it isn't there yet. The download must match
+/// `sha256` when given, else the hash Hugging Face publishes.
+pub fn get_default_model(models_dir: Option<PathBuf>, sha256: Option<&str>) -> Result<PathBuf> {
+    let models_dir = match models_dir {
+        Some(dir) => dir,
+        None => dirs::cache_dir()
+            .ok_or_else(|| anyhow!("Could not determine cache directory"))?
+            .join("tapssp-project")
+            .join("models"),
+    };
+
+    std::fs::create_dir_all(&models_dir)?;
+    
+    let model_path = models_dir.join(DEFAULT_MODEL_FILE);
+    
+    if !model_path.exists() {
+        println!("Downloading Mistral 7B model...");
+        runtime::block_on(download::download(DEFAULT_MODEL_URL, &model_path, sha256))?;
+        println!("Model downloaded successfully!");
+    }
+
+    Ok(model_path)
+}
+
+/// Returned from the token callback to halt inference early
+#[derive(Debug)]
+struct StopGeneration;
+
+impl std::fmt::Display for StopGeneration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "generation stopped")
+    }
+}
+
+impl std::error::Error for StopGeneration {}
@@ -1,131 +1,1077 @@
 use anyhow::{Result, anyhow};
-use llama_rs::{
-    Model, ModelParams, InferenceParams, InferenceSession,
-    InferenceRequest, InferenceResponse, TokenId
-};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
 use std::{path::PathBuf, sync::Arc};
-use std::io::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::answer_format::AnswerFormat;
+use crate::context_fit;
+use crate::i18n;
+use crate::injection;
+#[cfg(feature = "llama")]
+use crate::llama;
+use crate::prompt_template::PromptTemplate;
+use crate::resources;
+use crate::timings::Timings;
+use crate::utils::contains_verbatim_block;
+
+/// GGUF file of the model downloaded when none is configured
+pub const DEFAULT_MODEL_FILE: &str = "mistral-7b-instruct-v0.1.Q4_K_M.gguf";
+#[cfg_attr(not(feature = "llama"), allow(dead_code))]
+pub const DEFAULT_MODEL_URL: &str =
+    "https://huggingface.co/TheBloke/Mistral-7B-Instruct-v0.1-GGUF/resolve/main/mistral-7b-instruct-v0.1.Q4_K_M.gguf";
+/// Temperature added for each consecutive `/retry` of the same question
+const RETRY_TEMPERATURE_STEP: f32 = 0.15;
+const MAX_RETRY_TEMPERATURE: f32 = 1.5;
+/// Length cap for conversation summaries
+const SUMMARY_MAX_TOKENS: usize = 256;
+/// Length cap for classification replies, which are a few labels
+const CLASSIFY_MAX_TOKENS: usize = 32;
+/// Fewest answer tokens a prompt must leave room for in the context window
+const MIN_ANSWER_TOKENS: usize = 64;
+/// Appended to answers cut short by `RepetitionGuard`
+const REPETITION_NOTICE: &str = "\n\n[Answer truncated: the model started repeating itself]";
+/// Appended when generation stops because memory ran low
+const MEMORY_NOTICE: &str = "\n\n[Answer truncated: the system is running out of memory. \
+    Close other programs, or lower --context-size or --max-tokens]";
+/// Generated pieces between checks of the available memory
+const MEMORY_CHECK_INTERVAL: usize = 32;
 
 pub struct LLMConfig {
     pub model_path: Option<PathBuf>,
+    /// Where the default model is downloaded when `model_path` is unset;
+    /// defaults to the user cache directory
+    pub models_dir: Option<PathBuf>,
+    /// SHA256 the downloaded default model is checked against
+    pub model_sha256: Option<String>,
     pub max_tokens: usize,
+    /// Tokens the model attends to; the prompt and the answer share them
+    pub context_tokens: usize,
+    /// Transformer layers offloaded to `backend`; 0 runs on the CPU alone
+    pub n_gpu_layers: usize,
+    pub backend: Backend,
+    /// GPU holding the KV cache and scratch buffers; with a single GPU, the
+    /// one all offloaded layers go to
+    pub main_gpu: usize,
+    /// Share of the offloaded layers each GPU takes, summing to 1; empty
+    /// keeps them on `main_gpu`
+    pub tensor_split: Vec<f32>,
+    /// Temperature and top_p for each kind of generation
+    pub profiles: StageProfiles,
+    pub repeat_penalty: f32,
+    /// Generation stops once any run of this many words has occurred more
+    /// than `max_ngram_repeats` times; 0 disables the check
+    pub repetition_ngram: usize,
+    pub max_ngram_repeats: usize,
+    /// Bounds for per-request overrides
+    pub limits: GenerationLimits,
+    /// Memory kept free: the context window shrinks at load time, and
+    /// generation stops, rather than eating into it; 0 disables the checks
+    pub memory_reserve: u64,
+    /// Chat format of the model's prompts
+    pub prompt_template: PromptTemplate,
+}
+
+/// GPU API layers are offloaded through. Each one needs the llama-rs build
+/// of the matching cargo feature (`cuda`, `metal` or `vulkan`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Cpu,
+    Cuda,
+    Metal,
+    Vulkan,
+}
+
+impl Backend {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "cpu" => Ok(Backend::Cpu),
+            "cuda" => Ok(Backend::Cuda),
+            "metal" => Ok(Backend::Metal),
+            "vulkan" => Ok(Backend::Vulkan),
+            _ => Err(anyhow!("Unknown GPU backend '{}' (cpu, cuda, metal or vulkan)", name)),
+        }
+    }
+
+    pub fn is_compiled(self) -> bool {
+        match self {
+            Backend::Cpu => true,
+            Backend::Cuda => cfg!(feature = "cuda"),
+            Backend::Metal => cfg!(feature = "metal"),
+            Backend::Vulkan => cfg!(feature = "vulkan"),
+        }
+    }
+}
+
+impl Default for Backend {
+    /// The first GPU backend this binary was built with, else the CPU
+    fn default() -> Self {
+        [Backend::Cuda, Backend::Metal, Backend::Vulkan]
+            .into_iter()
+            .find(|backend| backend.is_compiled())
+            .unwrap_or(Backend::Cpu)
+    }
+}
+
+/// Upper bounds that per-request `GenerationOverrides` must respect
+#[derive(Debug, Clone)]
+pub struct GenerationLimits {
+    pub max_tokens: usize,
+    pub max_temperature: f32,
+    pub max_stop_sequences: usize,
+    pub max_system_prompt_chars: usize,
+}
+
+impl Default for GenerationLimits {
+    fn default() -> Self {
+        Self {
+            max_tokens: 4096,
+            max_temperature: 2.0,
+            max_stop_sequences: 4,
+            max_system_prompt_chars: 4000,
+        }
+    }
+}
+
+/// Temperature and nucleus size suited to one kind of generation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplingProfile {
     pub temperature: f32,
     pub top_p: f32,
-    pub repeat_penalty: f32,
+}
+
+impl SamplingProfile {
+    /// Near-greedy, for labels and extracted values that must come out the
+    /// same every time
+    pub const DETERMINISTIC: Self = SamplingProfile { temperature: 0.1, top_p: 0.5 };
+    /// Stays close to the most likely wording, for answers grounded in context
+    pub const CONSERVATIVE: Self = SamplingProfile { temperature: 0.4, top_p: 0.85 };
+    /// Freer wording, for summaries
+    pub const CREATIVE: Self = SamplingProfile { temperature: 0.8, top_p: 0.95 };
+}
+
+/// What a generation is for, which picks its `SamplingProfile`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Answers to questions, from retrieved context
+    Answer,
+    /// Conversation summaries
+    Summary,
+    /// Short labels, e.g. moderation categories
+    Classification,
+}
+
+/// The sampling profile of each stage
+#[derive(Debug, Clone)]
+pub struct StageProfiles {
+    pub answer: SamplingProfile,
+    pub summary: SamplingProfile,
+    pub classification: SamplingProfile,
+}
+
+impl Default for StageProfiles {
+    fn default() -> Self {
+        StageProfiles {
+            answer: SamplingProfile::CONSERVATIVE,
+            summary: SamplingProfile::CREATIVE,
+            classification: SamplingProfile::DETERMINISTIC,
+        }
+    }
+}
+
+impl StageProfiles {
+    pub fn get(&self, stage: Stage) -> SamplingProfile {
+        match stage {
+            Stage::Answer => self.answer,
+            Stage::Summary => self.summary,
+            Stage::Classification => self.classification,
+        }
+    }
+}
+
+/// Sampling parameters a single request (HTTP call or REPL session) may
+/// override for its answer; unset fields fall back to `LLMConfig`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct GenerationOverrides {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<usize>,
+    pub top_p: Option<f32>,
+    pub stop: Vec<String>,
+    pub system_prompt: Option<String>,
+    /// Shape of the answer, see `AnswerFormat`
+    pub format: Option<AnswerFormat>,
+    /// Fixed sampling seed, so the same prompt gets the same answer
+    pub seed: Option<u64>,
+}
+
+impl GenerationOverrides {
+    /// Sets one parameter from REPL input, e.g. `("temperature", "0.2")`.
+    /// `stop` appends a sequence; an empty value clears the parameter.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = value.trim();
+        match (key, value.is_empty()) {
+            ("temperature", true) => self.temperature = None,
+            ("temperature", false) => self.temperature = Some(parse_param(key, value)?),
+            ("max_tokens", true) => self.max_tokens = None,
+            ("max_tokens", false) => self.max_tokens = Some(parse_param(key, value)?),
+            ("top_p", true) => self.top_p = None,
+            ("top_p", false) => self.top_p = Some(parse_param(key, value)?),
+            ("stop", true) => self.stop.clear(),
+            ("stop", false) => self.stop.push(value.replace("\\n", "\n")),
+            ("system", true) => self.system_prompt = None,
+            ("system", false) => self.system_prompt = Some(value.to_string()),
+            ("format", true) => self.format = None,
+            ("format", false) => self.format = Some(AnswerFormat::parse(value)?),
+            ("seed", true) => self.seed = None,
+            ("seed", false) => self.seed = Some(parse_param(key, value)?),
+            _ => return Err(anyhow!("Unknown parameter '{}' (temperature, max_tokens, top_p, stop, system, format, seed)", key)),
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self, limits: &GenerationLimits) -> Result<()> {
+        if let Some(t) = self.temperature
+            && !(0.0..=limits.max_temperature).contains(&t)
+        {
+            return Err(anyhow!("temperature must be between 0 and {}", limits.max_temperature));
+        }
+        if let Some(n) = self.max_tokens
+            && !(1..=limits.max_tokens).contains(&n)
+        {
+            return Err(anyhow!("max_tokens must be between 1 and {}", limits.max_tokens));
+        }
+        if let Some(p) = self.top_p
+            && !(p > 0.0 && p <= 1.0)
+        {
+            return Err(anyhow!("top_p must be in (0, 1]"));
+        }
+        if self.stop.len() > limits.max_stop_sequences || self.stop.iter().any(|s| s.is_empty()) {
+            return Err(anyhow!("at most {} non-empty stop sequences are allowed", limits.max_stop_sequences));
+        }
+        if let Some(system) = &self.system_prompt
+            && system.chars().count() > limits.max_system_prompt_chars
+        {
+            return Err(anyhow!("system_prompt exceeds {} characters", limits.max_system_prompt_chars));
+        }
+        Ok(())
+    }
+}
+
+/// The `/set` parameters in the `key: value` form `/profile` shows
+impl std::fmt::Display for GenerationOverrides {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |value: Option<String>| value.unwrap_or_else(|| "(unset)".to_string());
+        let stop = (!self.stop.is_empty()).then(|| self.stop.iter().map(|s| format!("{:?}", s)).collect::<Vec<_>>().join(", "));
+        write!(
+            f,
+            "temperature: {}\nmax_tokens: {}\ntop_p: {}\nstop: {}\nsystem: {}\nformat: {}\nseed: {}",
+            show(self.temperature.map(|t| t.to_string())),
+            show(self.max_tokens.map(|n| n.to_string())),
+            show(self.top_p.map(|p| p.to_string())),
+            show(stop),
+            show(self.system_prompt.clone()),
+            show(self.format.map(|format| format.name().to_string())),
+            show(self.seed.map(|seed| seed.to_string())),
+        )
+    }
+}
+
+fn parse_param<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| anyhow!("Invalid value '{}' for {}", value, key))
+}
+
+/// Fully resolved parameters for one generation
+#[derive(Debug, Clone)]
+struct Sampling {
+    temperature: f32,
+    top_p: f32,
+    max_tokens: usize,
+    seed: Option<u64>,
+    stop: Vec<String>,
+    system_prompt: Option<String>,
+    format: Option<AnswerFormat>,
 }
 
 impl Default for LLMConfig {
     fn default() -> Self {
         Self {
             model_path: None,
+            models_dir: None,
+            model_sha256: None,
             max_tokens: 1000,
-            temperature: 0.7,
-            top_p: 0.9,
+            context_tokens: 2048,
+            n_gpu_layers: 0,
+            backend: Backend::default(),
+            main_gpu: 0,
+            tensor_split: Vec::new(),
+            profiles: StageProfiles::default(),
             repeat_penalty: 1.1,
+            repetition_ngram: 8,
+            max_ngram_repeats: 3,
+            limits: GenerationLimits::default(),
+            memory_reserve: 256 * 1024 * 1024,
+            prompt_template: PromptTemplate::default(),
         }
     }
 }
 
+/// Source of generated text behind `LLM`, which builds the prompts and
+/// applies stop sequences, the repetition guard and timings on top
+pub trait LLMBackend: Send + Sync {
+    /// Completes `prompt`, rendered with the configured `PromptTemplate`, passing
+    /// text to `on_piece` as it is decoded until it returns `Break`
+    fn infer(&self, prompt: String, options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()>;
+
+    /// Tokens `text` takes in the model's vocabulary, where the backend can
+    /// tokenize it locally; `LLM` estimates the count otherwise
+    fn count_tokens(&self, _text: &str) -> Option<usize> {
+        None
+    }
+
+    /// Whether the backend can generate at all; see `LLM::unavailable`
+    fn is_available(&self) -> bool {
+        true
+    }
+}
+
+/// Stands in for a model that couldn't be loaded, failing every completion
+/// with the reason
+struct NoModel {
+    reason: String,
+}
+
+impl LLMBackend for NoModel {
+    fn infer(&self, _prompt: String, _options: &InferenceOptions, _on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+        Err(anyhow!("No model is loaded ({}); only search is available", self.reason))
+    }
+
+    fn is_available(&self) -> bool {
+        false
+    }
+}
+
+/// Sampling parameters of one completion
+#[derive(Debug, Clone)]
+pub struct InferenceOptions {
+    pub max_tokens: usize,
+    pub temperature: f32,
+    pub top_p: f32,
+    pub repeat_penalty: f32,
+    /// Fixed seed; the backend picks one when unset
+    pub seed: Option<u64>,
+}
+
 pub struct LLM {
-    model: Arc<Model>,
+    backend: Arc<dyn LLMBackend>,
     config: LLMConfig,
 }
 
 impl LLM {
-    pub fn new(mut config: LLMConfig) -> Result<Self> {
-        // If model path not provided, download and use default model
-        if config.model_path.is_none() {
-            config.model_path = Some(Self::get_default_model()?);
-        }
+    pub fn new(config: LLMConfig) -> Result<Self> {
+        #[cfg(feature = "llama")]
+        return llama::load(config);
+        #[cfg(not(feature = "llama"))]
+        Err(anyhow!(
+            "Can't load {}: tapssp was built without the `llama` feature; use a remote backend such as --backend ollama",
+            config.model_path.as_deref().map_or("the default model".into(), |path| path.display().to_string())
+        ))
+    }
 
-        let model_path = config.model_path.as_ref()
-            .ok_or_else(|| anyhow!("Model path not set"))?;
+    /// Generates with `backend` instead of a local GGUF model; the model
+    /// and GPU settings of `config` are left to the backend
+    pub fn with_backend(backend: Arc<dyn LLMBackend>, config: LLMConfig) -> Self {
+        LLM { backend, config }
+    }
 
-        if !model_path.exists() {
-            return Err(anyhow!("Model file not found at {:?}", model_path));
+    /// A placeholder for a model that failed to load because of `reason`,
+    /// so retrieval keeps working; generating returns an error
+    pub fn unavailable(reason: impl Into<String>) -> Self {
+        LLM::with_backend(Arc::new(NoModel { reason: reason.into() }), LLMConfig::default())
+    }
+
+    /// Whether answers can be generated, false for `LLM::unavailable`
+    pub fn is_available(&self) -> bool {
+        self.backend.is_available()
+    }
+
+    /// The GGUF file answers are generated with, or the model name with the
+    /// Ollama backend
+    pub fn model_path(&self) -> Option<&std::path::Path> {
+        self.config.model_path.as_deref()
+    }
+
+    /// Tokens the model attends to, shared by the prompt and the answer
+    pub fn context_tokens(&self) -> usize {
+        self.config.context_tokens
+    }
+
+    pub fn generate_response(&self, query: &str, context: Vec<String>) -> Result<String> {
+        self.generate(query, context, None, &GenerationOverrides::default(), &mut Timings::new())
+    }
+
+    /// Generates an answer following the prior turns in `history`, with
+    /// per-call parameter overrides, recording "prompt build", "prompt eval"
+    /// (up to the first token) and "decode" stage timings
+    pub fn generate(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        overrides: &GenerationOverrides,
+        timings: &mut Timings,
+    ) -> Result<String> {
+        self.generate_streaming(query, context, history, overrides, timings, |_| ControlFlow::Continue(()))
+    }
+
+    /// `generate`, passing the answer text to `on_token` as it is decoded.
+    /// Text that could be the start of a stop sequence is held back until
+    /// it is known not to be one, so the pieces add up to the returned answer.
+    /// Generation stops early, keeping the text so far, once `on_token`
+    /// returns `Break`.
+    pub fn generate_streaming(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        overrides: &GenerationOverrides,
+        timings: &mut Timings,
+        mut on_token: impl FnMut(&str) -> ControlFlow<()>,
+    ) -> Result<String> {
+        let sampling = self.sampling(Stage::Answer, overrides)?;
+        self.generate_sampled(query, context, history, &sampling, timings, &mut on_token)
+    }
+
+    /// Checks overrides against the configured `GenerationLimits`
+    pub fn validate_overrides(&self, overrides: &GenerationOverrides) -> Result<()> {
+        overrides.validate(&self.config.limits)
+    }
+
+    /// Regenerates an answer with a fresh seed and a temperature raised by
+    /// `RETRY_TEMPERATURE_STEP` per attempt, so retries actually differ
+    pub fn regenerate_response(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        attempt: usize,
+        overrides: &GenerationOverrides,
+    ) -> Result<String> {
+        let mut sampling = self.retry_sampling(overrides, attempt)?;
+        sampling.seed = Some(fresh_seed());
+        self.generate_sampled(query, context, history, &sampling, &mut Timings::new(), &mut |_| ControlFlow::Continue(()))
+    }
+
+    /// The parameters used for an answer generated with `overrides`, on
+    /// retry `attempt` (0 for the first answer), in human-readable form
+    pub fn describe_sampling(&self, overrides: &GenerationOverrides, attempt: usize) -> Result<String> {
+        let sampling = self.retry_sampling(overrides, attempt)?;
+        let mut description = format!(
+            "temperature {:.2}, top_p {:.2}, max_tokens {}, repeat_penalty {:.2}",
+            sampling.temperature, sampling.top_p, sampling.max_tokens, self.config.repeat_penalty
+        );
+        if !sampling.stop.is_empty() {
+            description.push_str(&format!(", stop {:?}", sampling.stop));
+        }
+        if sampling.system_prompt.is_some() {
+            description.push_str(", custom system prompt");
+        }
+        if let Some(format) = sampling.format {
+            description.push_str(&format!(", {} format", format.name()));
+        }
+        if attempt > 0 {
+            description.push_str(&format!(", retry {} with a fresh seed", attempt));
         }
+        Ok(description)
+    }
 
-        let model_params = ModelParams::default();
-        let model = Model::load(&model_path, model_params)?;
+    /// Produces `n` candidate answers for the same query, each with its own seed
+    pub fn generate_variants(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        n: usize,
+        overrides: &GenerationOverrides,
+    ) -> Result<Vec<String>> {
+        (0..n).map(|_| self.generate_variant(query, context.clone(), history, overrides)).collect()
+    }
 
-        Ok(LLM {
-            model: Arc::new(model),
-            config,
-        })
+    /// One candidate answer with a fresh seed, as `generate_variants` makes them
+    pub fn generate_variant(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        overrides: &GenerationOverrides,
+    ) -> Result<String> {
+        let sampling = Sampling { seed: Some(fresh_seed()), ..self.sampling(Stage::Answer, overrides)? };
+        self.generate_sampled(query, context, history, &sampling, &mut Timings::new(), &mut |_| ControlFlow::Continue(()))
     }
 
-    fn get_default_model() -> Result<PathBuf> {
-        let models_dir = dirs::cache_dir()
-            .ok_or_else(|| anyhow!("Could not determine cache directory"))?
-            .join("tapssp-project")
-            .join("models");
+    /// `sampling` with the temperature raised for retry `attempt`
+    fn retry_sampling(&self, overrides: &GenerationOverrides, attempt: usize) -> Result<Sampling> {
+        let mut sampling = self.sampling(Stage::Answer, overrides)?;
+        sampling.temperature = (sampling.temperature + RETRY_TEMPERATURE_STEP * attempt as f32)
+            .min(MAX_RETRY_TEMPERATURE);
+        Ok(sampling)
+    }
+
+    /// The profile of `stage` with validated overrides applied on top
+    fn sampling(&self, stage: Stage, overrides: &GenerationOverrides) -> Result<Sampling> {
+        overrides.validate(&self.config.limits)?;
+        let profile = self.config.profiles.get(stage);
+        Ok(Sampling {
+            temperature: overrides.temperature.unwrap_or(profile.temperature),
+            top_p: overrides.top_p.unwrap_or(profile.top_p),
+            // The configured answer length is bounded like requested ones
+            max_tokens: overrides.max_tokens.unwrap_or(self.config.max_tokens).min(self.config.limits.max_tokens),
+            seed: overrides.seed,
+            stop: overrides
+                .stop
+                .iter()
+                .cloned()
+                .chain(self.config.prompt_template.stop_sequences().iter().map(|stop| stop.to_string()))
+                .collect(),
+            system_prompt: overrides.system_prompt.clone(),
+            format: overrides.format,
+        })
+    }
 
-        std::fs::create_dir_all(&models_dir)?;
-        
-        let model_path = models_dir.join("mistral-7b-instruct-v0.1.Q4_K_M.gguf");
-        
-        if !model_path.exists() {
-            println!("Downloading Mistral 7B model...");
-            // Download model from HuggingFace
-            let url = "https://huggingface.co/TheBloke/Mistral-7B-Instruct-v0.1-GGUF/resolve/main/mistral-7b-instruct-v0.1.Q4_K_M.gguf";
-            let response = reqwest::blocking::get(url)?;
-            let mut file = std::fs::File::create(&model_path)?;
-            let mut content = std::io::Cursor::new(response.bytes()?);
-            std::io::copy(&mut content, &mut file)?;
-            println!("Model downloaded successfully!");
+    /// Folds all but the most recent turns of `conversation` into its
+    /// running summary, so long sessions stay within the history budget
+    pub fn summarize_conversation(&self, conversation: &mut Conversation) -> Result<()> {
+        let (older, count) = conversation.turns_to_summarize();
+        if count == 0 {
+            return Ok(());
         }
 
-        Ok(model_path)
+        let previous = conversation.summary.clone().unwrap_or_else(|| i18n::text("prompt-summary-none"));
+        let instruction = format!(
+            "{}\n\n{} {previous}\n\n{}\n{older}",
+            i18n::text("prompt-summary"),
+            i18n::text("prompt-summary-earlier"),
+            i18n::text("prompt-summary-conversation"),
+        );
+        let prompt = self.config.prompt_template.render("", &instruction);
+        let sampling = Sampling {
+            max_tokens: SUMMARY_MAX_TOKENS,
+            ..self.sampling(Stage::Summary, &GenerationOverrides::default())?
+        };
+        let summary = self.run_inference(prompt, &sampling, &mut Timings::new(), &mut |_| ControlFlow::Continue(()))?;
+        conversation.apply_summary(summary.trim().to_string(), count);
+        Ok(())
     }
 
-    pub fn generate_response(&self, query: &str, context: Vec<String>) -> Result<String> {
+    /// Answers a classification `prompt` with a short, near-deterministic
+    /// completion
+    pub fn classify(&self, prompt: String) -> Result<String> {
+        let sampling = Sampling {
+            max_tokens: CLASSIFY_MAX_TOKENS,
+            ..self.sampling(Stage::Classification, &GenerationOverrides::default())?
+        };
+        let prompt = self.config.prompt_template.render("", &prompt);
+        let reply = self.run_inference(prompt, &sampling, &mut Timings::new(), &mut |_| ControlFlow::Continue(()))?;
+        Ok(reply.trim().to_string())
+    }
+
+    /// Tokens `text` takes, counted with the model's tokenizer where the
+    /// backend has one
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.backend.count_tokens(text).unwrap_or_else(|| estimate_tokens(text))
+    }
+
+    /// The prompt `generate` would send for `query`, without generating
+    pub fn prompt(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        overrides: &GenerationOverrides,
+    ) -> Result<String> {
+        let mut sampling = self.sampling(Stage::Answer, overrides)?;
+        self.build_prompt(query, context, history, &mut sampling)
+    }
+
+    fn generate_sampled(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        sampling: &Sampling,
+        timings: &mut Timings,
+        on_token: &mut dyn FnMut(&str) -> ControlFlow<()>,
+    ) -> Result<String> {
         if query.trim().is_empty() {
             return Err(anyhow!("Query cannot be empty"));
         }
 
-        let prompt = self.construct_prompt(query, context);
-        
-        let inference_params = InferenceParams {
-            n_threads: num_cpus::get(),  // Use all available CPU cores
-            n_tokens: self.config.max_tokens,
-            temperature: self.config.temperature,
-            top_p: self.config.top_p,
+        let mut sampling = sampling.clone();
+        let prompt = timings.time("prompt build", || self.build_prompt(query, context, history, &mut sampling))?;
+        let answer = self.run_inference(prompt, &sampling, timings, on_token)?;
+        Ok(match sampling.format {
+            Some(format) => format.enforce(answer),
+            None => answer,
+        })
+    }
+
+    /// Fits `context` into what the context window leaves after the
+    /// question, history and answer, capping the answer length in
+    /// `sampling` when even that doesn't fit
+    fn build_prompt(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        sampling: &mut Sampling,
+    ) -> Result<String> {
+        let system_prompt = sampling.system_prompt.as_deref();
+        let overhead = self.count_tokens(&self.construct_prompt(query, vec![String::new()], history, system_prompt, sampling.format));
+        let window = self.config.context_tokens;
+        let available = window.checked_sub(overhead).filter(|tokens| *tokens >= MIN_ANSWER_TOKENS).ok_or_else(|| {
+            anyhow!(
+                "The question and conversation take {} of the model's {} context tokens, leaving no room for an answer; \
+                 shorten the question, start a new conversation or raise --context-size",
+                overhead,
+                window
+            )
+        })?;
+        // The answer gets its full length where it fits, and the
+        // retrieved context whatever is left over
+        if sampling.max_tokens > available {
+            tracing::warn!(max_tokens = sampling.max_tokens, available, "capping the answer length to fit the context window");
+            sampling.max_tokens = available;
+        }
+        let budget = available - sampling.max_tokens;
+        let context = context_fit::fit(query, context, budget, &|text: &str| self.count_tokens(text));
+        Ok(self.construct_prompt(query, context, history, system_prompt, sampling.format))
+    }
+
+    fn run_inference(
+        &self,
+        prompt: String,
+        sampling: &Sampling,
+        timings: &mut Timings,
+        on_token: &mut dyn FnMut(&str) -> ControlFlow<()>,
+    ) -> Result<String> {
+        let options = InferenceOptions {
+            max_tokens: sampling.max_tokens,
+            temperature: sampling.temperature,
+            top_p: sampling.top_p,
             repeat_penalty: self.config.repeat_penalty,
-            ..InferenceParams::default()
+            seed: sampling.seed,
         };
 
-        let mut session = InferenceSession::new(
-            self.model.clone(),
-            inference_params,
-        )?;
-
+        let eval_start = Instant::now();
+        let mut first_token_at: Option<Instant> = None;
         let mut response = String::new();
-        let mut tokens = session.infer::<std::io::Stdout>(
-            InferenceRequest::from_prompt(prompt),
-            |r| match r {
-                InferenceResponse::InferredToken(token) => {
-                    response.push_str(&token);
-                    Ok(())
+        // Length of `response` already passed to `on_token`
+        let mut emitted = 0;
+        let mut repetition = RepetitionGuard::new(self.config.repetition_ngram, self.config.max_ngram_repeats);
+        let mut pieces = 0;
+        self.backend.infer(prompt, &options, &mut |piece| {
+            first_token_at.get_or_insert_with(Instant::now);
+            response.push_str(piece);
+            pieces += 1;
+            let low_memory = self.config.memory_reserve > 0
+                && pieces % MEMORY_CHECK_INTERVAL == 0
+                && resources::memory_exhausted(self.config.memory_reserve);
+            let (safe, flow) = match find_stop(&response, &sampling.stop) {
+                Some(end) => {
+                    response.truncate(end);
+                    (end, ControlFlow::Break(()))
+                }
+                None if repetition.is_looping(&response) => {
+                    tracing::warn!(chars = response.len(), "stopped generation on repeated output");
+                    response.push_str(REPETITION_NOTICE);
+                    (response.len(), ControlFlow::Break(()))
+                }
+                None if low_memory => {
+                    response.push_str(MEMORY_NOTICE);
+                    (response.len(), ControlFlow::Break(()))
                 }
-                InferenceResponse::EotToken => Ok(()),
-            },
-        )?;
+                None => (response.len() - partial_stop_len(&response, &sampling.stop), ControlFlow::Continue(())),
+            };
+            if safe > emitted {
+                let wanted = on_token(&response[emitted..safe]);
+                emitted = safe;
+                // The caller can stop generation, e.g. when its client left
+                if wanted.is_break() {
+                    return wanted;
+                }
+            }
+            flow
+        })?;
+        if emitted < response.len() {
+            let _ = on_token(&response[emitted..]);
+        }
+
+        match first_token_at {
+            Some(first) => {
+                timings.record("prompt eval", first - eval_start);
+                timings.record("decode", first.elapsed());
+            }
+            None => timings.record("prompt eval", eval_start.elapsed()),
+        }
 
         Ok(response)
     }
 
-    fn construct_prompt(&self, query: &str, context: Vec<String>) -> String {
+    fn construct_prompt(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        system_prompt: Option<&str>,
+        format: Option<AnswerFormat>,
+    ) -> String {
+        let system_str = match system_prompt {
+            Some(system) => format!("{}\n\n", system),
+            None => String::new(),
+        };
+        let history_str = match history.map(Conversation::render).filter(|h| !h.is_empty()) {
+            Some(history) => format!("{}\n{}\n\n", i18n::text("prompt-history"), history),
+            None => String::new(),
+        };
         let context_str = if context.is_empty() {
-            String::new()
+            // Retrieval found nothing relevant enough, or the question is
+            // small talk; the model must not make up an answer either way
+            format!("{}\n\n", i18n::text("prompt-no-context"))
         } else {
+            // Asks for code and LaTeX blocks to be quoted unchanged
+            let verbatim = if context.iter().any(|chunk| contains_verbatim_block(chunk)) {
+                format!("{}\n\n", i18n::text("prompt-verbatim"))
+            } else {
+                String::new()
+            };
+            // The citations are the markers `citations::cited_numbers` reads back
             format!(
-                "Using the following context to answer the question:\n\n{}\n\n",
-                context.join("\n\n")
+                "{} {} {}\n\n{}\n\n{}",
+                i18n::text("prompt-context"),
+                i18n::text("prompt-context-safety"),
+                i18n::text("prompt-citations"),
+                injection::delimit(&context),
+                verbatim
             )
         };
 
-        format!(
-            "<s>[INST] {context_str}Question: {query} [/INST]",
+        let format_str = match format {
+            Some(format) => format!("{}\n\n", format.instruction()),
+            None => String::new(),
+        };
+
+        let language = i18n::text("prompt-answer-language");
+        let question = i18n::text("prompt-question");
+
+        self.config.prompt_template.render(
+            &format!("{system_str}{history_str}{context_str}{format_str}{language}\n\n"),
+            &format!("{question} {query}"),
         )
     }
-}
\ No newline at end of file
+}
+
+/// One question/answer exchange in a chat session
+#[derive(Debug, Clone)]
+pub struct Turn {
+    pub question: String,
+    pub answer: String,
+}
+
+/// Chat history fed into prompts. Once the estimated size exceeds the token
+/// budget, older turns are folded into a running summary by
+/// `LLM::summarize_conversation`, keeping the last `keep_recent` turns verbatim.
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    summary: Option<String>,
+    turns: Vec<Turn>,
+    token_budget: usize,
+    keep_recent: usize,
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        Self::new(1500, 2)
+    }
+}
+
+impl Conversation {
+    pub fn new(token_budget: usize, keep_recent: usize) -> Self {
+        Conversation {
+            summary: None,
+            turns: Vec::new(),
+            token_budget,
+            keep_recent,
+        }
+    }
+
+    pub fn push(&mut self, question: &str, answer: &str) {
+        self.turns.push(Turn {
+            question: question.to_string(),
+            answer: answer.to_string(),
+        });
+    }
+
+    /// Removes and returns the most recent turn, e.g. to regenerate it
+    pub fn pop(&mut self) -> Option<Turn> {
+        self.turns.pop()
+    }
+
+    pub fn clear(&mut self) {
+        self.summary = None;
+        self.turns.clear();
+    }
+
+    /// Whether the history is over budget and has turns that can be folded
+    pub fn needs_summary(&self) -> bool {
+        self.turns.len() > self.keep_recent && estimate_tokens(&self.render()) > self.token_budget
+    }
+
+    /// Summary followed by the verbatim turns, as shown to the model
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(summary) = &self.summary {
+            out.push_str(&format!("Summary of earlier conversation: {}\n", summary));
+        }
+        out.push_str(&render_turns(&self.turns));
+        out.trim_end().to_string()
+    }
+
+    /// Rendered text and count of the turns older than `keep_recent`
+    fn turns_to_summarize(&self) -> (String, usize) {
+        let count = self.turns.len().saturating_sub(self.keep_recent);
+        (render_turns(&self.turns[..count]), count)
+    }
+
+    fn apply_summary(&mut self, summary: String, summarized: usize) {
+        self.summary = Some(summary);
+        self.turns.drain(..summarized);
+    }
+}
+
+fn render_turns(turns: &[Turn]) -> String {
+    turns
+        .iter()
+        .map(|turn| format!("User: {}\nAssistant: {}\n", turn.question, turn.answer))
+        .collect()
+}
+
+/// Rough token count (~4 characters per token for English text)
+pub(crate) fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Byte offset where the earliest stop sequence in `text` begins
+fn find_stop(text: &str, stop: &[String]) -> Option<usize> {
+    stop.iter().filter_map(|s| text.find(s.as_str())).min()
+}
+
+/// Length of the longest suffix of `text` that is a proper prefix of a stop
+/// sequence, i.e. text that may still turn out to be a stop
+fn partial_stop_len(text: &str, stop: &[String]) -> usize {
+    stop.iter()
+        .flat_map(|s| s.char_indices().skip(1).map(move |(i, _)| &s[..i]))
+        .filter(|prefix| text.ends_with(prefix))
+        .map(str::len)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Detects degenerate generation loops: the same run of `ngram` words
+/// (ignoring case) occurring more than `max_repeats` times
+struct RepetitionGuard {
+    ngram: usize,
+    max_repeats: usize,
+    /// Lowercased words of the text that are known to be complete
+    words: Vec<String>,
+    /// Bytes of the text already split into `words`
+    scanned: usize,
+    counts: HashMap<Vec<String>, usize>,
+}
+
+impl RepetitionGuard {
+    fn new(ngram: usize, max_repeats: usize) -> Self {
+        RepetitionGuard { ngram, max_repeats, words: Vec::new(), scanned: 0, counts: HashMap::new() }
+    }
+
+    /// Takes the whole text generated so far, which only ever grows
+    fn is_looping(&mut self, text: &str) -> bool {
+        if self.ngram == 0 {
+            return false;
+        }
+        // The last word may still be extended by the next token
+        let Some(complete) = text.rfind(char::is_whitespace).filter(|end| *end >= self.scanned) else {
+            return false;
+        };
+        let new_words: Vec<String> = text[self.scanned..complete].split_whitespace().map(str::to_lowercase).collect();
+        self.scanned = complete;
+        let mut looping = false;
+        for word in new_words {
+            self.words.push(word);
+            if self.words.len() >= self.ngram {
+                let gram = self.words[self.words.len() - self.ngram..].to_vec();
+                let count = self.counts.entry(gram).or_insert(0);
+                *count += 1;
+                looping |= *count > self.max_repeats;
+            }
+        }
+        looping
+    }
+}
+
+/// Seed derived from the clock, good enough to decorrelate retries
+fn fresh_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_validated_against_limits() {
+        let limits = GenerationLimits::default();
+        let ok = GenerationOverrides {
+            temperature: Some(0.2),
+            max_tokens: Some(256),
+            stop: vec!["###".to_string()],
+            ..GenerationOverrides::default()
+        };
+        assert!(ok.validate(&limits).is_ok());
+
+        let too_hot = GenerationOverrides { temperature: Some(5.0), ..GenerationOverrides::default() };
+        assert!(too_hot.validate(&limits).is_err());
+        let too_long = GenerationOverrides { max_tokens: Some(limits.max_tokens + 1), ..GenerationOverrides::default() };
+        assert!(too_long.validate(&limits).is_err());
+    }
+
+    #[test]
+    fn test_stage_profiles_order_by_freedom() {
+        let profiles = StageProfiles::default();
+        let (classify, answer, summary) =
+            (profiles.get(Stage::Classification), profiles.get(Stage::Answer), profiles.get(Stage::Summary));
+        assert!(classify.temperature < answer.temperature && answer.temperature < summary.temperature);
+        assert!(classify.top_p < answer.top_p && answer.top_p < summary.top_p);
+        assert_eq!(answer, SamplingProfile::CONSERVATIVE);
+    }
+
+    #[test]
+    fn test_backend_parse_and_default() -> Result<()> {
+        assert_eq!(Backend::parse("CUDA")?, Backend::Cuda);
+        assert!(Backend::parse("opencl").is_err());
+        assert!(Backend::default().is_compiled());
+        assert!(Backend::Cpu.is_compiled());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_stop_picks_earliest_sequence() {
+        let stop = vec!["END".to_string(), "\n\n".to_string()];
+        assert_eq!(find_stop("answer\n\nmore END", &stop), Some(6));
+        assert_eq!(find_stop("answer", &stop), None);
+    }
+
+    #[test]
+    fn test_partial_stop_held_back() {
+        let stop = vec!["END".to_string(), "\n\n".to_string()];
+        assert_eq!(partial_stop_len("the EN", &stop), 2);
+        assert_eq!(partial_stop_len("line\n", &stop), 1);
+        assert_eq!(partial_stop_len("the ENDING", &stop), 0);
+        assert_eq!(partial_stop_len("text", &[]), 0);
+    }
+
+    #[test]
+    fn test_repetition_guard_stops_loops_only() {
+        let mut guard = RepetitionGuard::new(3, 2);
+        let mut text = String::from("Refunds take thirty days. ");
+        assert!(!guard.is_looping(&text));
+        // Streamed token by token, a word split across tokens counts once
+        for token in ["The", " sa", "le ends", " Monday. "].iter().cycle().take(8) {
+            text.push_str(token);
+            assert!(!guard.is_looping(&text));
+        }
+        for token in ["The", " sale ends", " Monday. "] {
+            text.push_str(token);
+        }
+        assert!(guard.is_looping(&text));
+
+        let mut disabled = RepetitionGuard::new(0, 2);
+        assert!(!disabled.is_looping(&"loop ".repeat(50)));
+    }
+
+    #[test]
+    fn test_conversation_summarizes_only_older_turns() {
+        let mut conversation = Conversation::new(20, 1);
+        conversation.push("What is tapssp?", "A retrieval-augmented generation tool written in Rust.");
+        assert!(!conversation.needs_summary());
+        conversation.push("Which model does it use?", "Mistral 7B by default.");
+        assert!(conversation.needs_summary());
+
+        let (older, count) = conversation.turns_to_summarize();
+        assert_eq!(count, 1);
+        assert!(older.contains("What is tapssp?"));
+
+        conversation.apply_summary("User asked what tapssp is.".to_string(), count);
+        let rendered = conversation.render();
+        assert!(rendered.starts_with("Summary of earlier conversation: User asked"));
+        assert!(rendered.contains("Which model does it use?"));
+        assert!(!rendered.contains("What is tapssp?"));
+    }
+
+    /// Counts one token per word and records the prompt sizes and answer
+    /// lengths it was asked for
+    struct WordBackend {
+        requests: std::sync::Mutex<Vec<(usize, usize)>>,
+    }
+
+    impl LLMBackend for WordBackend {
+        fn infer(&self, prompt: String, options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+            self.requests.lock().unwrap().push((prompt.split_whitespace().count(), options.max_tokens));
+            let _ = on_piece("Thirty days.");
+            Ok(())
+        }
+
+        fn count_tokens(&self, text: &str) -> Option<usize> {
+            Some(text.split_whitespace().count())
+        }
+    }
+
+    #[test]
+    fn test_prompt_and_answer_fit_the_context_window() {
+        let backend = Arc::new(WordBackend { requests: Default::default() });
+        let config = LLMConfig { context_tokens: 400, max_tokens: 1000, ..LLMConfig::default() };
+        let llm = LLM::with_backend(backend.clone(), config);
+        let chunks = vec!["Refunds are paid within thirty days.".to_string(), "word ".repeat(500)];
+
+        assert_eq!(llm.generate_response("How long do refunds take?", chunks).unwrap(), "Thirty days.");
+        let (prompt_tokens, max_tokens) = backend.requests.lock().unwrap()[0];
+        assert!(max_tokens < 1000);
+        assert!(prompt_tokens + max_tokens <= 400, "{} + {}", prompt_tokens, max_tokens);
+
+        let error = llm.generate_response(&"why ".repeat(400), Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("--context-size"), "{}", error);
+    }
+
+    #[test]
+    fn test_configured_limits_bound_answer_length() {
+        let backend = Arc::new(WordBackend { requests: Default::default() });
+        let limits = GenerationLimits { max_tokens: 128, ..GenerationLimits::default() };
+        let llm = LLM::with_backend(backend.clone(), LLMConfig { max_tokens: 512, limits, ..LLMConfig::default() });
+
+        llm.generate_response("How long do refunds take?", Vec::new()).unwrap();
+        assert_eq!(backend.requests.lock().unwrap()[0].1, 128);
+        let too_long = GenerationOverrides { max_tokens: Some(256), ..GenerationOverrides::default() };
+        assert!(llm.validate_overrides(&too_long).is_err());
+    }
+
+    #[test]
+    fn test_unavailable_model_reports_why_it_cannot_answer() {
+        let llm = LLM::unavailable("download failed");
+        assert!(!llm.is_available());
+        let error = llm.generate_response("Why?", vec!["Because.".to_string()]).unwrap_err();
+        assert!(error.to_string().contains("download failed"), "{}", error);
+    }
+}
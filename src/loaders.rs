@@ -0,0 +1,213 @@
+use anyhow::Result;
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::transforms::Transforms;
+
+/// Extensions of files ingested as text. Markdown and HTML are split into
+/// sections by heading first, see `sections`.
+pub const TEXT_EXTENSIONS: &[&str] = &["txt", "md", "markdown", "html", "htm"];
+
+lazy_static! {
+    static ref MD_HEADING: Regex = Regex::new(r"^(#{1,6})\s+(.*?)\s*#*\s*$").unwrap();
+    static ref MD_IMAGE: Regex = Regex::new(r"!\[([^\]]*)\]\([^)]*\)").unwrap();
+    static ref MD_LINK: Regex = Regex::new(r"\[([^\]]+)\]\([^)]*\)").unwrap();
+    static ref HTML_DROPPED: Regex =
+        Regex::new(r"(?is)<!--.*?-->|<head\b.*?</head>|<script\b.*?</script>|<style\b.*?</style>|<nav\b.*?</nav>|<footer\b.*?</footer>").unwrap();
+    static ref HTML_TITLE: Regex = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+    static ref HTML_PRE: Regex = Regex::new(r"(?is)<pre\b[^>]*>\n?(.*?)</pre>").unwrap();
+    static ref HTML_HEADING: Regex = Regex::new(r"(?is)<h([1-6])\b[^>]*>(.*?)</h[1-6]>").unwrap();
+    static ref HTML_BLOCK: Regex = Regex::new(r"(?i)<(br|/?p|/?div|/?li|/?tr|/?pre|/?section|/?article|/?ul|/?ol|/?table|/?blockquote)\b[^>]*>").unwrap();
+    static ref HTML_TAG: Regex = Regex::new(r"(?s)<[^>]*>").unwrap();
+    static ref HTML_ENTITY: Regex = Regex::new(r"&(#x[0-9a-fA-F]+|#[0-9]+|[a-zA-Z]+);").unwrap();
+}
+
+/// A part of a document under one heading
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// The headings above the section, outermost first, joined with ` > `;
+    /// `None` for text before the first heading and for plain text files
+    pub heading: Option<String>,
+    pub text: String,
+}
+
+/// The text file at `path` after `transforms`, by section, each with
+/// `metadata` plus its `heading` and, for HTML, the page `title`
+pub fn load_text(path: &Path, transforms: &Transforms, metadata: &BTreeMap<String, String>) -> Result<Vec<(String, BTreeMap<String, String>)>> {
+    let raw = fs::read_to_string(path)?;
+    let title = html_title(&raw);
+    let text = transforms.text(path, raw);
+    Ok(sections(path, &text)
+        .into_iter()
+        .map(|section| {
+            let mut metadata = metadata.clone();
+            metadata.extend(title.clone().map(|title| ("title".to_string(), title)));
+            metadata.extend(section.heading.map(|heading| ("heading".to_string(), heading)));
+            (section.text, metadata)
+        })
+        .collect())
+}
+
+/// `text` of the file at `path` split into sections: by heading for
+/// Markdown and HTML (whose tags are stripped first), as one section
+/// otherwise
+pub fn sections(path: &Path, text: &str) -> Vec<Section> {
+    match path.extension().and_then(|ext| ext.to_str()).map(str::to_lowercase).as_deref() {
+        Some("md" | "markdown") => markdown_sections(text),
+        Some("html" | "htm") => markdown_sections(&html_to_markdown(text)),
+        _ => vec![Section { heading: None, text: text.to_string() }],
+    }
+}
+
+/// The `<title>` of an HTML page
+fn html_title(html: &str) -> Option<String> {
+    let title = HTML_TITLE.captures(html)?.get(1)?.as_str();
+    Some(decode_entities(&HTML_TAG.replace_all(title, "")).trim().to_string()).filter(|title| !title.is_empty())
+}
+
+fn markdown_sections(markdown: &str) -> Vec<Section> {
+    let mut sections = Vec::new();
+    // (level, title) of the enclosing headings
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut text = String::new();
+    let mut in_fence = false;
+    let mut flush = |headings: &[(usize, String)], text: &mut String| {
+        if !text.trim().is_empty() {
+            let path: Vec<&str> = headings.iter().map(|(_, title)| title.as_str()).collect();
+            let heading = (!path.is_empty()).then(|| path.join(" > "));
+            sections.push(Section { heading, text: text.trim().to_string() });
+        }
+        text.clear();
+    };
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        let heading = if in_fence { None } else { MD_HEADING.captures(line) };
+        match heading {
+            Some(captures) => {
+                flush(&headings, &mut text);
+                let level = captures[1].len();
+                let title = inline_text(&captures[2]);
+                headings.retain(|(outer, _)| *outer < level);
+                headings.push((level, title.clone()));
+                text.push_str(&title);
+                text.push('\n');
+            }
+            None if in_fence || line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~") => {
+                text.push_str(line);
+                text.push('\n');
+            }
+            None => {
+                text.push_str(&inline_text(line));
+                text.push('\n');
+            }
+        }
+    }
+    flush(&headings, &mut text);
+    sections
+}
+
+/// A Markdown line with links and images reduced to their text
+fn inline_text(line: &str) -> String {
+    let line = MD_IMAGE.replace_all(line, "$1");
+    MD_LINK.replace_all(&line, "$1").into_owned()
+}
+
+/// Text of an HTML page, with headings as Markdown `#` lines and `<pre>`
+/// blocks as fenced code so it can be sectioned like Markdown
+fn html_to_markdown(html: &str) -> String {
+    let html = HTML_DROPPED.replace_all(html, "");
+    let html = HTML_PRE.replace_all(&html, |captures: &Captures| {
+        format!("\n```\n{}\n```\n", HTML_TAG.replace_all(&captures[1], "").trim_end())
+    });
+    let html = HTML_HEADING.replace_all(&html, |captures: &Captures| {
+        let level: usize = captures[1].parse().unwrap_or(1);
+        let title = HTML_TAG.replace_all(&captures[2], "");
+        format!("\n{} {}\n", "#".repeat(level), title.split_whitespace().collect::<Vec<_>>().join(" "))
+    });
+    let html = HTML_BLOCK.replace_all(&html, "\n");
+    let text = decode_entities(&HTML_TAG.replace_all(&html, ""));
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_pre = false;
+    for line in text.lines() {
+        if line.trim() == "```" {
+            in_pre = !in_pre;
+            lines.push("```");
+            continue;
+        }
+        // Indentation and blank lines are content in preformatted text
+        if in_pre {
+            lines.push(line.trim_end());
+            continue;
+        }
+        let line = line.trim();
+        // Collapse runs of blank lines left by the markup
+        if !line.is_empty() || lines.last().is_some_and(|last| !last.is_empty()) {
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+fn decode_entities(text: &str) -> String {
+    HTML_ENTITY
+        .replace_all(text, |captures: &Captures| {
+            let entity = &captures[1];
+            let decoded = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => entity
+                    .strip_prefix("#x")
+                    .map(|hex| u32::from_str_radix(hex, 16).ok())
+                    .unwrap_or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            decoded.map_or_else(|| captures[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_and_html_split_by_heading() {
+        let markdown = "Intro text.\n\n# Guide\nSee [the docs](http://x).\n## Install\nRun it.\n```\n# not a heading\n```\n# FAQ ##\nAsk.\n";
+        let sections = sections(Path::new("guide.md"), markdown);
+        let headings: Vec<Option<&str>> = sections.iter().map(|section| section.heading.as_deref()).collect();
+        assert_eq!(headings, vec![None, Some("Guide"), Some("Guide > Install"), Some("FAQ")]);
+        assert_eq!(sections[1].text, "Guide\nSee the docs.");
+        assert!(sections[2].text.contains("# not a heading"));
+
+        let html = "<html><head><title>Help &amp; FAQ</title><style>p {}</style></head><body>\
+            <h1>Refunds</h1><p>Paid within <b>30</b>&nbsp;days.</p><script>x()</script>\
+            <h2 id=\"card\">To <em>cards</em></h2><p>Original card.</p></body></html>";
+        assert_eq!(html_title(html).as_deref(), Some("Help & FAQ"));
+        let sections = super::sections(Path::new("help.html"), html);
+        assert_eq!(sections, vec![
+            Section { heading: Some("Refunds".to_string()), text: "Refunds\n\nPaid within 30 days.".to_string() },
+            Section { heading: Some("Refunds > To cards".to_string()), text: "To cards\n\nOriginal card.".to_string() },
+        ]);
+
+        assert_eq!(super::sections(Path::new("notes.txt"), "# kept"), vec![Section { heading: None, text: "# kept".to_string() }]);
+    }
+
+    #[test]
+    fn test_html_preformatted_text_keeps_indentation() {
+        let html = "<h1>Config</h1><p>  Example:  </p>\
+            <pre><code>[server]\n  # port to bind\n  port = 80\n\n\n  host = &quot;::&quot;\n</code></pre><p>Done.</p>";
+        assert_eq!(html_to_markdown(html), "# Config\n\nExample:\n\n```\n[server]\n  # port to bind\n  port = 80\n\n\n  host = \"::\"\n```\n\nDone.");
+        let sections = super::sections(Path::new("config.html"), html);
+        assert_eq!(sections.len(), 1);
+        assert!(sections[0].text.contains("\n  # port to bind\n  port = 80\n"), "{}", sections[0].text);
+    }
+}
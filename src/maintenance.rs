@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::retriever::Retriever;
+
+/// How often the maintenance thread wakes up to check for idle time
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Remembers when the pipeline last did interactive work
+pub struct ActivityTracker {
+    last_active: Mutex<Instant>,
+}
+
+impl ActivityTracker {
+    pub fn new() -> Self {
+        ActivityTracker {
+            last_active: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn touch(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+    }
+
+    pub fn idle_for(&self) -> Duration {
+        self.last_active.lock().unwrap().elapsed()
+    }
+}
+
+impl Default for ActivityTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Background thread that rebuilds a stale index (vocabulary compaction,
+/// IDF recomputation, re-embedding) and saves the retrieval counts once no
+/// query has run for `idle_after`. The rebuild is computed under the read
+/// lock and only swapped in under the write lock.
+/// The thread is stopped and joined when the worker is dropped.
+pub struct MaintenanceWorker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MaintenanceWorker {
+    pub fn spawn(
+        retriever: Arc<RwLock<Retriever>>,
+        activity: Arc<ActivityTracker>,
+        idle_after: Duration,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = Arc::clone(&stop);
+
+        let handle = thread::spawn(move || {
+            while !stop_flag.load(Ordering::Relaxed) {
+                thread::sleep(POLL_INTERVAL);
+                if activity.idle_for() < idle_after {
+                    continue;
+                }
+                if let Ok(retriever) = retriever.read()
+                    && let Err(e) = retriever.flush_access_log()
+                {
+                    tracing::warn!("Couldn't save retrieval counts: {}", e);
+                }
+                let stale = retriever.read().map(|r| r.is_stale()).unwrap_or(false);
+                if !stale {
+                    continue;
+                }
+
+                let _span = tracing::debug_span!("index_maintenance").entered();
+                let start = Instant::now();
+                // Queries keep running while the replacement is computed;
+                // the write lock is only held to swap it in
+                let rebuild = retriever.read().ok().and_then(|r| r.prepare_rebuild());
+                let Some(rebuild) = rebuild else {
+                    continue;
+                };
+                let swapped = retriever.write().map(|mut r| r.finish_rebuild(rebuild)).unwrap_or(false);
+                match swapped {
+                    true => tracing::debug!(elapsed = ?start.elapsed(), "rebuilt stale index"),
+                    false => tracing::debug!("index changed during the rebuild, retrying when idle"),
+                }
+            }
+        });
+
+        MaintenanceWorker {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for MaintenanceWorker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
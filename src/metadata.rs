@@ -0,0 +1,250 @@
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::snapshots;
+
+/// Fields of `of_file` that differ between checkouts and runs of the same
+/// files, left out of deterministic builds
+pub const VOLATILE_FIELDS: [&str; 2] = ["modified", "ingested"];
+
+/// Position of a chunk within its source file, from 0, as recorded by
+/// `ingest::add_file`
+pub const CHUNK_POSITION: &str = "chunk";
+
+/// Metadata recorded for every chunk of a file: `source` (file name),
+/// `path`, `file_type` (extension), `modified` and `ingested`, both as
+/// `YYYY-MM-DDTHH:MM:SSZ` so they compare in time order as text
+pub fn of_file(path: &Path) -> Result<BTreeMap<String, String>> {
+    let mut metadata = BTreeMap::new();
+    let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+    metadata.insert("source".to_string(), name);
+    metadata.insert("path".to_string(), path.display().to_string());
+    if let Some(extension) = path.extension() {
+        metadata.insert("file_type".to_string(), extension.to_string_lossy().to_lowercase());
+    }
+    let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    if let Ok(modified) = fs::metadata(path)?.modified() {
+        metadata.insert("modified".to_string(), snapshots::format_time(seconds(modified)));
+    }
+    metadata.insert("ingested".to_string(), snapshots::format_time(seconds(SystemTime::now())));
+    Ok(metadata)
+}
+
+/// A condition on document metadata, such as
+/// `source == "manual.pdf" and (modified >= 2024-01-01 or not file_type == csv)`.
+///
+/// Comparisons are `==`, `!=`, `<`, `<=`, `>` and `>=`, combined with `and`,
+/// `or`, `not` and parentheses. Values are compared as numbers when both
+/// sides are numbers and as text otherwise; they need quotes only when they
+/// contain spaces or operator characters. A field the document doesn't have
+/// fails every comparison.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Compare(String, Op, String),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// A quoted value, which is never a keyword
+    Quoted(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+impl Filter {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let filter = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(filter),
+            Some(token) => Err(anyhow!("Unexpected {:?} in filter '{}'", token, expression)),
+        }
+    }
+
+    pub fn matches(&self, metadata: &BTreeMap<String, String>) -> bool {
+        match self {
+            Filter::Compare(field, op, value) => metadata.get(field).is_some_and(|actual| op.holds(actual, value)),
+            Filter::And(a, b) => a.matches(metadata) && b.matches(metadata),
+            Filter::Or(a, b) => a.matches(metadata) || b.matches(metadata),
+            Filter::Not(filter) => !filter.matches(metadata),
+        }
+    }
+}
+
+impl Op {
+    fn holds(self, actual: &str, value: &str) -> bool {
+        let ordering = match (actual.parse::<f64>(), value.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.total_cmp(&b),
+            _ => actual.cmp(value),
+        };
+        match self {
+            Op::Eq => ordering.is_eq(),
+            Op::Ne => ordering.is_ne(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(ch) if ch == c => break,
+                        Some(ch) => value.push(ch),
+                        None => return Err(anyhow!("Unterminated quote in filter '{}'", expression)),
+                    }
+                }
+                tokens.push(Token::Quoted(value));
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let equals = chars.next_if_eq(&'=').is_some();
+                tokens.push(Token::Op(match (c, equals) {
+                    ('=', true) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    _ => return Err(anyhow!("Unknown operator '{}' in filter '{}'; use ==, !=, <, <=, > or >=", c, expression)),
+                }));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(ch) = chars.next_if(|ch| !ch.is_whitespace() && !"()\"'=!<>".contains(*ch)) {
+                    word.push(ch);
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over `or` > `and` > `not` > comparison
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<Filter> {
+        let mut filter = self.and()?;
+        while self.keyword("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter> {
+        let mut filter = self.not()?;
+        while self.keyword("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.not()?));
+        }
+        Ok(filter)
+    }
+
+    fn not(&mut self) -> Result<Filter> {
+        if self.keyword("not") {
+            return Ok(Filter::Not(Box::new(self.not()?)));
+        }
+        if self.tokens.get(self.position) == Some(&Token::Open) {
+            self.position += 1;
+            let filter = self.or()?;
+            if self.next() != Some(Token::Close) {
+                return Err(anyhow!("Missing ')' in filter"));
+            }
+            return Ok(filter);
+        }
+        match (self.next(), self.next(), self.next()) {
+            (Some(Token::Word(field)), Some(Token::Op(op)), Some(Token::Word(value) | Token::Quoted(value))) => {
+                Ok(Filter::Compare(field, op, value))
+            }
+            _ => Err(anyhow!("Expected a comparison like `source == \"manual.pdf\"` in filter")),
+        }
+    }
+
+    /// Consumes the next token if it is `keyword`, in any case
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.tokens.get(self.position), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_expressions() -> Result<()> {
+        let metadata: BTreeMap<String, String> = [
+            ("source", "manual.pdf"),
+            ("file_type", "pdf"),
+            ("modified", "2024-03-05T10:00:00Z"),
+            ("page", "12"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        let matches = |expression: &str| Filter::parse(expression).map(|filter| filter.matches(&metadata));
+
+        assert!(matches("source == \"manual.pdf\"")?);
+        assert!(!matches("source != 'manual.pdf'")?);
+        // Numbers compare by value, timestamps as text
+        assert!(matches("page > 9 and page <= 12")?);
+        assert!(matches("modified >= 2024-01-01 AND modified < 2025-01-01")?);
+        assert!(matches("file_type == csv or (not file_type == csv and page == 12.0)")?);
+        assert!(!matches("author == anyone")? && !matches("author != anyone")?);
+        assert!(matches("not not source == manual.pdf")?);
+
+        assert!(Filter::parse("source = x").is_err());
+        assert!(Filter::parse("source == 'x").is_err());
+        assert!(Filter::parse("(source == x").is_err());
+        assert!(Filter::parse("source == x page == 1").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::embeddings::Embedder;
+use crate::vector_db::VectorDB;
+
+/// Documents embedded between checkpoints of the working copy
+const CHECKPOINT_EVERY: usize = 200;
+
+/// Progress of an interrupted `reembed`, stored next to the index
+#[derive(Debug, Serialize, Deserialize)]
+struct ReembedState {
+    model_id: String,
+}
+
+/// Re-embeds every document of the index at `index_path` with `embedder`.
+///
+/// Work happens on a copy (`<index>.reembed`) that is checkpointed as it
+/// goes, so the live index keeps serving queries and an interrupted run
+/// resumes where it stopped. Documents added or changed in the live index
+/// meanwhile are picked up by another pass, which keeps the embeddings of
+/// unchanged documents. When all documents are done the copy replaces the
+/// index atomically. `progress` is called with (done, total). Returns
+/// false if `stop` was set before the swap; the run can then be resumed.
+pub fn reembed(
+    index_path: &Path,
+    embedder: &dyn Embedder,
+    stop: &AtomicBool,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<bool> {
+    let model_id = embedder.model_id();
+    let (work_path, state_path) = work_paths(index_path);
+
+    // A state left by an older version or another model is started over
+    let resumed = fs::read_to_string(&state_path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<ReembedState>(&content).ok())
+        .is_some_and(|state| state.model_id == model_id);
+    let mut done = if resumed && work_path.exists() {
+        Some(VectorDB::load(&work_path)?)
+    } else {
+        None
+    };
+
+    loop {
+        // Each pass starts from the live index, so nothing ingested since
+        // the last one is dropped by the swap
+        let mut work = VectorDB::load(index_path)?;
+        if work.model_id() == model_id {
+            return Err(anyhow!("Index is already embedded with '{}'", model_id));
+        }
+        if done.is_none() {
+            fs::write(&state_path, serde_json::to_string(&ReembedState { model_id: model_id.to_string() })?)?;
+        }
+        let base_fingerprint = work.fingerprint();
+        if let Some(done) = &done {
+            work.adopt_variants(done, model_id);
+        }
+
+        let total = work.len();
+        loop {
+            progress(work.variant_coverage(model_id), total);
+            if stop.load(Ordering::Relaxed) {
+                work.save(&work_path)?;
+                return Ok(false);
+            }
+            if work.embed_missing_variants(embedder, CHECKPOINT_EVERY)? == 0 {
+                break;
+            }
+            work.save(&work_path)?;
+        }
+
+        if VectorDB::load(index_path)?.fingerprint() == base_fingerprint {
+            work.promote_variant(model_id)?;
+            work.save(index_path)?;
+            break;
+        }
+        work.save(&work_path)?;
+        done = Some(work);
+    }
+
+    let _ = fs::remove_file(&work_path);
+    let _ = fs::remove_file(&state_path);
+    Ok(true)
+}
+
+/// Runs `reembed` on a background thread. Dropping the worker stops it at
+/// the next checkpoint, to be resumed by a later run.
+pub struct ReembedWorker {
+    stop: Arc<AtomicBool>,
+    done: Arc<AtomicUsize>,
+    total: Arc<AtomicUsize>,
+    handle: Option<JoinHandle<Result<bool>>>,
+}
+
+impl ReembedWorker {
+    pub fn spawn(index_path: PathBuf, embedder: Arc<dyn Embedder>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let done = Arc::new(AtomicUsize::new(0));
+        let total = Arc::new(AtomicUsize::new(0));
+        let (stop_flag, done_count, total_count) = (Arc::clone(&stop), Arc::clone(&done), Arc::clone(&total));
+
+        let handle = thread::spawn(move || {
+            reembed(&index_path, embedder.as_ref(), &stop_flag, |done, total| {
+                done_count.store(done, Ordering::Relaxed);
+                total_count.store(total, Ordering::Relaxed);
+            })
+        });
+
+        ReembedWorker { stop, done, total, handle: Some(handle) }
+    }
+
+    /// Documents embedded so far and in total, as of the last checkpoint
+    pub fn progress(&self) -> (usize, usize) {
+        (self.done.load(Ordering::Relaxed), self.total.load(Ordering::Relaxed))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.as_ref().is_none_or(|handle| handle.is_finished())
+    }
+
+    /// Waits for the run to end, returning whether the index was swapped
+    pub fn join(mut self) -> Result<bool> {
+        match self.handle.take() {
+            Some(handle) => handle.join().map_err(|_| anyhow!("Re-embedding worker panicked"))?,
+            None => Ok(false),
+        }
+    }
+}
+
+impl Drop for ReembedWorker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn work_paths(index_path: &Path) -> (PathBuf, PathBuf) {
+    let name = index_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    (
+        index_path.with_file_name(format!("{}.reembed", name)),
+        index_path.with_file_name(format!("{}.reembed.json", name)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct LengthEmbedder;
+
+    impl Embedder for LengthEmbedder {
+        fn model_id(&self) -> &str {
+            "length@1"
+        }
+
+        fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            Ok(vec![text.len() as f32, 1.0])
+        }
+    }
+
+    #[test]
+    fn test_reembed_swaps_in_new_model() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("index.bin");
+        let mut db = VectorDB::new();
+        db.add_document("Short note".to_string())?;
+        db.add_document("A considerably longer note about something".to_string())?;
+        db.save(&path)?;
+
+        let mut reports = Vec::new();
+        let swapped = reembed(&path, &LengthEmbedder, &AtomicBool::new(false), |done, total| reports.push((done, total)))?;
+        assert!(swapped);
+        assert_eq!(reports.first(), Some(&(0, 2)));
+        assert_eq!(reports.last(), Some(&(2, 2)));
+
+        let mut migrated = VectorDB::load(&path)?;
+        assert_eq!(migrated.model_id(), "length@1");
+        migrated.use_embedding_model(Some(Arc::new(LengthEmbedder)))?;
+        assert!(!work_paths(&path).0.exists());
+        assert!(reembed(&path, &LengthEmbedder, &AtomicBool::new(false), |_, _| {}).is_err());
+        Ok(())
+    }
+
+    /// Adds a document to the live index on its first call, like an ingest
+    /// running next to the migration
+    struct IngestingEmbedder {
+        index_path: PathBuf,
+        calls: AtomicUsize,
+    }
+
+    impl Embedder for IngestingEmbedder {
+        fn model_id(&self) -> &str {
+            "length@1"
+        }
+
+        fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            if self.calls.fetch_add(1, Ordering::Relaxed) == 0 {
+                let mut live = VectorDB::load(&self.index_path)?;
+                live.add_document("Ingested while re-embedding".to_string())?;
+                live.save(&self.index_path)?;
+            }
+            LengthEmbedder.embed(text)
+        }
+    }
+
+    #[test]
+    fn test_reembed_worker_keeps_progress_when_index_changes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("index.bin");
+        let mut db = VectorDB::new();
+        db.add_document("Short note".to_string())?;
+        db.add_document("A considerably longer note about something".to_string())?;
+        db.save(&path)?;
+
+        let embedder = Arc::new(IngestingEmbedder { index_path: path.clone(), calls: AtomicUsize::new(0) });
+        let worker = ReembedWorker::spawn(path.clone(), Arc::clone(&embedder) as Arc<dyn Embedder>);
+        assert!(worker.join()?);
+
+        // The new document is in the swapped index and only it was embedded again
+        let migrated = VectorDB::load(&path)?;
+        assert_eq!(migrated.model_id(), "length@1");
+        assert_eq!(migrated.len(), 3);
+        assert_eq!(embedder.calls.load(Ordering::Relaxed), 3);
+        assert!(!work_paths(&path).1.exists());
+        Ok(())
+    }
+}
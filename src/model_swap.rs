@@ -0,0 +1,155 @@
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Instant;
+
+use crate::llm::LLM;
+
+/// Loads the generator for a model name given to `/model switch`
+pub type ModelLoader = Box<dyn Fn(&str) -> Result<LLM> + Send + Sync>;
+
+/// The generator answers are currently produced with. Each request takes
+/// its own `Arc` to it, so a request started before a swap finishes on the
+/// old model, which is freed once the last such request is done.
+pub type LlmSlot = Arc<RwLock<Arc<LLM>>>;
+
+/// Model being served and the state of the latest switch
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SwitchStatus {
+    pub model: String,
+    /// Model being loaded in the background
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loading: Option<String>,
+    /// Why the last switch failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Swaps the generator without downtime: the new model is loaded on a
+/// background thread while the current one keeps answering, and replaces it
+/// in the slot once ready. One switch runs at a time; a failed load leaves
+/// the current model in place.
+pub struct ModelSwitcher {
+    slot: LlmSlot,
+    loader: Arc<ModelLoader>,
+    status: Arc<Mutex<SwitchStatus>>,
+}
+
+impl ModelSwitcher {
+    pub fn new(slot: LlmSlot, current: String, loader: ModelLoader) -> Self {
+        ModelSwitcher {
+            slot,
+            loader: Arc::new(loader),
+            status: Arc::new(Mutex::new(SwitchStatus { model: current, loading: None, error: None })),
+        }
+    }
+
+    pub fn status(&self) -> SwitchStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Starts loading `name` in the background; fails when another switch
+    /// is still loading or `name` is already served
+    pub fn switch(&self, name: &str) -> Result<()> {
+        let name = name.trim().to_string();
+        {
+            let mut status = self.status.lock().unwrap();
+            if let Some(loading) = &status.loading {
+                return Err(anyhow!("Still loading '{}'; try again once it is ready", loading));
+            }
+            if status.model == name {
+                return Err(anyhow!("'{}' is already the current model", name));
+            }
+            status.loading = Some(name.clone());
+            status.error = None;
+        }
+
+        let (slot, loader, status) = (Arc::clone(&self.slot), Arc::clone(&self.loader), Arc::clone(&self.status));
+        thread::spawn(move || {
+            let _span = tracing::info_span!("model_switch", model = %name).entered();
+            let start = Instant::now();
+            let loaded = loader(&name);
+            let mut status = status.lock().unwrap();
+            status.loading = None;
+            match loaded {
+                Ok(llm) => {
+                    *slot.write().unwrap() = Arc::new(llm);
+                    tracing::info!(from = %status.model, elapsed = ?start.elapsed(), "switched model");
+                    status.model = name;
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "model switch failed; keeping the current model");
+                    status.error = Some(e.to_string());
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{InferenceOptions, LLMBackend, LLMConfig};
+    use std::ops::ControlFlow;
+    use std::time::Duration;
+
+    /// Answers with the name of the model it stands for
+    struct Named(String);
+
+    impl LLMBackend for Named {
+        fn infer(&self, _prompt: String, _options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+            let _ = on_piece(&self.0);
+            Ok(())
+        }
+    }
+
+    fn named(name: &str) -> LLM {
+        LLM::with_backend(Arc::new(Named(name.to_string())), LLMConfig::default())
+    }
+
+    fn wait_until_loaded(switcher: &ModelSwitcher) -> SwitchStatus {
+        for _ in 0..200 {
+            let status = switcher.status();
+            if status.loading.is_none() {
+                return status;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("model switch didn't finish");
+    }
+
+    #[test]
+    fn test_switch_swaps_in_background_and_keeps_old_model_on_failure() -> Result<()> {
+        let slot: LlmSlot = Arc::new(RwLock::new(Arc::new(named("small"))));
+        let loader: ModelLoader = Box::new(|name| match name {
+            "missing" => Err(anyhow!("No model file 'missing'")),
+            name => {
+                thread::sleep(Duration::from_millis(50));
+                Ok(named(name))
+            }
+        });
+        let switcher = ModelSwitcher::new(Arc::clone(&slot), "small".to_string(), loader);
+        let answer = |llm: &LLM| llm.generate_response("Which model?", Vec::new());
+
+        // A request in flight keeps the model it started with
+        let in_flight = Arc::clone(&slot.read().unwrap());
+        switcher.switch("large")?;
+        assert_eq!(switcher.status().loading.as_deref(), Some("large"));
+        assert!(switcher.switch("other").is_err());
+        assert_eq!(answer(&slot.read().unwrap())?, "small");
+
+        assert_eq!(wait_until_loaded(&switcher), SwitchStatus { model: "large".to_string(), loading: None, error: None });
+        assert_eq!(answer(&slot.read().unwrap())?, "large");
+        assert_eq!(answer(&in_flight)?, "small");
+        assert!(switcher.switch("large").is_err());
+
+        switcher.switch("missing")?;
+        let status = wait_until_loaded(&switcher);
+        assert_eq!(status.model, "large");
+        assert!(status.error.is_some_and(|error| error.contains("missing")));
+        assert_eq!(answer(&slot.read().unwrap())?, "large");
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Result, anyhow};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::llm::LLM;
+
+/// What happens to an answer in a category
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Replace the answer with the `reply-blocked` message
+    Block,
+    /// Return the answer, listing the category in the response
+    #[default]
+    Flag,
+}
+
+#[derive(Debug, Deserialize)]
+struct CategoryConfig {
+    name: String,
+    /// Shown to the model for the LLM check
+    #[serde(default)]
+    description: String,
+    /// Regular expressions, matched case-insensitively
+    #[serde(default)]
+    patterns: Vec<String>,
+    #[serde(default)]
+    action: Action,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModerationConfig {
+    categories: Vec<CategoryConfig>,
+    /// Also ask the model whether an answer falls into a category
+    #[serde(default)]
+    llm_check: bool,
+}
+
+struct Category {
+    name: String,
+    description: String,
+    patterns: Vec<Regex>,
+    action: Action,
+}
+
+/// Categories an answer was found in, and whether any of them blocks it
+#[derive(Debug, Default, PartialEq)]
+pub struct Verdict {
+    pub categories: Vec<String>,
+    pub blocked: bool,
+}
+
+/// Output moderation: keyword/regex rules per sensitive category, plus an
+/// optional LLM classification for answers the rules don't catch
+pub struct Moderator {
+    categories: Vec<Category>,
+    llm_check: bool,
+}
+
+impl Moderator {
+    /// Reads a JSON object with a `categories` array of `{"name",
+    /// "description", "patterns", "action"}` objects and an optional
+    /// `llm_check` flag
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let config: ModerationConfig =
+            serde_json::from_str(&content).map_err(|e| anyhow!("Invalid moderation file {:?}: {}", path, e))?;
+        Self::new(config)
+    }
+
+    fn new(config: ModerationConfig) -> Result<Self> {
+        let categories = config
+            .categories
+            .into_iter()
+            .map(|category| {
+                let patterns = category
+                    .patterns
+                    .iter()
+                    .map(|pattern| {
+                        RegexBuilder::new(pattern).case_insensitive(true).build().map_err(|e| {
+                            anyhow!("Invalid pattern '{}' in moderation category '{}': {}", pattern, category.name, e)
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(Category { name: category.name, description: category.description, patterns, action: category.action })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Moderator { categories, llm_check: config.llm_check })
+    }
+
+    pub fn len(&self) -> usize {
+        self.categories.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.categories.is_empty()
+    }
+
+    /// Whether some answers may be replaced, so streamed text can't be
+    /// shown before the check
+    pub fn can_block(&self) -> bool {
+        self.categories.iter().any(|category| category.action == Action::Block)
+    }
+
+    /// Whether answers are also classified by the model
+    pub fn checks_with_llm(&self) -> bool {
+        self.llm_check
+    }
+
+    /// Checks `answer` against the rules and, when enabled and no blocking
+    /// rule matched, against `llm`. A failed LLM check is logged and only
+    /// the rule results count.
+    pub fn check(&self, answer: &str, llm: &LLM) -> Verdict {
+        self.check_with(answer, llm, true)
+    }
+
+    /// `check`, skipping the LLM classification unless `use_llm`
+    pub fn check_with(&self, answer: &str, llm: &LLM, use_llm: bool) -> Verdict {
+        let mut matched = self.rule_matches(answer);
+        let blocked_by_rules = matched.iter().any(|&i| self.categories[i].action == Action::Block);
+        if self.llm_check && use_llm && !blocked_by_rules {
+            match llm.classify(self.llm_prompt(answer)) {
+                Ok(reply) => matched.extend(self.parse_reply(&reply)),
+                Err(e) => tracing::warn!(error = %e, "moderation check failed"),
+            }
+        }
+        matched.sort_unstable();
+        matched.dedup();
+        Verdict {
+            blocked: matched.iter().any(|&i| self.categories[i].action == Action::Block),
+            categories: matched.into_iter().map(|i| self.categories[i].name.clone()).collect(),
+        }
+    }
+
+    /// Indices of the categories with a pattern matching `answer`
+    fn rule_matches(&self, answer: &str) -> Vec<usize> {
+        (0..self.categories.len())
+            .filter(|&i| self.categories[i].patterns.iter().any(|pattern| pattern.is_match(answer)))
+            .collect()
+    }
+
+    fn llm_prompt(&self, answer: &str) -> String {
+        let categories: String = self
+            .categories
+            .iter()
+            .map(|category| format!("- {}: {}\n", category.name, category.description))
+            .collect();
+        format!(
+            "Decide which of these categories the text below falls into:\n{}\n\
+             Reply with the matching category names separated by commas, or NONE.\n\nText:\n{}",
+            categories, answer
+        )
+    }
+
+    /// Indices of the categories named in the model's reply
+    fn parse_reply(&self, reply: &str) -> Vec<usize> {
+        let reply = reply.to_lowercase();
+        let named: Vec<&str> = reply.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-')).collect();
+        (0..self.categories.len())
+            .filter(|&i| named.contains(&self.categories[i].name.to_lowercase().as_str()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_and_llm_reply_select_categories() -> Result<()> {
+        let moderator = Moderator::new(serde_json::from_str(r#"{
+            "llm_check": true,
+            "categories": [
+                {"name": "medical", "patterns": ["\\bdosage\\b", "\\bmg\\b"], "action": "block"},
+                {"name": "legal", "description": "Legal advice", "patterns": ["\\blawsuit\\b"]},
+                {"name": "self_harm", "description": "Self-harm"}
+            ]
+        }"#)?)?;
+
+        assert_eq!(moderator.rule_matches("The usual DOSAGE is 200 mg."), [0]);
+        assert_eq!(moderator.rule_matches("File a lawsuit."), [1]);
+        assert!(moderator.rule_matches("Refunds take 30 days.").is_empty());
+        assert!(moderator.can_block());
+
+        assert_eq!(moderator.parse_reply("Self_harm, legal."), [1, 2]);
+        assert!(moderator.parse_reply("NONE").is_empty());
+        assert!(moderator.llm_prompt("text").contains("- legal: Legal advice\n"));
+
+        assert!(Moderator::new(serde_json::from_str(r#"{"categories": [{"name": "x", "patterns": ["("]}]}"#)?).is_err());
+        Ok(())
+    }
+}
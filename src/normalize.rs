@@ -0,0 +1,188 @@
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+use std::ops::Range;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::canonical_combining_class;
+
+const MONTHS: &str = r"(january|february|march|april|may|june|july|august|september|october|november|december|jan|feb|mar|apr|jun|jul|aug|sept|sep|oct|nov|dec)\.?";
+
+lazy_static! {
+    static ref ISO_DATE: Regex = Regex::new(r"\b(\d{4})[-/.](\d{1,2})[-/.](\d{1,2})\b").unwrap();
+    static ref NUMERIC_DATE: Regex = Regex::new(r"\b(\d{1,2})([-/.])(\d{1,2})[-/.](\d{4})\b").unwrap();
+    static ref MONTH_FIRST_DATE: Regex =
+        Regex::new(&format!(r"\b{}\s+(\d{{1,2}})(?:st|nd|rd|th)?,?\s+(\d{{4}})\b", MONTHS)).unwrap();
+    static ref DAY_FIRST_DATE: Regex =
+        Regex::new(&format!(r"\b(\d{{1,2}})(?:st|nd|rd|th)?\s+(?:of\s+)?{},?\s+(\d{{4}})\b", MONTHS)).unwrap();
+    static ref NUMBER: Regex = Regex::new(r"\b(?:\d{1,3}(?:,\d{3})+|\d+)(?:\.\d+)*\b").unwrap();
+    static ref WORD: Regex = Regex::new(r"\w+").unwrap();
+}
+
+/// An index term and the bytes of the original text it was read from, so
+/// matches can be shown in the text's own casing and spelling
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub term: String,
+    pub span: Range<usize>,
+}
+
+/// Normalized form of a text that remembers where each byte came from. The
+/// original is never modified; only index terms are read from `text`.
+struct Normalized {
+    text: String,
+    /// Offset in the original of the unit each byte of `text` was produced
+    /// from, plus the original's length as a final entry
+    origins: Vec<usize>,
+}
+
+impl Normalized {
+    /// NFC and lowercase. Composition runs per base character and its
+    /// combining marks, so every output byte has a single origin.
+    fn new(original: &str) -> Self {
+        let mut text = String::with_capacity(original.len());
+        let mut origins = Vec::with_capacity(original.len() + 1);
+        let mut segments = original.char_indices().peekable();
+        while let Some((start, c)) = segments.next() {
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, mark)) = segments.peek() {
+                if canonical_combining_class(mark) == 0 {
+                    break;
+                }
+                end = i + mark.len_utf8();
+                segments.next();
+            }
+            for composed in original[start..end].nfc() {
+                for lower in composed.to_lowercase() {
+                    text.push(lower);
+                    origins.resize(text.len(), start);
+                }
+            }
+        }
+        origins.push(original.len());
+        Normalized { text, origins }
+    }
+
+    /// Like `Regex::replace_all`; replacement bytes map to the start of
+    /// the match they replace
+    fn replace_all(self, re: &Regex, mut replacement: impl FnMut(&Captures) -> String) -> Self {
+        let mut text = String::with_capacity(self.text.len());
+        let mut origins = Vec::with_capacity(self.origins.len());
+        let mut last = 0;
+        for caps in re.captures_iter(&self.text) {
+            let matched = caps.get(0).unwrap();
+            text.push_str(&self.text[last..matched.start()]);
+            origins.extend_from_slice(&self.origins[last..matched.start()]);
+            text.push_str(&replacement(&caps));
+            origins.resize(text.len(), self.origins[matched.start()]);
+            last = matched.end();
+        }
+        text.push_str(&self.text[last..]);
+        origins.extend_from_slice(&self.origins[last..]);
+        Normalized { text, origins }
+    }
+
+    /// Rewrites dates and numbers into canonical tokens so different
+    /// spellings of the same value match: "Jan 5, 2024", "2024/1/5" and
+    /// "01/05/2024" all become `2024_01_05`, "1,000" and "1000.00" become
+    /// `1000`, and "2.50" becomes `2_5`. Underscores keep each value a
+    /// single word.
+    ///
+    /// All-numeric dates with the year last are read month-first after a
+    /// slash (US style) and day-first after a dot or dash, unless only the
+    /// other reading is a valid date.
+    fn canonicalize(self) -> Self {
+        self.replace_all(&ISO_DATE, |caps| {
+            date_token(&caps[1], &caps[2], &caps[3]).unwrap_or_else(|| caps[0].to_string())
+        })
+        .replace_all(&NUMERIC_DATE, |caps| {
+            let (month, day) = if &caps[2] == "/" { (&caps[1], &caps[3]) } else { (&caps[3], &caps[1]) };
+            date_token(&caps[4], month, day)
+                .or_else(|| date_token(&caps[4], day, month))
+                .unwrap_or_else(|| caps[0].to_string())
+        })
+        .replace_all(&MONTH_FIRST_DATE, |caps| {
+            date_token(&caps[3], &month_number(&caps[1]), &caps[2]).unwrap_or_else(|| caps[0].to_string())
+        })
+        .replace_all(&DAY_FIRST_DATE, |caps| {
+            date_token(&caps[3], &month_number(&caps[2]), &caps[1]).unwrap_or_else(|| caps[0].to_string())
+        })
+        .replace_all(&NUMBER, |caps| number_token(&caps[0]))
+    }
+}
+
+/// Splits `text` into normalized words (lowercase NFC, canonical dates and
+/// numbers) with their spans in `text`
+pub fn tokens(text: &str) -> Vec<Token> {
+    let normalized = Normalized::new(text).canonicalize();
+    WORD.find_iter(&normalized.text)
+        .map(|word| Token {
+            term: word.as_str().to_string(),
+            span: normalized.origins[word.start()]..normalized.origins[word.end()],
+        })
+        .collect()
+}
+
+/// `yyyy_mm_dd`, or `None` if month or day are out of range
+fn date_token(year: &str, month: &str, day: &str) -> Option<String> {
+    let month: u32 = month.parse().ok()?;
+    let day: u32 = day.parse().ok()?;
+    ((1..=12).contains(&month) && (1..=31).contains(&day)).then(|| format!("{}_{:02}_{:02}", year, month, day))
+}
+
+fn month_number(name: &str) -> String {
+    let position = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ]
+    .iter()
+    .position(|prefix| name.starts_with(prefix))
+    .unwrap_or(0);
+    (position + 1).to_string()
+}
+
+/// Drops thousands separators and trailing fraction zeros. Dotted
+/// sequences such as version numbers ("1.2.3") are left alone.
+fn number_token(number: &str) -> String {
+    if number.matches('.').count() > 1 {
+        return number.to_string();
+    }
+    let number = number.replace(',', "");
+    match number.split_once('.') {
+        Some((whole, fraction)) => match fraction.trim_end_matches('0') {
+            "" => whole.to_string(),
+            fraction => format!("{}_{}", whole, fraction),
+        },
+        None => number,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(text: &str) -> Vec<String> {
+        tokens(text).into_iter().map(|token| token.term).collect()
+    }
+
+    #[test]
+    fn test_dates_and_numbers_share_canonical_tokens() {
+        for date in ["2024-01-05", "2024/1/5", "01/05/2024", "05.01.2024", "Jan 5, 2024", "5th of January 2024"] {
+            assert_eq!(terms(date), ["2024_01_05"], "{}", date);
+        }
+        // Only the day-first reading is valid
+        assert_eq!(terms("25/12/2024"), ["2024_12_25"]);
+
+        assert_eq!(terms("1,000 or 1000.00 USD"), ["1000", "or", "1000", "usd"]);
+        assert_eq!(terms("2.50 kg, version 1.2.3"), ["2_5", "kg", "version", "1", "2", "3"]);
+        assert_eq!(terms("items 1, 2"), ["items", "1", "2"]);
+    }
+
+    #[test]
+    fn test_spans_point_into_original_text() {
+        // "Cafe\u{301}" composes to "café", one char shorter than the original
+        let text = "Cafe\u{301} opens on Jan 5, 2024 in İzmir";
+        let tokens = tokens(text);
+        let originals: Vec<&str> = tokens.iter().map(|token| &text[token.span.clone()]).collect();
+        assert_eq!(originals, ["Cafe\u{301}", "opens", "on", "Jan 5, 2024", "in", "İzmir"]);
+        assert_eq!(tokens[0].term, "café");
+        assert_eq!(tokens[3].term, "2024_01_05");
+    }
+}
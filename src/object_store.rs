@@ -0,0 +1,98 @@
+use anyhow::{Result, anyhow};
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use std::fs;
+use std::path::Path;
+
+/// File name of the index object under the pushed prefix
+const INDEX_OBJECT: &str = "index.bin";
+
+/// An `s3://bucket/prefix` location holding a published index
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectUrl {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl ObjectUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("s3://")
+            .ok_or_else(|| anyhow!("Expected an s3://bucket/prefix URL, got '{}'", url))?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err(anyhow!("Missing bucket name in '{}'", url));
+        }
+        Ok(ObjectUrl {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        })
+    }
+
+    fn index_key(&self) -> String {
+        if self.prefix.is_empty() {
+            INDEX_OBJECT.to_string()
+        } else {
+            format!("{}/{}", self.prefix, INDEX_OBJECT)
+        }
+    }
+
+    /// Connects using the standard AWS environment (`AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY`, `AWS_REGION`, profiles). Setting `S3_ENDPOINT`
+    /// targets an S3-compatible store such as MinIO, using path-style URLs.
+    fn bucket(&self) -> Result<Box<Bucket>> {
+        let region_name = std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string());
+        let credentials = Credentials::default()?;
+
+        match std::env::var("S3_ENDPOINT") {
+            Ok(endpoint) => {
+                let region = Region::Custom { region: region_name, endpoint };
+                Ok(Bucket::new(&self.bucket, region, credentials)?.with_path_style())
+            }
+            Err(_) => Ok(Bucket::new(&self.bucket, region_name.parse()?, credentials)?),
+        }
+    }
+}
+
+/// Uploads a saved index file to `url`
+pub fn push_index(index_path: impl AsRef<Path>, url: &ObjectUrl) -> Result<()> {
+    let bytes = fs::read(index_path)?;
+    let response = url.bucket()?.put_object(url.index_key(), &bytes)?;
+    if response.status_code() != 200 {
+        return Err(anyhow!("Upload to s3://{}/{} failed with status {}",
+            url.bucket, url.index_key(), response.status_code()));
+    }
+    Ok(())
+}
+
+/// Downloads the index at `url` to `index_path`, replacing any existing file
+/// atomically so concurrent readers keep a consistent view
+pub fn pull_index(url: &ObjectUrl, index_path: impl AsRef<Path>) -> Result<()> {
+    let index_path = index_path.as_ref();
+    let response = url.bucket()?.get_object(url.index_key())?;
+    if response.status_code() != 200 {
+        return Err(anyhow!("Download of s3://{}/{} failed with status {}",
+            url.bucket, url.index_key(), response.status_code()));
+    }
+
+    let tmp_path = index_path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp_path, response.bytes())?;
+    fs::rename(&tmp_path, index_path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_object_url() -> Result<()> {
+        let url = ObjectUrl::parse("s3://kb-releases/team/docs/")?;
+        assert_eq!(url.bucket, "kb-releases");
+        assert_eq!(url.index_key(), "team/docs/index.bin");
+
+        assert_eq!(ObjectUrl::parse("s3://kb-releases")?.index_key(), "index.bin");
+        assert!(ObjectUrl::parse("https://example.com/x").is_err());
+        Ok(())
+    }
+}
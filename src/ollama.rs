@@ -0,0 +1,166 @@
+use anyhow::{Result, anyhow};
+use reqwest::StatusCode;
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader};
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use crate::llm::{InferenceOptions, LLMBackend};
+use crate::prompt_template::instruction;
+
+/// Model used when `--model` is unset
+pub const DEFAULT_MODEL: &str = "mistral";
+/// Time allowed to reach the server; generation itself isn't limited
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Generates with a model served by a local Ollama server through its
+/// streaming `/api/chat` endpoint, so no GGUF file has to be downloaded
+pub struct OllamaBackend {
+    client: Client,
+    url: String,
+    model: String,
+    /// Context window requested from the server
+    num_ctx: usize,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<Message<'a>>,
+    stream: bool,
+    options: Options,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct Options {
+    num_predict: usize,
+    num_ctx: usize,
+    temperature: f32,
+    top_p: f32,
+    repeat_penalty: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+/// One line of the streamed reply
+#[derive(Deserialize)]
+struct ChatChunk {
+    message: Option<ChunkMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChunkMessage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct Tags {
+    models: Vec<Tag>,
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+impl OllamaBackend {
+    /// Connects to the server at `url` (`host:port` is read as HTTP) and
+    /// checks that `model` has been pulled
+    pub fn connect(url: &str, model: &str, num_ctx: usize) -> Result<Self> {
+        let url = match url.trim_end_matches('/') {
+            url if url.contains("://") => url.to_string(),
+            url => format!("http://{}", url),
+        };
+        let client = Client::builder().connect_timeout(CONNECT_TIMEOUT).timeout(None).build()?;
+        let tags: Tags = client
+            .get(format!("{}/api/tags", url))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| anyhow!("Can't reach Ollama at {} ({}); start it with `ollama serve`", url, e))?
+            .json()?;
+        if !tags.models.iter().any(|tag| tag.name == model || tag.name == format!("{}:latest", model)) {
+            return Err(anyhow!("Ollama at {} has no model '{}'; fetch it with `ollama pull {}`", url, model, model));
+        }
+        Ok(OllamaBackend { client, url, model: model.to_string(), num_ctx })
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+impl LLMBackend for OllamaBackend {
+    fn infer(&self, prompt: String, options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+        let request = ChatRequest {
+            model: &self.model,
+            // Ollama applies the model's own chat template
+            messages: vec![Message { role: "user", content: instruction(&prompt) }],
+            stream: true,
+            options: Options {
+                num_predict: options.max_tokens,
+                num_ctx: self.num_ctx,
+                temperature: options.temperature,
+                top_p: options.top_p,
+                repeat_penalty: options.repeat_penalty,
+                seed: options.seed,
+            },
+        };
+        let response = self.client.post(format!("{}/api/chat", self.url)).json(&request).send()?;
+        match response.status() {
+            status if status.is_success() => {}
+            StatusCode::NOT_FOUND => return Err(anyhow!("Ollama has no model '{}'; fetch it with `ollama pull {}`", self.model, self.model)),
+            status => return Err(anyhow!("Ollama returned {}: {}", status, response.text().unwrap_or_default())),
+        }
+
+        // Dropping the response on `Break` closes the connection, which
+        // stops generation on the server
+        for line in BufReader::new(response).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let chunk: ChatChunk = serde_json::from_str(&line)?;
+            if let Some(error) = chunk.error {
+                return Err(anyhow!("Ollama: {}", error));
+            }
+            if let Some(message) = chunk.message.filter(|message| !message.content.is_empty())
+                && on_piece(&message.content).is_break()
+            {
+                break;
+            }
+            if chunk.done {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_becomes_message_and_stream_parses() -> Result<()> {
+        assert_eq!(instruction("<s>[INST] Context...\n\nQuestion: Why? [/INST]"), "Context...\n\nQuestion: Why?");
+        assert_eq!(instruction("plain"), "plain");
+
+        let chunk: ChatChunk = serde_json::from_str(r#"{"model":"mistral","message":{"role":"assistant","content":"Thirty"},"done":false}"#)?;
+        assert_eq!(chunk.message.map(|message| message.content).as_deref(), Some("Thirty"));
+        let last: ChatChunk = serde_json::from_str(r#"{"model":"mistral","done":true,"eval_count":12}"#)?;
+        assert!(last.done && last.message.is_none());
+        let error: ChatChunk = serde_json::from_str(r#"{"error":"model not found"}"#)?;
+        assert_eq!(error.error.as_deref(), Some("model not found"));
+        Ok(())
+    }
+}
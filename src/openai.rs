@@ -0,0 +1,273 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::answer_format::AnswerFormat;
+use crate::llm::{Conversation, GenerationOverrides};
+
+/// Model name reported when a request doesn't name one
+pub const DEFAULT_MODEL: &str = "tapssp";
+
+/// Body of `POST /v1/chat/completions`. Fields the pipeline has no use for
+/// (`n`, `presence_penalty`, `tools`, ...) are accepted and ignored.
+#[derive(Debug, Deserialize)]
+pub struct ChatRequest {
+    pub model: Option<String>,
+    pub messages: Vec<ChatMessage>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<usize>,
+    pub top_p: Option<f32>,
+    pub stop: Option<OneOrMany>,
+    #[serde(default)]
+    pub stream: bool,
+    /// Not part of the OpenAI API: the tapssp answer format
+    pub format: Option<AnswerFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: MessageContent,
+}
+
+/// A plain string, or the array of `{"type": "text", "text": ...}` parts
+/// newer clients send
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentPart {
+    #[serde(default)]
+    pub text: String,
+}
+
+impl MessageContent {
+    fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts.iter().map(|part| part.text.as_str()).collect::<Vec<_>>().join("\n"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+/// What the RAG pipeline needs from a chat request
+pub struct ChatTurn {
+    /// The last user message, used as the retrieval query
+    pub question: String,
+    /// Earlier user messages paired with the assistant replies after them
+    pub history: Conversation,
+    /// Sampling parameters, with system messages as the system prompt
+    pub overrides: GenerationOverrides,
+}
+
+impl ChatRequest {
+    pub fn model(&self) -> String {
+        self.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string())
+    }
+
+    /// Splits the messages into the question, history and overrides. The
+    /// conversation must end with a user message.
+    pub fn into_turn(self) -> Result<ChatTurn> {
+        let mut system = Vec::new();
+        let mut history = Conversation::default();
+        let mut pending: Option<String> = None;
+        for message in &self.messages {
+            let text = message.content.text();
+            match message.role.as_str() {
+                "system" | "developer" => system.push(text),
+                "user" => {
+                    // Consecutive user messages are read as one
+                    pending = Some(match pending.take() {
+                        Some(earlier) => format!("{}\n{}", earlier, text),
+                        None => text,
+                    })
+                }
+                "assistant" => history.push(&pending.take().unwrap_or_default(), &text),
+                _ => {}
+            }
+        }
+        let question = pending
+            .filter(|question| !question.trim().is_empty())
+            .ok_or_else(|| anyhow!("The last message must be a non-empty user message"))?;
+        let overrides = GenerationOverrides {
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            top_p: self.top_p,
+            stop: self.stop.map(OneOrMany::into_vec).unwrap_or_default(),
+            system_prompt: (!system.is_empty()).then(|| system.join("\n")),
+            format: self.format,
+            seed: None,
+        };
+        Ok(ChatTurn { question, history, overrides })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatCompletion {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<Choice>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Choice {
+    pub index: usize,
+    pub message: AssistantMessage,
+    pub finish_reason: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssistantMessage {
+    pub role: &'static str,
+    pub content: String,
+}
+
+impl ChatCompletion {
+    pub fn new(id: String, model: String, answer: String) -> Self {
+        ChatCompletion {
+            id,
+            object: "chat.completion",
+            created: now(),
+            model,
+            choices: vec![Choice {
+                index: 0,
+                message: AssistantMessage { role: "assistant", content: answer },
+                finish_reason: "stop",
+            }],
+        }
+    }
+}
+
+/// One server-sent event of a streamed completion
+#[derive(Debug, Serialize)]
+pub struct ChatCompletionChunk {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<ChunkChoice>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChunkChoice {
+    pub index: usize,
+    pub delta: Delta,
+    pub finish_reason: Option<&'static str>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Delta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+impl ChatCompletionChunk {
+    pub fn new(id: &str, model: &str, delta: Delta, finish_reason: Option<&'static str>) -> Self {
+        ChatCompletionChunk {
+            id: id.to_string(),
+            object: "chat.completion.chunk",
+            created: now(),
+            model: model.to_string(),
+            choices: vec![ChunkChoice { index: 0, delta, finish_reason }],
+        }
+    }
+}
+
+/// Body of `POST /v1/embeddings`
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingRequest {
+    pub input: OneOrMany,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingResponse {
+    pub object: &'static str,
+    pub data: Vec<EmbeddingData>,
+    /// The index's embedding model
+    pub model: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingData {
+    pub object: &'static str,
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+impl EmbeddingResponse {
+    pub fn new(model: String, embeddings: Vec<Vec<f32>>) -> Self {
+        let data = embeddings
+            .into_iter()
+            .enumerate()
+            .map(|(index, embedding)| EmbeddingData { object: "embedding", index, embedding })
+            .collect();
+        EmbeddingResponse { object: "list", data, model }
+    }
+}
+
+/// `chatcmpl-` followed by a random ID, as OpenAI formats completion IDs
+pub fn completion_id() -> String {
+    format!("chatcmpl-{}", uuid::Uuid::new_v4().simple())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_request_splits_into_turn() -> Result<()> {
+        let request: ChatRequest = serde_json::from_str(r#"{
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "system", "content": "Answer briefly."},
+                {"role": "user", "content": "How long do refunds take?"},
+                {"role": "assistant", "content": "Thirty days."},
+                {"role": "user", "content": [{"type": "text", "text": "And exchanges?"}]}
+            ],
+            "temperature": 0.2,
+            "stop": "\n\n",
+            "n": 1
+        }"#)?;
+        assert_eq!(request.model(), "gpt-4o");
+
+        let turn = request.into_turn()?;
+        assert_eq!(turn.question, "And exchanges?");
+        assert_eq!(turn.history.render(), "User: How long do refunds take?\nAssistant: Thirty days.");
+        assert_eq!(turn.overrides.system_prompt.as_deref(), Some("Answer briefly."));
+        assert_eq!((turn.overrides.temperature, turn.overrides.stop), (Some(0.2), vec!["\n\n".to_string()]));
+
+        let ends_with_reply: ChatRequest = serde_json::from_str(
+            r#"{"messages": [{"role": "user", "content": "Hi"}, {"role": "assistant", "content": "Hello"}]}"#,
+        )?;
+        assert!(ends_with_reply.into_turn().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Result, anyhow};
+use reqwest::StatusCode;
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader};
+use std::ops::ControlFlow;
+use std::time::Duration;
+
+use crate::llm::{InferenceOptions, LLMBackend};
+use crate::prompt_template::instruction;
+
+/// Model used when `--model` is unset
+pub const DEFAULT_MODEL: &str = "gpt-4o-mini";
+/// Time allowed to reach the server; generation itself isn't limited
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Generates with a hosted model through an OpenAI-compatible streaming
+/// `/chat/completions` endpoint: OpenAI itself, or a gateway or inference
+/// server (vLLM, llama.cpp, LiteLLM, ...) exposing the same API. Only the
+/// prompt leaves the machine; retrieval stays local.
+pub struct OpenAiBackend {
+    client: Client,
+    /// Base URL including the version, e.g. `https://api.openai.com/v1`
+    url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<Message<'a>>,
+    stream: bool,
+    max_tokens: usize,
+    temperature: f32,
+    top_p: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+/// One `data:` event of the streamed reply
+#[derive(Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    delta: Delta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Delta {
+    content: Option<String>,
+}
+
+/// Body of an error response
+#[derive(Deserialize)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    message: String,
+}
+
+impl OpenAiBackend {
+    /// Connects to the API at `url` and checks the API key against its
+    /// `/models` endpoint. Servers without that endpoint are accepted; a
+    /// wrong model name is reported by the first request.
+    pub fn connect(url: &str, api_key: Option<&str>, model: &str) -> Result<Self> {
+        let backend = OpenAiBackend {
+            client: Client::builder().connect_timeout(CONNECT_TIMEOUT).timeout(None).build()?,
+            url: url.trim_end_matches('/').to_string(),
+            api_key: api_key.map(str::to_string),
+            model: model.to_string(),
+        };
+        let response = backend
+            .authorize(backend.client.get(format!("{}/models", backend.url)))
+            .send()
+            .map_err(|e| anyhow!("Can't reach the OpenAI-compatible API at {} ({})", backend.url, e))?;
+        if matches!(response.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            return Err(anyhow!(
+                "{} rejected the API key ({}); set OPENAI_API_KEY",
+                backend.url,
+                error_message(response)
+            ));
+        }
+        Ok(backend)
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+}
+
+impl LLMBackend for OpenAiBackend {
+    fn infer(&self, prompt: String, options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+        let request = ChatRequest {
+            model: &self.model,
+            // The API applies the model's own chat template
+            messages: vec![Message { role: "user", content: instruction(&prompt) }],
+            stream: true,
+            max_tokens: options.max_tokens,
+            temperature: options.temperature,
+            top_p: options.top_p,
+            seed: options.seed,
+        };
+        let response = self.authorize(self.client.post(format!("{}/chat/completions", self.url))).json(&request).send()?;
+        match response.status() {
+            status if status.is_success() => {}
+            StatusCode::NOT_FOUND => {
+                return Err(anyhow!("{} has no model '{}': {}", self.url, self.model, error_message(response)));
+            }
+            status => return Err(anyhow!("{} returned {}: {}", self.url, status, error_message(response))),
+        }
+
+        // Dropping the response on `Break` closes the connection, which
+        // stops generation on the server
+        for line in BufReader::new(response).lines() {
+            let line = line?;
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if data == "[DONE]" {
+                break;
+            }
+            let chunk: ChatChunk = serde_json::from_str(data)?;
+            let Some(choice) = chunk.choices.into_iter().next() else {
+                continue;
+            };
+            if let Some(content) = choice.delta.content.filter(|content| !content.is_empty())
+                && on_piece(&content).is_break()
+            {
+                break;
+            }
+            if choice.finish_reason.is_some() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `error.message` of an error response, or its raw body
+fn error_message(response: reqwest::blocking::Response) -> String {
+    let body = response.text().unwrap_or_default();
+    serde_json::from_str::<ErrorBody>(&body).map_or(body, |error| error.error.message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_and_stream_events_parse() -> Result<()> {
+        let request = ChatRequest {
+            model: "gpt-4o-mini",
+            messages: vec![Message { role: "user", content: instruction("<s>[INST] Why? [/INST]") }],
+            stream: true,
+            max_tokens: 64,
+            temperature: 0.0,
+            top_p: 1.0,
+            seed: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&request)?,
+            r#"{"model":"gpt-4o-mini","messages":[{"role":"user","content":"Why?"}],"stream":true,"max_tokens":64,"temperature":0.0,"top_p":1.0}"#
+        );
+
+        let chunk: ChatChunk = serde_json::from_str(
+            r#"{"id":"c1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"content":"Thirty"},"finish_reason":null}]}"#,
+        )?;
+        assert_eq!(chunk.choices[0].delta.content.as_deref(), Some("Thirty"));
+        let last: ChatChunk = serde_json::from_str(r#"{"choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#)?;
+        assert_eq!(last.choices[0].finish_reason.as_deref(), Some("stop"));
+        // Usage-only events at the end of some streams have no choices
+        let usage: ChatChunk = serde_json::from_str(r#"{"choices":[],"usage":{"total_tokens":12}}"#)?;
+        assert!(usage.choices.is_empty());
+        let error: ErrorBody = serde_json::from_str(r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error"}}"#)?;
+        assert_eq!(error.error.message, "Incorrect API key provided");
+        Ok(())
+    }
+}
@@ -0,0 +1,860 @@
+use anyhow::{Result, anyhow};
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use tracing::info_span;
+
+use crate::answer_cache::AnswerCache;
+use crate::citations::Sources;
+use crate::collection::{Collections, DEFAULT_COLLECTION};
+use crate::escalation::{AbstainPolicy, Escalation, EscalationEvent, Outcome};
+use crate::explain::{self, Explanation};
+use crate::faq::{Faq, FaqEntry};
+use crate::federation::{self, Federation};
+use crate::highlight::Highlight;
+use crate::i18n;
+use crate::intent::{Intent, IntentClassifier};
+use crate::llm::{Conversation, GenerationOverrides, LLM, LLMConfig};
+use crate::maintenance::ActivityTracker;
+use crate::metadata::Filter;
+use crate::model_swap::{LlmSlot, ModelLoader, ModelSwitcher};
+use crate::planner::{Budget, Capabilities, ModelChoice, Plan, Planner};
+use crate::moderation::Moderator;
+use crate::repeats;
+use crate::retriever::{self, Retriever, ScoredChunk};
+use crate::timings::Timings;
+use crate::vector_db::VectorDB;
+use crate::webhooks::{WebhookEvent, Webhooks};
+
+/// Rewrites the query before it reaches the retriever
+pub type PreRetrievalHook = Box<dyn Fn(&mut String) + Send + Sync>;
+/// Filters or reorders retrieved chunks for a query
+pub type PostRetrievalHook = Box<dyn Fn(&str, &mut Vec<ScoredChunk>) + Send + Sync>;
+/// Adjusts the query and context right before the prompt is built
+pub type PreGenerationHook = Box<dyn Fn(&mut String, &mut Vec<String>) + Send + Sync>;
+/// Rewrites the generated answer for a query
+pub type PostGenerationHook = Box<dyn Fn(&str, &mut String) + Send + Sync>;
+
+#[derive(Default)]
+struct Hooks {
+    pre_retrieval: Vec<PreRetrievalHook>,
+    post_retrieval: Vec<PostRetrievalHook>,
+    pre_generation: Vec<PreGenerationHook>,
+    post_generation: Vec<PostGenerationHook>,
+}
+
+/// Retrieve -> prompt -> generate, with hook points around each stage.
+/// Hooks of the same stage run in registration order. Clones share
+/// everything, including the knowledge base.
+#[derive(Clone)]
+pub struct RagPipeline {
+    retriever: Arc<RwLock<Retriever>>,
+    /// Served collection answered from; the main index when `None`
+    collection: Option<String>,
+    /// Chunks retrieved per query in `collection`, if it sets its own
+    collection_top_k: Option<usize>,
+    shared: Arc<Shared>,
+}
+
+/// Everything but the knowledge base, which all collections are served with
+struct Shared {
+    /// The main index, selected as `DEFAULT_COLLECTION`
+    main: Arc<RwLock<Retriever>>,
+    collections: Collections,
+    llm: LlmSlot,
+    switcher: Option<ModelSwitcher>,
+    /// Faster generator the planner may pick for tight budgets
+    small_llm: Option<Arc<LLM>>,
+    planner: Planner,
+    top_k: usize,
+    hooks: Hooks,
+    activity: Arc<ActivityTracker>,
+    faq: Option<Faq>,
+    answer_cache: Option<AnswerCache>,
+    abstain: AbstainPolicy,
+    escalation: Option<Escalation>,
+    webhooks: Webhooks,
+    moderator: Option<Moderator>,
+    federation: Federation,
+    intent: IntentClassifier,
+}
+
+impl RagPipeline {
+    pub fn new(retriever: Retriever, llm: LLM) -> Self {
+        let retriever = Arc::new(RwLock::new(retriever));
+        let shared = Shared {
+            main: Arc::clone(&retriever),
+            collections: Collections::default(),
+            llm: Arc::new(RwLock::new(Arc::new(llm))),
+            switcher: None,
+            small_llm: None,
+            planner: Planner::default(),
+            top_k: 3,
+            hooks: Hooks::default(),
+            activity: Arc::new(ActivityTracker::new()),
+            faq: None,
+            answer_cache: None,
+            abstain: AbstainPolicy::default(),
+            escalation: None,
+            webhooks: Webhooks::default(),
+            moderator: None,
+            federation: Federation::default(),
+            intent: IntentClassifier::default(),
+        };
+        RagPipeline { retriever, collection: None, collection_top_k: None, shared: Arc::new(shared) }
+    }
+
+    /// Assembles a pipeline from an index or documents and a model; see
+    /// `PipelineBuilder`
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.shared_mut().top_k = top_k;
+        self
+    }
+
+    /// Chunks retrieved per query
+    pub fn top_k(&self) -> usize {
+        self.collection_top_k.unwrap_or(self.shared.top_k)
+    }
+
+    /// Serves `collections` next to the main index, for `collection` to
+    /// select from
+    pub(crate) fn with_collections(mut self, collections: Collections) -> Self {
+        self.shared_mut().collections = collections;
+        self
+    }
+
+    /// This pipeline answering from the served collection `name`, sharing
+    /// the model, hooks and everything else. `DEFAULT_COLLECTION` is the
+    /// main index.
+    pub(crate) fn collection(&self, name: &str) -> Result<RagPipeline> {
+        let shared = Arc::clone(&self.shared);
+        if name == DEFAULT_COLLECTION {
+            return Ok(RagPipeline { retriever: Arc::clone(&shared.main), collection: None, collection_top_k: None, shared });
+        }
+        let Some(collection) = shared.collections.get(name) else {
+            return Err(anyhow!("No collection named '{}'; collections: {}", name, shared.collections.names().join(", ")));
+        };
+        Ok(RagPipeline {
+            retriever: Arc::clone(&collection.retriever),
+            collection: Some(name.to_string()),
+            collection_top_k: collection.top_k,
+            shared,
+        })
+    }
+
+    /// The collection answered from, `DEFAULT_COLLECTION` for the main index
+    pub(crate) fn collection_name(&self) -> &str {
+        self.collection.as_deref().unwrap_or(DEFAULT_COLLECTION)
+    }
+
+    pub(crate) fn collections(&self) -> &Collections {
+        &self.shared.collections
+    }
+
+    /// Configuration is only changed while the pipeline is being set up,
+    /// before it is cloned for other collections or threads
+    fn shared_mut(&mut self) -> &mut Shared {
+        Arc::get_mut(&mut self.shared).expect("pipeline configured after it was shared")
+    }
+
+    /// Answers matching questions from `faq` instead of running the pipeline
+    pub fn with_faq(mut self, faq: Faq) -> Self {
+        self.shared_mut().faq = Some(faq);
+        self
+    }
+
+    /// Serves repeated questions from `cache` until a document their answer
+    /// came from changes
+    pub(crate) fn with_answer_cache(mut self, cache: AnswerCache) -> Self {
+        self.shared_mut().answer_cache = Some(cache);
+        self
+    }
+
+    /// Sends questions that end in a "no answer" outcome to `escalation`
+    pub(crate) fn with_escalation(mut self, escalation: Escalation) -> Self {
+        self.shared_mut().escalation = Some(escalation);
+        self
+    }
+
+    /// Notifies `webhooks` of low-confidence answers
+    pub(crate) fn with_webhooks(mut self, webhooks: Webhooks) -> Self {
+        self.shared_mut().webhooks = webhooks;
+        self
+    }
+
+    /// Checks generated answers against `moderator` in `moderate`
+    pub fn with_moderation(mut self, moderator: Moderator) -> Self {
+        self.shared_mut().moderator = Some(moderator);
+        self
+    }
+
+    /// Merges chunks retrieved from the `federation` peers into the context
+    pub(crate) fn with_federation(mut self, federation: Federation) -> Self {
+        self.shared_mut().federation = federation;
+        self
+    }
+
+    /// Decides with `intent` which questions are answered without retrieval
+    pub(crate) fn with_intent(mut self, intent: IntentClassifier) -> Self {
+        self.shared_mut().intent = intent;
+        self
+    }
+
+    /// Lets the planner answer on `llm` when the main model won't fit a
+    /// query's budget
+    pub fn with_small_model(mut self, llm: LLM) -> Self {
+        self.shared_mut().small_llm = Some(Arc::new(llm));
+        self
+    }
+
+    /// Lets `/model switch` replace the generator with models `loader`
+    /// builds, starting from the one named `current`
+    pub(crate) fn with_model_switching(mut self, current: String, loader: ModelLoader) -> Self {
+        let switcher = ModelSwitcher::new(Arc::clone(&self.shared.llm), current, loader);
+        self.shared_mut().switcher = Some(switcher);
+        self
+    }
+
+    pub fn on_pre_retrieval(&mut self, hook: impl Fn(&mut String) + Send + Sync + 'static) -> &mut Self {
+        self.shared_mut().hooks.pre_retrieval.push(Box::new(hook));
+        self
+    }
+
+    pub fn on_post_retrieval(&mut self, hook: impl Fn(&str, &mut Vec<ScoredChunk>) + Send + Sync + 'static) -> &mut Self {
+        self.shared_mut().hooks.post_retrieval.push(Box::new(hook));
+        self
+    }
+
+    pub fn on_pre_generation(
+        &mut self,
+        hook: impl Fn(&mut String, &mut Vec<String>) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.shared_mut().hooks.pre_generation.push(Box::new(hook));
+        self
+    }
+
+    pub fn on_post_generation(&mut self, hook: impl Fn(&str, &mut String) + Send + Sync + 'static) -> &mut Self {
+        self.shared_mut().hooks.post_generation.push(Box::new(hook));
+        self
+    }
+
+    /// Shared handle to the retriever, e.g. for background maintenance
+    pub fn retriever(&self) -> Arc<RwLock<Retriever>> {
+        Arc::clone(&self.retriever)
+    }
+
+    /// Records when the pipeline last served a query
+    pub(crate) fn activity(&self) -> Arc<ActivityTracker> {
+        Arc::clone(&self.shared.activity)
+    }
+
+    /// The current generator; a model switch doesn't affect the returned one
+    pub fn llm(&self) -> Arc<LLM> {
+        Arc::clone(&self.shared.llm.read().expect("llm lock poisoned"))
+    }
+
+    pub(crate) fn model_switcher(&self) -> Option<&ModelSwitcher> {
+        self.shared.switcher.as_ref()
+    }
+
+    /// The curated FAQ entry answering `query`, if any
+    pub fn faq_answer(&self, query: &str) -> Option<&FaqEntry> {
+        let entry = self.shared.faq.as_ref()?.find(query)?;
+        self.shared.activity.touch();
+        Some(entry)
+    }
+
+    /// How to serve a query within `budget`
+    pub fn plan(&self, budget: &Budget) -> Plan {
+        let capabilities = Capabilities {
+            top_k: self.top_k(),
+            rerank: self.retriever.read().expect("retriever lock poisoned").has_reranker(),
+            verify: self.shared.moderator.as_ref().is_some_and(Moderator::checks_with_llm),
+            small_model: self.shared.small_llm.is_some(),
+        };
+        self.shared.planner.plan(budget, &capabilities)
+    }
+
+    /// The model answering queries served with `plan`
+    fn planned_llm(&self, plan: &Plan) -> Arc<LLM> {
+        match (plan.model, &self.shared.small_llm) {
+            (ModelChoice::Small, Some(small)) => Arc::clone(small),
+            _ => self.llm(),
+        }
+    }
+
+    /// Tokens the model that generated `answer` (the planned one, with a
+    /// `plan`) counts in it
+    pub fn count_answer_tokens(&self, answer: &str, plan: Option<&Plan>) -> usize {
+        match plan {
+            Some(plan) => self.planned_llm(plan).count_tokens(answer),
+            None => self.llm().count_tokens(answer),
+        }
+    }
+
+    /// Updates the planner's cost figures from a query served with `plan`
+    pub fn observe_plan(&self, plan: &Plan, timings: &Timings, context: &[String], answer_tokens: usize) {
+        let context_tokens = context.iter().map(|chunk| crate::llm::estimate_tokens(chunk)).sum();
+        self.shared.planner.observe(plan, timings, context_tokens, answer_tokens);
+    }
+
+    /// A cached answer to `query` whose source documents haven't changed
+    pub(crate) fn cached_answer(&self, query: &str) -> Option<String> {
+        let cache = self.answer_cache()?;
+        let answer = cache.get(&self.retriever.read().expect("retriever lock poisoned"), query)?;
+        self.shared.activity.touch();
+        Some(answer)
+    }
+
+    /// Caches `answer` to `query` along with the source documents of
+    /// `context`. Answers using chunks of federated peers aren't cached,
+    /// since changes on the peers can't be seen.
+    pub(crate) fn cache_answer(&self, query: &str, context: &[String], answer: &str) {
+        let Some(cache) = self.answer_cache() else {
+            return;
+        };
+        let retriever = self.retriever.read().expect("retriever lock poisoned");
+        if let Some(sources) = retriever.source_revisions(context) {
+            cache.insert(&retriever, query, answer.to_string(), sources);
+        }
+    }
+
+    /// Answers are only cached for the main index, whose documents the
+    /// cache checks for changes
+    fn answer_cache(&self) -> Option<&AnswerCache> {
+        self.shared.answer_cache.as_ref().filter(|_| self.collection.is_none())
+    }
+
+    /// See `repeats::find_repeat`
+    pub fn find_repeat(&self, query: &str, earlier: &[&str]) -> Option<usize> {
+        repeats::find_repeat(&self.retriever.read().expect("retriever lock poisoned"), query, earlier)
+    }
+
+    /// Retrieves context for `query`, running the pre/post-retrieval hooks.
+    /// Greetings and questions about the assistant get no context.
+    pub fn retrieve(&self, query: &str) -> Vec<ScoredChunk> {
+        self.retrieve_timed(query, &mut Timings::new())
+    }
+
+    pub fn retrieve_timed(&self, query: &str, timings: &mut Timings) -> Vec<ScoredChunk> {
+        self.retrieve_with(query, self.top_k(), true, timings)
+    }
+
+    /// `retrieve_timed` with `top_k` chunks in place of the pipeline's
+    pub fn retrieve_top_k(&self, query: &str, top_k: usize, timings: &mut Timings) -> Vec<ScoredChunk> {
+        self.retrieve_with(query, top_k, true, timings)
+    }
+
+    /// `retrieve_timed` with the depth and reranking chosen by `plan`
+    pub fn retrieve_planned(&self, query: &str, plan: &Plan, timings: &mut Timings) -> Vec<ScoredChunk> {
+        self.retrieve_with(query, plan.top_k, plan.rerank, timings)
+    }
+
+    fn retrieve_with(&self, query: &str, top_k: usize, rerank: bool, timings: &mut Timings) -> Vec<ScoredChunk> {
+        let span = info_span!("retrieval", top_k, chunk_count = tracing::field::Empty);
+        let _guard = span.enter();
+
+        if timings.time("intent", || self.shared.intent.classify(query, &self.llm())) == Intent::ChitChat {
+            tracing::debug!("small talk, skipping retrieval");
+            span.record("chunk_count", 0);
+            return Vec::new();
+        }
+
+        let query = self.rewrite_query(query);
+
+        self.shared.activity.touch();
+        let mut chunks = self.retriever
+            .read()
+            .expect("retriever lock poisoned")
+            .retrieve_planned(&query, top_k, rerank, timings);
+        if !self.shared.federation.is_empty() {
+            let start = Instant::now();
+            let remote = self.shared.federation.fetch(&query, top_k);
+            timings.record("federation", start.elapsed());
+            chunks = federation::merge(std::iter::once(chunks).chain(remote).collect(), top_k);
+        }
+        for hook in &self.shared.hooks.post_retrieval {
+            hook(&query, &mut chunks);
+        }
+        span.record("chunk_count", chunks.len());
+        chunks
+    }
+
+    /// Local chunks for `query` with their scores, without federation, for
+    /// peers calling `/query/raw` and the remote retriever endpoints, which
+    /// may restrict them to chunks whose metadata matches `filter`
+    pub(crate) fn retrieve_local(&self, query: &str, top_k: usize, filter: Option<&Filter>) -> Vec<ScoredChunk> {
+        let query = self.rewrite_query(query);
+        self.shared.activity.touch();
+        self.retriever
+            .read()
+            .expect("retriever lock poisoned")
+            .retrieve_with_scores(&query, top_k, filter)
+    }
+
+    /// Highlights for each of `chunks` as retrieved for `query`, after the
+    /// pre-retrieval hooks rewrote it
+    pub fn highlight(&self, query: &str, chunks: &[String]) -> Vec<Vec<Highlight>> {
+        let query = self.rewrite_query(query);
+        let retriever = self.retriever.read().expect("retriever lock poisoned");
+        chunks.iter().map(|chunk| retriever.highlight(&query, chunk)).collect()
+    }
+
+    /// The documents of `context` with their origin and score, marking
+    /// those `answer` cites
+    pub fn sources(&self, context: &[ScoredChunk], answer: &str) -> Sources {
+        Sources::new(context, answer)
+    }
+
+    /// Why `answer` was given: context scores, the chunk behind each answer
+    /// sentence and the sampling parameters of retry `attempt`
+    pub(crate) fn explain(
+        &self,
+        query: &str,
+        context: &[String],
+        answer: &str,
+        overrides: &GenerationOverrides,
+        attempt: usize,
+    ) -> Result<Explanation> {
+        let sampling = self.llm().describe_sampling(overrides, attempt)?;
+        let query = self.rewrite_query(query);
+        let retriever = self.retriever.read().expect("retriever lock poisoned");
+        Ok(explain::explain(&retriever, &query, context, answer, sampling))
+    }
+
+    /// `text` embedded like the indexed documents, with the model's ID
+    pub fn embed(&self, text: &str) -> Result<(String, Vec<f32>)> {
+        let retriever = self.retriever.read().expect("retriever lock poisoned");
+        Ok((retriever.model_id().to_string(), retriever.embed(text)?))
+    }
+
+    /// `query` as the retriever sees it, after the pre-retrieval hooks
+    fn rewrite_query(&self, query: &str) -> String {
+        let mut query = query.to_string();
+        for hook in &self.shared.hooks.pre_retrieval {
+            hook(&mut query);
+        }
+        query
+    }
+
+    /// Generates an answer from already retrieved context, running the
+    /// pre/post-generation hooks
+    pub fn generate(&self, query: &str, context: Vec<String>) -> Result<String> {
+        self.generate_with(query, context, None, &GenerationOverrides::default(), &mut Timings::new())
+    }
+
+    /// `generate` following a chat history, with per-request parameter
+    /// overrides and stage timings
+    pub fn generate_with(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        overrides: &GenerationOverrides,
+        timings: &mut Timings,
+    ) -> Result<String> {
+        self.generate_streaming(query, context, history, overrides, timings, |_| ControlFlow::Continue(()))
+    }
+
+    /// `generate_with`, passing answer text to `on_token` as it is decoded.
+    /// The post-generation hooks only see the complete answer, so the
+    /// returned text can differ from the streamed pieces. `on_token`
+    /// returning `Break` stops generation, see `LLM::generate_streaming`.
+    pub fn generate_streaming(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        overrides: &GenerationOverrides,
+        timings: &mut Timings,
+        on_token: impl FnMut(&str) -> ControlFlow<()>,
+    ) -> Result<String> {
+        self.generate_on(&self.llm(), query, context, history, overrides, timings, on_token)
+    }
+
+    /// `generate_streaming` on the model chosen by `plan`, with its answer
+    /// length cap
+    pub fn generate_planned(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        overrides: &GenerationOverrides,
+        plan: &Plan,
+        timings: &mut Timings,
+        on_token: impl FnMut(&str) -> ControlFlow<()>,
+    ) -> Result<String> {
+        let llm = self.planned_llm(plan);
+        let mut overrides = overrides.clone();
+        if let Some(cap) = plan.max_tokens {
+            overrides.max_tokens = Some(overrides.max_tokens.map_or(cap, |max_tokens| max_tokens.min(cap)));
+        }
+        self.generate_on(&llm, query, context, None, &overrides, timings, on_token)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn generate_on(
+        &self,
+        llm: &LLM,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        overrides: &GenerationOverrides,
+        timings: &mut Timings,
+        on_token: impl FnMut(&str) -> ControlFlow<()>,
+    ) -> Result<String> {
+        self.run_generation(query, context, |query, context| {
+            llm.generate_streaming(query, context, history, overrides, timings, on_token)
+        })
+    }
+
+    /// Generates a new answer to an answered question for `/retry` attempt
+    /// `attempt`, through the same hooks, abstain review and moderation as
+    /// the first answer
+    pub fn regenerate(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        attempt: usize,
+        overrides: &GenerationOverrides,
+    ) -> Result<(Outcome, String)> {
+        let llm = self.llm();
+        let answer = self.run_generation(query, context.clone(), |query, context| {
+            llm.regenerate_response(query, context, history, attempt, overrides)
+        })?;
+        let (outcome, answer) = self.review_answer(query, &context, answer);
+        let (outcome, answer, _) = self.moderate(outcome, answer);
+        Ok((outcome, answer))
+    }
+
+    /// `n` alternative answers to an answered question for `/variants`, each
+    /// through the hooks, abstain review and moderation
+    pub fn variants(
+        &self,
+        query: &str,
+        context: Vec<String>,
+        history: Option<&Conversation>,
+        n: usize,
+        overrides: &GenerationOverrides,
+    ) -> Result<Vec<(Outcome, String)>> {
+        let llm = self.llm();
+        (0..n)
+            .map(|_| {
+                let answer = self.run_generation(query, context.clone(), |query, context| {
+                    llm.generate_variant(query, context, history, overrides)
+                })?;
+                let (outcome, answer) = self.review_answer(query, &context, answer);
+                let (outcome, answer, _) = self.moderate(outcome, answer);
+                Ok((outcome, answer))
+            })
+            .collect()
+    }
+
+    /// Runs `generate` between the pre- and post-generation hooks
+    fn run_generation(
+        &self,
+        query: &str,
+        mut context: Vec<String>,
+        generate: impl FnOnce(&str, Vec<String>) -> Result<String>,
+    ) -> Result<String> {
+        let span = info_span!("generation", context_chunks = context.len(), answer_chars = tracing::field::Empty);
+        let _guard = span.enter();
+
+        let mut query = query.to_string();
+        for hook in &self.shared.hooks.pre_generation {
+            hook(&mut query, &mut context);
+        }
+
+        let answer = generate(&query, context);
+        self.shared.activity.touch();
+        let mut answer = answer?;
+        for hook in &self.shared.hooks.post_generation {
+            hook(&query, &mut answer);
+        }
+        span.record("answer_chars", answer.chars().count());
+        Ok(answer)
+    }
+
+    /// The prompt `generate` would send for `query` and `context`, after
+    /// the pre-generation hooks, without generating
+    pub fn prompt(&self, query: &str, mut context: Vec<String>) -> Result<String> {
+        let mut query = query.to_string();
+        for hook in &self.shared.hooks.pre_generation {
+            hook(&mut query, &mut context);
+        }
+        self.llm().prompt(&query, context, None, &GenerationOverrides::default())
+    }
+
+    /// Checks a generated answer against the abstain policy. When retrieval
+    /// was weak and the model signals uncertainty, the answer is replaced by
+    /// a "no answer" reply and the question is escalated.
+    pub fn review_answer(&self, query: &str, context: &[String], answer: String) -> (Outcome, String) {
+        if !crate::escalation::signals_uncertainty(&answer) {
+            return (Outcome::Answered, answer);
+        }
+        let query = self.rewrite_query(query);
+        let confidence = self.retriever.read().expect("retriever lock poisoned").top_score(&query);
+        if !self.shared.abstain.should_abstain(confidence, &answer) {
+            return (Outcome::Answered, answer);
+        }
+
+        tracing::info!(confidence, "no answer found, escalating");
+        self.shared.webhooks.send_in_background(WebhookEvent::LowConfidence { query: query.clone(), confidence });
+        if let Some(escalation) = &self.shared.escalation {
+            escalation.send_in_background(EscalationEvent {
+                query,
+                answer,
+                confidence,
+                context: context.to_vec(),
+            });
+        }
+        (Outcome::NoAnswer, i18n::text("reply-no-answer"))
+    }
+
+    /// Whether `moderate` may replace an answer, in which case it must not
+    /// be shown while it is generated
+    pub fn may_block_answers(&self) -> bool {
+        self.shared.moderator.as_ref().is_some_and(Moderator::can_block)
+    }
+
+    /// Runs output moderation on a reviewed answer. Returns the outcome and
+    /// answer to send, which are replaced when a blocking category matched,
+    /// and the names of all matched categories. Only generated answers are
+    /// checked; FAQ and "no answer" replies are curated text.
+    pub fn moderate(&self, outcome: Outcome, answer: String) -> (Outcome, String, Vec<String>) {
+        self.moderate_with(outcome, answer, true)
+    }
+
+    /// `moderate`, asking the model to check the answer only if `plan`
+    /// verifies; the rules always apply
+    pub fn moderate_planned(&self, outcome: Outcome, answer: String, plan: &Plan, timings: &mut Timings) -> (Outcome, String, Vec<String>) {
+        timings.time("verify", || self.moderate_with(outcome, answer, plan.verify))
+    }
+
+    fn moderate_with(&self, outcome: Outcome, answer: String, use_llm: bool) -> (Outcome, String, Vec<String>) {
+        let Some(moderator) = self.shared.moderator.as_ref().filter(|_| outcome == Outcome::Answered) else {
+            return (outcome, answer, Vec::new());
+        };
+        let verdict = moderator.check_with(&answer, &self.llm(), use_llm);
+        if verdict.categories.is_empty() {
+            return (outcome, answer, Vec::new());
+        }
+        tracing::info!(categories = ?verdict.categories, blocked = verdict.blocked, "answer moderated");
+        if verdict.blocked {
+            (Outcome::Blocked, i18n::text("reply-blocked"), verdict.categories)
+        } else {
+            (outcome, answer, verdict.categories)
+        }
+    }
+
+    /// Runs the whole pipeline for a single query, short-circuiting on an
+    /// FAQ match or a cached answer
+    pub fn answer(&self, query: &str) -> Result<(Outcome, String)> {
+        self.answer_with(query, &GenerationOverrides::default())
+    }
+
+    /// `answer` with per-request sampling overrides
+    pub fn answer_with(&self, query: &str, overrides: &GenerationOverrides) -> Result<(Outcome, String)> {
+        if let Some(entry) = self.faq_answer(query) {
+            return Ok((Outcome::Faq, entry.answer.clone()));
+        }
+        if let Some(answer) = self.cached_answer(query) {
+            return Ok((Outcome::Cached, answer));
+        }
+        let context = retriever::contents(&self.retrieve(query));
+        let answer = self.generate_with(query, context.clone(), None, overrides, &mut Timings::new())?;
+        let (outcome, answer) = self.review_answer(query, &context, answer);
+        if outcome == Outcome::Answered {
+            self.cache_answer(query, &context, &answer);
+        }
+        Ok((outcome, answer))
+    }
+}
+
+/// Builds a `RagPipeline` for programs embedding tapssp. The knowledge base
+/// is a `Retriever`, an index saved by `tapssp index`, or empty, with any
+/// `document`s added; the model is an `LLM` or is loaded from `llm_config`.
+#[derive(Default)]
+pub struct PipelineBuilder {
+    retriever: Option<Retriever>,
+    index: Option<PathBuf>,
+    documents: Vec<(String, BTreeMap<String, String>)>,
+    llm: Option<LLM>,
+    llm_config: LLMConfig,
+    top_k: Option<usize>,
+}
+
+impl PipelineBuilder {
+    pub fn retriever(mut self, retriever: Retriever) -> Self {
+        self.retriever = Some(retriever);
+        self
+    }
+
+    /// Loads the knowledge base from the index file at `path`
+    pub fn index(mut self, path: impl Into<PathBuf>) -> Self {
+        self.index = Some(path.into());
+        self
+    }
+
+    /// Adds a text to the knowledge base, chunked per its settings
+    pub fn document(self, text: impl Into<String>) -> Self {
+        self.document_with_metadata(text, BTreeMap::new())
+    }
+
+    /// Adds a text whose chunks all get `metadata`, for filters and citations
+    pub fn document_with_metadata(mut self, text: impl Into<String>, metadata: BTreeMap<String, String>) -> Self {
+        self.documents.push((text.into(), metadata));
+        self
+    }
+
+    /// Generates with `llm` instead of loading a model
+    pub fn llm(mut self, llm: LLM) -> Self {
+        self.llm = Some(llm);
+        self
+    }
+
+    pub fn llm_config(mut self, config: LLMConfig) -> Self {
+        self.llm_config = config;
+        self
+    }
+
+    /// The GGUF model file to load; the default model is downloaded when unset
+    pub fn model(mut self, path: impl Into<PathBuf>) -> Self {
+        self.llm_config.model_path = Some(path.into());
+        self
+    }
+
+    pub fn top_k(mut self, top_k: usize) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    pub fn build(self) -> Result<RagPipeline> {
+        let mut retriever = match (self.retriever, self.index) {
+            (Some(retriever), _) => retriever,
+            (None, Some(path)) => Retriever::with_vector_db(VectorDB::load(&path)?),
+            (None, None) => Retriever::new(),
+        };
+        for (text, metadata) in self.documents {
+            retriever.add_to_knowledge_base(text, metadata)?;
+        }
+        let llm = match self.llm {
+            Some(llm) => llm,
+            None => LLM::new(self.llm_config)?,
+        };
+        let pipeline = RagPipeline::new(retriever, llm);
+        Ok(match self.top_k {
+            Some(top_k) => pipeline.with_top_k(top_k),
+            None => pipeline,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{InferenceOptions, LLMBackend};
+    use std::ops::ControlFlow;
+
+    /// Answers from the prompt's context, like a model that read it
+    struct Reader;
+
+    impl LLMBackend for Reader {
+        fn infer(&self, prompt: String, _options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+            let _ = on_piece(if prompt.contains("thirty days") { "Thirty days." } else { "I don't know." });
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_builder_assembles_pipeline_from_documents() -> Result<()> {
+        let pipeline = RagPipeline::builder()
+            .document("Refunds are paid within thirty days of the return.")
+            .document_with_metadata("Shipping takes two days.", BTreeMap::from([("source".to_string(), "shipping.md".to_string())]))
+            .llm(LLM::with_backend(Arc::new(Reader), LLMConfig::default()))
+            .top_k(1)
+            .build()?;
+
+        assert_eq!(pipeline.retriever().read().unwrap().len(), 2);
+        assert_eq!(pipeline.answer("How long until refunds are paid?")?, (Outcome::Answered, "Thirty days.".to_string()));
+        assert!(RagPipeline::builder().index("/nonexistent/index.bin").llm(LLM::with_backend(Arc::new(Reader), LLMConfig::default())).build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_score_leaves_unrelated_questions_without_context() -> Result<()> {
+        let mut retriever = Retriever::new();
+        retriever.add_to_knowledge_base("Refunds are paid within thirty days of the return.".to_string(), BTreeMap::new())?;
+        retriever.add_to_knowledge_base("Shipping takes two days.".to_string(), BTreeMap::new())?;
+        retriever.set_min_score(Some(0.1));
+        let pipeline = RagPipeline::builder()
+            .retriever(retriever)
+            .llm(LLM::with_backend(Arc::new(Reader), LLMConfig::default()))
+            .top_k(2)
+            .build()?;
+
+        assert_eq!(retriever::contents(&pipeline.retrieve("How long until refunds are paid?")), ["Refunds are paid within thirty days of the return."]);
+        assert!(pipeline.retrieve("Which colours does the umbrella come in?").is_empty());
+        assert!(pipeline.prompt("Which colours does the umbrella come in?", Vec::new())?.contains(&i18n::text("prompt-no-context")));
+        // The model saying it doesn't know is then answered as "no answer"
+        assert_eq!(pipeline.answer("Which colours does the umbrella come in?")?.0, Outcome::NoAnswer);
+        Ok(())
+    }
+
+    #[test]
+    fn test_collections_answer_from_their_own_index() -> Result<()> {
+        let mut handbook = Retriever::new();
+        handbook.add_to_knowledge_base("Shipping takes two days.".to_string(), BTreeMap::new())?;
+        let mut collections = Collections::default();
+        collections.insert("handbook", handbook, PathBuf::from("handbook.bin"), Some(1))?;
+        assert!(collections.insert(DEFAULT_COLLECTION, Retriever::new(), PathBuf::from("main.bin"), None).is_err());
+        let pipeline = RagPipeline::builder()
+            .document("Refunds are paid within thirty days of the return.")
+            .llm(LLM::with_backend(Arc::new(Reader), LLMConfig::default()))
+            .top_k(2)
+            .build()?
+            .with_collections(collections)
+            .with_answer_cache(AnswerCache::new(8));
+
+        let question = "How long until refunds are paid?";
+        assert_eq!(pipeline.answer(question)?, (Outcome::Answered, "Thirty days.".to_string()));
+        let handbook = pipeline.collection("handbook")?;
+        assert_eq!((handbook.collection_name(), handbook.top_k()), ("handbook", 1));
+        assert_eq!(retriever::contents(&handbook.retrieve("How long does shipping take?")), ["Shipping takes two days."]);
+        // The main index's cached answer isn't served from another collection
+        assert_ne!(handbook.answer(question)?.1, "Thirty days.");
+        let main = handbook.collection(DEFAULT_COLLECTION)?;
+        assert_eq!(main.answer(question)?.0, Outcome::Cached);
+        assert!(pipeline.collection("archive").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_retries_and_variants_go_through_hooks_and_review() -> Result<()> {
+        let mut pipeline = RagPipeline::builder()
+            .document("Refunds are paid within thirty days of the return.")
+            .llm(LLM::with_backend(Arc::new(Reader), LLMConfig::default()))
+            .top_k(1)
+            .build()?;
+        pipeline.on_post_generation(|_, answer| *answer = answer.to_uppercase());
+
+        let question = "How long until refunds are paid?";
+        let context = retriever::contents(&pipeline.retrieve(question));
+        let overrides = GenerationOverrides::default();
+        assert_eq!(pipeline.regenerate(question, context, None, 1, &overrides)?, (Outcome::Answered, "THIRTY DAYS.".to_string()));
+        // A retry that doesn't know is answered as "no answer", like the first attempt
+        let (outcome, _) = pipeline.regenerate("Which colours does the umbrella come in?", Vec::new(), None, 1, &overrides)?;
+        assert_eq!(outcome, Outcome::NoAnswer);
+
+        let context = retriever::contents(&pipeline.retrieve(question));
+        let variants = pipeline.variants(question, context, None, 2, &overrides)?;
+        assert_eq!(variants, vec![(Outcome::Answered, "THIRTY DAYS.".to_string()); 2]);
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::timings::Timings;
+
+/// Prompt tokens besides the context: instructions, question and template
+const PROMPT_OVERHEAD_TOKENS: f64 = 120.0;
+/// Prompt tokens of the verification check besides the answer
+const VERIFY_OVERHEAD_TOKENS: f64 = 80.0;
+/// Weight of each observed query in the running cost averages
+const OBSERVED_WEIGHT: f64 = 0.2;
+/// Fewest answer tokens a token budget is cut down to
+const MIN_ANSWER_TOKENS: usize = 32;
+
+/// Stages of the pipeline that count as retrieval
+const RETRIEVAL_STAGES: &[&str] = &["embed", "tokenize", "retrieval", "routing", "keyword", "fusion", "federation"];
+
+/// What a single query may cost: a wall-clock target and/or a number of
+/// tokens processed (prompt plus answer, and the verification check)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct Budget {
+    #[serde(rename = "latency_budget_ms", default, with = "millis")]
+    pub latency: Option<Duration>,
+    #[serde(rename = "token_budget", default)]
+    pub tokens: Option<usize>,
+}
+
+impl Budget {
+    pub fn is_empty(&self) -> bool {
+        self.latency.is_none() && self.tokens.is_none()
+    }
+
+    fn allows(&self, estimate: &Estimate) -> bool {
+        self.latency.is_none_or(|latency| estimate.ms <= latency.as_secs_f64() * 1000.0)
+            && self.tokens.is_none_or(|tokens| estimate.tokens <= tokens as f64)
+    }
+
+    /// Whether `cheaper` lowers the estimate on a limit `estimate` exceeds
+    fn saves(&self, estimate: &Estimate, cheaper: &Estimate) -> bool {
+        let over_latency = self.latency.is_some_and(|latency| estimate.ms > latency.as_secs_f64() * 1000.0);
+        let over_tokens = self.tokens.is_some_and(|tokens| estimate.tokens > tokens as f64);
+        (over_latency && cheaper.ms < estimate.ms) || (over_tokens && cheaper.tokens < estimate.tokens)
+    }
+}
+
+mod millis {
+    use serde::{Deserialize, Deserializer};
+    use std::time::Duration;
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+    }
+}
+
+/// Which generator answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelChoice {
+    Large,
+    Small,
+}
+
+/// What the pipeline may do for a query, the most thorough option of each
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    pub top_k: usize,
+    pub rerank: bool,
+    pub verify: bool,
+    pub small_model: bool,
+}
+
+/// How one query is served, chosen to fit its budget
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Plan {
+    /// Chunks retrieved
+    pub top_k: usize,
+    pub rerank: bool,
+    /// Whether moderation asks the model to check the answer
+    pub verify: bool,
+    pub model: ModelChoice,
+    /// Answer length cap leaving the rest of a token budget to the prompt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    pub estimated_ms: u64,
+    pub estimated_tokens: usize,
+    /// False when even the cheapest plan is expected to exceed the budget
+    pub within_budget: bool,
+}
+
+impl Plan {
+    /// Everything the pipeline can do
+    fn thorough(capabilities: &Capabilities) -> Self {
+        Plan {
+            top_k: capabilities.top_k.max(1),
+            rerank: capabilities.rerank,
+            verify: capabilities.verify,
+            model: ModelChoice::Large,
+            max_tokens: None,
+            estimated_ms: 0,
+            estimated_tokens: 0,
+            within_budget: true,
+        }
+    }
+
+    /// Plans one step cheaper, in the order they are tried: without
+    /// verification, without reranking, on the small model, with one chunk
+    /// fewer
+    fn cheaper(&self, capabilities: &Capabilities) -> Vec<Plan> {
+        let mut steps = Vec::new();
+        if self.verify {
+            steps.push(Plan { verify: false, ..self.clone() });
+        }
+        if self.rerank {
+            steps.push(Plan { rerank: false, ..self.clone() });
+        }
+        if self.model == ModelChoice::Large && capabilities.small_model {
+            steps.push(Plan { model: ModelChoice::Small, ..self.clone() });
+        }
+        if self.top_k > 1 {
+            steps.push(Plan { top_k: self.top_k - 1, ..self.clone() });
+        }
+        steps
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelCosts {
+    pub prompt_ms_per_token: f64,
+    pub decode_ms_per_token: f64,
+}
+
+/// Expected cost of each choice, starting from rough figures for a 7B
+/// model on a CPU and refined from the timings of served queries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostModel {
+    pub retrieval_ms: f64,
+    pub rerank_ms: f64,
+    pub verify_ms: f64,
+    pub large: ModelCosts,
+    pub small: ModelCosts,
+    pub chunk_tokens: f64,
+    pub answer_tokens: f64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel {
+            retrieval_ms: 20.0,
+            rerank_ms: 150.0,
+            verify_ms: 800.0,
+            large: ModelCosts { prompt_ms_per_token: 2.0, decode_ms_per_token: 60.0 },
+            small: ModelCosts { prompt_ms_per_token: 0.5, decode_ms_per_token: 15.0 },
+            chunk_tokens: 150.0,
+            answer_tokens: 150.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Estimate {
+    ms: f64,
+    tokens: f64,
+}
+
+impl CostModel {
+    fn model(&self, choice: ModelChoice) -> &ModelCosts {
+        match choice {
+            ModelChoice::Large => &self.large,
+            ModelChoice::Small => &self.small,
+        }
+    }
+
+    fn prompt_tokens(&self, plan: &Plan) -> f64 {
+        PROMPT_OVERHEAD_TOKENS + plan.top_k as f64 * self.chunk_tokens
+    }
+
+    fn estimate(&self, plan: &Plan) -> Estimate {
+        let model = self.model(plan.model);
+        let prompt = self.prompt_tokens(plan);
+        let answer = plan.max_tokens.map_or(self.answer_tokens, |cap| self.answer_tokens.min(cap as f64));
+        let verify_tokens = if plan.verify { VERIFY_OVERHEAD_TOKENS + answer } else { 0.0 };
+        let ms = self.retrieval_ms
+            + if plan.rerank { self.rerank_ms } else { 0.0 }
+            + prompt * model.prompt_ms_per_token
+            + answer * model.decode_ms_per_token
+            + if plan.verify { self.verify_ms } else { 0.0 };
+        Estimate { ms, tokens: prompt + answer + verify_tokens }
+    }
+}
+
+/// Picks per query how much work fits its budget, degrading from the most
+/// thorough plan one step at a time (see `Plan::cheaper`) and only by steps
+/// that save on the exceeded limit. The cost figures follow the timings of
+/// the queries it planned.
+#[derive(Debug, Default)]
+pub struct Planner {
+    costs: Mutex<CostModel>,
+}
+
+impl Planner {
+    pub fn plan(&self, budget: &Budget, capabilities: &Capabilities) -> Plan {
+        let costs = *self.costs.lock().unwrap();
+        let mut plan = Plan::thorough(capabilities);
+        loop {
+            let estimate = costs.estimate(&plan);
+            if budget.allows(&estimate) {
+                break;
+            }
+            let cheaper = plan
+                .cheaper(capabilities)
+                .into_iter()
+                .find(|cheaper| budget.saves(&estimate, &costs.estimate(cheaper)));
+            match cheaper {
+                Some(cheaper) => plan = cheaper,
+                None => break,
+            }
+        }
+        if let Some(tokens) = budget.tokens {
+            let verify = if plan.verify { VERIFY_OVERHEAD_TOKENS } else { 0.0 };
+            let left = tokens as f64 - costs.prompt_tokens(&plan) - verify;
+            // The verification check reads the answer again
+            let left = if plan.verify { left / 2.0 } else { left };
+            plan.max_tokens = Some((left.max(0.0) as usize).max(MIN_ANSWER_TOKENS));
+        }
+        let estimate = costs.estimate(&plan);
+        plan.estimated_ms = estimate.ms.round() as u64;
+        plan.estimated_tokens = estimate.tokens.round() as usize;
+        plan.within_budget = budget.allows(&estimate);
+        plan
+    }
+
+    /// Folds the stage timings of a query served with `plan` into the cost
+    /// figures, along with the tokens of its context and answer
+    pub fn observe(&self, plan: &Plan, timings: &Timings, context_tokens: usize, answer_tokens: usize) {
+        let ms = |stages: &[&str]| -> Option<f64> {
+            let durations: Vec<Duration> = stages.iter().filter_map(|stage| timings.stage(stage)).collect();
+            (!durations.is_empty()).then(|| durations.iter().sum::<Duration>().as_secs_f64() * 1000.0)
+        };
+        let update = |average: &mut f64, observed: f64| *average += OBSERVED_WEIGHT * (observed - *average);
+
+        let mut costs = self.costs.lock().unwrap();
+        if let Some(observed) = ms(RETRIEVAL_STAGES) {
+            update(&mut costs.retrieval_ms, observed);
+        }
+        if plan.rerank && let Some(observed) = ms(&["rerank"]) {
+            update(&mut costs.rerank_ms, observed);
+        }
+        if plan.verify && let Some(observed) = ms(&["verify"]) {
+            update(&mut costs.verify_ms, observed);
+        }
+        if plan.top_k > 0 && context_tokens > 0 {
+            update(&mut costs.chunk_tokens, context_tokens as f64 / plan.top_k as f64);
+        }
+        let prompt_tokens = PROMPT_OVERHEAD_TOKENS + context_tokens as f64;
+        let model = match plan.model {
+            ModelChoice::Large => &mut costs.large,
+            ModelChoice::Small => &mut costs.small,
+        };
+        if let Some(observed) = ms(&["prompt eval"]) {
+            update(&mut model.prompt_ms_per_token, observed / prompt_tokens);
+        }
+        if answer_tokens > 0 {
+            if let Some(observed) = ms(&["decode"]) {
+                update(&mut model.decode_ms_per_token, observed / answer_tokens as f64);
+            }
+            // Answers cut short by a token budget say little about their
+            // natural length
+            if plan.max_tokens.is_none_or(|cap| answer_tokens < cap) {
+                update(&mut costs.answer_tokens, answer_tokens as f64);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plans_degrade_until_the_budget_fits() {
+        let planner = Planner::default();
+        let capabilities = Capabilities { top_k: 5, rerank: true, verify: true, small_model: true };
+        let plan = |latency_ms: Option<u64>, tokens: Option<usize>| {
+            planner.plan(&Budget { latency: latency_ms.map(Duration::from_millis), tokens }, &capabilities)
+        };
+
+        let thorough = plan(None, None);
+        assert_eq!((thorough.top_k, thorough.rerank, thorough.verify, thorough.model), (5, true, true, ModelChoice::Large));
+        assert_eq!((thorough.max_tokens, thorough.within_budget), (None, true));
+
+        // 20 retrieval + 150 rerank + 870 prompt tokens * 2 + 150 * 60 decode = 10910ms without verification
+        let fast = plan(Some(11_000), None);
+        assert_eq!((fast.top_k, fast.rerank, fast.verify, fast.model), (5, true, false, ModelChoice::Large));
+        let faster = plan(Some(4_000), None);
+        assert_eq!((faster.rerank, faster.verify, faster.model), (false, false, ModelChoice::Small));
+        assert!(faster.within_budget && faster.estimated_ms <= 4_000);
+        let impossible = plan(Some(10), None);
+        assert_eq!((impossible.top_k, impossible.model, impossible.within_budget), (1, ModelChoice::Small, false));
+
+        // A token budget leaves the reranker and the model alone, trims the
+        // context and caps the answer
+        let small = plan(None, Some(600));
+        assert_eq!((small.rerank, small.verify, small.model), (true, false, ModelChoice::Large));
+        assert_eq!((small.top_k, small.max_tokens), (2, Some(180)));
+        assert!(small.within_budget);
+
+        let mut timings = Timings::new();
+        timings.record("retrieval", Duration::from_millis(120));
+        timings.record("decode", Duration::from_millis(1500));
+        planner.observe(&thorough, &timings, 500, 100);
+        let costs = *planner.costs.lock().unwrap();
+        assert_eq!(costs.retrieval_ms, 40.0);
+        assert_eq!(costs.large.decode_ms_per_token, 51.0);
+        assert_eq!((costs.chunk_tokens, costs.answer_tokens), (140.0, 140.0));
+        assert_eq!(costs.small, CostModel::default().small);
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::llm::GenerationOverrides;
+use crate::utils::ensure_dir;
+
+/// Who is asking, kept on disk so answers stay tailored across sessions
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserProfile {
+    /// e.g. "support engineer"
+    pub role: Option<String>,
+    /// Domains the user already knows well, e.g. "Kubernetes, networking"
+    pub expertise: Option<String>,
+    /// e.g. "short bullet points"
+    pub style: Option<String>,
+}
+
+impl UserProfile {
+    /// Reads the profile at `path`, or an empty one if none was saved yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(UserProfile::default());
+        }
+        let content = fs::read_to_string(path)?;
+        serde_json::from_str(&content).map_err(|e| anyhow!("Invalid profile {:?}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            ensure_dir(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Sets one field from `/profile set KEY VALUE`; an empty value clears it
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let value = Some(value.trim().to_string()).filter(|v| !v.is_empty());
+        match key {
+            "role" => self.role = value,
+            "expertise" => self.expertise = value,
+            "style" => self.style = value,
+            _ => return Err(anyhow!("Unknown profile field '{}' (role, expertise, style)", key)),
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.role.is_none() && self.expertise.is_none() && self.style.is_none()
+    }
+
+    /// Instructions describing the user, or `None` for an empty profile
+    pub fn system_prompt(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut lines = Vec::new();
+        if let Some(role) = &self.role {
+            lines.push(format!("The user is a {}.", role));
+        }
+        if let Some(expertise) = &self.expertise {
+            lines.push(format!("They are already familiar with: {}. Skip basics in these areas.", expertise));
+        }
+        if let Some(style) = &self.style {
+            lines.push(format!("Preferred answer style: {}.", style));
+        }
+        Some(lines.join(" "))
+    }
+
+    /// `overrides` with the profile placed ahead of any explicit system prompt
+    pub fn apply(&self, overrides: &GenerationOverrides) -> GenerationOverrides {
+        let system_prompt = match (self.system_prompt(), &overrides.system_prompt) {
+            (Some(profile), Some(system)) => Some(format!("{}\n\n{}", profile, system)),
+            (profile, system) => profile.or_else(|| system.clone()),
+        };
+        GenerationOverrides { system_prompt, ..overrides.clone() }
+    }
+}
+
+impl std::fmt::Display for UserProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "(unset)".to_string());
+        write!(
+            f,
+            "role: {}\nexpertise: {}\nstyle: {}",
+            show(&self.role),
+            show(&self.expertise),
+            show(&self.style)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_prefixes_system_prompt() -> Result<()> {
+        let mut profile = UserProfile::default();
+        assert_eq!(profile.apply(&GenerationOverrides::default()).system_prompt, None);
+
+        profile.set("role", "site reliability engineer")?;
+        profile.set("style", "terse")?;
+        assert!(profile.set("mood", "happy").is_err());
+
+        let overrides = GenerationOverrides {
+            system_prompt: Some("Answer in German.".to_string()),
+            ..GenerationOverrides::default()
+        };
+        let prompt = profile.apply(&overrides).system_prompt.unwrap();
+        assert!(prompt.starts_with("The user is a site reliability engineer."));
+        assert!(prompt.ends_with("\n\nAnswer in German."));
+
+        profile.set("role", "")?;
+        assert_eq!(profile.role, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::path::Path;
+
+const MISTRAL: &str = "<s>[INST] {context}{query} [/INST]";
+const LLAMA3: &str = "<|begin_of_text|><|start_header_id|>user<|end_header_id|>\n\n{context}{query}<|eot_id|>\
+    <|start_header_id|>assistant<|end_header_id|>\n\n";
+const CHATML: &str = "<|im_start|>user\n{context}{query}<|im_end|>\n<|im_start|>assistant\n";
+
+/// Chat format prompts are wrapped in for the model. `{context}` becomes
+/// the system prompt, history, retrieved documents and answering
+/// instructions, ending in a blank line when there are any; `{query}` the
+/// question line. Prompts without retrieval, such as summaries and
+/// classifications, are passed whole as `{query}` with an empty `{context}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PromptTemplate {
+    /// `<s>[INST] ... [/INST]`, which Mistral and Mixtral were tuned on
+    #[default]
+    Mistral,
+    /// Llama 3 header and `<|eot_id|>` tokens
+    Llama3,
+    /// `<|im_start|>` / `<|im_end|>` turns, used by Qwen, Yi and many fine-tunes
+    ChatMl,
+    /// A user template read from a file
+    Custom(String),
+}
+
+impl PromptTemplate {
+    /// A preset name (`mistral`, `llama3`, `chatml`) or the path of a
+    /// template file
+    pub fn parse(spec: &str) -> Result<Self> {
+        match spec.trim().to_lowercase().as_str() {
+            "mistral" => return Ok(PromptTemplate::Mistral),
+            "llama3" | "llama-3" => return Ok(PromptTemplate::Llama3),
+            "chatml" => return Ok(PromptTemplate::ChatMl),
+            _ => {}
+        }
+        let path = Path::new(spec.trim());
+        if !path.is_file() {
+            return Err(anyhow!("Unknown prompt template '{}' (mistral, llama3, chatml or a template file)", spec));
+        }
+        let template = fs::read_to_string(path).map_err(|e| anyhow!("Can't read prompt template {:?}: {}", path, e))?;
+        PromptTemplate::custom(template)
+    }
+
+    /// A template with the `{context}` and `{query}` placeholders, each
+    /// exactly once
+    pub fn custom(template: String) -> Result<Self> {
+        for placeholder in ["{context}", "{query}"] {
+            if template.matches(placeholder).count() != 1 {
+                return Err(anyhow!("A prompt template needs {} exactly once", placeholder));
+            }
+        }
+        Ok(PromptTemplate::Custom(template))
+    }
+
+    fn template(&self) -> &str {
+        match self {
+            PromptTemplate::Mistral => MISTRAL,
+            PromptTemplate::Llama3 => LLAMA3,
+            PromptTemplate::ChatMl => CHATML,
+            PromptTemplate::Custom(template) => template,
+        }
+    }
+
+    /// The prompt for `context` and `query`, which are inserted as they are
+    pub fn render(&self, context: &str, query: &str) -> String {
+        // Split first, so placeholders in the inserted text stay literal
+        let (before, after) = self.template().split_once("{query}").unwrap_or((self.template(), ""));
+        format!("{}{}{}", before.replacen("{context}", context, 1), query, after.replacen("{context}", context, 1))
+    }
+
+    /// Markers that end the model's turn, in case the model file doesn't
+    /// declare them as end of text
+    pub fn stop_sequences(&self) -> &'static [&'static str] {
+        match self {
+            PromptTemplate::Mistral | PromptTemplate::Custom(_) => &[],
+            PromptTemplate::Llama3 => &["<|eot_id|>"],
+            PromptTemplate::ChatMl => &["<|im_end|>"],
+        }
+    }
+}
+
+/// The text of a `<s>[INST] ... [/INST]` prompt, for chat APIs that apply
+/// the model's own template to a user message
+#[cfg_attr(not(feature = "ollama"), allow(dead_code))]
+pub fn instruction(prompt: &str) -> &str {
+    let prompt = prompt.trim();
+    let prompt = prompt.strip_prefix("<s>").unwrap_or(prompt).trim_start();
+    let prompt = prompt.strip_prefix("[INST]").unwrap_or(prompt);
+    prompt.strip_suffix("[/INST]").unwrap_or(prompt).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets_and_custom_templates_render_context_and_query() -> Result<()> {
+        let context = "Context: <document index=\"1\">Refunds take 30 days.</document>\n\n";
+        assert_eq!(
+            PromptTemplate::default().render(context, "Question: {query}?"),
+            "<s>[INST] Context: <document index=\"1\">Refunds take 30 days.</document>\n\nQuestion: {query}? [/INST]"
+        );
+        assert_eq!(
+            PromptTemplate::parse("ChatML")?.render("", "Summarize."),
+            "<|im_start|>user\nSummarize.<|im_end|>\n<|im_start|>assistant\n"
+        );
+        assert!(PromptTemplate::parse("llama-3")?.render("", "Hi").ends_with("<|eot_id|><|start_header_id|>assistant<|end_header_id|>\n\n"));
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("alpaca.txt");
+        fs::write(&path, "### Instruction:\n{context}{query}\n\n### Response:\n")?;
+        let custom = PromptTemplate::parse(path.to_str().unwrap())?;
+        assert_eq!(custom.render("", "Why?"), "### Instruction:\nWhy?\n\n### Response:\n");
+        assert!(custom.stop_sequences().is_empty());
+
+        assert!(PromptTemplate::custom("{query} only".to_string()).is_err());
+        assert!(PromptTemplate::parse("vicuna").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::retriever::ScoredChunk;
+
+/// Chunks returned when a request doesn't say, as LangChain's
+/// `VectorStoreRetriever` defaults to `k=4`
+pub const DEFAULT_TOP_K: usize = 4;
+
+/// Body of `POST /retriever/invoke`, as LangServe's `RemoteRunnable` sends
+/// it. `config` and `kwargs` are accepted and ignored.
+#[derive(Debug, Deserialize)]
+pub struct InvokeRequest {
+    pub input: String,
+    /// Not part of LangServe; lets callers ask for more or fewer chunks
+    pub top_k: Option<usize>,
+    /// Not part of LangServe either; a `metadata::Filter` expression
+    pub filter: Option<String>,
+    /// Served collection to retrieve from; the main index when unset
+    pub collection: Option<String>,
+}
+
+/// Body of `POST /retriever/batch`
+#[derive(Debug, Deserialize)]
+pub struct BatchRequest {
+    pub inputs: Vec<String>,
+    pub top_k: Option<usize>,
+    pub filter: Option<String>,
+    pub collection: Option<String>,
+}
+
+/// A LangChain `Document`. The chunk's ID and score are also put in the
+/// metadata, where older LangChain versions look for them.
+#[derive(Debug, Serialize)]
+pub struct LangChainDocument {
+    pub id: String,
+    pub page_content: String,
+    pub metadata: BTreeMap<String, serde_json::Value>,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+impl From<ScoredChunk> for LangChainDocument {
+    fn from(chunk: ScoredChunk) -> Self {
+        let mut metadata: BTreeMap<String, serde_json::Value> =
+            chunk.metadata.into_iter().map(|(key, value)| (key, value.into())).collect();
+        metadata.insert("id".to_string(), chunk.id.clone().into());
+        metadata.insert("score".to_string(), chunk.score.into());
+        LangChainDocument { id: chunk.id, page_content: chunk.content, metadata, kind: "Document" }
+    }
+}
+
+/// Response of `/retriever/invoke` (`output` is a list of documents) and
+/// `/retriever/batch` (a list of such lists)
+#[derive(Debug, Serialize)]
+pub struct InvokeResponse<T> {
+    pub output: T,
+    pub metadata: RunMetadata,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunMetadata {
+    pub run_id: String,
+    pub feedback_tokens: Vec<String>,
+}
+
+impl<T> InvokeResponse<T> {
+    pub fn new(output: T) -> Self {
+        let run_id = uuid::Uuid::new_v4().to_string();
+        InvokeResponse { output, metadata: RunMetadata { run_id, feedback_tokens: Vec::new() } }
+    }
+}
+
+/// Body of `POST /retriever/retrieve`, named like the fields of LlamaIndex's
+/// `QueryBundle` and `similarity_top_k`
+#[derive(Debug, Deserialize)]
+pub struct RetrieveRequest {
+    pub query_str: String,
+    pub similarity_top_k: Option<usize>,
+    /// A `metadata::Filter` expression, in place of LlamaIndex's `filters`
+    pub filter: Option<String>,
+    pub collection: Option<String>,
+}
+
+/// Response of `/retriever/retrieve`: serialized `NodeWithScore`s, which
+/// `NodeWithScore.from_dict` reads back
+#[derive(Debug, Serialize)]
+pub struct RetrieveResponse {
+    pub nodes: Vec<NodeWithScore>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeWithScore {
+    pub node: TextNode,
+    pub score: f32,
+    pub class_name: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TextNode {
+    pub id_: String,
+    pub text: String,
+    pub metadata: BTreeMap<String, String>,
+    pub class_name: &'static str,
+}
+
+impl From<ScoredChunk> for NodeWithScore {
+    fn from(chunk: ScoredChunk) -> Self {
+        NodeWithScore {
+            node: TextNode { id_: chunk.id, text: chunk.content, metadata: chunk.metadata, class_name: "TextNode" },
+            score: chunk.score,
+            class_name: "NodeWithScore",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_serialize_in_langchain_and_llamaindex_shapes() -> serde_json::Result<()> {
+        let chunk = || ScoredChunk {
+            id: "doc-1".to_string(),
+            score: 0.5,
+            content: "Refunds take thirty days.".to_string(),
+            metadata: BTreeMap::from([("source".to_string(), "faq.md".to_string())]),
+            highlights: Vec::new(),
+        };
+        let request: InvokeRequest = serde_json::from_str(r#"{"input": "refunds", "config": {}, "kwargs": {}}"#)?;
+        assert_eq!((request.input.as_str(), request.top_k), ("refunds", None));
+
+        let response = serde_json::to_value(InvokeResponse::new(vec![LangChainDocument::from(chunk())]))?;
+        assert_eq!(response["output"][0], serde_json::json!({
+            "id": "doc-1",
+            "page_content": "Refunds take thirty days.",
+            "metadata": {"id": "doc-1", "score": 0.5, "source": "faq.md"},
+            "type": "Document",
+        }));
+        assert!(response["metadata"]["run_id"].is_string());
+
+        let node = serde_json::to_value(NodeWithScore::from(chunk()))?;
+        assert_eq!(node["node"]["id_"], "doc-1");
+        assert_eq!(node["node"]["metadata"]["source"], "faq.md");
+        assert_eq!(node["score"], 0.5);
+        Ok(())
+    }
+}
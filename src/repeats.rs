@@ -0,0 +1,87 @@
+use std::collections::BTreeSet;
+
+use crate::normalize;
+use crate::retriever::Retriever;
+
+/// Share of words two questions must have in common to be the same question
+/// without comparing embeddings
+const WORD_OVERLAP: f32 = 0.8;
+/// Embedding similarity at which two questions are the same question
+const SIMILARITY: f32 = 0.9;
+/// Words that turn a question around, so "can I" and "can't I" differ
+const NEGATIONS: &[&str] = &["not", "no", "never", "t", "without", "nicht", "kein", "keine", "pas", "jamais", "sin", "nunca"];
+
+/// The most recent of the `earlier` questions of a session that `query`
+/// asks again, by index. Questions match when nearly all their words are
+/// shared, or else when their embeddings are close; either way they must
+/// mention the same numbers and agree on negation, so "order 1234" and
+/// "order 5678" stay different questions.
+pub fn find_repeat(retriever: &Retriever, query: &str, earlier: &[&str]) -> Option<usize> {
+    let words = Words::new(query);
+    if words.terms.is_empty() {
+        return None;
+    }
+    let mut best: Option<(f32, usize)> = None;
+    for (i, question) in earlier.iter().enumerate().rev() {
+        let other = Words::new(question);
+        if other.numbers != words.numbers || other.negated != words.negated {
+            continue;
+        }
+        let overlap = words.overlap(&other);
+        let score = match overlap >= WORD_OVERLAP {
+            true => overlap.max(SIMILARITY),
+            false => retriever.similarity(query, question),
+        };
+        if score >= SIMILARITY && best.is_none_or(|(best, _)| score > best) {
+            best = Some((score, i));
+        }
+    }
+    best.map(|(_, i)| i)
+}
+
+struct Words {
+    terms: BTreeSet<String>,
+    numbers: BTreeSet<String>,
+    negated: bool,
+}
+
+impl Words {
+    fn new(text: &str) -> Self {
+        let terms: BTreeSet<String> = normalize::tokens(text).into_iter().map(|token| token.term).collect();
+        let numbers = terms.iter().filter(|term| term.chars().any(|c| c.is_ascii_digit())).cloned().collect();
+        let negated = terms.iter().any(|term| NEGATIONS.contains(&term.as_str()));
+        Words { terms, numbers, negated }
+    }
+
+    /// Jaccard similarity of the word sets
+    fn overlap(&self, other: &Words) -> f32 {
+        let shared = self.terms.intersection(&other.terms).count();
+        let all = self.terms.union(&other.terms).count();
+        if all == 0 { 0.0 } else { shared as f32 / all as f32 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_repeated_questions_are_found_but_not_different_ones() -> anyhow::Result<()> {
+        let mut retriever = Retriever::new();
+        retriever.add_to_knowledge_base("Refunds take thirty days. Orders ship in two days.".to_string(), BTreeMap::new())?;
+        let earlier = ["How long do refunds take?", "Where is order 1234?", "Can I return opened items?"];
+
+        assert_eq!(find_repeat(&retriever, "how long do refunds take", &earlier), Some(0));
+        assert_eq!(find_repeat(&retriever, "Where is order 1234 now?", &earlier), Some(1));
+        assert_eq!(find_repeat(&retriever, "Where is order 5678?", &earlier), None);
+        assert_eq!(find_repeat(&retriever, "Can't I return opened items?", &earlier), None);
+        assert_eq!(find_repeat(&retriever, "Do you ship abroad?", &earlier), None);
+        assert_eq!(find_repeat(&retriever, "?", &earlier), None);
+
+        // The latest of several matching questions is offered
+        let asked_twice = ["How long do refunds take?", "Hi", "How long do refunds take?"];
+        assert_eq!(find_repeat(&retriever, "How long do refunds take?", &asked_twice), Some(2));
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+#[cfg(not(feature = "candle"))]
+use anyhow::anyhow;
+use rayon::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::device::Device;
+use crate::vector_db::Document;
+
+/// Scores how well a passage answers a query by reading both together,
+/// which is more precise than comparing separately computed embeddings but
+/// too slow to run over a whole index
+pub trait CrossEncoder: Send + Sync {
+    /// Relevance of `passage` to `query`; higher is better, on the model's
+    /// own scale
+    fn score(&self, query: &str, passage: &str) -> Result<f32>;
+}
+
+/// Loads a BERT-style cross-encoder checkpoint such as
+/// `cross-encoder/ms-marco-MiniLM-L-6-v2` (`config.json`, `tokenizer.json`
+/// and `model.safetensors`) onto `device`
+pub fn load_cross_encoder(dir: &Path, device: Device) -> Result<Arc<dyn CrossEncoder>> {
+    #[cfg(feature = "candle")]
+    return Ok(Arc::new(candle::CandleCrossEncoder::load(dir, device)?));
+
+    #[cfg(not(feature = "candle"))]
+    Err(anyhow!("Cannot load reranking model {:?} onto {}: tapssp was built without the `candle` feature", dir, device))
+}
+
+/// Second retrieval stage: the best `candidates` chunks by similarity are
+/// re-scored with a cross-encoder and the best of those are kept
+pub struct Reranker {
+    model: Arc<dyn CrossEncoder>,
+    pub candidates: usize,
+}
+
+impl Reranker {
+    pub fn new(model: Arc<dyn CrossEncoder>, candidates: usize) -> Self {
+        Reranker { model, candidates }
+    }
+
+    /// The `top_k` of `ranked` that the cross-encoder scores best, each
+    /// still with its first-stage score. If the model fails on any chunk,
+    /// the first-stage order is kept.
+    pub fn rerank<'a>(&self, query: &str, ranked: Vec<(f32, &'a Document)>, top_k: usize) -> Vec<(f32, &'a Document)> {
+        let scores: Result<Vec<f32>> = ranked.par_iter().map(|(_, doc)| self.model.score(query, &doc.content)).collect();
+        let mut ranked = match scores {
+            Ok(scores) => {
+                let mut scored: Vec<(f32, (f32, &Document))> = scores.into_iter().zip(ranked).collect();
+                scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+                scored.into_iter().map(|(_, ranked)| ranked).collect()
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "reranking failed; keeping the similarity ranking");
+                ranked
+            }
+        };
+        ranked.truncate(top_k);
+        ranked
+    }
+}
+
+#[cfg(feature = "candle")]
+mod candle {
+    use anyhow::{Result, anyhow};
+    use candle_core::{DType, Device, IndexOp, Tensor};
+    use candle_nn::{Linear, Module, VarBuilder, linear};
+    use candle_transformers::models::bert::{BertModel, Config};
+    use std::fs;
+    use std::path::Path;
+    use tokenizers::{Tokenizer, TruncationParams};
+
+    use super::CrossEncoder;
+    use crate::device;
+
+    /// `BertForSequenceClassification` with a single relevance logit
+    pub struct CandleCrossEncoder {
+        model: BertModel,
+        pooler: Linear,
+        classifier: Linear,
+        tokenizer: Tokenizer,
+        device: Device,
+    }
+
+    impl CandleCrossEncoder {
+        pub fn load(dir: &Path, placement: device::Device) -> Result<Self> {
+            let device: Device = placement.candle()?;
+            let config_json = fs::read_to_string(dir.join("config.json"))?;
+            let config: Config = serde_json::from_str(&config_json)?;
+            let hidden = serde_json::from_str::<serde_json::Value>(&config_json)?["hidden_size"]
+                .as_u64()
+                .ok_or_else(|| anyhow!("No hidden_size in the config of {:?}", dir))? as usize;
+            let mut tokenizer = Tokenizer::from_file(dir.join("tokenizer.json"))
+                .map_err(|e| anyhow!("Invalid tokenizer in {:?}: {}", dir, e))?;
+            tokenizer
+                .with_truncation(Some(TruncationParams { max_length: 512, ..Default::default() }))
+                .map_err(|e| anyhow!("{}", e))?;
+
+            let weights = dir.join("model.safetensors");
+            // SAFETY: the checkpoint isn't modified while tapssp runs
+            let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[&weights], DType::F32, &device)? };
+            let model = BertModel::load(vb.clone(), &config)?;
+            let pooler = linear(hidden, hidden, vb.pp("bert.pooler.dense"))?;
+            let classifier = linear(hidden, 1, vb.pp("classifier"))?;
+
+            Ok(CandleCrossEncoder { model, pooler, classifier, tokenizer, device })
+        }
+    }
+
+    impl CrossEncoder for CandleCrossEncoder {
+        fn score(&self, query: &str, passage: &str) -> Result<f32> {
+            let encoding = self.tokenizer.encode((query, passage), true).map_err(|e| anyhow!("{}", e))?;
+            let ids = Tensor::new(encoding.get_ids(), &self.device)?.unsqueeze(0)?;
+            let type_ids = Tensor::new(encoding.get_type_ids(), &self.device)?.unsqueeze(0)?;
+            let mask = Tensor::new(encoding.get_attention_mask(), &self.device)?.unsqueeze(0)?;
+
+            let hidden = self.model.forward(&ids, &type_ids, Some(&mask))?;
+            let cls = hidden.i((.., 0))?;
+            let pooled = self.pooler.forward(&cls)?.tanh()?;
+            Ok(self.classifier.forward(&pooled)?.flatten_all()?.to_vec1::<f32>()?[0])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector_db::VectorDB;
+
+    /// Counts query words in the passage
+    struct Overlap;
+
+    impl CrossEncoder for Overlap {
+        fn score(&self, query: &str, passage: &str) -> Result<f32> {
+            let passage = passage.to_lowercase();
+            Ok(query.split_whitespace().filter(|word| passage.contains(&word.to_lowercase())).count() as f32)
+        }
+    }
+
+    #[test]
+    fn test_rerank_reorders_candidates() -> Result<()> {
+        let mut db = VectorDB::new();
+        for content in ["Refunds overview.", "Refunds are paid by card within thirty days.", "Card payments."] {
+            db.add_document(content.to_string())?;
+        }
+        db.rebuild();
+        let ranked: Vec<(f32, &Document)> = db.documents().enumerate().map(|(i, doc)| (1.0 - i as f32 / 10.0, doc)).collect();
+
+        let reranked = Reranker::new(Arc::new(Overlap), 3).rerank("refunds card days", ranked.clone(), 2);
+        assert_eq!(reranked.len(), 2);
+        assert_eq!(reranked[0].1.content, "Refunds are paid by card within thirty days.");
+        // First-stage scores are kept
+        let original = ranked.iter().find(|(_, doc)| doc.id == reranked[0].1.id).map(|(score, _)| *score);
+        assert_eq!(Some(reranked[0].0), original);
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::{Result, anyhow};
+use std::fs;
+
+/// Rough KV cache size per context token of a 7B model with grouped-query
+/// attention (32 layers x 8 KV heads x 128 dims x K and V x f16)
+const KV_BYTES_PER_TOKEN: u64 = 128 * 1024;
+/// Smallest context window the loader shrinks to before giving up
+const MIN_CONTEXT_TOKENS: usize = 512;
+/// Memory taken on top of the weights and KV cache (scratch buffers, the
+/// runtime, the index)
+const OVERHEAD_BYTES: u64 = 512 * 1024 * 1024;
+
+const GIB: f64 = (1024 * 1024 * 1024) as f64;
+
+/// Memory of this process and of the system, in bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryUsage {
+    /// Resident set size of this process
+    pub rss: u64,
+    /// Memory the system can hand out without swapping
+    pub available: u64,
+}
+
+impl MemoryUsage {
+    /// The current usage, where the OS reports it (Linux `/proc`)
+    pub fn sample() -> Option<Self> {
+        let status = fs::read_to_string("/proc/self/status").ok()?;
+        let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+        Some(MemoryUsage { rss: kilobytes(&status, "VmRSS")? * 1024, available: kilobytes(&meminfo, "MemAvailable")? * 1024 })
+    }
+}
+
+/// The `key:  1234 kB` value of a `/proc` status file
+fn kilobytes(text: &str, key: &str) -> Option<u64> {
+    text.lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+        .and_then(|value| value.split_whitespace().next()?.parse().ok())
+}
+
+/// The context window to load a model of `model_bytes` with: `context`,
+/// halved while the weights, KV cache and `reserve` don't fit in
+/// `available` memory. Fails with what to change when even
+/// `MIN_CONTEXT_TOKENS` doesn't fit.
+#[cfg_attr(not(feature = "llama"), allow(dead_code))]
+pub fn plan_context(model_bytes: u64, context: usize, available: u64, reserve: u64) -> Result<usize> {
+    let needed = |context: usize| model_bytes + context as u64 * KV_BYTES_PER_TOKEN + OVERHEAD_BYTES + reserve;
+    let mut planned = context;
+    while needed(planned) > available && planned / 2 >= MIN_CONTEXT_TOKENS {
+        planned /= 2;
+    }
+    if needed(planned) > available {
+        return Err(anyhow!(
+            "Not enough memory for the model: it needs about {:.1} GiB with a {}-token context, but only {:.1} GiB is available. \
+             Close other programs, use a smaller quantization (e.g. a Q4_K_S or Q3_K_M GGUF), offload layers with --gpu-layers \
+             or generate through Ollama with --backend ollama",
+            needed(planned) as f64 / GIB,
+            planned,
+            available as f64 / GIB
+        ));
+    }
+    if planned < context {
+        tracing::warn!(
+            requested = context,
+            context = planned,
+            available_gib = available as f64 / GIB,
+            "reduced the context window to fit in memory; lower --context-size to silence this"
+        );
+    }
+    Ok(planned)
+}
+
+/// Whether generation should stop before the system runs out of memory:
+/// when less than `reserve` bytes are left. Always false where memory
+/// can't be sampled.
+pub fn memory_exhausted(reserve: u64) -> bool {
+    match MemoryUsage::sample() {
+        Some(usage) if usage.available < reserve => {
+            tracing::warn!(rss_gib = usage.rss as f64 / GIB, available_gib = usage.available as f64 / GIB, "low memory during generation");
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_shrinks_to_fit_available_memory() {
+        assert_eq!(kilobytes("Name:\ttapssp\nVmRSS:\t  204800 kB\n", "VmRSS"), Some(204800));
+        assert_eq!(kilobytes("MemTotal: 1 kB\n", "MemAvailable"), None);
+
+        let gib = 1024 * 1024 * 1024;
+        let model = 4 * gib;
+        // 4 GiB weights + 1 GiB KV cache for 8192 tokens + overhead
+        assert_eq!(plan_context(model, 8192, 8 * gib, 0).unwrap(), 8192);
+        assert_eq!(plan_context(model, 8192, 5 * gib, 0).unwrap(), 4096);
+        assert_eq!(plan_context(model, 8192, 5 * gib, gib / 4).unwrap(), 2048);
+        let error = plan_context(model, 8192, 4 * gib, 0).unwrap_err().to_string();
+        assert!(error.contains("--gpu-layers"), "{}", error);
+    }
+}
@@ -1,25 +1,761 @@
-use crate::vector_db::VectorDB;
-use anyhow::Result;
+use crate::access_log::AccessLog;
+use crate::collection::CollectionSettings;
+use crate::embeddings::Embedder;
+use crate::highlight::{self, Highlight};
+use crate::metadata::Filter;
+use crate::rerank::Reranker;
+use crate::routing::DocumentRouter;
+use crate::synonyms::Synonyms;
+use crate::tables::Table;
+use crate::timings::Timings;
+use crate::vector_db::{self, Document, HnswParams, IngestReport, IngestSegment, Rebuild, SearchMode, VectorDB};
+use anyhow::{Result, anyhow};
+use ndarray::Array1;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Rank offset in reciprocal rank fusion, as in `VectorDB`'s fused search
+const RRF_K: f32 = 60.0;
+/// Each ranking fused by hybrid search contributes this many times `top_k`
+/// candidates
+const HYBRID_CANDIDATES: usize = 4;
+/// MMR picks the `top_k` chunks from this many times as many candidates
+const MMR_CANDIDATES: usize = 4;
 
 pub struct Retriever {
     vector_db: VectorDB,
+    /// Also rank by BM25 and fuse the rankings, see `set_hybrid`
+    hybrid: Option<Fusion>,
+    /// Ranks source documents before chunks, see `set_routing`
+    router: Option<DocumentRouter>,
+    /// Re-scores the best chunks with a cross-encoder, see `set_reranker`
+    reranker: Option<Reranker>,
+    /// Chunks less similar to the query are never retrieved, see
+    /// `set_min_score`
+    min_score: Option<f32>,
+    /// Lambda of maximal marginal relevance selection, see `set_mmr`
+    mmr: Option<f32>,
+    /// Content fingerprint of each source document computed so far,
+    /// forgotten when its chunks change, see `source_revisions`
+    fingerprints: Mutex<HashMap<String, u64>>,
+    /// Counts how often each chunk is retrieved, see `set_access_log`
+    access_log: Option<AccessLog>,
+}
+
+/// How hybrid search combines the BM25 and embedding rankings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fusion {
+    /// Reciprocal rank fusion, which only looks at ranks and so needs no
+    /// tuning for the very different score scales
+    Rrf,
+    /// `weight * bm25 + (1 - weight) * similarity`, with both scores scaled
+    /// by the best one of their ranking
+    Weighted(f32),
+}
+
+impl Fusion {
+    /// `rrf`, or the keyword weight between 0 and 1
+    pub fn parse(s: &str) -> Result<Self> {
+        if s == "rrf" {
+            return Ok(Fusion::Rrf);
+        }
+        match s.parse::<f32>() {
+            Ok(weight) if (0.0..=1.0).contains(&weight) => Ok(Fusion::Weighted(weight)),
+            _ => Err(anyhow!("Hybrid fusion must be 'rrf' or a keyword weight between 0 and 1, got '{}'", s)),
+        }
+    }
+}
+
+/// A retrieved chunk with its similarity to the query and the spans that
+/// matched it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScoredChunk {
+    /// Document ID, for `remove_document` and `update_document`; empty for
+    /// chunks from federated peers
+    pub id: String,
+    pub score: f32,
+    pub content: String,
+    /// Source file details or fields carried over from an import, plus
+    /// `rows` (`first-last`, 1-based) for table chunks
+    pub metadata: BTreeMap<String, String>,
+    /// Only filled in by `retrieve_with_scores`
+    pub highlights: Vec<Highlight>,
+}
+
+/// The text of each of `chunks`, e.g. to put into a prompt
+pub fn contents(chunks: &[ScoredChunk]) -> Vec<String> {
+    chunks.iter().map(|chunk| chunk.content.clone()).collect()
+}
+
+impl Default for Retriever {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Retriever {
     pub fn new() -> Self {
+        Retriever::with_vector_db(VectorDB::new())
+    }
+
+    pub fn with_vector_db(vector_db: VectorDB) -> Self {
         Retriever {
-            vector_db: VectorDB::new(),
+            vector_db,
+            hybrid: None,
+            router: None,
+            reranker: None,
+            min_score: None,
+            mmr: None,
+            fingerprints: Mutex::default(),
+            access_log: None,
+        }
+    }
+
+    /// Number of documents in the knowledge base
+    pub fn len(&self) -> usize {
+        self.vector_db.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vector_db.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.vector_db.contains(id)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.vector_db.save(path)
+    }
+
+    /// Indexed terms with a commonness weight, see `VectorDB::term_weights`
+    pub fn term_weights(&self) -> Vec<(String, f32)> {
+        self.vector_db.term_weights()
+    }
+
+    /// See `VectorDB::term_revision`
+    pub fn term_revision(&self) -> u64 {
+        self.vector_db.term_revision()
+    }
+
+    /// ID of the model the index was embedded with
+    pub fn model_id(&self) -> &str {
+        self.vector_db.model_id()
+    }
+
+    /// See `VectorDB::use_embedding_model`
+    pub fn use_embedding_model(&mut self, embedder: Option<Arc<dyn Embedder>>) -> Result<()> {
+        self.vector_db.use_embedding_model(embedder)
+    }
+
+    pub fn settings(&self) -> &CollectionSettings {
+        self.vector_db.settings()
+    }
+
+    pub fn set_settings(&mut self, settings: CollectionSettings) -> Result<()> {
+        self.vector_db.set_settings(settings)
+    }
+
+    /// See `VectorDB::set_synonyms`
+    pub fn set_synonyms(&mut self, synonyms: Synonyms) -> Result<bool> {
+        self.vector_db.set_synonyms(synonyms)
+    }
+
+    /// See `VectorDB::set_ann_params`
+    pub fn set_ann_params(&mut self, params: HnswParams) {
+        self.vector_db.set_ann_params(params)
+    }
+
+    /// See `VectorDB::uses_ann`
+    pub fn uses_ann(&self) -> bool {
+        self.vector_db.uses_ann()
+    }
+
+    /// See `VectorDB::add_embedding_variant`
+    pub fn add_embedding_variant(&mut self, embedder: Arc<dyn Embedder>) -> Result<()> {
+        self.vector_db.add_embedding_variant(embedder)
+    }
+
+    pub fn variant_coverage(&self, model_id: &str) -> usize {
+        self.vector_db.variant_coverage(model_id)
+    }
+
+    pub fn set_search_mode(&mut self, mode: SearchMode) -> Result<()> {
+        self.vector_db.set_search_mode(mode)
+    }
+
+    /// Ranks by BM25 keyword scores as well as embeddings and fuses the two,
+    /// so exact matches of error codes or identifiers aren't missed. Scores
+    /// reported for chunks stay embedding similarities.
+    pub fn set_hybrid(&mut self, fusion: Option<Fusion>) {
+        if fusion.is_some() {
+            self.vector_db.enable_keyword_index();
+        }
+        self.hybrid = fusion;
+    }
+
+    /// Indexes a text, split into chunks per the collection settings that
+    /// all get `metadata`, returning the IDs of the chunks
+    pub fn add_to_knowledge_base(&mut self, content: String, metadata: BTreeMap<String, String>) -> Result<Vec<String>> {
+        let chunks = self.vector_db.settings().chunk(&content);
+        let ids = self.vector_db.add_documents(chunks, metadata)?;
+        self.touch_chunk(ids.first());
+        self.reroute(&self.sources_of(&ids), &ids);
+        Ok(ids)
+    }
+
+    /// See `VectorDB::number_chunks`
+    pub fn number_chunks(&mut self, ids: &[String]) -> Result<()> {
+        self.vector_db.number_chunks(ids)?;
+        self.reroute(&self.sources_of(ids), ids);
+        Ok(())
+    }
+
+    /// See `VectorDB::take_ingest_report`
+    pub fn take_ingest_report(&mut self) -> IngestReport {
+        self.vector_db.take_ingest_report()
+    }
+
+    /// See `VectorDB::ingest_report`
+    pub fn ingest_report(&self) -> IngestReport {
+        self.vector_db.ingest_report()
+    }
+
+    /// See `VectorDB::remove_stale_documents`
+    pub fn remove_stale_documents(&mut self, metadata: &BTreeMap<String, String>, current: &[String], added: usize) -> Result<usize> {
+        let removed = self.vector_db.remove_stale_documents(metadata, current, added)?;
+        if removed > 0 {
+            let key = source_key(metadata, "");
+            self.fingerprints.get_mut().unwrap().remove(&key);
+            self.reroute(&[key], &[]);
+        }
+        Ok(removed)
+    }
+
+    /// See `VectorDB::to_segment`
+    pub(crate) fn to_segment(&self) -> IngestSegment {
+        self.vector_db.to_segment()
+    }
+
+    /// Removes a document by ID, returning whether it existed
+    pub fn remove_document(&mut self, id: &str) -> Result<bool> {
+        Ok(self.remove_documents(&[id.to_string()])? == 1)
+    }
+
+    /// Removes the documents with `ids`, returning how many there were
+    pub fn remove_documents(&mut self, ids: &[String]) -> Result<usize> {
+        for id in ids {
+            self.touch_chunk(Some(id));
         }
+        let sources = self.sources_of(ids);
+        let removed = self.vector_db.remove_documents(ids)?;
+        self.reroute(&sources, ids);
+        Ok(removed)
     }
 
-    pub fn add_to_knowledge_base(&mut self, content: String) -> Result<()> {
-        self.vector_db.add_document(content)
+    /// Removes every chunk loaded from the file at `path` (their `path`
+    /// metadata), returning how many there were
+    pub fn remove_source(&mut self, path: &Path) -> Result<usize> {
+        let path = path.display().to_string();
+        let normalized = vector_db::normalize_path(&path);
+        let ids: Vec<String> = self
+            .vector_db
+            .documents()
+            .filter(|doc| doc.metadata.get("path").is_some_and(|source| *source == path || vector_db::normalize_path(source) == normalized))
+            .map(|doc| doc.id.clone())
+            .collect();
+        let sources = self.sources_of(&ids);
+        let removed = self.vector_db.remove_documents(&ids)?;
+        self.fingerprints.get_mut().unwrap().remove(&path);
+        self.reroute(&sources, &ids);
+        Ok(removed)
+    }
+
+    /// Swaps in the documents of `db`, keeping the embedding models and
+    /// retrieval settings, see `VectorDB::replace_documents`
+    pub fn reload(&mut self, db: VectorDB) -> Result<()> {
+        self.vector_db.replace_documents(db)?;
+        self.fingerprints.get_mut().unwrap().clear();
+        if let Some(router) = &self.router {
+            self.router = Some(DocumentRouter::build(&self.vector_db, router.top_documents));
+        }
+        Ok(())
+    }
+
+    /// Moves the chunks with `ids` out into an index of their own, see
+    /// `VectorDB::split_off`
+    pub fn split_off(&mut self, ids: &[String]) -> Result<VectorDB> {
+        self.fingerprints.get_mut().unwrap().clear();
+        let sources = self.sources_of(ids);
+        let archived = self.vector_db.split_off(ids)?;
+        self.reroute(&sources, ids);
+        Ok(archived)
+    }
+
+    /// Replaces a document's content by ID, returning whether it existed
+    pub fn update_document(&mut self, id: &str, content: String) -> Result<bool> {
+        self.touch_chunk(Some(id));
+        let ids = [id.to_string()];
+        let updated = self.vector_db.update_document(id, content)?;
+        self.reroute(&self.sources_of(&ids), &ids);
+        Ok(updated)
+    }
+
+    /// Indexes a table as chunks of whole rows of at most `max_chars`
+    /// characters, returning the IDs of the chunks
+    pub(crate) fn add_table(&mut self, table: &Table, max_chars: usize, metadata: BTreeMap<String, String>) -> Result<Vec<String>> {
+        let ids = table
+            .chunks(max_chars)
+            .into_iter()
+            .map(|chunk| self.vector_db.add_table_document(chunk.markdown, chunk.info, metadata.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(file) = metadata.get("path").or(metadata.get("source")) {
+            self.fingerprints.get_mut().unwrap().remove(file);
+        }
+        self.reroute(&self.sources_of(&ids), &ids);
+        Ok(ids)
+    }
+
+    /// The distinct source documents of the chunks with `ids`
+    fn sources_of(&self, ids: &[String]) -> Vec<String> {
+        let mut sources: Vec<String> = ids
+            .iter()
+            .filter_map(|id| self.vector_db.document(id))
+            .map(|doc| source_key(&doc.metadata, &doc.id))
+            .collect();
+        sources.sort_unstable();
+        sources.dedup();
+        sources
+    }
+
+    /// Keeps the router's summaries of `sources` in step after the chunks
+    /// `ids` changed
+    fn reroute(&mut self, sources: &[String], ids: &[String]) {
+        if let Some(router) = &mut self.router {
+            router.update(&self.vector_db, sources, ids);
+        }
+    }
+
+    /// Records a change to the source document of chunk `id`
+    fn touch_chunk(&mut self, id: Option<impl AsRef<str>>) {
+        let Some(id) = id else {
+            return;
+        };
+        let key = match self.vector_db.documents().find(|doc| doc.id == id.as_ref()) {
+            Some(doc) => source_key(&doc.metadata, &doc.id),
+            None => return,
+        };
+        self.fingerprints.get_mut().unwrap().remove(&key);
+    }
+
+    /// The source document of each of `chunks` with its revision, for
+    /// telling later whether an answer generated from them is stale. None
+    /// when a chunk isn't indexed, e.g. one from a federated peer.
+    pub fn source_revisions(&self, chunks: &[String]) -> Option<BTreeMap<String, u64>> {
+        chunks
+            .iter()
+            .map(|chunk| {
+                let key = self.chunk_source(chunk)?;
+                let revision = self.source_revision(&key);
+                Some((key, revision))
+            })
+            .collect()
+    }
+
+    /// The source document of the indexed chunk with text `chunk`: its
+    /// `path` or `source` metadata, or its ID
+    pub fn chunk_source(&self, chunk: &str) -> Option<String> {
+        let doc = self.vector_db.documents().find(|doc| doc.content == chunk)?;
+        Some(source_key(&doc.metadata, &doc.id))
+    }
+
+    /// Fingerprint of the current chunks of source document `key`, 0 when
+    /// it has none. It depends on the content only, so server replicas
+    /// that index the same files agree on it.
+    pub fn source_revision(&self, key: &str) -> u64 {
+        if let Some(revision) = self.fingerprints.lock().unwrap().get(key) {
+            return *revision;
+        }
+        let mut contents: Vec<&str> = self
+            .vector_db
+            .documents()
+            .filter(|doc| source_key(&doc.metadata, &doc.id) == key)
+            .map(|doc| doc.content.as_str())
+            .collect();
+        let revision = match contents.is_empty() {
+            true => 0,
+            false => {
+                contents.sort_unstable();
+                let mut hasher = Sha256::new();
+                for content in contents {
+                    hasher.update(content.as_bytes());
+                    hasher.update([0]);
+                }
+                u64::from_be_bytes(hasher.finalize()[..8].try_into().unwrap())
+            }
+        };
+        self.fingerprints.lock().unwrap().insert(key.to_string(), revision);
+        revision
+    }
+
+    /// Whether the index would benefit from a `rebuild()`
+    pub fn is_stale(&self) -> bool {
+        self.vector_db.is_stale()
+    }
+
+    pub fn rebuild(&mut self) {
+        self.vector_db.rebuild();
+        self.rebuild_router();
+    }
+
+    /// Computes a `rebuild()` under a shared reference, see
+    /// `VectorDB::prepare_rebuild`
+    pub(crate) fn prepare_rebuild(&self) -> Option<Rebuild> {
+        self.vector_db.prepare_rebuild()
+    }
+
+    /// Swaps in a `prepare_rebuild` result, returning false if the index
+    /// changed in the meantime
+    pub(crate) fn finish_rebuild(&mut self, rebuild: Rebuild) -> bool {
+        let done = self.vector_db.finish_rebuild(rebuild);
+        if done {
+            self.rebuild_router();
+        }
+        done
+    }
+
+    fn rebuild_router(&mut self) {
+        if let Some(router) = &self.router {
+            self.router = Some(DocumentRouter::build(&self.vector_db, router.top_documents));
+        }
+    }
+
+    /// Two-stage retrieval: rank source documents by their summaries first
+    /// and only search the chunks of the `top_documents` best; `None`
+    /// searches all chunks
+    pub fn set_routing(&mut self, top_documents: Option<usize>) {
+        self.router = top_documents.map(|top_documents| DocumentRouter::build(&self.vector_db, top_documents));
+    }
+
+    /// Rerank the best `Reranker::candidates` chunks of each search with a
+    /// cross-encoder, returning its top-k; `None` keeps the similarity order
+    pub(crate) fn set_reranker(&mut self, reranker: Option<Reranker>) {
+        self.reranker = reranker;
+    }
+
+    pub fn has_reranker(&self) -> bool {
+        self.reranker.is_some()
+    }
+
+    /// Leave out chunks whose embedding similarity to the query is below
+    /// `min_score`, so a question the documents don't cover retrieves
+    /// nothing rather than the top-k least bad chunks; `None` always
+    /// returns top-k
+    pub fn set_min_score(&mut self, min_score: Option<f32>) {
+        self.min_score = min_score;
+    }
+
+    pub fn min_score(&self) -> Option<f32> {
+        self.min_score
+    }
+
+    /// Select chunks by maximal marginal relevance: each next chunk
+    /// maximizes `lambda * relevance - (1 - lambda) * similarity to the
+    /// chunks already selected`, so near-duplicates of a retrieved chunk
+    /// give way to other relevant ones. 1.0 ranks by relevance alone, lower
+    /// values favour diversity; `None` turns MMR off.
+    pub fn set_mmr(&mut self, lambda: Option<f32>) -> Result<()> {
+        if let Some(lambda) = lambda
+            && !(0.0..=1.0).contains(&lambda)
+        {
+            return Err(anyhow!("MMR lambda must be between 0 and 1, got {}", lambda));
+        }
+        self.mmr = lambda;
+        Ok(())
+    }
+
+    /// Count every chunk returned by the `retrieve*` methods in `log`, for
+    /// finding sources nobody asks about; `None` stops counting
+    pub(crate) fn set_access_log(&mut self, log: Option<AccessLog>) {
+        self.access_log = log;
+    }
+
+    /// Adds the retrievals counted so far to the stats file
+    pub fn flush_access_log(&self) -> Result<()> {
+        self.access_log.as_ref().map_or(Ok(()), AccessLog::flush)
+    }
+
+    /// Counts `docs` as retrieved and returns their content
+    fn retrieved<'a>(&self, docs: impl IntoIterator<Item = &'a Document>) -> Vec<String> {
+        let docs: Vec<&Document> = docs.into_iter().collect();
+        if let Some(log) = &self.access_log {
+            log.record(docs.iter().map(|doc| doc.id.as_str()));
+        }
+        docs.into_iter().map(|doc| doc.content.clone()).collect()
+    }
+
+    /// Counts the `found` documents as retrieved and returns them with
+    /// their scores and metadata
+    fn scored(&self, found: Vec<(f32, &Document)>) -> Vec<ScoredChunk> {
+        if let Some(log) = &self.access_log {
+            log.record(found.iter().map(|(_, doc)| doc.id.as_str()));
+        }
+        found
+            .into_iter()
+            .map(|(score, doc)| {
+                let mut metadata = doc.metadata.clone();
+                if let Some(table) = &doc.table {
+                    metadata.insert("rows".to_string(), format!("{}-{}", table.first_row + 1, table.first_row + table.row_count));
+                }
+                ScoredChunk { id: doc.id.clone(), score, content: doc.content.clone(), metadata, highlights: Vec::new() }
+            })
+            .collect()
+    }
+
+    /// Whether retrieval goes through `search` rather than straight to the
+    /// vector database
+    fn staged(&self) -> bool {
+        self.hybrid.is_some() || self.router.is_some() || self.reranker.is_some() || self.min_score.is_some() || self.mmr.is_some()
+    }
+
+    /// Similarity of the best matching document, 0.0 for an empty index
+    pub fn top_score(&self, query: &str) -> f32 {
+        self.search(query, 1, |_| true, &mut Timings::new()).first().map_or(0.0, |(score, _)| *score)
     }
 
     pub fn retrieve(&self, query: &str, top_k: usize) -> Vec<String> {
-        self.vector_db.search_similar(query, top_k)
+        if self.staged() {
+            return contents(&self.retrieve_timed(query, top_k, &mut Timings::new()));
+        }
+        self.retrieved(self.vector_db.search_similar(query, top_k))
+    }
+
+    /// Like `retrieve`, with each chunk's score and highlights. With a
+    /// `filter`, only chunks whose metadata matches it are ranked.
+    pub fn retrieve_with_scores(&self, query: &str, top_k: usize, filter: Option<&Filter>) -> Vec<ScoredChunk> {
+        let accepts = |doc: &Document| filter.is_none_or(|filter| filter.matches(&doc.metadata));
+        let mut chunks = self.scored(self.search(query, top_k, accepts, &mut Timings::new()));
+        for chunk in &mut chunks {
+            chunk.highlights = self.highlight(query, &chunk.content);
+        }
+        chunks
+    }
+
+    /// Spans of `chunk` that explain why it matches `query`
+    pub fn highlight(&self, query: &str, chunk: &str) -> Vec<Highlight> {
+        highlight::highlight(&self.vector_db, query, chunk)
+    }
+
+    /// `text` embedded like the indexed documents. TF-IDF vectors have one
+    /// dimension per vocabulary term, so their length grows with the index.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        Ok(self.vector_db.embed_text(text)?.to_vec())
+    }
+
+    /// Cosine similarity of two embeddings from `embed`
+    pub fn embedding_similarity(&self, a: &[f32], b: &[f32]) -> f32 {
+        self.vector_db.cosine_similarity(&Array1::from(a.to_vec()), &Array1::from(b.to_vec()))
+    }
+
+    /// Cosine similarity of two texts embedded like the indexed documents,
+    /// 0.0 if either can't be embedded
+    pub fn similarity(&self, a: &str, b: &str) -> f32 {
+        match (self.vector_db.embed_text(a), self.vector_db.embed_text(b)) {
+            (Ok(a), Ok(b)) => self.vector_db.cosine_similarity(&a, &b),
+            _ => 0.0,
+        }
+    }
+
+    /// Retrieves the top chunks among those whose content passes `filter`
+    pub fn retrieve_filtered<F>(&self, query: &str, top_k: usize, filter: F) -> Vec<String>
+    where
+        F: Fn(&str) -> bool,
+    {
+        if self.staged() {
+            let found = self.search(query, top_k, |doc| filter(&doc.content), &mut Timings::new());
+            return self.retrieved(found.into_iter().map(|(_, doc)| doc));
+        }
+        self.retrieved(self.vector_db.search_similar_filtered(query, top_k, |doc| filter(&doc.content)))
+    }
+
+    /// Same as `retrieve` with each chunk's score and metadata, recording
+    /// stage timings into `timings`
+    pub fn retrieve_timed(&self, query: &str, top_k: usize, timings: &mut Timings) -> Vec<ScoredChunk> {
+        if self.staged() {
+            return self.scored(self.search(query, top_k, |_| true, timings));
+        }
+        self.scored(self.vector_db.search_scored_timed(query, top_k, |_| true, timings))
+    }
+
+    /// `retrieve_timed`, skipping the reranker unless `rerank`
+    pub fn retrieve_planned(&self, query: &str, top_k: usize, rerank: bool, timings: &mut Timings) -> Vec<ScoredChunk> {
+        if rerank || self.reranker.is_none() {
+            return self.retrieve_timed(query, top_k, timings);
+        }
+        let candidates = self.rank(query, self.candidates(top_k), |_| true, timings);
+        self.scored(self.diversify(candidates, top_k, timings))
+    }
+
+    /// The `top_k` best documents accepted by `filter` with their embedding
+    /// similarity, in the reranker's order if there is one
+    fn search<F>(&self, query: &str, top_k: usize, filter: F, timings: &mut Timings) -> Vec<(f32, &Document)>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        let n = self.candidates(top_k);
+        let candidates = match &self.reranker {
+            Some(reranker) => {
+                let candidates = self.rank(query, n.max(reranker.candidates), filter, timings);
+                timings.time("rerank", || reranker.rerank(query, candidates, n))
+            }
+            None => self.rank(query, n, filter, timings),
+        };
+        self.diversify(candidates, top_k, timings)
+    }
+
+    /// How many ranked chunks `diversify` chooses `top_k` from
+    fn candidates(&self, top_k: usize) -> usize {
+        if self.mmr.is_some() { top_k * MMR_CANDIDATES } else { top_k }
+    }
+
+    /// The `top_k` of `candidates` picked by MMR (see `set_mmr`), in the
+    /// order they were picked; `candidates` as they are without MMR
+    fn diversify<'a>(&self, candidates: Vec<(f32, &'a Document)>, top_k: usize, timings: &mut Timings) -> Vec<(f32, &'a Document)> {
+        let Some(lambda) = self.mmr else {
+            return candidates;
+        };
+        timings.time("mmr", || {
+            mmr(candidates, top_k, lambda, |a, b| self.vector_db.cosine_similarity(&a.embedding, &b.embedding))
+        })
+    }
+
+    /// First-stage ranking of `search`: chunks of the routed documents by
+    /// embedding similarity, fused with the BM25 ranking for hybrid search,
+    /// without those below the minimum score
+    fn rank<F>(&self, query: &str, top_k: usize, filter: F, timings: &mut Timings) -> Vec<(f32, &Document)>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        let mut ranked = self.rank_all(query, top_k, filter, timings);
+        if let Some(min_score) = self.min_score {
+            ranked.retain(|(similarity, _)| *similarity >= min_score);
+        }
+        ranked
+    }
+
+    fn rank_all<F>(&self, query: &str, top_k: usize, filter: F, timings: &mut Timings) -> Vec<(f32, &Document)>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        let routed = self.router.as_ref()
+            .map(|router| timings.time("routing", || router.route(&self.vector_db, query, top_k)));
+        let filter = |doc: &Document| routed.as_ref().is_none_or(|chunks| chunks.contains(doc.id.as_str())) && filter(doc);
+        let Some(fusion) = self.hybrid else {
+            return self.vector_db.search_scored_timed(query, top_k, filter, timings);
+        };
+        let candidates = top_k * HYBRID_CANDIDATES;
+        let dense = self.vector_db.search_scored_timed(query, candidates, filter, timings);
+        let keyword = timings.time("keyword", || self.vector_db.search_keywords(query, candidates, filter));
+
+        // Fused score and embedding similarity, if the dense ranking had it
+        let mut fused: HashMap<&str, (f32, Option<f32>, &Document)> = HashMap::new();
+        let best = |ranking: &[(f32, &Document)]| ranking.first().map_or(1.0, |(score, _)| *score).max(f32::EPSILON);
+        let (best_dense, best_keyword) = (best(&dense), best(&keyword));
+        for (rank, (similarity, doc)) in dense.into_iter().enumerate() {
+            let contribution = match fusion {
+                Fusion::Rrf => 1.0 / (RRF_K + rank as f32 + 1.0),
+                Fusion::Weighted(weight) => (1.0 - weight) * similarity / best_dense,
+            };
+            fused.insert(doc.id.as_str(), (contribution, Some(similarity), doc));
+        }
+        for (rank, (score, doc)) in keyword.into_iter().enumerate() {
+            let contribution = match fusion {
+                Fusion::Rrf => 1.0 / (RRF_K + rank as f32 + 1.0),
+                Fusion::Weighted(weight) => weight * score / best_keyword,
+            };
+            fused.entry(doc.id.as_str()).or_insert((0.0, None, doc)).0 += contribution;
+        }
+
+        let mut fused: Vec<(f32, Option<f32>, &Document)> = fused.into_values().collect();
+        fused.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.2.id.cmp(&b.2.id)));
+        fused.truncate(top_k);
+        // Keyword-only matches get their similarity computed here
+        let query_embedding = fused.iter().any(|(_, similarity, _)| similarity.is_none())
+            .then(|| self.vector_db.embed_text(query).ok())
+            .flatten();
+        fused
             .into_iter()
-            .map(|doc| doc.content.clone())
+            .map(|(_, similarity, doc)| {
+                let similarity = similarity.unwrap_or_else(|| match &query_embedding {
+                    Some(embedding) => self.vector_db.cosine_similarity(embedding, &doc.embedding),
+                    None => 0.0,
+                });
+                (similarity, doc)
+            })
             .collect()
     }
-}
\ No newline at end of file
+}
+
+/// Maximal marginal relevance: greedily picks `top_k` of the `(relevance,
+/// document)` candidates, each time the one whose relevance, less its
+/// highest `similarity` to a document already picked, weighs the most
+fn mmr(
+    mut candidates: Vec<(f32, &Document)>,
+    top_k: usize,
+    lambda: f32,
+    similarity: impl Fn(&Document, &Document) -> f32,
+) -> Vec<(f32, &Document)> {
+    let mut selected: Vec<(f32, &Document)> = Vec::with_capacity(top_k.min(candidates.len()));
+    // Highest similarity of each candidate to the selected documents
+    let mut redundancy = vec![0.0f32; candidates.len()];
+    while selected.len() < top_k && !candidates.is_empty() {
+        let score = |i: usize| lambda * candidates[i].0 - (1.0 - lambda) * redundancy[i];
+        let best = (0..candidates.len()).max_by(|&a, &b| score(a).total_cmp(&score(b)).then(b.cmp(&a))).unwrap_or(0);
+        let picked = candidates.remove(best);
+        redundancy.remove(best);
+        for ((_, doc), redundancy) in candidates.iter().zip(&mut redundancy) {
+            *redundancy = redundancy.max(similarity(doc, picked.1));
+        }
+        selected.push(picked);
+    }
+    selected
+}
+
+/// Name a chunk's source document goes by: the file it was loaded from,
+/// the `source` of an imported document, or else the chunk's own ID
+pub(crate) fn source_key(metadata: &BTreeMap<String, String>, id: &str) -> String {
+    metadata.get("path").or(metadata.get("source")).cloned().unwrap_or_else(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mmr_skips_near_duplicates_of_retrieved_chunks() -> Result<()> {
+        let mut retriever = Retriever::new();
+        for text in [
+            "Refunds are paid within thirty days of the return.",
+            "Refunds are paid within thirty days of the return, by bank transfer.",
+            "Store credit is offered instead of refunds for opened items.",
+            "Shipping takes two days.",
+        ] {
+            retriever.add_to_knowledge_base(text.to_string(), BTreeMap::new())?;
+        }
+        let query = "When are refunds paid after a return?";
+        let top = retriever.retrieve(query, 2);
+        assert!(top[1].starts_with("Refunds are paid within thirty days"), "{:?}", top);
+
+        retriever.set_mmr(Some(0.5))?;
+        let diverse = retriever.retrieve(query, 2);
+        assert_eq!(diverse[0], top[0]);
+        assert_eq!(diverse[1], "Store credit is offered instead of refunds for opened items.");
+        // Lambda 1.0 is plain relevance ranking
+        retriever.set_mmr(Some(1.0))?;
+        assert_eq!(retriever.retrieve(query, 2), top);
+        assert!(retriever.set_mmr(Some(1.5)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use ndarray::Array1;
+use rayon::prelude::*;
+use rustc_hash::FxHashSet;
+use std::collections::BTreeMap;
+
+use crate::highlight::sentence_spans;
+use crate::metadata;
+use crate::retriever::source_key;
+use crate::vector_db::{Document, VectorDB};
+
+/// Length cap of a document summary
+const SUMMARY_CHARS: usize = 2000;
+
+/// First stage of two-stage retrieval: one summary embedding per source
+/// document, so a query is first matched against whole documents and chunks
+/// are only ranked within the best ones. Summaries are extractive (the
+/// opening sentence of each chunk, in the order of the source), so no model
+/// is needed to build them, and they're kept in memory, rebuilt with the
+/// index and updated as documents change.
+pub struct DocumentRouter {
+    /// Documents whose chunks are searched for each query
+    pub top_documents: usize,
+    /// By `path` (or `source`) metadata, or the ID of a chunk with neither
+    documents: BTreeMap<String, RoutedDocument>,
+}
+
+struct RoutedDocument {
+    embedding: Array1<f32>,
+    chunk_ids: Vec<String>,
+}
+
+impl DocumentRouter {
+    /// Groups the chunks of `db` into documents by their `path` (or
+    /// `source`) metadata, and summarizes and embeds each. Chunks without
+    /// either are documents of their own.
+    pub fn build(db: &VectorDB, top_documents: usize) -> Self {
+        let mut groups: BTreeMap<String, Vec<&Document>> = BTreeMap::new();
+        for doc in db.documents() {
+            groups.entry(source_key(&doc.metadata, &doc.id)).or_default().push(doc);
+        }
+        let documents = groups
+            .into_par_iter()
+            .filter_map(|(key, chunks)| Some((key, route_document(db, chunks)?)))
+            .collect();
+        DocumentRouter { top_documents, documents }
+    }
+
+    /// Summarizes the source documents `keys` again after the chunks `ids`
+    /// were added to, changed in or removed from them. Only the chunks each
+    /// document had and `ids` are looked up, not the whole index.
+    pub fn update(&mut self, db: &VectorDB, keys: &[String], ids: &[String]) {
+        for key in keys {
+            let known = self.documents.get(key).map(|doc| doc.chunk_ids.as_slice()).unwrap_or_default();
+            let mut seen = FxHashSet::default();
+            let chunks = known
+                .iter()
+                .chain(ids)
+                .filter(|id| seen.insert(id.as_str()))
+                .filter_map(|id| db.document(id))
+                .filter(|doc| source_key(&doc.metadata, &doc.id) == *key)
+                .collect();
+            match route_document(db, chunks) {
+                Some(document) => self.documents.insert(key.clone(), document),
+                None => self.documents.remove(key),
+            };
+        }
+    }
+
+    /// IDs of the chunks of the documents whose summaries best match
+    /// `query`: the `top_documents` best, plus as many more as it takes to
+    /// have `min_chunks` chunks
+    pub fn route(&self, db: &VectorDB, query: &str, min_chunks: usize) -> FxHashSet<&str> {
+        let Ok(query) = db.embed_text(query) else {
+            return self.documents.values().flat_map(|doc| doc.chunk_ids.iter().map(String::as_str)).collect();
+        };
+        let mut ranked: Vec<(f32, &RoutedDocument)> =
+            self.documents.values().map(|doc| (db.cosine_similarity(&query, &doc.embedding), doc)).collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut chunks = FxHashSet::default();
+        for (rank, (_, doc)) in ranked.into_iter().enumerate() {
+            if rank >= self.top_documents && chunks.len() >= min_chunks {
+                break;
+            }
+            chunks.extend(doc.chunk_ids.iter().map(String::as_str));
+        }
+        chunks
+    }
+}
+
+/// The summary embedding of a source document's `chunks`, taken in their
+/// order within the source; `None` when it has none left
+fn route_document(db: &VectorDB, mut chunks: Vec<&Document>) -> Option<RoutedDocument> {
+    if chunks.is_empty() {
+        return None;
+    }
+    // Chunks without a recorded position follow, in a stable order
+    let position = |doc: &Document| doc.metadata.get(metadata::CHUNK_POSITION).and_then(|p| p.parse::<usize>().ok()).unwrap_or(usize::MAX);
+    chunks.sort_by(|a, b| position(a).cmp(&position(b)).then_with(|| a.id.cmp(&b.id)));
+    let embedding = db.embed_text(&summarize(chunks.iter().map(|doc| doc.content.as_str()))).ok()?;
+    Some(RoutedDocument { embedding, chunk_ids: chunks.iter().map(|doc| doc.id.clone()).collect() })
+}
+
+/// The opening sentence of each chunk, up to `SUMMARY_CHARS`
+fn summarize<'a>(chunks: impl Iterator<Item = &'a str>) -> String {
+    let mut summary = String::new();
+    for chunk in chunks {
+        let Some(first) = sentence_spans(chunk).into_iter().next() else {
+            continue;
+        };
+        if !summary.is_empty() && summary.len() + first.len() > SUMMARY_CHARS {
+            break;
+        }
+        summary.push_str(&chunk[first]);
+        summary.push(' ');
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_chunks_are_searched_within_matching_documents() -> Result<()> {
+        let mut db = VectorDB::new();
+        let file = |path: &str| BTreeMap::from([("path".to_string(), path.to_string())]);
+        db.add_documents(
+            vec!["Refunds are paid within thirty days.".to_string(), "Refunds go to the original card.".to_string()],
+            file("refunds.txt"),
+        )?;
+        db.add_documents(
+            vec!["Shipping takes two days.".to_string(), "Shipping is free over fifty dollars.".to_string()],
+            file("shipping.txt"),
+        )?;
+        db.add_document("Our offices close on public holidays.".to_string())?;
+        db.rebuild();
+
+        let router = DocumentRouter::build(&db, 1);
+        let routed = router.route(&db, "how do refunds work", 1);
+        let contents: Vec<&str> = db.documents().filter(|doc| routed.contains(doc.id.as_str())).map(|doc| doc.content.as_str()).collect();
+        assert_eq!(contents.len(), 2);
+        assert!(contents.iter().all(|content| content.starts_with("Refunds")));
+
+        // More documents are searched when the best ones are too small
+        assert!(router.route(&db, "how do refunds work", 3).len() >= 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_updates_follow_the_index_in_source_order() -> Result<()> {
+        let mut db = VectorDB::new();
+        let file = |path: &str| BTreeMap::from([("path".to_string(), path.to_string())]);
+        let refunds = db.add_documents(
+            vec!["Refunds go to the original card.".to_string(), "Refunds are paid within thirty days.".to_string()],
+            file("refunds.txt"),
+        )?;
+        // Read in the opposite order to how they were added
+        db.number_chunks(&[refunds[1].clone(), refunds[0].clone()])?;
+        db.rebuild();
+        let mut router = DocumentRouter::build(&db, 1);
+        assert_eq!(router.documents["refunds.txt"].chunk_ids, [refunds[1].clone(), refunds[0].clone()]);
+
+        let shipping = db.add_documents(vec!["Shipping takes two days.".to_string()], file("shipping.txt"))?;
+        router.update(&db, &["shipping.txt".to_string()], &shipping);
+        assert!(router.route(&db, "how long does shipping take", 1).contains(shipping[0].as_str()));
+
+        db.remove_documents(&refunds[..1])?;
+        router.update(&db, &["refunds.txt".to_string()], &refunds[..1]);
+        assert_eq!(router.documents["refunds.txt"].chunk_ids, [refunds[1].clone()]);
+        db.remove_documents(&refunds[1..])?;
+        router.update(&db, &["refunds.txt".to_string()], &refunds[1..]);
+        assert!(!router.documents.contains_key("refunds.txt"));
+        Ok(())
+    }
+}
@@ -0,0 +1,23 @@
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::runtime::Runtime;
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// The process's tokio runtime, started on first use. Downloads, document
+/// loading and the HTTP server run on it; model inference stays on blocking
+/// threads so it never holds up the async workers.
+pub fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| Runtime::new().expect("failed to start the tokio runtime"))
+}
+
+/// Runs `future` to completion from synchronous code, such as the CLI or a
+/// `spawn_blocking` task. Must not be called from async code, which should
+/// `.await` the future instead.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    match tokio::runtime::Handle::try_current() {
+        // Inside a blocking task of the runtime
+        Ok(handle) => handle.block_on(future),
+        Err(_) => runtime().block_on(future),
+    }
+}
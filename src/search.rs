@@ -0,0 +1,86 @@
+#[cfg(feature = "server")]
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::highlight::Highlight;
+use crate::retriever::ScoredChunk;
+
+/// Body of `POST /search`
+#[cfg(feature = "server")]
+#[derive(Debug, Deserialize)]
+pub struct SearchRequest {
+    pub query: String,
+    pub top_k: Option<usize>,
+    /// A `metadata::Filter` expression
+    pub filter: Option<String>,
+    /// Served collection to search; the main index when unset
+    pub collection: Option<String>,
+}
+
+/// Ranked chunks for a query, as `POST /search` returns them and
+/// `tapssp search --json` prints them. No answer is generated.
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub query: String,
+    pub results: Vec<SearchHit>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    /// Position in the ranking, from 1
+    pub rank: usize,
+    pub id: String,
+    pub score: f32,
+    pub content: String,
+    pub metadata: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub highlights: Vec<Highlight>,
+}
+
+impl SearchResults {
+    /// `chunks` best first
+    pub fn new(query: String, chunks: Vec<ScoredChunk>) -> Self {
+        let results = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| SearchHit {
+                rank: i + 1,
+                id: chunk.id,
+                score: chunk.score,
+                content: chunk.content,
+                metadata: chunk.metadata,
+                highlights: chunk.highlights,
+            })
+            .collect();
+        SearchResults { query, results }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_results_are_ranked_with_scores_and_metadata() {
+        let chunk = |id: &str, score, source: &str| ScoredChunk {
+            id: id.to_string(),
+            score,
+            content: format!("Text of {}", id),
+            metadata: BTreeMap::from([("source".to_string(), source.to_string())]),
+            highlights: Vec::new(),
+        };
+        let results = SearchResults::new("refunds".to_string(), vec![chunk("a", 0.9, "faq.md"), chunk("b", 0.5, "terms.pdf")]);
+        assert_eq!(
+            serde_json::to_value(&results).unwrap(),
+            json!({
+                "query": "refunds",
+                "results": [
+                    {"rank": 1, "id": "a", "score": 0.9f32, "content": "Text of a", "metadata": {"source": "faq.md"}},
+                    {"rank": 2, "id": "b", "score": 0.5f32, "content": "Text of b", "metadata": {"source": "terms.pdf"}},
+                ],
+            })
+        );
+    }
+}
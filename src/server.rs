@@ -0,0 +1,1144 @@
+use anyhow::Result;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{Span, info_span};
+
+use crate::conversations::{CheckoutError, ConversationStore, Exchange};
+use crate::escalation::Outcome;
+use crate::federation::{RawChunk, RawQuery, RawResults};
+use crate::highlight::Highlight;
+use crate::llm::{Conversation, GenerationOverrides};
+use crate::metadata::Filter;
+use crate::model_swap::SwitchStatus;
+use crate::openai::{self, ChatCompletion, ChatCompletionChunk, ChatRequest, ChatTurn, Delta, EmbeddingRequest, EmbeddingResponse};
+use crate::pipeline::RagPipeline;
+use crate::planner::{Budget, Plan};
+use crate::remote_retriever::{
+    self, BatchRequest, InvokeRequest, InvokeResponse, LangChainDocument, NodeWithScore, RetrieveRequest, RetrieveResponse,
+};
+use crate::retriever::{self, ScoredChunk};
+use crate::search::{SearchRequest, SearchResults};
+use crate::systemd;
+use crate::timings::Timings;
+
+/// Most chunks a peer may ask `/query/raw` for
+const MAX_RAW_TOP_K: usize = 50;
+/// Most texts one `/v1/embeddings` request may embed
+const MAX_EMBEDDING_INPUTS: usize = 256;
+/// Most queries one `/retriever/batch` request may run
+const MAX_BATCH_QUERIES: usize = 64;
+/// Most alternative answers one `/query/retry` request may ask for
+const MAX_VARIANTS: usize = 8;
+
+struct AppState {
+    pipeline: RagPipeline,
+    sessions: ConversationStore,
+}
+
+#[derive(Deserialize)]
+struct QueryRequest {
+    query: String,
+    /// Optional temperature, max_tokens, top_p, stop, system_prompt, seed
+    /// and answer format
+    #[serde(flatten)]
+    overrides: GenerationOverrides,
+    /// Optional latency_budget_ms and token_budget the answer is planned
+    /// to fit
+    #[serde(flatten)]
+    budget: Budget,
+    /// In a session, generate anew even when the question was asked before
+    #[serde(default)]
+    regenerate: bool,
+    /// Served collection to answer from; the main index when unset
+    collection: Option<String>,
+    /// Return the stage timings of a generated answer
+    #[serde(default)]
+    timings: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct QueryResponse {
+    /// `answered`, `faq`, `cached`, `repeated`, `no_answer` or `blocked`
+    outcome: Outcome,
+    answer: String,
+    /// Moderation categories the answer was found in
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    flags: Vec<String>,
+    context: Vec<String>,
+    /// Matched spans of each context chunk, as byte offsets into it
+    highlights: Vec<Vec<Highlight>>,
+    /// The curated question answered, when the answer came from the FAQ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    faq_question: Option<String>,
+    /// The earlier question of the session whose answer was given again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repeated_question: Option<String>,
+    /// How the answer was planned to fit the request's budget
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<Plan>,
+    /// Stage name -> milliseconds, when the request asked for `timings`
+    /// and the answer was generated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timings: Option<Timings>,
+}
+
+#[derive(Deserialize)]
+struct RetryRequest {
+    #[serde(flatten)]
+    request: QueryRequest,
+    /// Which retry of the question this is, raising the temperature like
+    /// repeated `/retry` in the REPL; 1 when unset
+    attempt: Option<usize>,
+    /// Return this many alternative answers, like `/variants`, instead of
+    /// one retried answer
+    variants: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct RetryResponse {
+    answers: Vec<RetryAnswer>,
+    context: Vec<String>,
+    highlights: Vec<Vec<Highlight>>,
+}
+
+#[derive(Debug, Serialize)]
+struct RetryAnswer {
+    outcome: Outcome,
+    answer: String,
+}
+
+#[derive(Serialize)]
+struct SessionCreated {
+    id: String,
+    /// Idle seconds after which the session is dropped
+    expires_in_secs: u64,
+}
+
+#[derive(Serialize)]
+struct SessionHistory {
+    id: String,
+    exchanges: Vec<Exchange>,
+}
+
+/// Progress of a `/query/stream` request, sent as server-sent events named
+/// after the variant, with the JSON object as data
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum StreamEvent {
+    RetrievalStarted { query: String },
+    Chunks { chunks: Vec<StreamChunk> },
+    GenerationStarted,
+    /// A piece of the answer as it is decoded
+    Token { text: String },
+    /// The final answer, which hooks, the abstain policy or moderation may
+    /// have changed from the streamed tokens
+    Done {
+        outcome: Outcome,
+        answer: String,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        flags: Vec<String>,
+        stats: StreamStats,
+    },
+    Error { message: String },
+}
+
+#[derive(Serialize)]
+struct StreamChunk {
+    content: String,
+    score: f32,
+    highlights: Vec<Highlight>,
+}
+
+#[derive(Serialize)]
+struct StreamStats {
+    /// Stage name -> milliseconds
+    timings: Timings,
+    tokens: usize,
+    context_chunks: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<Plan>,
+}
+
+impl StreamEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            StreamEvent::RetrievalStarted { .. } => "retrieval_started",
+            StreamEvent::Chunks { .. } => "chunks",
+            StreamEvent::GenerationStarted => "generation_started",
+            StreamEvent::Token { .. } => "token",
+            StreamEvent::Done { .. } => "done",
+            StreamEvent::Error { .. } => "error",
+        }
+    }
+
+    fn into_sse(self) -> Event {
+        let data = serde_json::to_string(&self).expect("stream events serialize");
+        Event::default().event(self.name()).data(data)
+    }
+}
+
+/// Error returned to HTTP clients as `{"error": "..."}`
+#[derive(Debug)]
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+impl From<CheckoutError> for ApiError {
+    fn from(e: CheckoutError) -> Self {
+        match e {
+            CheckoutError::NotFound => ApiError(StatusCode::NOT_FOUND, "No such session".to_string()),
+            CheckoutError::Busy => {
+                ApiError(StatusCode::CONFLICT, "The session is still answering a previous message".to_string())
+            }
+        }
+    }
+}
+
+/// Serves the pipeline over HTTP until Ctrl+C or SIGTERM. Under systemd
+/// socket activation the passed socket is used instead of binding `addr`,
+/// and readiness is reported via sd_notify once the server accepts requests.
+/// Conversations under `/sessions` are dropped after `session_ttl` idle.
+pub async fn serve(pipeline: RagPipeline, addr: SocketAddr, session_ttl: Duration) -> Result<()> {
+    let listener = match systemd::take_activated_listener()? {
+        Some(listener) => tokio::net::TcpListener::from_std(listener)?,
+        None => tokio::net::TcpListener::bind(addr).await?,
+    };
+    eprintln!("Listening on http://{}", listener.local_addr()?);
+    systemd::notify("READY=1")?;
+
+    let state = AppState { pipeline, sessions: ConversationStore::new(session_ttl) };
+    axum::serve(listener, router(Arc::new(state)))
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+    systemd::notify("STOPPING=1")?;
+    Ok(())
+}
+
+fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/query", post(query))
+        .route("/query/stream", post(query_stream))
+        .route("/query/retry", post(query_retry))
+        .route("/query/raw", post(query_raw))
+        .route("/search", post(search))
+        .route("/sessions", post(create_session))
+        .route("/sessions/:id", get(session_history).delete(delete_session))
+        .route("/sessions/:id/messages", post(session_message))
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/embeddings", post(embeddings))
+        .route("/v1/models", get(models))
+        .route("/model", get(model_status))
+        .route("/model/switch", post(switch_model))
+        .route("/retriever/invoke", post(retriever_invoke))
+        .route("/retriever/batch", post(retriever_batch))
+        .route("/retriever/retrieve", post(retriever_retrieve))
+        .with_state(state)
+}
+
+/// Fails generating endpoints with 503 while no model is loaded; search and
+/// the retriever endpoints keep working, and `/model/switch` can load one
+fn require_model(pipeline: &RagPipeline) -> Result<(), ApiError> {
+    match pipeline.llm().is_available() {
+        true => Ok(()),
+        false => Err(ApiError(StatusCode::SERVICE_UNAVAILABLE, "No model is loaded; only /search and the retriever endpoints are available".to_string())),
+    }
+}
+
+/// The pipeline answering from the collection a request names
+fn select(state: &AppState, collection: Option<&str>) -> Result<RagPipeline, ApiError> {
+    match collection {
+        Some(name) => state.pipeline.collection(name).map_err(|e| ApiError(StatusCode::NOT_FOUND, e.to_string())),
+        None => Ok(state.pipeline.clone()),
+    }
+}
+
+/// The span one request's blocking work runs in, so the retrieval and
+/// generation spans of a trace hang off the request that caused them
+fn request_span(route: &'static str) -> Span {
+    info_span!("request", route, outcome = tracing::field::Empty)
+}
+
+fn validate(pipeline: &RagPipeline, request: &QueryRequest) -> Result<(), ApiError> {
+    if request.query.trim().is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "Query cannot be empty".to_string()));
+    }
+    require_model(pipeline)?;
+    pipeline.llm()
+        .validate_overrides(&request.overrides)
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn query(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<QueryResponse>, ApiError> {
+    let pipeline = select(&state, request.collection.as_deref())?;
+    validate(&pipeline, &request)?;
+
+    let span = request_span("/query");
+    if let Some(entry) = span.in_scope(|| pipeline.faq_answer(&request.query)) {
+        span.record("outcome", tracing::field::debug(Outcome::Faq));
+        return Ok(Json(QueryResponse {
+            outcome: Outcome::Faq,
+            answer: entry.answer.clone(),
+            flags: Vec::new(),
+            context: Vec::new(),
+            highlights: Vec::new(),
+            faq_question: Some(entry.question.clone()),
+            repeated_question: None,
+            plan: None,
+            timings: None,
+        }));
+    }
+
+    // Inference is CPU-bound and blocking; keep it off the async workers
+    let response = tokio::task::spawn_blocking(move || span.in_scope(|| answer_query(&pipeline, &request)))
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+
+    Ok(Json(response))
+}
+
+/// Answers a `/query` request from the answer cache or the pipeline,
+/// recording the outcome on the current request span
+fn answer_query(pipeline: &RagPipeline, request: &QueryRequest) -> Result<QueryResponse> {
+    // Answers to requests with overrides or a budget may differ, so
+    // they aren't cached
+    let cacheable = request.overrides == GenerationOverrides::default() && request.budget.is_empty();
+    if cacheable && let Some(answer) = pipeline.cached_answer(&request.query) {
+        Span::current().record("outcome", tracing::field::debug(Outcome::Cached));
+        return Ok(QueryResponse {
+            outcome: Outcome::Cached,
+            answer,
+            flags: Vec::new(),
+            context: Vec::new(),
+            highlights: Vec::new(),
+            faq_question: None,
+            repeated_question: None,
+            plan: None,
+            timings: None,
+        });
+    }
+    let response = generate_response(pipeline, request, None)?;
+    Span::current().record("outcome", tracing::field::debug(response.outcome));
+    if cacheable && response.outcome == Outcome::Answered && response.flags.is_empty() {
+        pipeline.cache_answer(&request.query, &response.context, &response.answer);
+    }
+    Ok(response)
+}
+
+fn generate_response(
+    pipeline: &RagPipeline,
+    request: &QueryRequest,
+    history: Option<&Conversation>,
+) -> Result<QueryResponse> {
+    // Session messages run the full pipeline, following their history
+    if !request.budget.is_empty() && history.is_none() {
+        return generate_planned(pipeline, request);
+    }
+    let mut timings = Timings::new();
+    let context = retriever::contents(&pipeline.retrieve_timed(&request.query, &mut timings));
+    let answer = pipeline.generate_with(&request.query, context.clone(), history, &request.overrides, &mut timings)?;
+    let (outcome, answer) = pipeline.review_answer(&request.query, &context, answer);
+    let (outcome, answer, flags) = pipeline.moderate(outcome, answer);
+    let highlights = pipeline.highlight(&request.query, &context);
+    Ok(QueryResponse {
+        outcome,
+        answer,
+        flags,
+        context,
+        highlights,
+        faq_question: None,
+        repeated_question: None,
+        plan: None,
+        timings: request.timings.then_some(timings),
+    })
+}
+
+/// `generate_response` with the retrieval depth, reranking, verification
+/// and model the planner chose for the request's budget
+fn generate_planned(pipeline: &RagPipeline, request: &QueryRequest) -> Result<QueryResponse> {
+    let plan = pipeline.plan(&request.budget);
+    let mut timings = Timings::new();
+    let context = retriever::contents(&pipeline.retrieve_planned(&request.query, &plan, &mut timings));
+    // Hooks may rewrite the answer; the cost is what the model generated
+    let mut generated = String::new();
+    let answer = pipeline.generate_planned(&request.query, context.clone(), &request.overrides, &plan, &mut timings, |text| {
+        generated.push_str(text);
+        ControlFlow::Continue(())
+    })?;
+    let tokens = pipeline.count_answer_tokens(&generated, Some(&plan));
+    let (outcome, answer) = pipeline.review_answer(&request.query, &context, answer);
+    let (outcome, answer, flags) = pipeline.moderate_planned(outcome, answer, &plan, &mut timings);
+    pipeline.observe_plan(&plan, &timings, &context, tokens);
+    let highlights = pipeline.highlight(&request.query, &context);
+    Ok(QueryResponse {
+        outcome,
+        answer,
+        flags,
+        context,
+        highlights,
+        faq_question: None,
+        repeated_question: None,
+        plan: Some(plan),
+        timings: request.timings.then_some(timings),
+    })
+}
+
+/// A fresh answer to a question `/query` answered, or `variants`
+/// alternative ones, generated like the REPL's `/retry` and `/variants`.
+/// Never served from or stored in the answer cache.
+async fn query_retry(
+    State(state): State<Arc<AppState>>,
+    Json(retry): Json<RetryRequest>,
+) -> Result<Json<RetryResponse>, ApiError> {
+    let pipeline = select(&state, retry.request.collection.as_deref())?;
+    validate(&pipeline, &retry.request)?;
+    if !retry.request.budget.is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "Retries don't take a budget".to_string()));
+    }
+    if retry.variants.is_some_and(|n| n == 0 || n > MAX_VARIANTS) {
+        return Err(ApiError(StatusCode::BAD_REQUEST, format!("variants must be between 1 and {}", MAX_VARIANTS)));
+    }
+
+    let span = request_span("/query/retry");
+    let response = tokio::task::spawn_blocking(move || span.in_scope(|| retry_answers(&pipeline, &retry)))
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+    Ok(Json(response))
+}
+
+fn retry_answers(pipeline: &RagPipeline, retry: &RetryRequest) -> Result<RetryResponse> {
+    let request = &retry.request;
+    let context = retriever::contents(&pipeline.retrieve(&request.query));
+    let answers = match retry.variants {
+        Some(n) => pipeline.variants(&request.query, context.clone(), None, n, &request.overrides)?,
+        None => {
+            let attempt = retry.attempt.unwrap_or(1);
+            vec![pipeline.regenerate(&request.query, context.clone(), None, attempt, &request.overrides)?]
+        }
+    };
+    let highlights = pipeline.highlight(&request.query, &context);
+    Ok(RetryResponse {
+        answers: answers.into_iter().map(|(outcome, answer)| RetryAnswer { outcome, answer }).collect(),
+        context,
+        highlights,
+    })
+}
+
+/// Retrieval only, for federated peers. Results never include this
+/// instance's own peers, so instances can federate with each other.
+async fn query_raw(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<RawQuery>,
+) -> Result<Json<RawResults>, ApiError> {
+    if request.query.trim().is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "Query cannot be empty".to_string()));
+    }
+    let top_k = request.top_k.unwrap_or(3).clamp(1, MAX_RAW_TOP_K);
+    let chunks = tokio::task::spawn_blocking(move || state.pipeline.retrieve_local(&request.query, top_k, None))
+        .await
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let chunks = chunks
+        .into_iter()
+        .map(|chunk| RawChunk { content: chunk.content, score: chunk.score, metadata: chunk.metadata })
+        .collect();
+    Ok(Json(RawResults { chunks }))
+}
+
+async fn create_session(State(state): State<Arc<AppState>>) -> (StatusCode, Json<SessionCreated>) {
+    let id = state.sessions.create();
+    (StatusCode::CREATED, Json(SessionCreated { id, expires_in_secs: state.sessions.ttl().as_secs() }))
+}
+
+async fn session_history(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<SessionHistory>, ApiError> {
+    let exchanges = state.sessions.history(&id).ok_or(CheckoutError::NotFound)?;
+    Ok(Json(SessionHistory { id, exchanges }))
+}
+
+async fn delete_session(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> Result<StatusCode, ApiError> {
+    if !state.sessions.delete(&id) {
+        return Err(CheckoutError::NotFound.into());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `/query` with the session's earlier exchanges as conversation history.
+/// A session answers one message at a time; concurrent ones get 409.
+async fn session_message(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<QueryResponse>, ApiError> {
+    let pipeline = select(&state, request.collection.as_deref())?;
+    validate(&pipeline, &request)?;
+    let conversation = state.sessions.check_out(&id)?;
+    // Released however answering ends, even by panicking, unless the answer
+    // is checked in
+    let checkout = Checkout { state: state.clone(), id: id.clone(), checked_in: false };
+    let history = state.sessions.history(&id).unwrap_or_default();
+
+    let span = request_span("/sessions/:id/messages");
+    let task = tokio::task::spawn_blocking(move || -> Result<QueryResponse> {
+        let _entered = span.enter();
+        let mut conversation = conversation;
+        let response = session_reply(&pipeline, &request, &mut conversation, &history)?;
+        span.record("outcome", tracing::field::debug(response.outcome));
+        let exchange = Exchange {
+            question: request.query,
+            answer: response.answer.clone(),
+            outcome: response.outcome,
+        };
+        checkout.check_in(conversation, exchange);
+        Ok(response)
+    });
+    let response = task.await.map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+    Ok(Json(response))
+}
+
+/// A session checked out for answering a message, released when dropped
+/// without being checked back in
+struct Checkout {
+    state: Arc<AppState>,
+    id: String,
+    checked_in: bool,
+}
+
+impl Checkout {
+    fn check_in(mut self, conversation: Conversation, exchange: Exchange) {
+        self.state.sessions.check_in(&self.id, conversation, exchange);
+        self.checked_in = true;
+    }
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        if !self.checked_in {
+            self.state.sessions.release(&self.id);
+        }
+    }
+}
+
+/// Answers `request` and adds the exchange to `conversation`, summarizing
+/// older turns once it outgrows its token budget. A question the session
+/// already got an answer to is answered the same way again, unless the
+/// request asks to regenerate.
+fn session_reply(
+    pipeline: &RagPipeline,
+    request: &QueryRequest,
+    conversation: &mut Conversation,
+    history: &[Exchange],
+) -> Result<QueryResponse> {
+    let answered: Vec<&Exchange> = history.iter().filter(|exchange| exchange.outcome == Outcome::Answered).collect();
+    let earlier: Vec<&str> = answered.iter().map(|exchange| exchange.question.as_str()).collect();
+    let repeat = match request.regenerate {
+        true => None,
+        false => pipeline.find_repeat(&request.query, &earlier).map(|i| answered[i]),
+    };
+    let response = match (pipeline.faq_answer(&request.query), repeat) {
+        (Some(entry), _) => QueryResponse {
+            outcome: Outcome::Faq,
+            answer: entry.answer.clone(),
+            flags: Vec::new(),
+            context: Vec::new(),
+            highlights: Vec::new(),
+            faq_question: Some(entry.question.clone()),
+            repeated_question: None,
+            plan: None,
+            timings: None,
+        },
+        (None, Some(exchange)) => QueryResponse {
+            outcome: Outcome::Repeated,
+            answer: exchange.answer.clone(),
+            flags: Vec::new(),
+            context: Vec::new(),
+            highlights: Vec::new(),
+            faq_question: None,
+            repeated_question: Some(exchange.question.clone()),
+            plan: None,
+            timings: None,
+        },
+        (None, None) => generate_response(pipeline, request, Some(conversation))?,
+    };
+    conversation.push(&request.query, &response.answer);
+    if conversation.needs_summary()
+        && let Err(e) = pipeline.llm().summarize_conversation(conversation)
+    {
+        // The turns stay verbatim; the next message tries again
+        eprintln!("Error summarizing session conversation: {}", e);
+    }
+    Ok(response)
+}
+
+/// `/query` as a stream of `StreamEvent`s, so frontends can show retrieval
+/// and generation progressing
+async fn query_stream(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<QueryRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let pipeline = select(&state, request.collection.as_deref())?;
+    validate(&pipeline, &request)?;
+
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let span = request_span("/query/stream");
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        if let Err(e) = stream_answer(&pipeline, &request, &sender) {
+            let _ = sender.send(StreamEvent::Error { message: e.to_string() });
+        }
+    });
+
+    let events = UnboundedReceiverStream::new(receiver).map(|event| Ok(event.into_sse()));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Sends the events of answering `request` to `events`. Generation stops
+/// once the channel is closed, which means the client went away.
+fn stream_answer(pipeline: &RagPipeline, request: &QueryRequest, events: &UnboundedSender<StreamEvent>) -> Result<()> {
+    let send = |event: StreamEvent| {
+        let _ = events.send(event);
+    };
+    let mut timings = Timings::new();
+    if let Some(entry) = pipeline.faq_answer(&request.query) {
+        send(StreamEvent::Done {
+            outcome: Outcome::Faq,
+            answer: entry.answer.clone(),
+            flags: Vec::new(),
+            stats: StreamStats { timings, tokens: 0, context_chunks: 0, plan: None },
+        });
+        return Ok(());
+    }
+
+    let plan = (!request.budget.is_empty()).then(|| pipeline.plan(&request.budget));
+    send(StreamEvent::RetrievalStarted { query: request.query.clone() });
+    let retrieved = match &plan {
+        Some(plan) => pipeline.retrieve_planned(&request.query, plan, &mut timings),
+        None => pipeline.retrieve_timed(&request.query, &mut timings),
+    };
+    let context = retriever::contents(&retrieved);
+    let highlights = pipeline.highlight(&request.query, &context);
+    let chunks = retrieved.into_iter().zip(highlights)
+        .map(|(chunk, highlights)| StreamChunk { content: chunk.content, score: chunk.score, highlights })
+        .collect();
+    send(StreamEvent::Chunks { chunks });
+
+    send(StreamEvent::GenerationStarted);
+    // An answer moderation may block can't be shown before it is checked
+    let withhold = pipeline.may_block_answers();
+    let mut generated = String::new();
+    let on_token = |text: &str| {
+        generated.push_str(text);
+        if !withhold {
+            send(StreamEvent::Token { text: text.to_string() });
+        }
+        match events.is_closed() {
+            true => ControlFlow::Break(()),
+            false => ControlFlow::Continue(()),
+        }
+    };
+    let answer = match &plan {
+        Some(plan) => pipeline.generate_planned(&request.query, context.clone(), &request.overrides, plan, &mut timings, on_token)?,
+        None => pipeline.generate_streaming(&request.query, context.clone(), None, &request.overrides, &mut timings, on_token)?,
+    };
+    if events.is_closed() {
+        return Ok(());
+    }
+    let tokens = pipeline.count_answer_tokens(&generated, plan.as_ref());
+    let (outcome, answer) = pipeline.review_answer(&request.query, &context, answer);
+    let (outcome, answer, flags) = match &plan {
+        Some(plan) => pipeline.moderate_planned(outcome, answer, plan, &mut timings),
+        None => pipeline.moderate(outcome, answer),
+    };
+    if let Some(plan) = &plan {
+        pipeline.observe_plan(plan, &timings, &context, tokens);
+    }
+    let stats = StreamStats { timings, tokens, context_chunks: context.len(), plan };
+    send(StreamEvent::Done { outcome, answer, flags, stats });
+    Ok(())
+}
+
+/// OpenAI-compatible chat: the last user message is answered through the
+/// RAG pipeline, with earlier messages as history and system messages as
+/// the system prompt. With `"stream": true` the answer is sent as
+/// `chat.completion.chunk` events ending in `[DONE]`.
+async fn chat_completions(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ChatRequest>,
+) -> Result<Response, ApiError> {
+    let (model, stream) = (request.model(), request.stream);
+    let turn = request.into_turn().map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    require_model(&state.pipeline)?;
+    state.pipeline.llm()
+        .validate_overrides(&turn.overrides)
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let id = openai::completion_id();
+
+    let span = request_span("/v1/chat/completions");
+    if !stream {
+        let answer = tokio::task::spawn_blocking(move || span.in_scope(|| chat_answer(&state.pipeline, turn, |_| ControlFlow::Continue(()))))
+            .await
+            .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+        return Ok(Json(ChatCompletion::new(id, model, answer)).into_response());
+    }
+
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let send = |data: String| {
+            let _ = sender.send(data);
+        };
+        let send_delta = |delta: Delta, finish_reason: Option<&'static str>| {
+            let chunk = ChatCompletionChunk::new(&id, &model, delta, finish_reason);
+            send(serde_json::to_string(&chunk).expect("chunks serialize"));
+        };
+        let content = |text: String| Delta { content: Some(text), ..Delta::default() };
+
+        send_delta(Delta { role: Some("assistant"), ..Delta::default() }, None);
+        // An answer moderation may block can't be shown before it is checked
+        let withhold = state.pipeline.may_block_answers();
+        let mut streamed = String::new();
+        let result = chat_answer(&state.pipeline, turn, |text| {
+            if !withhold {
+                streamed.push_str(text);
+                send_delta(content(text.to_string()), None);
+            }
+            // Stop generating for a client that went away
+            match sender.is_closed() {
+                true => ControlFlow::Break(()),
+                false => ControlFlow::Continue(()),
+            }
+        });
+        match result {
+            Ok(answer) => {
+                // Hooks, the abstain policy or moderation may have replaced
+                // the streamed text; the final answer then follows it
+                if streamed.is_empty() {
+                    send_delta(content(answer), None);
+                } else if answer != streamed {
+                    send_delta(content(format!("\n\n{}", answer)), None);
+                }
+                send_delta(Delta::default(), Some("stop"));
+            }
+            Err(e) => send(serde_json::json!({ "error": { "message": e.to_string() } }).to_string()),
+        }
+    });
+
+    let events = UnboundedReceiverStream::new(receiver)
+        .chain(tokio_stream::once("[DONE]".to_string()))
+        .map(|data| Ok::<_, Infallible>(Event::default().data(data)));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()).into_response())
+}
+
+/// Answers a chat turn like `/query`, passing answer text to `on_token`
+fn chat_answer(pipeline: &RagPipeline, turn: ChatTurn, on_token: impl FnMut(&str) -> ControlFlow<()>) -> Result<String> {
+    let ChatTurn { question, mut history, overrides } = turn;
+    if let Some(entry) = pipeline.faq_answer(&question) {
+        return Ok(entry.answer.clone());
+    }
+    if history.needs_summary()
+        && let Err(e) = pipeline.llm().summarize_conversation(&mut history)
+    {
+        // The turns stay verbatim and may be cut short by the context window
+        tracing::warn!(error = %e, "failed to summarize chat history");
+    }
+    let context = retriever::contents(&pipeline.retrieve(&question));
+    let answer = pipeline.generate_streaming(&question, context.clone(), Some(&history), &overrides, &mut Timings::new(), on_token)?;
+    let (outcome, answer) = pipeline.review_answer(&question, &context, answer);
+    let (_, answer, _) = pipeline.moderate(outcome, answer);
+    Ok(answer)
+}
+
+/// OpenAI-compatible embeddings from the index's own embedding model, so
+/// clients can embed text into the space the knowledge base is searched in
+async fn embeddings(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<EmbeddingRequest>,
+) -> Result<Json<EmbeddingResponse>, ApiError> {
+    let inputs = request.input.into_vec();
+    if inputs.is_empty() || inputs.len() > MAX_EMBEDDING_INPUTS {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("input must hold between 1 and {} texts", MAX_EMBEDDING_INPUTS),
+        ));
+    }
+    let response = tokio::task::spawn_blocking(move || -> Result<EmbeddingResponse> {
+        let mut model = String::new();
+        let mut embeddings = Vec::with_capacity(inputs.len());
+        for text in &inputs {
+            let (model_id, embedding) = state.pipeline.embed(text)?;
+            model = model_id;
+            embeddings.push(embedding);
+        }
+        Ok(EmbeddingResponse::new(model, embeddings))
+    })
+    .await
+    .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))??;
+    Ok(Json(response))
+}
+
+/// The single model chat requests are answered by, for clients that list
+/// models before chatting
+async fn models() -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "object": "list",
+        "data": [{ "id": openai::DEFAULT_MODEL, "object": "model", "owned_by": "tapssp" }],
+    }))
+}
+
+#[derive(Deserialize)]
+struct SwitchModelRequest {
+    /// GGUF file path or name in the models directory; the model name with
+    /// the Ollama backend
+    model: String,
+}
+
+/// The model being served and the state of the latest switch
+async fn model_status(State(state): State<Arc<AppState>>) -> Result<Json<SwitchStatus>, ApiError> {
+    let switcher = state.pipeline.model_switcher()
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "Model switching is not enabled".to_string()))?;
+    Ok(Json(switcher.status()))
+}
+
+/// Starts loading another model; the current one keeps answering until the
+/// new one is swapped in. Poll `/model` for completion.
+async fn switch_model(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SwitchModelRequest>,
+) -> Result<(StatusCode, Json<SwitchStatus>), ApiError> {
+    let switcher = state.pipeline.model_switcher()
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "Model switching is not enabled".to_string()))?;
+    if request.model.trim().is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "Model cannot be empty".to_string()));
+    }
+    switcher.switch(&request.model).map_err(|e| ApiError(StatusCode::CONFLICT, e.to_string()))?;
+    Ok((StatusCode::ACCEPTED, Json(switcher.status())))
+}
+
+/// Local chunks for each query, best first, as the remote retriever
+/// endpoints return them
+async fn retrieve_chunks(
+    state: Arc<AppState>,
+    collection: Option<String>,
+    queries: Vec<String>,
+    top_k: Option<usize>,
+    filter: Option<String>,
+) -> Result<Vec<Vec<ScoredChunk>>, ApiError> {
+    let pipeline = select(&state, collection.as_deref())?;
+    if queries.iter().any(|query| query.trim().is_empty()) {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "Query cannot be empty".to_string()));
+    }
+    let filter = filter
+        .map(|expression| Filter::parse(&expression))
+        .transpose()
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let top_k = top_k.unwrap_or(remote_retriever::DEFAULT_TOP_K).clamp(1, MAX_RAW_TOP_K);
+    tokio::task::spawn_blocking(move || {
+        queries.iter().map(|query| pipeline.retrieve_local(query, top_k, filter.as_ref())).collect()
+    })
+    .await
+    .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Ranked local chunks with their scores and metadata, without generating
+/// an answer, so the index can serve as a plain semantic search engine
+async fn search(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SearchRequest>,
+) -> Result<Json<SearchResults>, ApiError> {
+    let mut results = retrieve_chunks(state, request.collection, vec![request.query.clone()], request.top_k, request.filter).await?;
+    Ok(Json(SearchResults::new(request.query, results.pop().unwrap_or_default())))
+}
+
+/// LangServe-style retriever, so Python pipelines can use the index as
+/// `RemoteRunnable("http://HOST:PORT/retriever")`
+async fn retriever_invoke(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<InvokeRequest>,
+) -> Result<Json<InvokeResponse<Vec<LangChainDocument>>>, ApiError> {
+    let mut results = retrieve_chunks(state, request.collection, vec![request.input], request.top_k, request.filter).await?;
+    let documents = results.pop().unwrap_or_default().into_iter().map(LangChainDocument::from).collect();
+    Ok(Json(InvokeResponse::new(documents)))
+}
+
+async fn retriever_batch(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<BatchRequest>,
+) -> Result<Json<InvokeResponse<Vec<Vec<LangChainDocument>>>>, ApiError> {
+    if request.inputs.len() > MAX_BATCH_QUERIES {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            format!("inputs may hold at most {} queries", MAX_BATCH_QUERIES),
+        ));
+    }
+    let results = retrieve_chunks(state, request.collection, request.inputs, request.top_k, request.filter).await?;
+    let documents = results
+        .into_iter()
+        .map(|chunks| chunks.into_iter().map(LangChainDocument::from).collect())
+        .collect();
+    Ok(Json(InvokeResponse::new(documents)))
+}
+
+/// LlamaIndex-style retriever returning serialized `NodeWithScore`s
+async fn retriever_retrieve(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<RetrieveRequest>,
+) -> Result<Json<RetrieveResponse>, ApiError> {
+    let mut results = retrieve_chunks(state, request.collection, vec![request.query_str], request.similarity_top_k, request.filter).await?;
+    let nodes = results.pop().unwrap_or_default().into_iter().map(NodeWithScore::from).collect();
+    Ok(Json(RetrieveResponse { nodes }))
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM as sent by `docker stop`
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        if let Ok(mut signal) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            signal.recv().await;
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{InferenceOptions, LLM, LLMBackend, LLMConfig};
+    use crate::retriever::Retriever;
+    use crate::runtime;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tracing::span::{Attributes, Id};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::registry::{LookupSpan, Registry};
+
+    /// Answers from the prompt's context, like a model that read it, and
+    /// crashes on questions about crashing
+    struct Reader;
+
+    impl LLMBackend for Reader {
+        fn infer(&self, prompt: String, _options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+            assert!(!prompt.contains("crash"), "model crashed");
+            let _ = on_piece(if prompt.contains("thirty days") { "Thirty days." } else { "I don't know." });
+            Ok(())
+        }
+    }
+
+    fn state(session_ttl: Duration) -> Arc<AppState> {
+        let mut retriever = Retriever::new();
+        retriever.add_to_knowledge_base("Refunds are paid within thirty days of the return.".to_string(), BTreeMap::new()).unwrap();
+        let pipeline = RagPipeline::new(retriever, LLM::with_backend(Arc::new(Reader), LLMConfig::default()));
+        Arc::new(AppState { pipeline, sessions: ConversationStore::new(session_ttl) })
+    }
+
+    fn body<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> Json<T> {
+        Json(serde_json::from_value(value).unwrap())
+    }
+
+    async fn text(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_session_messages_when_busy_failed_and_expired() {
+        runtime::block_on(async {
+            let state = state(Duration::from_millis(300));
+            let id = state.sessions.create();
+            let ask = |query: &str| session_message(State(state.clone()), Path(id.clone()), body(json!({ "query": query })));
+
+            let Json(response) = ask("How long until refunds are paid?").await.unwrap();
+            assert_eq!(response.answer, "Thirty days.");
+            assert!(response.timings.is_none());
+
+            let held = state.sessions.check_out(&id).unwrap();
+            assert_eq!(ask("And exchanges?").await.unwrap_err().0, StatusCode::CONFLICT);
+            state.sessions.check_in(&id, held, Exchange {
+                question: "And exchanges?".to_string(),
+                answer: "I don't know.".to_string(),
+                outcome: Outcome::Answered,
+            });
+
+            // A crashed answer leaves the session free for the next message
+            assert_eq!(ask("Why did it crash?").await.unwrap_err().0, StatusCode::INTERNAL_SERVER_ERROR);
+            let Json(response) = ask("How long until refunds are paid?").await.unwrap();
+            assert_eq!(response.outcome, Outcome::Repeated);
+            let Json(history) = session_history(State(state.clone()), Path(id.clone())).await.unwrap();
+            assert_eq!(history.exchanges.len(), 3);
+
+            tokio::time::sleep(Duration::from_millis(400)).await;
+            assert_eq!(ask("Still there?").await.unwrap_err().0, StatusCode::NOT_FOUND);
+            assert_eq!(delete_session(State(state.clone()), Path(id.clone())).await.unwrap_err().0, StatusCode::NOT_FOUND);
+        });
+    }
+
+    #[test]
+    fn test_query_retry_regenerates_and_returns_variants() {
+        runtime::block_on(async {
+            let state = state(Duration::from_secs(60));
+            let question = "How long until refunds are paid?";
+            let Json(retried) = query_retry(State(state.clone()), body(json!({ "query": question, "attempt": 2 }))).await.unwrap();
+            assert_eq!(retried.answers.len(), 1);
+            assert_eq!(retried.answers[0].answer, "Thirty days.");
+            assert!(retried.context[0].contains("thirty days"));
+
+            let Json(variants) = query_retry(State(state.clone()), body(json!({ "query": question, "variants": 3 }))).await.unwrap();
+            assert_eq!(variants.answers.len(), 3);
+            assert!(variants.answers.iter().all(|variant| variant.outcome == Outcome::Answered));
+
+            let too_many = query_retry(State(state), body(json!({ "query": question, "variants": MAX_VARIANTS + 1 }))).await;
+            assert_eq!(too_many.unwrap_err().0, StatusCode::BAD_REQUEST);
+        });
+    }
+
+    /// A span's name and its parent's
+    type SpanLink = (&'static str, Option<&'static str>);
+
+    /// Records the `SpanLink` of each new span
+    #[derive(Clone, Default)]
+    struct SpanParents(Arc<Mutex<Vec<SpanLink>>>);
+
+    impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanParents {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let parent = ctx.span(id).and_then(|span| span.parent()).map(|parent| parent.name());
+            self.0.lock().unwrap().push((attrs.metadata().name(), parent));
+        }
+    }
+
+    #[test]
+    fn test_query_runs_retrieval_and_generation_in_the_request_span() {
+        // Blocking tasks run on other threads, which only a global
+        // subscriber reaches, as in `telemetry::init`
+        let parents = SpanParents::default();
+        tracing::subscriber::set_global_default(Registry::default().with(parents.clone())).unwrap();
+        runtime::block_on(async {
+            let request = body(json!({ "query": "How long until refunds are paid?", "timings": true }));
+            let Json(response) = query(State(state(Duration::from_secs(60))), request).await.unwrap();
+            assert_eq!(response.answer, "Thirty days.");
+            let timings = serde_json::to_value(response.timings).unwrap();
+            assert!(timings.get("retrieval").is_some() && timings.get("prompt build").is_some(), "{}", timings);
+        });
+        let parents = parents.0.lock().unwrap();
+        for stage in ["retrieval", "generation"] {
+            assert!(parents.contains(&(stage, Some("request"))), "{:?}", parents);
+        }
+    }
+
+    #[test]
+    fn test_query_stream_reports_each_stage() {
+        runtime::block_on(async {
+            let sse = query_stream(State(state(Duration::from_secs(60))), body(json!({ "query": "How long until refunds are paid?" })))
+                .await
+                .unwrap();
+            let events = text(sse.into_response()).await;
+            let names: Vec<&str> = events.lines().filter_map(|line| line.strip_prefix("event: ")).collect();
+            assert_eq!(names, ["retrieval_started", "chunks", "generation_started", "token", "done"]);
+            assert!(events.contains("thirty days of the return") && events.contains("\"answer\":\"Thirty days.\""), "{}", events);
+        });
+    }
+
+    /// Talks until told to stop, counting the pieces it decoded, with a
+    /// tokenizer that splits on whitespace
+    struct Babbler(Arc<AtomicUsize>);
+
+    impl LLMBackend for Babbler {
+        fn infer(&self, _prompt: String, _options: &InferenceOptions, on_piece: &mut dyn FnMut(&str) -> ControlFlow<()>) -> Result<()> {
+            for i in 0..50 {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                if on_piece(&format!("word{} word{} ", 2 * i, 2 * i + 1)).is_break() {
+                    break;
+                }
+            }
+            Ok(())
+        }
+
+        fn count_tokens(&self, text: &str) -> Option<usize> {
+            Some(text.split_whitespace().count())
+        }
+    }
+
+    #[test]
+    fn test_query_stream_stops_for_gone_clients_and_counts_tokens() {
+        let decoded = Arc::new(AtomicUsize::new(0));
+        let mut retriever = Retriever::new();
+        retriever.add_to_knowledge_base("Refunds are paid within thirty days of the return.".to_string(), BTreeMap::new()).unwrap();
+        let pipeline = RagPipeline::new(retriever, LLM::with_backend(Arc::new(Babbler(Arc::clone(&decoded))), LLMConfig::default()));
+        let Json(request) = body::<QueryRequest>(json!({ "query": "How long until refunds are paid?" }));
+
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        drop(receiver);
+        stream_answer(&pipeline, &request, &sender).unwrap();
+        assert_eq!(decoded.load(Ordering::Relaxed), 1);
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        stream_answer(&pipeline, &request, &sender).unwrap();
+        let mut tokens = None;
+        while let Ok(event) = receiver.try_recv() {
+            if let StreamEvent::Done { stats, .. } = event {
+                tokens = Some(stats.tokens);
+            }
+        }
+        // 50 pieces of two tokens each
+        assert_eq!(tokens, Some(100));
+    }
+
+    #[test]
+    fn test_chat_completions_answer_through_the_pipeline() {
+        runtime::block_on(async {
+            let state = state(Duration::from_secs(60));
+            let messages = json!([
+                { "role": "system", "content": "Be brief." },
+                { "role": "user", "content": "How long until refunds are paid?" },
+            ]);
+            let response = chat_completions(State(state.clone()), body(json!({ "messages": messages }))).await.unwrap();
+            let completion: serde_json::Value = serde_json::from_str(&text(response).await).unwrap();
+            assert_eq!(completion["object"], "chat.completion");
+            assert_eq!(completion["choices"][0]["message"]["content"], "Thirty days.");
+
+            let response = chat_completions(State(state.clone()), body(json!({ "messages": messages, "stream": true }))).await.unwrap();
+            let events = text(response).await;
+            assert!(events.contains("Thirty days.") && events.trim_end().ends_with("data: [DONE]"), "{}", events);
+
+            let empty = chat_completions(State(state), body(json!({ "messages": [] }))).await;
+            assert_eq!(empty.unwrap_err().0, StatusCode::BAD_REQUEST);
+        });
+    }
+}
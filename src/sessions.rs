@@ -0,0 +1,148 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::utils::ensure_dir;
+
+/// One answered query in a recorded chat session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionEntry {
+    pub query: String,
+    pub answer: String,
+    pub context: Vec<String>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// User feedback given with `/feedback good|bad`
+    #[serde(default)]
+    pub helpful: Option<bool>,
+}
+
+/// A named session stored as JSON lines in `<dir>/<name>.jsonl`, so
+/// recorded queries can be replayed against another index or config
+pub struct SessionLog {
+    path: PathBuf,
+}
+
+impl SessionLog {
+    pub fn new(dir: &Path, name: &str) -> Result<Self> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(anyhow!("Session name may only contain letters, digits, '-' and '_'"));
+        }
+        Ok(SessionLog { path: dir.join(format!("{}.jsonl", name)) })
+    }
+
+    pub fn record(&self, query: &str, answer: &str, context: &[String]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            ensure_dir(dir)?;
+        }
+        let entry = SessionEntry {
+            query: query.to_string(),
+            answer: answer.to_string(),
+            context: context.to_vec(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            helpful: None,
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    /// Attaches feedback to the most recently recorded answer
+    pub fn rate_last(&self, helpful: bool) -> Result<()> {
+        let mut entries = self.entries()?;
+        let last = entries.last_mut().ok_or_else(|| anyhow!("No answers recorded in this session yet"))?;
+        last.helpful = Some(helpful);
+
+        let mut content = String::new();
+        for entry in &entries {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+        let tmp_path = self.path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<SessionEntry>> {
+        let content = fs::read_to_string(&self.path)
+            .map_err(|_| anyhow!("No recorded session at {:?}", self.path))?;
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+}
+
+/// Word-level diff in `git diff --word-diff` style: removed words as
+/// `[-old-]`, added ones as `{+new+}`. `None` when the texts have the same words.
+pub fn word_diff(old: &str, new: &str) -> Option<String> {
+    let old: Vec<&str> = old.split_whitespace().collect();
+    let new: Vec<&str> = new.split_whitespace().collect();
+    if old == new {
+        return None;
+    }
+
+    // lcs[i][j] = length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            out.push(old[i].to_string());
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(format!("[-{}-]", old[i]));
+            i += 1;
+        } else {
+            out.push(format!("{{+{}+}}", new[j]));
+            j += 1;
+        }
+    }
+    Some(out.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_diff_marks_changes() {
+        assert_eq!(word_diff("Refunds take 30 days.", "Refunds  take 30 days."), None);
+        assert_eq!(
+            word_diff("Refunds take 30 days.", "Refunds take 14 days."),
+            Some("Refunds take [-30-] {+14+} days.".to_string())
+        );
+    }
+
+    #[test]
+    fn test_session_log_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = SessionLog::new(dir.path(), "s1")?;
+        log.record("What is the refund window?", "30 days.", &["Refunds: 30 days".to_string()])?;
+        log.record("Who approves refunds?", "The billing team.", &[])?;
+        log.rate_last(true)?;
+
+        let entries = log.entries()?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].answer, "The billing team.");
+        assert_eq!((entries[0].helpful, entries[1].helpful), (None, Some(true)));
+        assert!(SessionLog::new(dir.path(), "../etc").is_err());
+        Ok(())
+    }
+}
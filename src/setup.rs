@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use crate::collection::CollectionSettings;
+use crate::config::{DEFAULT_OLLAMA_URL, DEFAULT_OPENAI_URL};
+
+/// Choices made in `tapssp init`, written out as a config file
+#[derive(Debug, Clone)]
+pub struct SetupChoices {
+    /// `llama`, `ollama` or `openai`; `None` when no generation backend is
+    /// compiled in
+    pub backend: Option<String>,
+    /// GGUF file with the llama backend, model name with the remote ones.
+    /// Unset means the default model.
+    pub model: Option<PathBuf>,
+    /// Server of a remote backend
+    pub url: Option<String>,
+    pub docs: PathBuf,
+    pub index: PathBuf,
+    pub chunking: CollectionSettings,
+}
+
+impl SetupChoices {
+    /// The `tapssp.toml` holding these choices; settings left at their
+    /// defaults are omitted so later releases can change them
+    pub fn to_toml(&self) -> String {
+        let quote = |text: &str| toml::Value::String(text.to_string()).to_string();
+        let path = |path: &PathBuf| quote(&path.to_string_lossy());
+        let mut out = String::from("# Written by `tapssp init`\n\n[paths]\n");
+        out += &format!("docs = {}\n", path(&self.docs));
+        out += &format!("index = {}\n", path(&self.index));
+        if let Some(model) = &self.model {
+            out += &format!("model = {}\n", path(model));
+        }
+        if let Some(backend) = &self.backend {
+            out += &format!("\n[llm]\nbackend = {}\n", quote(backend));
+            let url_key = match backend.as_str() {
+                "ollama" => Some(("ollama_url", DEFAULT_OLLAMA_URL)),
+                "openai" => Some(("openai_url", DEFAULT_OPENAI_URL)),
+                _ => None,
+            };
+            if let (Some((key, default)), Some(url)) = (url_key, &self.url)
+                && url != default
+            {
+                out += &format!("{} = {}\n", key, quote(url));
+            }
+        }
+        out += "\n[chunking]\n";
+        if let Some(size) = self.chunking.chunk_size {
+            out += &format!("size = {}\noverlap = {}\n", size, self.chunking.chunk_overlap);
+        }
+        out += &format!("strategy = {}\n", quote(&self.chunking.chunk_strategy.to_string()));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunking::ChunkStrategy;
+    use crate::config::RuntimeConfig;
+    use anyhow::Result;
+
+    #[test]
+    fn test_written_config_loads_the_chosen_settings() -> Result<()> {
+        let mut chunking = CollectionSettings::default();
+        chunking.set("chunk-size", "800")?;
+        chunking.set("chunk-overlap", "100")?;
+        chunking.set("chunk-strategy", "paragraph")?;
+        let choices = SetupChoices {
+            backend: Some("ollama".to_string()),
+            model: Some(PathBuf::from("llama3")),
+            url: Some("http://gpu-box:11434".to_string()),
+            docs: PathBuf::from("C:\\Users\\kb \"docs\""),
+            index: PathBuf::from("data/index.bin"),
+            chunking,
+        };
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("tapssp.toml");
+        std::fs::write(&path, choices.to_toml())?;
+
+        let config = RuntimeConfig::from_file(&path)?;
+        assert_eq!(config.backend.as_deref(), Some("ollama"));
+        assert_eq!(config.model_path, Some(PathBuf::from("llama3")));
+        assert_eq!(config.ollama_url, "http://gpu-box:11434");
+        assert_eq!(config.docs_dir(), choices.docs);
+        assert_eq!(config.index_path(), Some(choices.index));
+        assert_eq!(config.collection.chunk_size, Some(800));
+        assert_eq!(config.collection.chunk_overlap, 100);
+        assert_eq!(config.collection.chunk_strategy, ChunkStrategy::Paragraph);
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+use anyhow::{Result, anyhow};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::utils::ensure_dir;
+
+const DAY: u64 = 24 * 60 * 60;
+/// Name of the index copy inside a snapshot directory
+const INDEX_FILE: &str = "index.bin";
+/// How often the snapshot thread checks whether its interval has passed
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Which snapshots `prune` keeps: the newest `last`, plus the newest of
+/// each of the last `daily` days and `weekly` weeks that have one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retention {
+    pub last: usize,
+    pub daily: usize,
+    pub weekly: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention { last: 5, daily: 7, weekly: 4 }
+    }
+}
+
+/// A saved copy of the index and session logs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub path: PathBuf,
+}
+
+impl Snapshot {
+    /// The saved index file
+    pub fn index_path(&self) -> PathBuf {
+        self.path.join(INDEX_FILE)
+    }
+}
+
+/// Snapshots of one index, each a `<dir>/<timestamp>` directory holding
+/// `index.bin` and a `sessions` directory
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new(dir: PathBuf) -> Self {
+        SnapshotStore { dir }
+    }
+
+    /// Copies `index` and the session logs in `sessions` into a new
+    /// snapshot. Files are copied to temporary names and renamed, so a
+    /// snapshot directory never holds a partial index.
+    pub fn take(&self, index: &Path, sessions: Option<&Path>) -> Result<Snapshot> {
+        let timestamp = now()?;
+        let path = self.dir.join(timestamp.to_string());
+        if path.exists() {
+            return Ok(Snapshot { timestamp, path });
+        }
+        let staging = self.dir.join(format!(".{}.tmp", timestamp));
+        ensure_dir(&staging)?;
+        if index.exists() {
+            fs::copy(index, staging.join(INDEX_FILE))?;
+        }
+        if let Some(sessions) = sessions.filter(|dir| dir.is_dir()) {
+            copy_files(sessions, &staging.join("sessions"))?;
+        }
+        fs::rename(&staging, &path)?;
+        Ok(Snapshot { timestamp, path })
+    }
+
+    /// Snapshots oldest first
+    pub fn list(&self) -> Result<Vec<Snapshot>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut snapshots: Vec<Snapshot> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                let timestamp = path.file_name()?.to_str()?.parse().ok()?;
+                Some(Snapshot { timestamp, path })
+            })
+            .collect();
+        snapshots.sort_by_key(|snapshot| snapshot.timestamp);
+        Ok(snapshots)
+    }
+
+    /// The newest snapshot taken at or before `at`
+    pub fn at(&self, at: u64) -> Result<Snapshot> {
+        self.list()?
+            .into_iter()
+            .rev()
+            .find(|snapshot| snapshot.timestamp <= at)
+            .ok_or_else(|| anyhow!("No snapshot taken at or before {}", format_time(at)))
+    }
+
+    /// Deletes the snapshots `retention` doesn't keep, returning how many
+    pub fn prune(&self, retention: Retention) -> Result<usize> {
+        let snapshots = self.list()?;
+        let timestamps: Vec<u64> = snapshots.iter().map(|snapshot| snapshot.timestamp).collect();
+        let kept = kept(&timestamps, retention);
+        let mut removed = 0;
+        for snapshot in snapshots.iter().filter(|snapshot| !kept.contains(&snapshot.timestamp)) {
+            fs::remove_dir_all(&snapshot.path)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Puts back the newest snapshot taken at or before `at`. The current
+    /// index and sessions are snapshotted first, so a restore can itself be
+    /// undone.
+    pub fn restore(&self, at: u64, index: &Path, sessions: Option<&Path>) -> Result<Snapshot> {
+        let snapshot = self.at(at)?;
+        self.take(index, sessions)?;
+
+        let saved_index = snapshot.index_path();
+        if saved_index.exists() {
+            let staging = index.with_extension("restore.tmp");
+            fs::copy(&saved_index, &staging)?;
+            fs::rename(&staging, index)?;
+        }
+        if let Some(sessions) = sessions {
+            if sessions.is_dir() {
+                fs::remove_dir_all(sessions)?;
+            }
+            let saved_sessions = snapshot.path.join("sessions");
+            if saved_sessions.is_dir() {
+                copy_files(&saved_sessions, sessions)?;
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Whether `index` or a session log changed after the newest snapshot
+    fn changed_since_latest(&self, index: &Path, sessions: Option<&Path>) -> Result<bool> {
+        let Some(latest) = self.list()?.pop() else {
+            return Ok(true);
+        };
+        let modified = |path: &Path| -> Option<u64> {
+            let time = fs::metadata(path).ok()?.modified().ok()?;
+            time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+        };
+        let mut paths = vec![index.to_path_buf()];
+        if let Some(dir) = sessions.filter(|dir| dir.is_dir()) {
+            paths.extend(fs::read_dir(dir)?.filter_map(|entry| entry.ok().map(|e| e.path())));
+        }
+        Ok(paths.iter().filter_map(|path| modified(path)).any(|time| time >= latest.timestamp))
+    }
+}
+
+/// Timestamps of the snapshots to keep under `retention`
+fn kept(timestamps: &[u64], retention: Retention) -> BTreeSet<u64> {
+    let mut newest_first = timestamps.to_vec();
+    newest_first.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut kept: BTreeSet<u64> = newest_first.iter().take(retention.last).copied().collect();
+    for (period, count) in [(DAY, retention.daily), (7 * DAY, retention.weekly)] {
+        let mut periods = BTreeSet::new();
+        for &timestamp in &newest_first {
+            if periods.len() == count {
+                break;
+            }
+            if periods.insert(timestamp / period) {
+                kept.insert(timestamp);
+            }
+        }
+    }
+    kept
+}
+
+fn copy_files(from: &Path, to: &Path) -> Result<()> {
+    ensure_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        if let (true, Some(name)) = (path.is_file(), path.file_name()) {
+            fs::copy(&path, to.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+fn now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Parses `--at` values: Unix seconds, `YYYY-MM-DD` (end of that day) or
+/// `YYYY-MM-DDTHH:MM[:SS]`, all in UTC
+pub fn parse_time(s: &str) -> Result<u64> {
+    if let Ok(seconds) = s.parse() {
+        return Ok(seconds);
+    }
+    let invalid = || anyhow!("Invalid time '{}'; use YYYY-MM-DD, YYYY-MM-DDTHH:MM[:SS] or Unix seconds", s);
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    let numbers = |text: &str, sep: char| -> Option<Vec<u64>> { text.split(sep).map(|n| n.parse().ok()).collect() };
+    let [year, month, day] = numbers(date, '-').and_then(|n| <[u64; 3]>::try_from(n).ok()).ok_or_else(invalid)?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return Err(invalid());
+    }
+    let seconds = match time {
+        None => DAY - 1,
+        Some(time) => match *numbers(time.trim_end_matches('Z'), ':').ok_or_else(invalid)?.as_slice() {
+            [h, m] if h < 24 && m < 60 => h * 3600 + m * 60,
+            [h, m, sec] if h < 24 && m < 60 && sec < 60 => h * 3600 + m * 60 + sec,
+            _ => return Err(invalid()),
+        },
+    };
+    Ok(days_from_civil(year, month, day) * DAY + seconds)
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ`
+pub fn format_time(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days(timestamp / DAY);
+    let seconds = timestamp % DAY;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's
+/// `days_from_civil`, restricted to dates after the epoch)
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Background thread taking a snapshot every `interval` when the index or
+/// a session log changed since the last one, then pruning old snapshots.
+/// The thread is stopped and joined when the worker is dropped.
+pub struct SnapshotWorker {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SnapshotWorker {
+    pub fn spawn(
+        store: SnapshotStore,
+        index: PathBuf,
+        sessions: Option<PathBuf>,
+        interval: Duration,
+        retention: Retention,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = Arc::clone(&stop);
+
+        let handle = thread::spawn(move || {
+            let mut last_run = Instant::now();
+            while !stop_flag.load(Ordering::Relaxed) {
+                thread::sleep(POLL_INTERVAL);
+                if last_run.elapsed() < interval {
+                    continue;
+                }
+                last_run = Instant::now();
+                let result = store
+                    .changed_since_latest(&index, sessions.as_deref())
+                    .and_then(|changed| match changed {
+                        true => store.take(&index, sessions.as_deref()).map(Some),
+                        false => Ok(None),
+                    })
+                    .and_then(|snapshot| Ok((snapshot, store.prune(retention)?)));
+                match result {
+                    Ok((Some(snapshot), pruned)) => {
+                        tracing::info!(path = ?snapshot.path, pruned, "took index snapshot")
+                    }
+                    Ok((None, _)) => {}
+                    Err(e) => tracing::warn!(error = %e, "index snapshot failed"),
+                }
+            }
+        });
+
+        SnapshotWorker { stop, handle: Some(handle) }
+    }
+}
+
+impl Drop for SnapshotWorker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retention_and_time_parsing() -> Result<()> {
+        let week = 2810 * 7 * DAY;
+        let timestamps = [week, week + DAY, week + 8 * DAY, week + 8 * DAY + 3600, week + 9 * DAY, week + 9 * DAY + 3600];
+        let kept = kept(&timestamps, Retention { last: 1, daily: 2, weekly: 2 });
+        // Newest overall, newest of the day before, newest of the week before
+        assert!(kept.into_iter().eq([week + DAY, week + 8 * DAY + 3600, week + 9 * DAY + 3600]));
+
+        assert_eq!(parse_time("2024-03-01T12:30")?, 1_709_296_200);
+        assert_eq!(format_time(1_709_296_200), "2024-03-01T12:30:00Z");
+        assert_eq!(parse_time("2024-03-01")?, 1_709_337_599);
+        assert_eq!(parse_time("1709296200")?, 1_709_296_200);
+        assert!(parse_time("2024-13-01").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_puts_back_older_index() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let index = dir.path().join("index.bin");
+        let sessions = dir.path().join("sessions");
+        fs::create_dir(&sessions)?;
+        fs::write(&index, "good")?;
+        fs::write(sessions.join("a.jsonl"), "{}")?;
+
+        let store = SnapshotStore::new(dir.path().join("snapshots"));
+        let snapshot = store.take(&index, Some(&sessions))?;
+        fs::write(&index, "corrupted")?;
+        fs::write(sessions.join("b.jsonl"), "{}")?;
+
+        let restored = store.restore(snapshot.timestamp + 10, &index, Some(&sessions))?;
+        assert_eq!(restored, snapshot);
+        assert_eq!(fs::read_to_string(&index)?, "good");
+        assert!(!sessions.join("b.jsonl").exists());
+        assert!(store.restore(snapshot.timestamp - 1, &index, Some(&sessions)).is_err());
+        Ok(())
+    }
+}
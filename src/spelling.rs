@@ -0,0 +1,210 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::retriever::Retriever;
+
+lazy_static! {
+    static ref WORD: Regex = Regex::new(r"\w+").unwrap();
+}
+
+/// Words shorter than this are left alone; there are too many close
+/// neighbours to pick the right one
+const MIN_WORD_CHARS: usize = 4;
+
+/// Characters a word needs per edit it may be corrected by, so a short word
+/// that is merely absent from the corpus isn't rewritten to whatever term
+/// happens to be two edits away
+const CHARS_PER_EDIT: usize = 4;
+
+/// Corrects query words against the corpus vocabulary with the SymSpell
+/// algorithm: every term is indexed under all strings reachable by deleting
+/// up to `max_distance` characters, so candidates for a typo are found by
+/// looking up the typo's own deletes instead of scanning the vocabulary.
+pub struct SpellCorrector {
+    /// Vocabulary with a frequency weight; higher is more common
+    terms: Vec<(String, f32)>,
+    known: FxHashMap<String, usize>,
+    /// Delete variant -> indices into `terms`
+    deletes: FxHashMap<String, Vec<usize>>,
+    max_distance: usize,
+}
+
+impl SpellCorrector {
+    pub fn new(terms: impl IntoIterator<Item = (String, f32)>, max_distance: usize) -> Self {
+        let terms: Vec<(String, f32)> = terms.into_iter().collect();
+        let mut known = FxHashMap::default();
+        let mut deletes: FxHashMap<String, Vec<usize>> = FxHashMap::default();
+        for (i, (term, _)) in terms.iter().enumerate() {
+            known.insert(term.clone(), i);
+            for variant in delete_variants(term, max_distance) {
+                deletes.entry(variant).or_default().push(i);
+            }
+        }
+        SpellCorrector { terms, known, deletes, max_distance }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// The closest vocabulary term to an unknown `word` (lowercase), or
+    /// `None` if the word is known, too short, or has no close match.
+    /// Words are corrected by one edit per `CHARS_PER_EDIT` characters, up
+    /// to `max_distance`. Ties on edit distance go to the more frequent term.
+    pub fn correct_word(&self, word: &str) -> Option<&str> {
+        let chars = word.chars().count();
+        if chars < MIN_WORD_CHARS
+            || word.chars().any(|c| c.is_ascii_digit())
+            || self.known.contains_key(word)
+        {
+            return None;
+        }
+
+        let max_distance = (chars / CHARS_PER_EDIT).min(self.max_distance);
+        let mut best: Option<(usize, f32, &str)> = None;
+        for variant in delete_variants(word, max_distance) {
+            for &i in self.deletes.get(&variant).into_iter().flatten() {
+                let (term, weight) = &self.terms[i];
+                let distance = edit_distance(word, term);
+                if distance > max_distance {
+                    continue;
+                }
+                let better = match best {
+                    None => true,
+                    Some((d, w, _)) => distance < d || (distance == d && *weight > w),
+                };
+                if better {
+                    best = Some((distance, *weight, term));
+                }
+            }
+        }
+        best.map(|(_, _, term)| term)
+    }
+
+    /// Replaces misspelled words in `query`, leaving everything else intact
+    pub fn correct_query(&self, query: &str) -> String {
+        WORD.replace_all(query, |caps: &regex::Captures| {
+            let word = &caps[0];
+            match self.correct_word(&word.to_lowercase()) {
+                Some(term) => term.to_string(),
+                None => word.to_string(),
+            }
+        })
+        .into_owned()
+    }
+}
+
+/// A `SpellCorrector` over the vocabulary of a retriever that is still
+/// being written to, rebuilt on the first query after the index changed so
+/// newly ingested terms aren't corrected away
+pub struct IndexSpellCorrector {
+    retriever: Arc<RwLock<Retriever>>,
+    max_distance: usize,
+    /// Corrector with the term revision it was built at
+    current: Mutex<(u64, Arc<SpellCorrector>)>,
+}
+
+impl IndexSpellCorrector {
+    pub fn new(retriever: Arc<RwLock<Retriever>>, max_distance: usize) -> Self {
+        let current = {
+            let retriever = retriever.read().expect("retriever lock poisoned");
+            (retriever.term_revision(), Arc::new(SpellCorrector::new(retriever.term_weights(), max_distance)))
+        };
+        IndexSpellCorrector { retriever, max_distance, current: Mutex::new(current) }
+    }
+
+    /// The corrector for the index as it is now
+    pub fn corrector(&self) -> Arc<SpellCorrector> {
+        let retriever = self.retriever.read().expect("retriever lock poisoned");
+        let mut current = self.current.lock().expect("spelling lock poisoned");
+        if current.0 != retriever.term_revision() {
+            *current = (retriever.term_revision(), Arc::new(SpellCorrector::new(retriever.term_weights(), self.max_distance)));
+        }
+        Arc::clone(&current.1)
+    }
+
+    /// See `SpellCorrector::correct_query`
+    pub fn correct_query(&self, query: &str) -> String {
+        self.corrector().correct_query(query)
+    }
+}
+
+/// `word` plus every string obtained by deleting up to `max_distance` chars
+fn delete_variants(word: &str, max_distance: usize) -> FxHashSet<String> {
+    let mut variants = FxHashSet::default();
+    variants.insert(word.to_string());
+    let mut frontier = vec![word.to_string()];
+    for _ in 0..max_distance {
+        let mut next = Vec::new();
+        for current in &frontier {
+            let chars: Vec<char> = current.chars().collect();
+            for i in 0..chars.len() {
+                let variant: String = chars[..i].iter().chain(&chars[i + 1..]).collect();
+                if variants.insert(variant.clone()) {
+                    next.push(variant);
+                }
+            }
+        }
+        frontier = next;
+    }
+    variants
+}
+
+/// Damerau-Levenshtein distance (optimal string alignment variant)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corrects_typos_against_vocabulary() {
+        let terms = [("ownership", 1.0), ("borrowing", 1.0), ("lifetimes", 1.0), ("lifetime", 2.0), ("rust", 1.0)];
+        let corrector = SpellCorrector::new(terms.iter().map(|(t, w)| (t.to_string(), *w)), 2);
+
+        assert_eq!(corrector.correct_query("How does ownrship work?"), "How does ownership work?");
+        assert_eq!(corrector.correct_word("borowing"), Some("borrowing"));
+        assert_eq!(corrector.correct_word("lifteime"), Some("lifetime"));
+        // Known, short and unrelated words are left alone
+        assert_eq!(corrector.correct_query("Rust lifetimes in 2024"), "Rust lifetimes in 2024");
+        assert_eq!(corrector.correct_word("zebra"), None);
+        // Short words only get one edit
+        assert_eq!(corrector.correct_word("rusy"), Some("rust"));
+        assert_eq!(corrector.correct_word("ruby"), None);
+    }
+
+    #[test]
+    fn test_index_corrector_follows_index_changes() -> anyhow::Result<()> {
+        let retriever = Arc::new(RwLock::new(Retriever::new()));
+        retriever.write().unwrap().add_to_knowledge_base("Ownership rules of the borrow checker".to_string(), Default::default())?;
+        let corrector = IndexSpellCorrector::new(Arc::clone(&retriever), 2);
+        assert_eq!(corrector.correct_query("ownrship lifetimez"), "ownership lifetimez");
+
+        // A term ingested later is no typo for an older one
+        retriever.write().unwrap().add_to_knowledge_base("Lifetimes annotate references".to_string(), Default::default())?;
+        assert_eq!(corrector.correct_query("ownrship lifetimez"), "ownership lifetimes");
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::i18n;
+
+/// The line the chat REPL shows above each prompt: what the index holds,
+/// which model answers, how much of its context the conversation takes and
+/// how fast the last answer was decoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusLine {
+    /// Documents in the index; `None` while an ingestion is writing to it
+    pub documents: Option<usize>,
+    pub collection: String,
+    pub model: String,
+    /// Tokens of conversation history the next prompt carries
+    pub context_used: usize,
+    pub context_size: usize,
+    /// Decoding speed of the last answer
+    pub tokens_per_second: Option<f32>,
+}
+
+impl StatusLine {
+    /// Collection name shown for an index: its file name without extension,
+    /// or the documents directory when nothing is saved
+    pub fn collection_name(index_path: Option<&Path>, docs_dir: &Path) -> String {
+        let name = match index_path {
+            Some(path) => path.file_stem(),
+            None => docs_dir.file_name(),
+        };
+        name.map_or_else(|| docs_dir.display().to_string(), |name| name.to_string_lossy().into_owned())
+    }
+
+    /// Model name shown for a GGUF path or remote model name
+    pub fn model_name(model: &str) -> String {
+        let name = Path::new(model).file_name().map_or(model.into(), |name| name.to_string_lossy());
+        name.strip_suffix(".gguf").unwrap_or(&name).to_string()
+    }
+
+    /// `tokens` decoded in `decode`, or `None` when too little was timed
+    pub fn speed(tokens: usize, decode: Duration) -> Option<f32> {
+        (tokens > 0 && !decode.is_zero()).then(|| tokens as f32 / decode.as_secs_f32())
+    }
+}
+
+impl fmt::Display for StatusLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let documents = match self.documents {
+            Some(count) => i18n::format("repl-status-documents", &[("count", count.into())]),
+            None => i18n::text("repl-status-indexing"),
+        };
+        let model = if self.model.is_empty() { i18n::text("repl-status-default-model") } else { self.model.clone() };
+        let percent = (self.context_used * 100).checked_div(self.context_size).unwrap_or(0);
+        let context = i18n::format("repl-status-context", &[
+            ("used", self.context_used.into()),
+            ("size", self.context_size.into()),
+            ("percent", percent.into()),
+        ]);
+        let mut parts = vec![documents, self.collection.clone(), model, context];
+        if let Some(speed) = self.tokens_per_second {
+            parts.push(i18n::format("repl-status-speed", &[("speed", format!("{:.1}", speed).into())]));
+        }
+        write!(f, "{}", parts.join(" · "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_status_line_names_and_speed() {
+        let docs = PathBuf::from("/home/me/docs");
+        assert_eq!(StatusLine::collection_name(Some(Path::new("data/handbook.bin")), &docs), "handbook");
+        assert_eq!(StatusLine::collection_name(None, &docs), "docs");
+        assert_eq!(StatusLine::model_name("models/mistral-7b-instruct.Q4_K_M.gguf"), "mistral-7b-instruct.Q4_K_M");
+        assert_eq!(StatusLine::model_name("qwen2.5:7b"), "qwen2.5:7b");
+        assert_eq!(StatusLine::speed(40, Duration::from_secs(2)), Some(20.0));
+        assert_eq!(StatusLine::speed(0, Duration::from_secs(2)), None);
+
+        let mut status = StatusLine {
+            documents: Some(42),
+            collection: "handbook".to_string(),
+            model: "llama3".to_string(),
+            context_used: 512,
+            context_size: 4096,
+            tokens_per_second: Some(21.3),
+        };
+        let text = status.to_string();
+        assert!(text.starts_with("42 ") && text.contains(" · handbook · llama3 · "), "{}", text);
+        assert!(text.contains("512") && text.contains("4096") && text.contains("12%") && text.contains("21.3"), "{}", text);
+        status.documents = None;
+        status.tokens_per_second = None;
+        assert!(!status.to_string().contains("42") && !status.to_string().contains("21.3"));
+    }
+}
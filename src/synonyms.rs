@@ -0,0 +1,92 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::normalize::Token;
+
+/// User-defined synonyms and acronyms such as `k8s = kubernetes`. Both
+/// documents and queries are tokenized through the map, so either spelling
+/// matches the other. Stored in the index, since it shapes its vocabulary.
+#[derive(Debug, Clone, Default, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Synonyms {
+    /// Lowercase token -> the tokens it stands for
+    map: BTreeMap<String, Vec<String>>,
+}
+
+impl Synonyms {
+    /// Parses one `term = replacement` per line; `#` starts a comment
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut map = BTreeMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (term, replacement) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Line {}: expected 'term = replacement'", number + 1))?;
+            let term = words(term);
+            let replacement = words(replacement);
+            match (term.as_slice(), replacement.is_empty()) {
+                ([term], false) => {
+                    map.insert(term.clone(), replacement);
+                }
+                _ => return Err(anyhow!("Line {}: the term must be a single word and the replacement non-empty", number + 1)),
+            }
+        }
+        Ok(Synonyms { map })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|e| anyhow!("{:?}: {}", path, e))
+    }
+
+    /// Terms that get replaced during tokenization
+    pub fn terms(&self) -> impl Iterator<Item = &str> {
+        self.map.keys().map(String::as_str)
+    }
+
+    /// Replaces every mapped token by the tokens it stands for, which all
+    /// keep the span of the original
+    pub(crate) fn expand(&self, tokens: Vec<Token>) -> Vec<Token> {
+        if self.map.is_empty() {
+            return tokens;
+        }
+        tokens
+            .into_iter()
+            .flat_map(|token| match self.map.get(&token.term) {
+                Some(replacement) => replacement
+                    .iter()
+                    .map(|term| Token { term: term.clone(), span: token.span.clone() })
+                    .collect(),
+                None => vec![token],
+            })
+            .collect()
+    }
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_expand() -> Result<()> {
+        let synonyms = Synonyms::parse("# infra\nk8s = kubernetes\nPO = purchase order  # finance\n\n")?;
+        let tokens = ["approve", "po", "on", "k8s"].map(|term| Token { term: term.to_string(), span: 0..0 }).to_vec();
+        let terms: Vec<String> = synonyms.expand(tokens).into_iter().map(|token| token.term).collect();
+        assert_eq!(terms, ["approve", "purchase", "order", "on", "kubernetes"]);
+
+        assert!(Synonyms::parse("k8s kubernetes").is_err());
+        assert!(Synonyms::parse("purchase order = PO").is_err());
+        Ok(())
+    }
+}
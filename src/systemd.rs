@@ -0,0 +1,93 @@
+//! Minimal systemd integration for server mode: socket activation
+//! (`LISTEN_FDS`) and readiness notification (`NOTIFY_SOCKET`).
+
+use anyhow::Result;
+use std::env;
+
+/// First file descriptor passed by systemd (`SD_LISTEN_FDS_START`)
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Number of sockets systemd passed to this process, if they are meant for
+/// us (`LISTEN_PID` matches our pid)
+fn activated_fd_count(listen_pid: Option<String>, listen_fds: Option<String>, pid: u32) -> usize {
+    match (listen_pid, listen_fds) {
+        (Some(listen_pid), Some(listen_fds)) if listen_pid.parse() == Ok(pid) => {
+            listen_fds.parse().unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+/// Takes over the first socket passed by systemd socket activation, or
+/// returns `None` when the process wasn't socket-activated or the socket
+/// was taken already. The environment is left as it is: child processes
+/// have another pid, so `LISTEN_PID` tells them the sockets aren't theirs.
+#[cfg(unix)]
+pub fn take_activated_listener() -> Result<Option<std::net::TcpListener>> {
+    use std::os::fd::FromRawFd;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+
+    let count = activated_fd_count(env::var("LISTEN_PID").ok(), env::var("LISTEN_FDS").ok(), std::process::id());
+    if count == 0 || TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+
+    // SAFETY: systemd guarantees fds LISTEN_FDS_START.. are open sockets
+    // handed to this process, and `TAKEN` makes this the only owner
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(listener))
+}
+
+#[cfg(not(unix))]
+pub fn take_activated_listener() -> Result<Option<std::net::TcpListener>> {
+    Ok(None)
+}
+
+/// Sends a state string such as `READY=1` to the service manager. A no-op
+/// when not running under systemd with `Type=notify`.
+#[cfg(unix)]
+pub fn notify(state: &str) -> Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(socket_path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(());
+    };
+    let socket = UnixDatagram::unbound()?;
+
+    let path = socket_path.to_string_lossy();
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), &*path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_activated_fd_count_checks_pid() {
+        let some = |s: &str| Some(s.to_string());
+        assert_eq!(activated_fd_count(some("42"), some("1"), 42), 1);
+        assert_eq!(activated_fd_count(some("41"), some("1"), 42), 0);
+        assert_eq!(activated_fd_count(None, some("1"), 42), 0);
+        assert_eq!(activated_fd_count(some("42"), some("junk"), 42), 0);
+    }
+}
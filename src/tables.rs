@@ -0,0 +1,169 @@
+use anyhow::{Result, anyhow};
+use calamine::{Reader, open_workbook_auto};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// File extensions loaded as tables instead of plain text
+pub const TABLE_EXTENSIONS: &[&str] = &["csv", "tsv", "xlsx", "xls", "ods"];
+
+/// Where a table chunk came from, kept alongside the document so the
+/// row/column structure survives indexing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TableInfo {
+    /// File name, plus `#sheet` for spreadsheets
+    pub source: String,
+    pub columns: Vec<String>,
+    /// Index of the chunk's first data row in the table (0-based, header excluded)
+    pub first_row: usize,
+    pub row_count: usize,
+}
+
+/// A header row plus data rows read from a CSV file or spreadsheet sheet
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub source: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Consecutive whole rows of a table, rendered as a Markdown table
+#[derive(Debug, Clone)]
+pub struct TableChunk {
+    pub info: TableInfo,
+    pub markdown: String,
+}
+
+impl Table {
+    /// Builds a table from raw records, using the first one as the header.
+    /// Returns `None` for files without any records.
+    pub fn from_records(source: &str, mut records: Vec<Vec<String>>) -> Option<Self> {
+        records.retain(|record| record.iter().any(|cell| !cell.trim().is_empty()));
+        if records.is_empty() {
+            return None;
+        }
+        let columns = records.remove(0);
+        Some(Table { source: source.to_string(), columns, rows: records })
+    }
+
+    /// Splits the table into chunks of at most `max_chars` characters. Rows
+    /// are never split and every chunk repeats the header, so each one is a
+    /// self-contained table; a single oversized row gets a chunk of its own.
+    pub fn chunks(&self, max_chars: usize) -> Vec<TableChunk> {
+        let header = markdown_header(&self.columns);
+        let mut chunks = Vec::new();
+        let mut first_row = 0;
+        let mut body = String::new();
+
+        for (i, row) in self.rows.iter().enumerate() {
+            let line = markdown_row(row, self.columns.len());
+            let too_long = header.len() + body.len() + line.len() > max_chars;
+            if too_long && !body.is_empty() {
+                chunks.push(self.chunk(&header, &body, first_row, i - first_row));
+                body.clear();
+                first_row = i;
+            }
+            body.push_str(&line);
+        }
+        if !body.is_empty() || chunks.is_empty() {
+            chunks.push(self.chunk(&header, &body, first_row, self.rows.len() - first_row));
+        }
+        chunks
+    }
+
+    fn chunk(&self, header: &str, body: &str, first_row: usize, row_count: usize) -> TableChunk {
+        TableChunk {
+            info: TableInfo {
+                source: self.source.clone(),
+                columns: self.columns.clone(),
+                first_row,
+                row_count,
+            },
+            markdown: format!("Table from {}:\n{}{}", self.source, header, body).trim_end().to_string(),
+        }
+    }
+}
+
+/// Reads every table in a CSV/TSV file or spreadsheet (one per sheet)
+pub fn load_tables(path: &Path) -> Result<Vec<Table>> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow!("Not a file: {:?}", path))?;
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+
+    match extension {
+        "csv" | "tsv" => {
+            let delimiter = if extension == "tsv" { b'\t' } else { b',' };
+            let mut reader = csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .has_headers(false)
+                .flexible(true)
+                .from_path(path)?;
+            let records = reader
+                .records()
+                .map(|record| Ok(record?.iter().map(str::to_string).collect()))
+                .collect::<Result<Vec<Vec<String>>>>()?;
+            Ok(Table::from_records(&name, records).into_iter().collect())
+        }
+        _ => {
+            let mut workbook = open_workbook_auto(path)?;
+            let mut tables = Vec::new();
+            for sheet in workbook.sheet_names() {
+                let range = workbook.worksheet_range(&sheet)?;
+                let records = range
+                    .rows()
+                    .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                    .collect();
+                tables.extend(Table::from_records(&format!("{}#{}", name, sheet), records));
+            }
+            Ok(tables)
+        }
+    }
+}
+
+fn markdown_header(columns: &[String]) -> String {
+    let separator = vec!["---".to_string(); columns.len()];
+    format!("{}{}", markdown_row(columns, columns.len()), markdown_row(&separator, columns.len()))
+}
+
+/// One `| a | b |` line, padded or truncated to `width` cells
+fn markdown_row(cells: &[String], width: usize) -> String {
+    let cells: Vec<String> = (0..width)
+        .map(|i| {
+            cells.get(i)
+                .map(|cell| cell.trim().replace('|', "\\|").replace(['\r', '\n'], " "))
+                .unwrap_or_default()
+        })
+        .collect();
+    format!("| {} |\n", cells.join(" | "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter().map(|row| row.iter().map(|cell| cell.to_string()).collect()).collect()
+    }
+
+    #[test]
+    fn test_chunks_keep_rows_whole_and_repeat_header() {
+        let table = Table::from_records("prices.csv", records(&[
+            &["plan", "price"],
+            &["basic", "10"],
+            &["pro", "25 | billed yearly"],
+            &["enterprise", "custom"],
+        ])).unwrap();
+
+        let chunks = table.chunks(70);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert!(chunk.markdown.contains("| plan | price |\n| --- | --- |"));
+        }
+        assert!(chunks.iter().any(|c| c.markdown.contains("| pro | 25 \\| billed yearly |")));
+
+        let rows: usize = chunks.iter().map(|c| c.info.row_count).sum();
+        assert_eq!(rows, 3);
+        assert_eq!(chunks[1].info.first_row, chunks[0].info.row_count);
+    }
+}
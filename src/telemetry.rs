@@ -0,0 +1,49 @@
+use anyhow::Result;
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Installs the global tracing subscriber. Log level comes from `RUST_LOG`
+/// (default `warn`); with the `otel` feature, spans are also exported over
+/// OTLP/HTTP when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+pub fn init() -> Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn"));
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr));
+
+    #[cfg(feature = "otel")]
+    if let Ok(endpoint) = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        let tracer = otlp_tracer(&endpoint)?;
+        registry.with(tracing_opentelemetry::layer().with_tracer(tracer)).try_init()?;
+        return Ok(());
+    }
+
+    registry.try_init()?;
+    Ok(())
+}
+
+/// Flushes spans still buffered by the OTLP exporter
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(feature = "otel")]
+fn otlp_tracer(endpoint: &str) -> Result<opentelemetry_sdk::trace::Tracer> {
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{Resource, trace};
+
+    let service_name = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "tapssp".to_string());
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/'))),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new("service.name", service_name)])),
+        )
+        .install_simple()?;
+    Ok(tracer)
+}
@@ -0,0 +1,176 @@
+use anyhow::{Result, anyhow};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::utils::ensure_dir;
+
+lazy_static! {
+    static ref PLACEHOLDER: Regex = Regex::new(r"\{(\w+)\}").unwrap();
+}
+
+/// How the answer of a saved query is printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Markdown,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(anyhow!("Unknown output format '{}' (expected text, markdown or json)", other)),
+        }
+    }
+
+    pub fn render(&self, title: &str, query: &str, answer: &str, sources: &[String]) -> Result<String> {
+        Ok(match self {
+            OutputFormat::Text => answer.to_string(),
+            OutputFormat::Markdown => format!("## {}\n\n{}\n", title, answer),
+            OutputFormat::Json => serde_json::to_string_pretty(&serde_json::json!({
+                "name": title,
+                "query": query,
+                "answer": answer,
+                "sources": sources,
+            }))?,
+        })
+    }
+}
+
+/// A named, parameterized query stored in the config directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedQuery {
+    pub name: String,
+    /// Prompt with `{variable}` placeholders filled in by `--var key=value`
+    pub template: String,
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    /// Only chunks containing this text (case-insensitive) are retrieved
+    #[serde(default)]
+    pub must_contain: Option<String>,
+    #[serde(default)]
+    pub format: OutputFormat,
+}
+
+fn default_top_k() -> usize {
+    3
+}
+
+impl SavedQuery {
+    pub fn new(name: &str, template: &str) -> Result<Self> {
+        check_name(name)?;
+        Ok(SavedQuery {
+            name: name.to_string(),
+            template: template.to_string(),
+            top_k: default_top_k(),
+            must_contain: None,
+            format: OutputFormat::default(),
+        })
+    }
+
+    /// Substitutes every `{variable}` in the template, failing on missing ones
+    pub fn render(&self, vars: &HashMap<String, String>) -> Result<String> {
+        let missing: Vec<&str> = PLACEHOLDER
+            .captures_iter(&self.template)
+            .map(|cap| cap.get(1).unwrap().as_str())
+            .filter(|name| !vars.contains_key(*name))
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!("Missing template variables: {}", missing.join(", ")));
+        }
+
+        Ok(PLACEHOLDER
+            .replace_all(&self.template, |cap: &regex::Captures| vars[&cap[1]].clone())
+            .into_owned())
+    }
+
+    /// Whether a retrieved chunk passes this query's retrieval filter
+    pub fn accepts(&self, chunk: &str) -> bool {
+        self.must_contain
+            .as_ref()
+            .is_none_or(|needle| chunk.to_lowercase().contains(&needle.to_lowercase()))
+    }
+
+    /// Writes the query to `<dir>/<name>.json`
+    pub fn save(&self, dir: &Path) -> Result<PathBuf> {
+        // `name` is public, so it may have been changed since `new`
+        check_name(&self.name)?;
+        ensure_dir(dir)?;
+        let path = dir.join(format!("{}.json", self.name));
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+
+    pub fn load(dir: &Path, name: &str) -> Result<Self> {
+        check_name(name)?;
+        let path = dir.join(format!("{}.json", name));
+        let content = fs::read_to_string(&path)
+            .map_err(|_| anyhow!("No saved query named '{}' (looked in {:?})", name, path))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+}
+
+/// Names become file names in the queries directory, so anything that
+/// could leave it, such as `/`, `\` or `..`, is rejected
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow!("Query name may only contain letters, digits, '-' and '_'"));
+    }
+    Ok(())
+}
+
+/// Parses `key=value` pairs given with `--var`
+pub fn parse_vars<'a>(pairs: impl IntoIterator<Item = &'a str>) -> Result<HashMap<String, String>> {
+    pairs
+        .into_iter()
+        .map(|pair| {
+            pair.split_once('=')
+                .map(|(k, v)| (k.trim().to_string(), v.to_string()))
+                .ok_or_else(|| anyhow!("Expected key=value, got '{}'", pair))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_substitutes_variables() -> Result<()> {
+        let query = SavedQuery::new("release-notes", "Summarize changes in {version} for {audience}")?;
+        let vars = parse_vars(["version=2.3", "audience=users"])?;
+        assert_eq!(query.render(&vars)?, "Summarize changes in 2.3 for users");
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_reports_missing_variables() -> Result<()> {
+        let query = SavedQuery::new("notes", "What changed in {version}?")?;
+        assert!(query.render(&HashMap::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_names_cannot_leave_the_queries_directory() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let queries = dir.path().join("queries");
+        for name in ["../escape", "a/b", "a\\b", ".."] {
+            assert!(SavedQuery::new(name, "?").is_err());
+            assert!(SavedQuery::load(&queries, name).is_err());
+        }
+        let mut query = SavedQuery::new("notes", "What changed?")?;
+        query.name = "../escape".to_string();
+        assert!(query.save(&queries).is_err());
+        assert!(!dir.path().join("escape.json").exists());
+        Ok(())
+    }
+}
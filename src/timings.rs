@@ -0,0 +1,78 @@
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Wall-clock time spent in each pipeline stage of a single query.
+/// Stages appear in the order they ran; stages that didn't run are absent.
+#[derive(Debug, Default, Clone)]
+pub struct Timings {
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `duration` to `stage`, creating it on first use
+    pub fn record(&mut self, stage: &'static str, duration: Duration) {
+        match self.stages.iter_mut().find(|(name, _)| *name == stage) {
+            Some((_, total)) => *total += duration,
+            None => self.stages.push((stage, duration)),
+        }
+    }
+
+    /// Runs `f` and records how long it took under `stage`
+    pub fn time<T>(&mut self, stage: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(stage, start.elapsed());
+        result
+    }
+
+    /// Time recorded under `stage`, if it ran
+    pub fn stage(&self, stage: &str) -> Option<Duration> {
+        self.stages.iter().find(|(name, _)| *name == stage).map(|(_, duration)| *duration)
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, d)| *d).sum()
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, duration) in &self.stages {
+            write!(f, "{} {:.1}ms | ", name, duration.as_secs_f64() * 1000.0)?;
+        }
+        write!(f, "total {:.1}ms", self.total().as_secs_f64() * 1000.0)
+    }
+}
+
+/// Stage name -> milliseconds, in the order the stages ran
+impl Serialize for Timings {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.stages.len()))?;
+        for (name, duration) in &self.stages {
+            map.serialize_entry(name, &(duration.as_secs_f64() * 1000.0))?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_accumulates_per_stage() {
+        let mut timings = Timings::new();
+        timings.record("retrieval", Duration::from_millis(2));
+        timings.record("decode", Duration::from_millis(5));
+        timings.record("retrieval", Duration::from_millis(3));
+
+        assert_eq!(timings.total(), Duration::from_millis(10));
+        assert_eq!(timings.to_string(), "retrieval 5.0ms | decode 5.0ms | total 10.0ms");
+        assert_eq!(serde_json::to_string(&timings).unwrap(), r#"{"retrieval":5.0,"decode":5.0}"#);
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::retriever::Retriever;
+use crate::sessions::SessionEntry;
+
+/// How far down the ranking to look for hard negatives
+const SEARCH_DEPTH: usize = 20;
+
+/// A training example in the sentence-transformers (anchor, positive,
+/// negative) triplet format
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Triple {
+    pub anchor: String,
+    pub positive: String,
+    pub negative: String,
+}
+
+/// Builds triples from answers the user marked helpful. The top chunk that
+/// backed the answer is the positive; hard negatives are the best-ranked
+/// chunks the answer didn't use, i.e. ones the current index confuses with
+/// the relevant one. Unrated and unhelpful answers are skipped, since their
+/// context isn't known to be relevant.
+pub fn mine_hard_negatives(retriever: &Retriever, entries: &[SessionEntry], per_query: usize) -> Vec<Triple> {
+    let mut triples = Vec::new();
+    for entry in entries {
+        let Some(positive) = entry.context.first() else { continue };
+        if entry.helpful != Some(true) {
+            continue;
+        }
+
+        let negatives = retriever
+            .retrieve(&entry.query, entry.context.len() + SEARCH_DEPTH)
+            .into_iter()
+            .filter(|chunk| !entry.context.contains(chunk))
+            .take(per_query);
+        triples.extend(negatives.map(|negative| Triple {
+            anchor: entry.query.clone(),
+            positive: positive.clone(),
+            negative,
+        }));
+    }
+    triples
+}
+
+/// Writes one JSON object per line
+pub fn write_triples(path: &Path, triples: &[Triple]) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    for triple in triples {
+        writeln!(out, "{}", serde_json::to_string(triple)?)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(query: &str, context: &[&str], helpful: Option<bool>) -> SessionEntry {
+        SessionEntry {
+            query: query.to_string(),
+            answer: String::new(),
+            context: context.iter().map(|c| c.to_string()).collect(),
+            timestamp: 0,
+            helpful,
+        }
+    }
+
+    #[test]
+    fn test_negatives_exclude_answer_context() -> Result<()> {
+        let mut retriever = Retriever::new();
+        for doc in [
+            "Refunds are issued within 30 days of purchase",
+            "Refunds for annual plans are prorated",
+            "Purchase orders require manager approval",
+            "The office is closed on public holidays",
+        ] {
+            retriever.add_to_knowledge_base(doc.to_string(), Default::default())?;
+        }
+        retriever.rebuild();
+
+        let entries = [
+            entry("How long do refunds take?", &["Refunds are issued within 30 days of purchase"], Some(true)),
+            entry("When is the office closed?", &["The office is closed on public holidays"], None),
+        ];
+        let triples = mine_hard_negatives(&retriever, &entries, 2);
+
+        assert_eq!(triples.len(), 2);
+        assert!(triples.iter().all(|t| t.positive.starts_with("Refunds are issued")));
+        assert_eq!(triples[0].negative, "Refunds for annual plans are prorated");
+        Ok(())
+    }
+}
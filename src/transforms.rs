@@ -0,0 +1,163 @@
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Clean-up applied to files as they are loaded, before chunking, set per
+/// group of sources in a TOML file:
+///
+/// ```toml
+/// [[source]]
+/// path = "\\.md$"                 # regex on the file path
+/// strip_front_matter = true       # drop a leading `---`/`+++` block
+/// drop_lines = ["^Copyright", "^\\s*<!--.*-->\\s*$"]
+/// metadata_from_filename = "^(?P<team>[a-z]+)_(?P<date>\\d{4}-\\d{2}-\\d{2})"
+/// ```
+///
+/// Every `[[source]]` whose `path` matches applies, in file order. Named
+/// groups of `metadata_from_filename` become metadata fields of the file's
+/// chunks. Table files only get the metadata; their text is parsed as is.
+#[derive(Debug, Default)]
+pub struct Transforms {
+    sources: Vec<SourceTransform>,
+}
+
+#[derive(Debug)]
+struct SourceTransform {
+    path: Regex,
+    strip_front_matter: bool,
+    drop_lines: Vec<Regex>,
+    metadata_from_filename: Option<Regex>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransformsFile {
+    #[serde(rename = "source", default)]
+    sources: Vec<SourceEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SourceEntry {
+    path: String,
+    #[serde(default)]
+    strip_front_matter: bool,
+    #[serde(default)]
+    drop_lines: Vec<String>,
+    metadata_from_filename: Option<String>,
+}
+
+impl Transforms {
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|e| anyhow!("Invalid transforms file {:?}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: TransformsFile = toml::from_str(text)?;
+        let regex = |pattern: &str| Regex::new(pattern).map_err(|e| anyhow!("bad pattern '{}': {}", pattern, e));
+        let mut sources = Vec::new();
+        for entry in file.sources {
+            sources.push(SourceTransform {
+                path: regex(&entry.path)?,
+                strip_front_matter: entry.strip_front_matter,
+                drop_lines: entry.drop_lines.iter().map(|pattern| regex(pattern)).collect::<Result<_>>()?,
+                metadata_from_filename: entry.metadata_from_filename.as_deref().map(regex).transpose()?,
+            });
+        }
+        Ok(Transforms { sources })
+    }
+
+    fn matching(&self, path: &Path) -> impl Iterator<Item = &SourceTransform> {
+        let path = path.to_string_lossy().into_owned();
+        self.sources.iter().filter(move |source| source.path.is_match(&path))
+    }
+
+    /// `text` of the file at `path` with the matching transforms applied
+    pub fn text(&self, path: &Path, mut text: String) -> String {
+        for source in self.matching(path) {
+            if source.strip_front_matter {
+                text = strip_front_matter(&text).to_string();
+            }
+            if !source.drop_lines.is_empty() {
+                text = text
+                    .lines()
+                    .filter(|line| !source.drop_lines.iter().any(|pattern| pattern.is_match(line)))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+            }
+        }
+        text
+    }
+
+    /// Adds the fields captured from the file name of `path` to `metadata`
+    pub fn add_metadata(&self, path: &Path, metadata: &mut BTreeMap<String, String>) {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        for pattern in self.matching(path).filter_map(|source| source.metadata_from_filename.as_ref()) {
+            let Some(captures) = pattern.captures(&name) else {
+                continue;
+            };
+            for field in pattern.capture_names().flatten() {
+                if let Some(value) = captures.name(field) {
+                    metadata.insert(field.to_string(), value.as_str().to_string());
+                }
+            }
+        }
+    }
+}
+
+/// `text` without a leading YAML (`---`) or TOML (`+++`) front-matter block
+fn strip_front_matter(text: &str) -> &str {
+    let fence = match text.lines().next().map(str::trim_end) {
+        Some(fence @ ("---" | "+++")) => fence,
+        _ => return text,
+    };
+    let mut offset = text.find('\n').map_or(text.len(), |end| end + 1);
+    while offset < text.len() {
+        let end = text[offset..].find('\n').map_or(text.len(), |end| offset + end + 1);
+        let line = text[offset..end].trim_end();
+        if line == fence || (fence == "---" && line == "...") {
+            return text[end..].trim_start_matches('\n');
+        }
+        offset = end;
+    }
+    // Unterminated: not front matter after all
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transforms_apply_to_matching_sources() -> Result<()> {
+        let transforms = Transforms::parse(
+            r#"
+            [[source]]
+            path = "\\.md$"
+            strip_front_matter = true
+            drop_lines = ["^Copyright"]
+
+            [[source]]
+            path = "reports/"
+            metadata_from_filename = "^(?P<team>[a-z]+)_(?P<year>\\d{4})"
+            "#,
+        )?;
+        let markdown = "---\ntitle: Refunds\n---\n\n# Refunds\nThirty days.\nCopyright ACME\n".to_string();
+        assert_eq!(transforms.text(Path::new("docs/refunds.md"), markdown.clone()), "# Refunds\nThirty days.");
+        assert_eq!(transforms.text(Path::new("docs/refunds.txt"), markdown.clone()), markdown);
+        assert_eq!(strip_front_matter("---\nnot closed\n"), "---\nnot closed\n");
+
+        let mut metadata = BTreeMap::new();
+        transforms.add_metadata(Path::new("reports/sales_2024_q1.csv"), &mut metadata);
+        assert_eq!(metadata.get("team").map(String::as_str), Some("sales"));
+        assert_eq!(metadata.get("year").map(String::as_str), Some("2024"));
+        transforms.add_metadata(Path::new("docs/sales_2025.txt"), &mut metadata);
+        assert_eq!(metadata.get("year").map(String::as_str), Some("2024"));
+
+        assert!(Transforms::parse("[[source]]\npath = \"(\"").is_err());
+        Ok(())
+    }
+}
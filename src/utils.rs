@@ -1,6 +1,8 @@
-use std::fs::{self, DirBuilder};
+use std::fs::{DirBuilder, File};
+use std::io::Read;
 use std::path::Path;
 use anyhow::Result;
+use sha2::{Digest, Sha256};
 
 /// Creates a directory if it doesn't exist
 pub fn ensure_dir(path: impl AsRef<Path>) -> Result<()> {
@@ -10,76 +12,183 @@ pub fn ensure_dir(path: impl AsRef<Path>) -> Result<()> {
     Ok(())
 }
 
-/// Splits text into chunks of approximately max_chars length at sentence boundaries
+/// Hex-encoded SHA256 of a file, read in blocks so large files (models,
+/// indexes) are never held in memory
+pub fn sha256_file(path: impl AsRef<Path>) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Splits text into chunks of approximately max_chars length at sentence
+/// boundaries. Fenced code blocks and LaTeX display blocks are kept whole and
+/// byte-for-byte intact, even if that makes a chunk exceed max_chars.
 pub fn split_into_chunks(text: &str, max_chars: usize) -> Vec<String> {
-    let mut chunks = Vec::new();
-    let mut current_chunk = String::new();
-    let mut current_length = 0;
+    let mut packer = SentencePacker { max_chars, chunks: Vec::new(), current: String::new(), length: 0 };
+
+    for block in split_blocks(text) {
+        match block {
+            Block::Verbatim(block) => packer.push_verbatim(block),
+            // Simple sentence splitting on .!?
+            Block::Prose(prose) => {
+                for sentence in prose.split(['.', '!', '?']) {
+                    packer.push_sentence(sentence.trim());
+                }
+            }
+        }
+    }
+
+    packer.finish()
+}
+
+/// Packs sentences and verbatim blocks into chunks of at most `max_chars`
+struct SentencePacker {
+    max_chars: usize,
+    chunks: Vec<String>,
+    current: String,
+    length: usize,
+}
 
-    // Simple sentence splitting on .!?
-    for sentence in text.split(|c| c == '.' || c == '!' || c == '?') {
-        let sentence = sentence.trim();
+impl SentencePacker {
+    fn push_sentence(&mut self, sentence: &str) {
         if sentence.is_empty() {
-            continue;
+            return;
         }
 
         let sentence_len = sentence.chars().count();
-        if current_length + sentence_len + 2 > max_chars && !current_chunk.is_empty() {
-            chunks.push(current_chunk.trim().to_string());
-            current_chunk.clear();
-            current_length = 0;
+        self.make_room(sentence_len + 2);
+
+        if !self.current.is_empty() {
+            self.current.push(' ');
+            self.length += 1;
         }
+        self.current.push_str(sentence);
+        self.current.push('.');
+        self.length += sentence_len + 1;
+    }
+
+    /// Adds a code/LaTeX block on its own lines without touching its content
+    fn push_verbatim(&mut self, block: &str) {
+        let block_len = block.chars().count();
+        self.make_room(block_len + 2);
 
-        if !current_chunk.is_empty() {
-            current_chunk.push(' ');
-            current_length += 1;
+        if !self.current.is_empty() {
+            self.current.push('\n');
+            self.length += 1;
         }
-        current_chunk.push_str(sentence);
-        current_chunk.push('.');
-        current_length += sentence_len + 1;
+        self.current.push_str(block);
+        self.current.push('\n');
+        self.length += block_len + 1;
     }
 
-    if !current_chunk.is_empty() {
-        chunks.push(current_chunk.trim().to_string());
+    /// Starts a new chunk if `len` more characters wouldn't fit
+    fn make_room(&mut self, len: usize) {
+        if self.length + len > self.max_chars && !self.current.is_empty() {
+            self.chunks.push(self.current.trim().to_string());
+            self.current.clear();
+            self.length = 0;
+        }
     }
 
-    chunks
+    fn finish(mut self) -> Vec<String> {
+        if !self.current.is_empty() {
+            self.chunks.push(self.current.trim().to_string());
+        }
+        self.chunks
+    }
 }
 
-/// Loads all text files from a directory recursively
-pub fn load_text_files(dir_path: impl AsRef<Path>) -> Result<Vec<String>> {
-    let mut texts = Vec::new();
-    
-    if !dir_path.as_ref().exists() {
-        ensure_dir(&dir_path)?;
-        return Ok(texts);
-    }
+/// Whether `text` contains a fenced code or LaTeX display block
+pub fn contains_verbatim_block(text: &str) -> bool {
+    split_blocks(text).iter().any(|block| matches!(block, Block::Verbatim(_)))
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Block<'a> {
+    Prose(&'a str),
+    /// Fenced code (``` or ~~~) or a LaTeX display block ($$ ... $$ or
+    /// \begin{..} ... \end{..}), including its delimiters
+    Verbatim(&'a str),
+}
+
+/// Separates verbatim blocks from the prose around them. An unterminated
+/// block runs to the end of the text.
+pub(crate) fn split_blocks(text: &str) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut prose_start = 0;
+    // Start offset of the open block and the line prefix that closes it
+    let mut open: Option<(usize, &str)> = None;
+    let mut offset = 0;
 
-    for entry in fs::read_dir(dir_path)? {
-        let entry = entry?;
-        let path = entry.path();
-        
-        if path.is_file() {
-            if let Some(ext) = path.extension() {
-                if ext == "txt" {
-                    let content = fs::read_to_string(path)?;
-                    texts.push(content);
+    for line in text.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let trimmed = line.trim();
+
+        match open {
+            Some((start, closer)) => {
+                let closes = match closer {
+                    "\\end{" => trimmed.contains(closer),
+                    _ => trimmed.starts_with(closer),
+                };
+                if closes {
+                    blocks.push(Block::Verbatim(text[start..offset].trim_end_matches(['\r', '\n'])));
+                    prose_start = offset;
+                    open = None;
+                }
+            }
+            None => {
+                let closer = if trimmed.starts_with("```") {
+                    Some("```")
+                } else if trimmed.starts_with("~~~") {
+                    Some("~~~")
+                } else if trimmed.starts_with("$$") {
+                    Some("$$")
+                } else if trimmed.starts_with("\\begin{") {
+                    Some("\\end{")
+                } else {
+                    None
+                };
+                let Some(closer) = closer else { continue };
+
+                if line_start > prose_start {
+                    blocks.push(Block::Prose(&text[prose_start..line_start]));
+                }
+                // `$$ x $$` or `\begin{..} .. \end{..}` on a single line
+                let single_line = match closer {
+                    "$$" => trimmed.len() > 2 && trimmed.ends_with("$$"),
+                    "\\end{" => trimmed.contains(closer),
+                    _ => false,
+                };
+                if single_line {
+                    blocks.push(Block::Verbatim(line.trim_end_matches(['\r', '\n'])));
+                    prose_start = offset;
+                } else {
+                    open = Some((line_start, closer));
                 }
             }
-        } else if path.is_dir() {
-            texts.extend(load_text_files(path)?);
         }
     }
 
-    Ok(texts)
+    match open {
+        Some((start, _)) => blocks.push(Block::Verbatim(text[start..].trim_end_matches(['\r', '\n']))),
+        None if prose_start < text.len() => blocks.push(Block::Prose(&text[prose_start..])),
+        None => {}
+    }
+    blocks
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tempfile::tempdir;
-    use std::fs::File;
-    use std::io::Write;
 
     #[test]
     fn test_split_into_chunks() {
@@ -90,16 +199,14 @@ mod tests {
     }
 
     #[test]
-    fn test_load_text_files() -> Result<()> {
-        let dir = tempdir()?;
-        let file_path = dir.path().join("test.txt");
-        let mut file = File::create(file_path)?;
-        writeln!(file, "Test content")?;
-
-        let texts = load_text_files(dir.path())?;
-        assert_eq!(texts.len(), 1);
-        assert_eq!(texts[0].trim(), "Test content");
+    fn test_split_into_chunks_keeps_code_blocks_verbatim() {
+        let code = "```python\ndef area(r):\n    return 3.14 * r ** 2  # pi.r^2!\n```";
+        let latex = "$$\nE = mc^2. \\quad ?\n$$";
+        let text = format!("First sentence. Second one!\n{}\nMore prose here.\n{}\n", code, latex);
 
-        Ok(())
+        let chunks = split_into_chunks(&text, 40);
+        assert!(chunks.iter().any(|chunk| chunk.contains(code)));
+        assert!(chunks.iter().any(|chunk| chunk.contains(latex)));
+        assert!(chunks.iter().any(|chunk| chunk.contains("More prose here.")));
     }
 }
\ No newline at end of file
@@ -1,153 +1,2389 @@
-use anyhow::Result;
-use ndarray::Array1;
-use regex::Regex;
+use anyhow::{Result, anyhow};
+use rayon::prelude::*;
+use ndarray::{Array1, s};
 use rustc_hash::{FxHashMap, FxHashSet};
-use std::collections::HashMap;
-use unicode_normalization::UnicodeNormalization;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
 use lazy_static::lazy_static;
 
-#[derive(Debug, Clone)]
+use crate::collection::CollectionSettings;
+use crate::embeddings::{Embedder, TFIDF_MODEL_ID};
+use crate::metadata;
+use crate::normalize::{self, Token};
+use crate::synonyms::Synonyms;
+use crate::tables::TableInfo;
+use crate::timings::Timings;
+
+mod bm25;
+mod hnsw;
+
+use bm25::Bm25;
+use hnsw::Hnsw;
+pub use hnsw::HnswParams;
+
+/// Magic bytes and format version at the start of a saved index file.
+/// Version 2 added table metadata to documents, version 3 the embedding
+/// model ID, version 4 per-document embedding variants, version 5 the
+/// synonym map, version 6 changed tokenization to canonicalize dates and
+/// numbers, version 7 added collection settings, version 8 the chunking
+/// strategy, version 9 document metadata, version 10 the HNSW graph.
+const INDEX_MAGIC: &[u8; 8] = b"TAPSSPIX";
+const INDEX_FORMAT_VERSION: u32 = 10;
+/// Last version without the HNSW graph, still read
+const INDEX_FORMAT_VERSION_WITHOUT_GRAPH: u32 = 9;
+/// Same for index delta files
+const DELTA_MAGIC: &[u8; 8] = b"TAPSSPDX";
+const DELTA_FORMAT_VERSION: u32 = 11;
+/// Same for ingestion write-ahead log segments
+const SEGMENT_MAGIC: &[u8; 8] = b"TAPSSPWL";
+const SEGMENT_FORMAT_VERSION: u32 = 9;
+/// `format` and version in the header line of JSONL exports
+const EXPORT_FORMAT: &str = "tapssp-export";
+const EXPORT_FORMAT_VERSION: u32 = 1;
+/// Rank offset in reciprocal rank fusion; damps the weight of top ranks
+const RRF_K: f32 = 60.0;
+/// Below this many documents searches scan every embedding, which is exact
+/// and fast enough; from here on an HNSW graph is kept for the primary space
+const ANN_MIN_DOCUMENTS: usize = 1000;
+
+lazy_static! {
+    /// Dropped from documents and queries with the `stop_words` setting
+    static ref STOP_WORDS: FxHashSet<&'static str> = {
+        let words = vec![
+            "a", "an", "and", "are", "as", "at", "be", "by", "for", "from",
+            "has", "he", "in", "is", "it", "its", "of", "on", "that", "the",
+            "to", "was", "were", "will", "with"
+        ];
+        words.into_iter().collect()
+    };
+}
+
+/// On-disk payload following the header: documents, vocabulary, IDF table,
+/// embedding model ID, synonym map, collection settings, HNSW graph
+type IndexSnapshot =
+    (Vec<Document>, FxHashMap<String, usize>, FxHashMap<String, f32>, String, Synonyms, CollectionSettings, Option<Hnsw>);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Document {
     pub id: String,
+    /// Text exactly as ingested, for display; matching runs on the
+    /// normalized terms from `VectorDB::tokenize_with_spans`
     pub content: String,
     pub embedding: Array1<f32>,
+    /// Set for chunks of CSV/spreadsheet tables; `content` is then a
+    /// Markdown table
+    pub table: Option<TableInfo>,
+    /// Embeddings from additional models, keyed by model ID, so an index
+    /// can move to a new model without being re-ingested
+    pub variants: BTreeMap<String, Array1<f32>>,
+    /// Where the document came from (`source`, `file_type`, `modified`,
+    /// ...; see `metadata::of_file`) or fields carried over from an import,
+    /// which `metadata::Filter` searches can be restricted by
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The tables and embeddings of a `VectorDB::rebuild()`, computed from a
+/// shared reference by `prepare_rebuild` and swapped in by `finish_rebuild`
+pub(crate) struct Rebuild {
+    /// `term_revision` of the index it was computed from
+    term_revision: u64,
+    vocabulary: FxHashMap<String, usize>,
+    doc_freqs: FxHashMap<String, usize>,
+    idf_values: FxHashMap<String, f32>,
+    /// The new embeddings, as documents carrying nothing else
+    embedded: HashMap<String, Document>,
+    ann: Option<Hnsw>,
+}
+
+/// A document of `VectorDB::add_documents` as prepared in parallel
+enum Embedded {
+    Dense(Array1<f32>),
+    /// TF-IDF terms, embedded once the batch's IDF values are known
+    Terms(Vec<String>),
+}
+
+/// Which embedding space queries are ranked in
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum SearchMode {
+    /// The index's own model (`VectorDB::model_id`)
+    #[default]
+    Primary,
+    /// A registered embedding variant, by model ID
+    Variant(String),
+    /// Reciprocal rank fusion of the primary ranking and every variant
+    Fused,
+}
+
+impl SearchMode {
+    /// `primary`, `fused`, or the model ID of a variant
+    pub fn parse(s: &str) -> Self {
+        match s {
+            "primary" => SearchMode::Primary,
+            "fused" | "fuse" => SearchMode::Fused,
+            id => SearchMode::Variant(id.to_string()),
+        }
+    }
 }
 
 pub struct VectorDB {
     documents: HashMap<String, Document>,
-    vocabulary: FxHashSet<String>,
+    /// Term -> embedding dimension. Indices are append-only, so an older
+    /// embedding is a valid (shorter) prefix of the current vector space.
+    vocabulary: FxHashMap<String, usize>,
     idf_values: FxHashMap<String, f32>,
+    /// Bumped whenever `idf_values` changes, see `term_revision`
+    term_revision: u64,
+    /// Number of documents containing each term, kept up to date on insert
+    /// and delta application so IDF updates needn't re-tokenize every
+    /// document. Not persisted: counted on the first insert after a load,
+    /// so processes that only query never pay for it.
+    doc_freqs: Option<FxHashMap<String, usize>>,
+    /// Set when existing embeddings were computed against an older
+    /// vocabulary or IDF table and would benefit from `rebuild()`
+    stale: bool,
+    /// Opened with `open_read_only`; all mutations are rejected
+    read_only: bool,
+    /// Model the stored embeddings come from, `TFIDF_MODEL_ID` by default
+    model_id: String,
+    /// Dense embedding model replacing TF-IDF, see `use_embedding_model`
+    embedder: Option<Arc<dyn Embedder>>,
+    /// Models whose embeddings are stored alongside the primary one
+    variant_embedders: Vec<Arc<dyn Embedder>>,
+    search_mode: SearchMode,
+    synonyms: Synonyms,
+    settings: CollectionSettings,
+    /// Approximate nearest-neighbour graph over primary embeddings, built
+    /// in memory once the index reaches `ANN_MIN_DOCUMENTS`
+    ann: Option<Hnsw>,
+    ann_params: HnswParams,
+    /// Derive new document IDs from their content, see `use_deterministic_ids`
+    deterministic_ids: bool,
+    /// Document ID by `dedup_key` in an earlier build, see `reuse_ids_from`
+    previous_ids: HashMap<String, String>,
+    /// BM25 index for keyword search, built in memory once
+    /// `enable_keyword_index` is called
+    keywords: Option<Bm25>,
+    /// Document ID by `dedup_key`, so re-ingesting a file doesn't add its
+    /// chunks again. Not persisted: built on the first insert, like
+    /// `doc_freqs`.
+    dedup_index: Option<HashMap<String, String>>,
+    /// Inserts since the last `take_ingest_report`
+    ingest_report: IngestReport,
+}
+
+/// How the documents inserted into an index were handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestReport {
+    /// Not indexed before
+    pub added: usize,
+    /// Already indexed from the same source, with metadata (such as the
+    /// file's `modified` time) that was replaced
+    pub updated: usize,
+    /// Already indexed from the same source as they are
+    pub skipped: usize,
+    /// Dropped because their re-ingested file no longer has them
+    pub removed: usize,
+}
+
+impl fmt::Display for IngestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} new, {} updated, {} unchanged", self.added, self.updated, self.skipped)?;
+        if self.removed > 0 {
+            write!(f, ", {} removed", self.removed)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for VectorDB {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VectorDB {
     pub fn new() -> Self {
         VectorDB {
             documents: HashMap::new(),
-            vocabulary: FxHashSet::default(),
+            vocabulary: FxHashMap::default(),
             idf_values: FxHashMap::default(),
+            term_revision: 0,
+            doc_freqs: Some(FxHashMap::default()),
+            stale: false,
+            read_only: false,
+            model_id: TFIDF_MODEL_ID.to_string(),
+            embedder: None,
+            variant_embedders: Vec::new(),
+            search_mode: SearchMode::Primary,
+            synonyms: Synonyms::default(),
+            settings: CollectionSettings::default(),
+            ann: None,
+            ann_params: HnswParams::default(),
+            deterministic_ids: false,
+            previous_ids: HashMap::new(),
+            keywords: None,
+            dedup_index: None,
+            ingest_report: IngestReport::default(),
         }
     }
 
-    pub fn add_document(&mut self, content: String) -> Result<()> {
-        let id = uuid::Uuid::new_v4().to_string();
-        let tokens = self.tokenize(&content);
-        
-        // Update vocabulary and document frequencies
-        for token in &tokens {
-            self.vocabulary.insert(token.clone());
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.documents.contains_key(id)
+    }
+
+    pub fn document(&self, id: &str) -> Option<&Document> {
+        self.documents.get(id)
+    }
+
+    /// All documents, in no particular order
+    pub fn documents(&self) -> impl Iterator<Item = &Document> {
+        self.documents.values()
+    }
+
+    /// Writes the index to `path`. The data goes to a temporary file that is
+    /// renamed over the target, so readers never see a half-written index
+    /// and a process in `open_read_only` keeps reading the file it opened.
+    /// Documents are written in ID order, so saving the same index twice
+    /// gives the same bytes. The HNSW graph is saved too, so loading a large
+    /// index doesn't build it again.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut documents: Vec<&Document> = self.documents.values().collect();
+        documents.sort_by(|a, b| a.id.cmp(&b.id));
+        let payload =
+            (documents, &self.vocabulary, &self.idf_values, &self.model_id, &self.synonyms, &self.settings, &self.ann);
+        write_with_header(path.as_ref(), INDEX_MAGIC, INDEX_FORMAT_VERSION, &payload)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// Opens a saved index for processes that only query. Any number of
+    /// processes can do this on the same file at once; each reads the whole
+    /// file into its own memory under a shared lock, so pages aren't shared
+    /// between them. The returned index rejects `add_document`.
+    pub fn open_read_only(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path)?;
+        file.lock_shared()?;
+        let mut bytes = Vec::with_capacity(file.metadata()?.len() as usize);
+        (&file).read_to_end(&mut bytes)?;
+        file.unlock()?;
+        let mut db = Self::from_bytes(&bytes)?;
+
+        db.read_only = true;
+        Ok(db)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (documents, vocabulary, idf_values, model_id, synonyms, settings, ann): IndexSnapshot =
+            match strip_header(bytes, INDEX_MAGIC, INDEX_FORMAT_VERSION, "index") {
+                Ok(payload) => bincode::deserialize(payload)?,
+                Err(_) => {
+                    let payload = strip_header(bytes, INDEX_MAGIC, INDEX_FORMAT_VERSION_WITHOUT_GRAPH, "index")?;
+                    let (documents, vocabulary, idf_values, model_id, synonyms, settings) = bincode::deserialize(payload)?;
+                    (documents, vocabulary, idf_values, model_id, synonyms, settings, None)
+                }
+            };
+        let mut db = VectorDB {
+            documents: documents.into_iter().map(|doc| (doc.id.clone(), doc)).collect(),
+            vocabulary,
+            idf_values,
+            term_revision: 0,
+            doc_freqs: None,
+            stale: false,
+            read_only: false,
+            model_id,
+            embedder: None,
+            variant_embedders: Vec::new(),
+            search_mode: SearchMode::Primary,
+            synonyms,
+            settings,
+            ann_params: ann.as_ref().map_or_else(HnswParams::default, Hnsw::params),
+            ann,
+            deterministic_ids: false,
+            previous_ids: HashMap::new(),
+            keywords: None,
+            dedup_index: None,
+            ingest_report: IngestReport::default(),
+        };
+        // A graph saved by this version over exactly these documents is kept
+        if !db.ann.as_ref().is_some_and(|ann| ann.covers(&db.documents)) {
+            db.rebuild_ann();
         }
-        
-        // Calculate TF-IDF embedding
-        let embedding = self.calculate_tfidf(&tokens);
-        
-        let document = Document {
-            id: id.clone(),
-            content,
-            embedding,
+        Ok(db)
+    }
+
+    /// Sets the HNSW parameters and rebuilds the graph with them
+    pub fn set_ann_params(&mut self, params: HnswParams) {
+        if params != self.ann_params {
+            self.ann_params = params;
+            self.rebuild_ann();
+        }
+    }
+
+    /// Whether searches in the primary space use the HNSW graph
+    pub fn uses_ann(&self) -> bool {
+        self.ann.is_some()
+    }
+
+    /// Brings the HNSW graph up to date after the documents `added` were
+    /// inserted. New documents are linked into the existing graph; it's only
+    /// rebuilt when others were `replaced` or removed, or it didn't exist.
+    fn update_ann(&mut self, added: Vec<String>, replaced: bool) {
+        match &mut self.ann {
+            Some(ann) if !replaced => {
+                for id in added {
+                    ann.insert(id, &self.documents);
+                }
+            }
+            _ => self.rebuild_ann(),
+        }
+    }
+
+    /// Recreates the HNSW graph from the current embeddings, or drops it
+    /// for indexes small enough to scan
+    fn rebuild_ann(&mut self) {
+        self.ann = build_ann(&self.documents, self.ann_params);
+    }
+
+    /// Keeps a BM25 index of all documents from now on, for `search_keywords`
+    pub fn enable_keyword_index(&mut self) {
+        if self.keywords.is_none() {
+            self.keywords = Some(Bm25::default());
+            self.rebuild_keywords();
+        }
+    }
+
+    /// Re-tokenizes every document into the BM25 index, if there is one
+    fn rebuild_keywords(&mut self) {
+        if self.keywords.is_none() {
+            return;
+        }
+        let mut keywords = Bm25::default();
+        for doc in self.documents.values() {
+            keywords.insert(&doc.id, &self.tokenize(&doc.content));
+        }
+        self.keywords = Some(keywords);
+    }
+
+    fn index_keywords(&mut self, id: &str) {
+        if self.keywords.is_none() {
+            return;
+        }
+        let Some(doc) = self.documents.get(id) else {
+            return;
         };
-        
-        self.documents.insert(id, document);
-        self.update_idf_values();
+        let tokens = self.tokenize(&doc.content);
+        if let Some(keywords) = &mut self.keywords {
+            keywords.insert(id, &tokens);
+        }
+    }
+
+    /// ID of the model the stored embeddings were computed with
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// TF-IDF vocabulary with a commonness weight (negated IDF, so more
+    /// frequent terms weigh more); empty for dense-only indexes. Synonym
+    /// and stop word terms are included, as they never reach the vocabulary
+    /// themselves.
+    pub fn term_weights(&self) -> Vec<(String, f32)> {
+        let mut weights: Vec<(String, f32)> =
+            self.idf_values.iter().map(|(term, idf)| (term.clone(), -idf)).collect();
+        if !weights.is_empty() {
+            weights.extend(self.synonyms.terms().map(|term| (term.to_string(), 0.0)));
+            if self.settings.stop_words {
+                weights.extend(STOP_WORDS.iter().map(|term| (term.to_string(), 0.0)));
+            }
+        }
+        weights
+    }
+
+    /// Changes whenever the terms or weights of `term_weights` may have,
+    /// so derived structures such as a spelling corrector can be rebuilt
+    pub fn term_revision(&self) -> u64 {
+        self.term_revision
+    }
+
+    /// Tokenizes documents and queries through `synonyms` from now on,
+    /// rebuilding the TF-IDF embeddings if the map changed. Returns whether
+    /// it did.
+    pub fn set_synonyms(&mut self, synonyms: Synonyms) -> Result<bool> {
+        if synonyms == self.synonyms {
+            return Ok(false);
+        }
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        self.synonyms = synonyms;
+        self.rebuild();
+        self.rebuild_keywords();
+        Ok(true)
+    }
+
+    pub fn settings(&self) -> &CollectionSettings {
+        &self.settings
+    }
+
+    /// Replaces the collection settings, rebuilding the TF-IDF embeddings
+    /// if tokenization changed. Chunking settings only apply to documents
+    /// added afterwards.
+    pub fn set_settings(&mut self, settings: CollectionSettings) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        let retokenize = settings.stop_words != self.settings.stop_words;
+        self.settings = settings;
+        if retokenize {
+            self.rebuild();
+            self.rebuild_keywords();
+        }
         Ok(())
     }
 
-    pub fn search_similar(&self, query: &str, top_k: usize) -> Vec<&Document> {
-        let tokens = self.tokenize(query);
-        let query_embedding = self.calculate_tfidf(&tokens);
+    /// Embeds documents and queries with `embedder` instead of TF-IDF
+    /// (`None` keeps TF-IDF). Fails if the index already holds embeddings
+    /// from a different model, since those aren't comparable to the queries.
+    pub fn use_embedding_model(&mut self, embedder: Option<Arc<dyn Embedder>>) -> Result<()> {
+        let wanted = embedder.as_ref().map_or(TFIDF_MODEL_ID, |e| e.model_id());
+        if !self.documents.is_empty() && self.model_id != wanted {
+            return Err(anyhow!(
+                "Index was embedded with model '{}' but '{}' is configured; rebuild the index or configure the matching model",
+                self.model_id, wanted
+            ));
+        }
+        self.model_id = wanted.to_string();
+        self.embedder = embedder;
+        Ok(())
+    }
 
-        let mut similarities: Vec<(f32, &Document)> = self
-            .documents
-            .values()
-            .map(|doc| {
-                let similarity = self.cosine_similarity(&doc.embedding, &query_embedding);
-                (similarity, doc)
+    /// Also embeds documents added from now on with `embedder`, keeping the
+    /// result as a variant next to the primary embedding
+    pub fn add_embedding_variant(&mut self, embedder: Arc<dyn Embedder>) -> Result<()> {
+        let id = embedder.model_id();
+        if id == self.model_id || self.variant_embedders.iter().any(|e| e.model_id() == id) {
+            return Err(anyhow!("Embedding model '{}' is already in use by this index", id));
+        }
+        self.variant_embedders.push(embedder);
+        Ok(())
+    }
+
+    /// Number of documents that have an embedding from variant `model_id`
+    pub fn variant_coverage(&self, model_id: &str) -> usize {
+        self.documents.values().filter(|doc| doc.variants.contains_key(model_id)).count()
+    }
+
+    /// Embeds up to `limit` documents that don't have a `embedder` variant
+    /// yet, returning how many were embedded (0 once all are covered)
+    pub fn embed_missing_variants(&mut self, embedder: &dyn Embedder, limit: usize) -> Result<usize> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        let model_id = embedder.model_id();
+        let mut missing: Vec<&mut Document> = self.documents
+            .values_mut()
+            .filter(|doc| !doc.variants.contains_key(model_id))
+            .collect();
+        // Deterministic order, so interrupted runs make steady progress
+        missing.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut embedded = 0;
+        for doc in missing.into_iter().take(limit) {
+            let embedding = Array1::from(embedder.embed(&doc.content)?);
+            doc.variants.insert(model_id.to_string(), embedding);
+            embedded += 1;
+        }
+        Ok(embedded)
+    }
+
+    /// Copies the `model_id` variants of `other` onto documents that have
+    /// the same ID and content here, returning how many were copied
+    pub fn adopt_variants(&mut self, other: &VectorDB, model_id: &str) -> usize {
+        let mut adopted = 0;
+        for doc in self.documents.values_mut() {
+            if let Some(theirs) = other.documents.get(&doc.id)
+                && theirs.content == doc.content
+                && let Some(embedding) = theirs.variants.get(model_id)
+            {
+                doc.variants.insert(model_id.to_string(), embedding.clone());
+                adopted += 1;
+            }
+        }
+        adopted
+    }
+
+    /// Makes variant `model_id` the primary embedding of every document,
+    /// dropping the old primary embeddings (and TF-IDF tables). Fails unless
+    /// every document has the variant.
+    pub fn promote_variant(&mut self, model_id: &str) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        let covered = self.variant_coverage(model_id);
+        if covered < self.documents.len() {
+            return Err(anyhow!("Only {} of {} documents have embeddings from '{}'",
+                covered, self.documents.len(), model_id));
+        }
+
+        for doc in self.documents.values_mut() {
+            doc.embedding = doc.variants.remove(model_id).expect("coverage checked above");
+        }
+        self.model_id = model_id.to_string();
+        self.embedder = None;
+        self.variant_embedders.retain(|e| e.model_id() != model_id);
+        self.vocabulary.clear();
+        self.idf_values.clear();
+        self.term_revision += 1;
+        self.doc_freqs = None;
+        self.stale = false;
+        self.search_mode = SearchMode::Primary;
+        self.rebuild_ann();
+        Ok(())
+    }
+
+    /// Chooses the embedding space used to rank queries. Variants must have
+    /// been registered with `add_embedding_variant` to embed the query.
+    pub fn set_search_mode(&mut self, mode: SearchMode) -> Result<()> {
+        if let SearchMode::Variant(id) = &mode
+            && !self.variant_embedders.iter().any(|e| e.model_id() == id)
+        {
+            return Err(anyhow!("No embedding variant '{}' is configured", id));
+        }
+        self.search_mode = mode;
+        Ok(())
+    }
+
+    /// Order-independent SHA256 of the documents, vocabulary and IDF
+    /// table, identical for equal indexes on any machine
+    pub fn fingerprint(&self) -> String {
+        fingerprint_of(&self.model_id, &self.synonyms, &self.settings, self.documents.values(), &self.vocabulary, &self.idf_values)
+    }
+
+    /// Computes the changes that turn `base` into this index. Only new or
+    /// modified documents are carried, together with the current vocabulary
+    /// and IDF table, which are small compared to the embeddings. TF-IDF
+    /// vectors that only changed with that table are recomputed by the
+    /// receiver, so a rebuild after adding one file doesn't ship them all.
+    pub fn diff(&self, base: &VectorDB) -> IndexDelta {
+        // The receiver tokenizes like the base, so it must tokenize like us
+        let recomputable = self.model_id == TFIDF_MODEL_ID
+            && self.synonyms == base.synonyms
+            && self.settings.stop_words == base.settings.stop_words;
+        let mut upserted = Vec::new();
+        let mut reembedded = Vec::new();
+        for doc in self.documents.values() {
+            match base.documents.get(&doc.id) {
+                Some(old) if old.content == doc.content && old.variants == doc.variants && old.metadata == doc.metadata => {
+                    if old.embedding == doc.embedding {
+                        continue;
+                    }
+                    if recomputable && self.calculate_tfidf(&self.tokenize(&doc.content)) == doc.embedding {
+                        reembedded.push(doc.id.clone());
+                    } else {
+                        upserted.push(doc.clone());
+                    }
+                }
+                _ => upserted.push(doc.clone()),
+            }
+        }
+        let removed = base.documents
+            .keys()
+            .filter(|id| !self.documents.contains_key(*id))
+            .cloned()
+            .collect();
+
+        IndexDelta {
+            base_fingerprint: base.fingerprint(),
+            target_fingerprint: self.fingerprint(),
+            upserted,
+            removed,
+            reembedded,
+            vocabulary: self.vocabulary.clone(),
+            idf_values: self.idf_values.clone(),
+            model_id: self.model_id.clone(),
+            synonyms: self.synonyms.clone(),
+            settings: self.settings.clone(),
+        }
+    }
+
+    /// Applies a delta produced by `diff` against this exact index. Fails
+    /// without modifying anything if the delta was made for another base.
+    pub fn apply_delta(&mut self, delta: IndexDelta) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        if self.fingerprint() != delta.base_fingerprint {
+            return Err(anyhow!("Delta was created for a different base index"));
+        }
+        if delta.model_id != self.model_id {
+            return Err(anyhow!("Delta uses embedding model '{}', index uses '{}'", delta.model_id, self.model_id));
+        }
+        // Check the result before touching anything, so a bad delta leaves
+        // the index as it was
+        let reembedded = delta.reembedded
+            .iter()
+            .map(|id| {
+                let doc = self.documents.get(id).ok_or_else(|| anyhow!("Delta re-embeds unknown document '{}'", id))?;
+                let embedding = tfidf_vector(&self.tokenize(&doc.content), &delta.vocabulary, &delta.idf_values);
+                Ok(Document { embedding, ..doc.clone() })
             })
+            .collect::<Result<Vec<Document>>>()?;
+        let changed: FxHashSet<&String> = delta.removed
+            .iter()
+            .chain(delta.upserted.iter().map(|doc| &doc.id))
+            .chain(&delta.reembedded)
             .collect();
+        let documents = self.documents.values().filter(|doc| !changed.contains(&doc.id)).chain(&delta.upserted).chain(&reembedded);
+        let target = fingerprint_of(&delta.model_id, &delta.synonyms, &delta.settings, documents, &delta.vocabulary, &delta.idf_values);
+        if target != delta.target_fingerprint {
+            return Err(anyhow!("Index would not match the delta's target after applying it"));
+        }
 
-        similarities.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-        similarities
-            .into_iter()
-            .take(top_k)
-            .map(|(_, doc)| doc)
-            .collect()
+        // Term counts depend on tokenization, which these settings change
+        if delta.synonyms != self.synonyms || delta.settings.stop_words != self.settings.stop_words {
+            self.doc_freqs = None;
+        }
+        let mut replaced = !delta.removed.is_empty() || !reembedded.is_empty();
+        // Same content, so the term counts stay as they are
+        for doc in reembedded {
+            self.documents.insert(doc.id.clone(), doc);
+        }
+        let mut added = Vec::new();
+        for id in &delta.removed {
+            if let Some(old) = self.documents.remove(id) {
+                self.count_terms(&old.content, false);
+            }
+        }
+        for doc in delta.upserted {
+            self.count_terms(&doc.content, true);
+            let id = doc.id.clone();
+            match self.documents.insert(id.clone(), doc) {
+                Some(old) => {
+                    self.count_terms(&old.content, false);
+                    replaced = true;
+                }
+                None => added.push(id),
+            }
+        }
+        self.dedup_index = None;
+        self.vocabulary = delta.vocabulary;
+        self.idf_values = delta.idf_values;
+        self.term_revision += 1;
+        self.synonyms = delta.synonyms;
+        self.settings = delta.settings;
+        self.update_ann(added, replaced);
+        self.rebuild_keywords();
+        Ok(())
     }
 
-    fn tokenize(&self, text: &str) -> Vec<String> {
-        lazy_static! {
-            static ref STOP_WORDS: FxHashSet<&'static str> = {
-                let words = vec![
-                    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from",
-                    "has", "he", "in", "is", "it", "its", "of", "on", "that", "the",
-                    "to", "was", "were", "will", "with"
-                ];
-                words.into_iter().collect()
-            };
+    /// Gives documents added from now on IDs derived from their content and
+    /// position instead of random UUIDs, and leaves out their modification
+    /// and ingestion times, so ingesting the same files in the same order
+    /// always builds the same index
+    pub fn use_deterministic_ids(&mut self) {
+        self.deterministic_ids = true;
+    }
+
+    /// Gives documents added from now on the ID they have in `previous`
+    /// when it holds the same content from the same source, so rebuilding
+    /// an index from unchanged files keeps its IDs and a delta against the
+    /// old build only carries what changed
+    pub fn reuse_ids_from(&mut self, previous: &VectorDB) {
+        self.previous_ids = previous.documents
+            .values()
+            .map(|doc| (dedup_key(&doc.content, &doc.metadata), doc.id.clone()))
+            .collect();
+    }
+
+    /// ID for a new document at `position` with `dedup_key` `key`
+    fn new_id(&mut self, position: usize, content: &str, key: &str) -> String {
+        if let Some(id) = self.previous_ids.remove(key)
+            && !self.documents.contains_key(&id)
+        {
+            return id;
+        }
+        match self.deterministic_ids {
+            true => content_id(position, content),
+            false => uuid::Uuid::new_v4().to_string(),
         }
+    }
 
-        // Normalize text
-        let text = text.nfc().collect::<String>().to_lowercase();
-        
-        // Remove special characters and split into tokens
-        let re = Regex::new(r"[^\w\s]").unwrap();
-        let text = re.replace_all(&text, " ");
-        
-        text.split_whitespace()
-            .filter(|&token| !STOP_WORDS.contains(token))
-            .map(|token| token.to_string())
-            .collect()
+    /// All documents of this index as a write-ahead log segment, so an
+    /// ingestion worker can hand what it embedded to the shared index
+    pub(crate) fn to_segment(&self) -> IngestSegment {
+        IngestSegment { model_id: self.model_id.clone(), documents: self.documents.values().cloned().collect() }
     }
 
-    fn calculate_tfidf(&self, tokens: &[String]) -> Array1<f32> {
-        let mut term_freq = FxHashMap::default();
-        
-        // Calculate term frequencies
-        for token in tokens {
-            *term_freq.entry(token.clone()).or_insert(0.0) += 1.0;
+    /// Adds the documents of a segment, replacing any with the same ID so
+    /// applying a segment twice is harmless. Dense embeddings are kept as
+    /// they are; TF-IDF ones are recomputed against the grown vocabulary.
+    pub(crate) fn apply_segment(&mut self, segment: IngestSegment) -> Result<usize> {
+        self.apply_segments(vec![segment])
+    }
+
+    /// `apply_segment` for many segments, rebuilding the index once. Fails
+    /// without changing anything if any segment doesn't fit the index.
+    pub(crate) fn apply_segments(&mut self, segments: Vec<IngestSegment>) -> Result<usize> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
         }
-        
-        // Normalize term frequencies
-        let tokens_count = tokens.len() as f32;
-        for freq in term_freq.values_mut() {
-            *freq /= tokens_count;
+        for segment in &segments {
+            self.check_segment(segment)?;
         }
-        
-        // Calculate TF-IDF vector
-        let vocab_size = self.vocabulary.len();
-        let mut tfidf = vec![0.0; vocab_size];
-        
-        for (i, term) in self.vocabulary.iter().enumerate() {
-            if let Some(tf) = term_freq.get(term) {
-                if let Some(idf) = self.idf_values.get(term) {
-                    tfidf[i] = tf * idf;
+        let mut count = 0;
+        let mut replaced = false;
+        let mut added = Vec::new();
+        for segment in segments {
+            count += segment.documents.len();
+            for doc in segment.documents {
+                let id = doc.id.clone();
+                match self.documents.insert(id.clone(), doc) {
+                    Some(_) => replaced = true,
+                    None => added.push(id),
                 }
             }
         }
-        
-        Array1::from(tfidf)
+        self.dedup_index = None;
+        if self.model_id == TFIDF_MODEL_ID {
+            self.rebuild();
+        } else {
+            self.update_ann(added, replaced);
+        }
+        self.rebuild_keywords();
+        Ok(count)
     }
 
-    fn update_idf_values(&mut self) {
-        let doc_count = self.documents.len() as f32;
-        
-        for term in &self.vocabulary {
-            let doc_freq = self.documents.values()
-                .filter(|doc| self.tokenize(&doc.content).contains(term))
-                .count() as f32;
-            
-            let idf = (1.0 + doc_count / (1.0 + doc_freq)).ln();
-            self.idf_values.insert(term.clone(), idf);
+    /// Whether `segment` can be applied: it must be embedded with this
+    /// index's model
+    pub(crate) fn check_segment(&self, segment: &IngestSegment) -> Result<()> {
+        if segment.model_id != self.model_id {
+            return Err(anyhow!("Segment uses embedding model '{}', index uses '{}'", segment.model_id, self.model_id));
         }
+        Ok(())
     }
 
-    fn cosine_similarity(&self, a: &Array1<f32>, b: &Array1<f32>) -> f32 {
-        let dot_product = a.dot(b);
-        let norm_a = (a.dot(a)).sqrt();
-        let norm_b = (b.dot(b)).sqrt();
-        
-        if norm_a == 0.0 || norm_b == 0.0 {
-            0.0
+    /// Writes every document (ID, content, metadata, embeddings) as one JSON
+    /// object per line, after a header line with the embedding model,
+    /// synonyms and collection settings, so an index can be inspected with
+    /// standard tools or moved to a machine with another tapssp version.
+    /// Documents are written in ID order. Returns the number written.
+    pub fn export_jsonl(&self, mut writer: impl Write) -> Result<usize> {
+        let header = ExportHeader {
+            format: EXPORT_FORMAT.to_string(),
+            version: EXPORT_FORMAT_VERSION,
+            model_id: self.model_id.clone(),
+            synonyms: self.synonyms.clone(),
+            settings: self.settings.clone(),
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writeln!(writer)?;
+
+        let mut documents: Vec<&Document> = self.documents.values().collect();
+        documents.sort_by(|a, b| a.id.cmp(&b.id));
+        for doc in &documents {
+            let exported = ExportedDocument {
+                id: doc.id.clone(),
+                content: doc.content.clone(),
+                metadata: doc.metadata.clone(),
+                embedding: doc.embedding.to_vec(),
+                table: doc.table.clone(),
+                variants: doc.variants.iter().map(|(model, embedding)| (model.clone(), embedding.to_vec())).collect(),
+            };
+            serde_json::to_writer(&mut writer, &exported)?;
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        Ok(documents.len())
+    }
+
+    /// Rebuilds an index from the output of `export_jsonl`, keeping document
+    /// IDs and dense embeddings. TF-IDF embeddings are recomputed, along
+    /// with the vocabulary they index into.
+    pub fn import_jsonl(reader: impl BufRead) -> Result<Self> {
+        let mut lines = reader.lines().enumerate().filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()));
+        let (_, header) = lines.next().ok_or_else(|| anyhow!("Empty export"))?;
+        let header: ExportHeader =
+            serde_json::from_str(&header?).map_err(|_| anyhow!("Not a tapssp export: missing header line"))?;
+        if header.format != EXPORT_FORMAT {
+            return Err(anyhow!("Not a tapssp export: format '{}'", header.format));
+        }
+        if header.version != EXPORT_FORMAT_VERSION {
+            return Err(anyhow!("Unsupported export format version {}", header.version));
+        }
+
+        let mut db = VectorDB::new();
+        db.model_id = header.model_id;
+        db.synonyms = header.synonyms;
+        db.settings = header.settings;
+        db.doc_freqs = None;
+        let mut dimensions = None;
+        for (i, line) in lines {
+            let doc: ExportedDocument =
+                serde_json::from_str(&line?).map_err(|e| anyhow!("Invalid export line {}: {}", i + 1, e))?;
+            // TF-IDF embeddings grow with the vocabulary; dense ones don't
+            let expected = *dimensions.get_or_insert(doc.embedding.len());
+            if db.model_id != TFIDF_MODEL_ID && doc.embedding.len() != expected {
+                return Err(anyhow!("Invalid export line {}: embedding has {} dimensions, expected {}",
+                    i + 1, doc.embedding.len(), expected));
+            }
+            let doc = Document {
+                id: doc.id,
+                content: doc.content,
+                embedding: Array1::from(doc.embedding),
+                table: doc.table,
+                variants: doc.variants.into_iter().map(|(model, embedding)| (model, Array1::from(embedding))).collect(),
+                metadata: doc.metadata,
+            };
+            if let Some(duplicate) = db.documents.insert(doc.id.clone(), doc) {
+                return Err(anyhow!("Invalid export line {}: duplicate document ID {}", i + 1, duplicate.id));
+            }
+        }
+        if db.model_id == TFIDF_MODEL_ID {
+            db.rebuild();
         } else {
-            dot_product / (norm_a * norm_b)
+            db.rebuild_ann();
+        }
+        Ok(db)
+    }
+
+    /// Adds a document, returning the ID it can be updated or removed by.
+    /// Content already indexed from the same source isn't added again; its
+    /// ID is returned instead, see `take_ingest_report`.
+    pub fn add_document(&mut self, content: String) -> Result<String> {
+        self.insert_document(content, None, BTreeMap::new())
+    }
+
+    /// Adds many documents sharing the same metadata, returning their IDs in
+    /// order. Tokenizing and embedding run in parallel, and the vocabulary
+    /// and IDF values are updated once for the batch instead of after every
+    /// document. Nothing is added if any document fails to embed. As with
+    /// `add_document`, duplicates get the ID of their indexed copy.
+    pub fn add_documents(&mut self, contents: Vec<String>, metadata: BTreeMap<String, String>) -> Result<Vec<String>> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        let metadata = self.stored_metadata(metadata);
+        let before = self.documents.len();
+        let mut all_ids = Vec::with_capacity(contents.len());
+        let (mut ids, mut new_contents) = (Vec::new(), Vec::new());
+        for content in contents {
+            if let Some(id) = self.find_duplicate(&content, &metadata) {
+                all_ids.push(id);
+                continue;
+            }
+            let key = dedup_key(&content, &metadata);
+            let id = self.new_id(before + ids.len(), &content, &key);
+            // Registered right away, so repeats within the batch are caught
+            self.dedup_index().insert(key, id.clone());
+            all_ids.push(id.clone());
+            ids.push(id);
+            new_contents.push(content);
+        }
+        let contents = new_contents;
+
+        let embedded = contents
+            .par_iter()
+            .map(|content| {
+                let variants = self.variant_embedders
+                    .iter()
+                    .map(|embedder| Ok((embedder.model_id().to_string(), Array1::from(embedder.embed(content)?))))
+                    .collect::<Result<BTreeMap<_, _>>>()?;
+                let embedded = match &self.embedder {
+                    Some(embedder) => Embedded::Dense(Array1::from(embedder.embed(content)?)),
+                    None => Embedded::Terms(self.tokenize(content)),
+                };
+                Ok((embedded, variants))
+            })
+            .collect::<Result<Vec<_>>>();
+        let embedded = match embedded {
+            Ok(embedded) => embedded,
+            Err(e) => {
+                // Forgets the IDs registered for the batch
+                self.dedup_index = None;
+                return Err(e);
+            }
+        };
+
+        let mut tokenized = Vec::new();
+        if self.embedder.is_none() {
+            self.ensure_doc_freqs();
+        }
+        for ((id, content), (embedded, variants)) in ids.iter().zip(contents).zip(embedded) {
+            let embedding = match embedded {
+                Embedded::Dense(embedding) => embedding,
+                Embedded::Terms(tokens) => {
+                    for token in &tokens {
+                        let next_index = self.vocabulary.len();
+                        self.vocabulary.entry(token.clone()).or_insert(next_index);
+                    }
+                    self.count_tokens(&tokens, true);
+                    tokenized.push((id.clone(), tokens));
+                    Array1::zeros(0)
+                }
+            };
+            let document = Document { id: id.clone(), content, embedding, table: None, variants, metadata: metadata.clone() };
+            self.documents.insert(id.clone(), document);
+        }
+
+        if !tokenized.is_empty() {
+            self.update_idf_values();
+            let embeddings: Vec<(String, Array1<f32>)> = tokenized
+                .into_par_iter()
+                .map(|(id, tokens)| (id, self.calculate_tfidf(&tokens)))
+                .collect();
+            for (id, embedding) in embeddings {
+                if let Some(doc) = self.documents.get_mut(&id) {
+                    doc.embedding = embedding;
+                }
+            }
+            self.stale = before > 0;
+        }
+        for id in &ids {
+            self.index_keywords(id);
+        }
+        match &mut self.ann {
+            Some(ann) => {
+                for id in &ids {
+                    ann.insert(id.clone(), &self.documents);
+                }
+            }
+            None if self.documents.len() >= ANN_MIN_DOCUMENTS => self.rebuild_ann(),
+            None => {}
+        }
+        self.ingest_report.added += ids.len();
+        Ok(all_ids)
+    }
+
+    /// Adds a table chunk, keeping its row/column metadata with the document
+    pub fn add_table_document(&mut self, content: String, table: TableInfo, metadata: BTreeMap<String, String>) -> Result<String> {
+        self.insert_document(content, Some(table), metadata)
+    }
+
+    fn insert_document(
+        &mut self,
+        content: String,
+        table: Option<TableInfo>,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<String> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        let metadata = self.stored_metadata(metadata);
+        if let Some(id) = self.find_duplicate(&content, &metadata) {
+            return Ok(id);
+        }
+
+        let key = dedup_key(&content, &metadata);
+        let id = self.new_id(self.documents.len(), &content, &key);
+        self.store_document(id.clone(), content, table, metadata)?;
+        self.dedup_index().insert(key, id.clone());
+        self.ingest_report.added += 1;
+        self.index_keywords(&id);
+        self.index_for_ann(id.clone());
+        Ok(id)
+    }
+
+    /// `metadata` as it is stored with new documents
+    fn stored_metadata(&self, mut metadata: BTreeMap<String, String>) -> BTreeMap<String, String> {
+        if self.deterministic_ids {
+            metadata.retain(|key, _| !metadata::VOLATILE_FIELDS.contains(&key.as_str()));
+        }
+        metadata
+    }
+
+    /// The ID of the document indexed with `content` from the same source
+    /// as `metadata` describes, None for new content. The indexed copy
+    /// takes the new metadata, so a re-ingested file's chunks show its
+    /// current `modified` time.
+    fn find_duplicate(&mut self, content: &str, metadata: &BTreeMap<String, String>) -> Option<String> {
+        let id = self.dedup_index().get(&dedup_key(content, metadata))?.clone();
+        match self.documents.get_mut(&id) {
+            Some(doc) if !same_metadata(&doc.metadata, metadata) => {
+                doc.metadata = metadata.clone();
+                self.ingest_report.updated += 1;
+            }
+            // Or a repeat within the batch being added
+            _ => self.ingest_report.skipped += 1,
+        }
+        Some(id)
+    }
+
+    fn dedup_index(&mut self) -> &mut HashMap<String, String> {
+        self.dedup_index.get_or_insert_with(|| {
+            self.documents.values().map(|doc| (dedup_key(&doc.content, &doc.metadata), doc.id.clone())).collect()
+        })
+    }
+
+    /// How the documents added since the last call were handled
+    pub fn take_ingest_report(&mut self) -> IngestReport {
+        std::mem::take(&mut self.ingest_report)
+    }
+
+    /// The report `take_ingest_report` would return, without resetting it
+    pub fn ingest_report(&self) -> IngestReport {
+        self.ingest_report
+    }
+
+    /// Removes the documents from the source `metadata` names (its `path`,
+    /// or an import's `source`) that aren't among `current`, the IDs the
+    /// source was just re-ingested as, and returns how many there were. Of
+    /// `current`, `added` were new; that many of the removed documents are
+    /// reported as updated rather than new and removed, since they're the
+    /// old versions of edited chunks.
+    pub fn remove_stale_documents(&mut self, metadata: &BTreeMap<String, String>, current: &[String], added: usize) -> Result<usize> {
+        let source = source_of(metadata);
+        if source.is_empty() {
+            return Ok(0);
         }
+        let current: FxHashSet<&String> = current.iter().collect();
+        // Resolved once per distinct `path` and `source`, not per chunk
+        let mut same_source = FxHashMap::default();
+        let stale: Vec<String> = self
+            .documents
+            .values()
+            .filter(|doc| {
+                !current.contains(&doc.id)
+                    && *same_source
+                        .entry((doc.metadata.get("path"), doc.metadata.get("source")))
+                        .or_insert_with(|| source_of(&doc.metadata) == source)
+            })
+            .map(|doc| doc.id.clone())
+            .collect();
+        let removed = self.remove_documents(&stale)?;
+        let replaced = removed.min(added).min(self.ingest_report.added);
+        self.ingest_report.added -= replaced;
+        self.ingest_report.updated += replaced;
+        self.ingest_report.removed += removed - replaced;
+        Ok(removed)
     }
-}
\ No newline at end of file
+
+    /// Records the position of each of `ids`, a source's chunks in reading
+    /// order, under `metadata::CHUNK_POSITION`
+    pub fn number_chunks(&mut self, ids: &[String]) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        for (position, id) in ids.iter().enumerate() {
+            if let Some(doc) = self.documents.get_mut(id) {
+                doc.metadata.insert(metadata::CHUNK_POSITION.to_string(), position.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the document with `id`, returning whether there was one. Its
+    /// terms stop counting towards IDF right away; the vocabulary slots they
+    /// no longer use are reclaimed by the next `rebuild()`.
+    pub fn remove_document(&mut self, id: &str) -> Result<bool> {
+        Ok(self.remove_documents(&[id.to_string()])? == 1)
+    }
+
+    /// Removes the documents with `ids` like `remove_document`, returning
+    /// how many there were. IDF values and the HNSW graph are updated once
+    /// for the whole batch.
+    pub fn remove_documents(&mut self, ids: &[String]) -> Result<usize> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        let removed = self.take_documents(ids);
+        if removed.is_empty() {
+            return Ok(0);
+        }
+        if self.embedder.is_none() {
+            self.update_idf_values();
+            self.stale = !self.documents.is_empty();
+        }
+        self.rebuild_ann();
+        Ok(removed.len())
+    }
+
+    /// Takes the documents with `ids` out of the document, term and keyword
+    /// tables, skipping unknown IDs; IDF values and the HNSW graph are left
+    /// to the caller
+    fn take_documents(&mut self, ids: &[String]) -> Vec<Document> {
+        let mut taken = Vec::new();
+        for id in ids {
+            let Some(doc) = self.documents.remove(id) else {
+                continue;
+            };
+            if let Some(index) = &mut self.dedup_index {
+                index.remove(&dedup_key(&doc.content, &doc.metadata));
+            }
+            self.count_terms(&doc.content, false);
+            if let Some(keywords) = &mut self.keywords {
+                keywords.remove(id);
+            }
+            taken.push(doc);
+        }
+        taken
+    }
+
+    /// Replaces every document with those of `other`, e.g. the same index
+    /// as saved on disk, keeping this index's embedding models, HNSW
+    /// parameters and search mode. Both must use the same embedding model.
+    pub fn replace_documents(&mut self, other: VectorDB) -> Result<()> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        if other.model_id != self.model_id {
+            return Err(anyhow!("Index uses embedding model '{}', this one '{}'", other.model_id, self.model_id));
+        }
+        self.documents = other.documents;
+        self.vocabulary = other.vocabulary;
+        self.idf_values = other.idf_values;
+        self.term_revision += 1;
+        self.synonyms = other.synonyms;
+        self.settings = other.settings;
+        self.doc_freqs = None;
+        self.dedup_index = None;
+        self.stale = false;
+        self.rebuild_ann();
+        self.rebuild_keywords();
+        Ok(())
+    }
+
+    /// Moves the documents with `ids` into a new index with the same
+    /// embedding model, synonyms and settings, e.g. to archive them.
+    /// Unknown IDs are skipped.
+    pub fn split_off(&mut self, ids: &[String]) -> Result<VectorDB> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        let mut moved = VectorDB::new();
+        moved.model_id = self.model_id.clone();
+        moved.synonyms = self.synonyms.clone();
+        moved.settings = self.settings.clone();
+        moved.doc_freqs = None;
+        for doc in self.take_documents(ids) {
+            moved.documents.insert(doc.id.clone(), doc);
+        }
+        if self.embedder.is_none() {
+            self.update_idf_values();
+            self.stale = !self.documents.is_empty();
+        }
+        self.rebuild_ann();
+        if moved.model_id == TFIDF_MODEL_ID {
+            moved.rebuild();
+        } else {
+            moved.rebuild_ann();
+        }
+        Ok(moved)
+    }
+
+    /// Replaces the content of the document with `id` and re-embeds it with
+    /// every model, keeping its ID, table info and metadata. Returns whether there
+    /// was such a document; if embedding fails the old one is kept.
+    pub fn update_document(&mut self, id: &str, content: String) -> Result<bool> {
+        Ok(self.update_documents(vec![(id.to_string(), content)])? == 1)
+    }
+
+    /// Applies `(id, content)` updates like `update_document`, returning how
+    /// many documents there were to update. The HNSW graph is rebuilt once
+    /// for the batch; when a document fails to embed, the updates before it
+    /// are kept and the rest aren't applied.
+    pub fn update_documents(&mut self, updates: Vec<(String, String)>) -> Result<usize> {
+        if self.read_only {
+            return Err(anyhow!("Index was opened read-only"));
+        }
+        let mut updated = 0;
+        let mut result = Ok(());
+        for (id, content) in updates {
+            match self.replace_content(&id, content) {
+                Ok(true) => updated += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if updated > 0 {
+            self.rebuild_ann();
+        }
+        result.map(|_| updated)
+    }
+
+    /// The body of `update_documents` for one document
+    fn replace_content(&mut self, id: &str, content: String) -> Result<bool> {
+        let Some(old) = self.documents.remove(id) else {
+            return Ok(false);
+        };
+        self.count_terms(&old.content, false);
+        let key = dedup_key(&content, &old.metadata);
+        if let Err(e) = self.store_document(id.to_string(), content, old.table.clone(), old.metadata.clone()) {
+            self.count_terms(&old.content, true);
+            self.documents.insert(id.to_string(), old);
+            return Err(e);
+        }
+        if let Some(index) = &mut self.dedup_index {
+            index.remove(&dedup_key(&old.content, &old.metadata));
+            index.insert(key, id.to_string());
+        }
+        self.index_keywords(id);
+        Ok(true)
+    }
+
+    /// Embeds `content` and stores it under `id`, updating the vocabulary and
+    /// document frequencies. Fails before changing anything if a model
+    /// can't embed it. The HNSW graph is left to the caller.
+    fn store_document(
+        &mut self,
+        id: String,
+        content: String,
+        table: Option<TableInfo>,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<()> {
+        let variants = self.variant_embedders
+            .iter()
+            .map(|embedder| Ok((embedder.model_id().to_string(), Array1::from(embedder.embed(&content)?))))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        if let Some(embedder) = &self.embedder {
+            let embedding = Array1::from(embedder.embed(&content)?);
+            self.documents.insert(id.clone(), Document { id, content, embedding, table, variants, metadata });
+            return Ok(());
+        }
+
+        let tokens = self.tokenize(&content);
+        
+        // Update vocabulary and document frequencies
+        for token in &tokens {
+            let next_index = self.vocabulary.len();
+            self.vocabulary.entry(token.clone()).or_insert(next_index);
+        }
+        self.ensure_doc_freqs();
+        self.count_tokens(&tokens, true);
+        
+        let document = Document {
+            id: id.clone(),
+            content,
+            embedding: Array1::zeros(0),
+            table,
+            variants,
+            metadata,
+        };
+        self.documents.insert(id.clone(), document);
+        self.update_idf_values();
+
+        // Calculate TF-IDF embedding once IDF values include the new terms
+        let embedding = self.calculate_tfidf(&tokens);
+        if let Some(doc) = self.documents.get_mut(&id) {
+            doc.embedding = embedding;
+        }
+        self.stale = self.documents.len() > 1;
+        Ok(())
+    }
+
+    /// Adds a new document to the HNSW graph, building the graph when the
+    /// index grows past `ANN_MIN_DOCUMENTS`
+    fn index_for_ann(&mut self, id: String) {
+        match &mut self.ann {
+            Some(ann) => ann.insert(id, &self.documents),
+            None if self.documents.len() >= ANN_MIN_DOCUMENTS => self.rebuild_ann(),
+            None => {}
+        }
+    }
+
+    /// Whether stored embeddings lag behind the current vocabulary/IDF table
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Compacts the vocabulary to terms still used by some document,
+    /// recomputes IDF values and re-embeds every document against them
+    pub fn rebuild(&mut self) {
+        if let Some(rebuild) = self.prepare_rebuild() {
+            self.finish_rebuild(rebuild);
+        }
+    }
+
+    /// Computes a `rebuild()` without applying it, so searches can go on
+    /// meanwhile; `None` if the index has nothing to rebuild
+    pub(crate) fn prepare_rebuild(&self) -> Option<Rebuild> {
+        // Dense embeddings don't depend on the rest of the collection
+        if self.read_only || self.embedder.is_some() {
+            return None;
+        }
+
+        // In ID order, so the same documents get the same vocabulary indices
+        let mut ids: Vec<&String> = self.documents.keys().collect();
+        ids.sort();
+        let tokenized: Vec<(String, Vec<String>)> = ids
+            .into_iter()
+            .map(|id| (id.clone(), self.tokenize(&self.documents[id].content)))
+            .collect();
+
+        let mut vocabulary = FxHashMap::default();
+        for (_, tokens) in &tokenized {
+            for token in tokens {
+                let next_index = vocabulary.len();
+                vocabulary.entry(token.clone()).or_insert(next_index);
+            }
+        }
+        vocabulary.shrink_to_fit();
+        let doc_freqs = count_doc_freqs(tokenized.iter().map(|(_, tokens)| tokens.as_slice()));
+        let doc_count = self.documents.len() as f32;
+        let idf_values: FxHashMap<String, f32> = vocabulary
+            .keys()
+            .filter_map(|term| Some((term.clone(), idf(doc_count, *doc_freqs.get(term)?))))
+            .collect();
+
+        let embedded: HashMap<String, Document> = tokenized
+            .into_iter()
+            .map(|(id, tokens)| {
+                let embedding = tfidf_vector(&tokens, &vocabulary, &idf_values);
+                let doc = Document {
+                    id: id.clone(),
+                    content: String::new(),
+                    embedding,
+                    table: None,
+                    variants: BTreeMap::new(),
+                    metadata: BTreeMap::new(),
+                };
+                (id, doc)
+            })
+            .collect();
+        let ann = build_ann(&embedded, self.ann_params);
+        Some(Rebuild { term_revision: self.term_revision, vocabulary, doc_freqs, idf_values, embedded, ann })
+    }
+
+    /// Swaps in a `prepare_rebuild` result. Returns false and changes
+    /// nothing if documents were added or removed since it was prepared;
+    /// the index then stays stale for the next attempt.
+    pub(crate) fn finish_rebuild(&mut self, rebuild: Rebuild) -> bool {
+        if rebuild.term_revision != self.term_revision || self.read_only || self.embedder.is_some() {
+            return false;
+        }
+        for (id, embedded) in rebuild.embedded {
+            if let Some(doc) = self.documents.get_mut(&id) {
+                doc.embedding = embedded.embedding;
+            }
+        }
+        self.vocabulary = rebuild.vocabulary;
+        self.doc_freqs = Some(rebuild.doc_freqs);
+        self.idf_values = rebuild.idf_values;
+        self.term_revision += 1;
+        self.stale = false;
+        self.ann = rebuild.ann;
+        true
+    }
+
+    pub fn search_similar(&self, query: &str, top_k: usize) -> Vec<&Document> {
+        self.search_similar_filtered(query, top_k, |_| true)
+    }
+
+    /// Like `search_similar`, but only documents accepted by `filter` are ranked
+    pub fn search_similar_filtered<F>(&self, query: &str, top_k: usize, filter: F) -> Vec<&Document>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        self.search_similar_timed(query, top_k, filter, &mut Timings::new())
+    }
+
+    /// Filtered search that records "tokenize" and "retrieval" stage timings
+    pub fn search_similar_timed<F>(
+        &self,
+        query: &str,
+        top_k: usize,
+        filter: F,
+        timings: &mut Timings,
+    ) -> Vec<&Document>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        self.search_scored_timed(query, top_k, filter, timings)
+            .into_iter()
+            .map(|(_, doc)| doc)
+            .collect()
+    }
+
+    /// The `top_k` best matches with their cosine similarity to `query`
+    pub fn search_scored(&self, query: &str, top_k: usize) -> Vec<(f32, &Document)> {
+        self.search_scored_timed(query, top_k, |_| true, &mut Timings::new())
+    }
+
+    /// `search_scored` among the documents accepted by `filter`, recording
+    /// stage timings
+    pub fn search_scored_timed<F>(
+        &self,
+        query: &str,
+        top_k: usize,
+        filter: F,
+        timings: &mut Timings,
+    ) -> Vec<(f32, &Document)>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        let mut similarities = match &self.search_mode {
+            SearchMode::Primary => self.rank_primary(query, &filter, Some(top_k), timings),
+            SearchMode::Variant(id) => self.rank_variant(id, query, &filter, timings),
+            SearchMode::Fused => {
+                let mut rankings = vec![self.rank_primary(query, &filter, None, timings)];
+                for embedder in &self.variant_embedders {
+                    rankings.push(self.rank_variant(embedder.model_id(), query, &filter, timings));
+                }
+                timings.time("fusion", || fuse_rankings(rankings))
+            }
+        };
+        similarities.truncate(top_k);
+        similarities
+    }
+
+    /// The `top_k` documents accepted by `filter` with the best BM25 score
+    /// for `query`; empty until `enable_keyword_index` is called
+    pub fn search_keywords<F>(&self, query: &str, top_k: usize, filter: F) -> Vec<(f32, &Document)>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        let Some(keywords) = &self.keywords else {
+            return Vec::new();
+        };
+        keywords
+            .score(&self.tokenize(query))
+            .into_iter()
+            .filter_map(|(score, id)| self.documents.get(id).map(|doc| (score, doc)))
+            .filter(|(_, doc)| filter(doc))
+            .take(top_k)
+            .collect()
+    }
+
+    /// Embeds `text` the way documents of this index are embedded
+    pub fn embed_text(&self, text: &str) -> Result<Array1<f32>> {
+        match &self.embedder {
+            Some(embedder) => Ok(Array1::from(embedder.embed(text)?)),
+            None => Ok(self.calculate_tfidf(&self.tokenize(text))),
+        }
+    }
+
+    /// All documents accepted by `filter`, best first, by similarity of
+    /// their primary embedding. With `top_k` set, the HNSW graph (if any)
+    /// may return just the approximate top `top_k` instead.
+    fn rank_primary<F>(&self, query: &str, filter: &F, top_k: Option<usize>, timings: &mut Timings) -> Vec<(f32, &Document)>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        let query_embedding = match &self.embedder {
+            Some(embedder) => match timings.time("embed", || embedder.embed(query)) {
+                Ok(embedding) => Array1::from(embedding),
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to embed query");
+                    return Vec::new();
+                }
+            },
+            None => {
+                let tokens = timings.time("tokenize", || self.tokenize(query));
+                self.calculate_tfidf(&tokens)
+            }
+        };
+        if let (Some(ann), Some(top_k)) = (&self.ann, top_k) {
+            let found = timings.time("retrieval", || self.rank_approximate(ann, &query_embedding, top_k, filter));
+            // A selective filter can reject most neighbours; scan instead
+            if found.len() >= top_k.min(self.documents.len()) {
+                return found;
+            }
+        }
+        timings.time("retrieval", || self.rank(&query_embedding, |doc| Some(&doc.embedding), filter))
+    }
+
+    /// The approximate `top_k` neighbours from the HNSW graph that pass
+    /// `filter`. Looks a few times deeper than `top_k` to leave room for
+    /// filtered-out neighbours.
+    fn rank_approximate<F>(&self, ann: &Hnsw, query_embedding: &Array1<f32>, top_k: usize, filter: &F) -> Vec<(f32, &Document)>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        ann.search(query_embedding, top_k * 4, &self.documents)
+            .into_iter()
+            .filter_map(|(similarity, id)| self.documents.get(id).map(|doc| (similarity, doc)))
+            .filter(|(_, doc)| filter(doc))
+            .take(top_k)
+            .collect()
+    }
+
+    /// Like `rank_primary` in the space of variant `model_id`; documents
+    /// without that variant yet are left out
+    fn rank_variant<F>(&self, model_id: &str, query: &str, filter: &F, timings: &mut Timings) -> Vec<(f32, &Document)>
+    where
+        F: Fn(&Document) -> bool,
+    {
+        let Some(embedder) = self.variant_embedders.iter().find(|e| e.model_id() == model_id) else {
+            return Vec::new();
+        };
+        let query_embedding = match timings.time("embed", || embedder.embed(query)) {
+            Ok(embedding) => Array1::from(embedding),
+            Err(e) => {
+                tracing::warn!(error = %e, model_id, "failed to embed query");
+                return Vec::new();
+            }
+        };
+        timings.time("retrieval", || self.rank(&query_embedding, |doc| doc.variants.get(model_id), filter))
+    }
+
+    fn rank<'a, E, F>(&'a self, query_embedding: &Array1<f32>, embedding_of: E, filter: &F) -> Vec<(f32, &'a Document)>
+    where
+        E: Fn(&'a Document) -> Option<&'a Array1<f32>>,
+        F: Fn(&Document) -> bool,
+    {
+        let mut similarities: Vec<(f32, &Document)> = self
+            .documents
+            .values()
+            .filter(|doc| filter(doc))
+            .filter_map(|doc| {
+                let embedding = embedding_of(doc)?;
+                Some((self.cosine_similarity(embedding, query_embedding), doc))
+            })
+            .collect();
+
+        similarities.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        similarities
+    }
+
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        self.tokenize_with_spans(text).into_iter().map(|token| token.term).collect()
+    }
+
+    /// Index terms of `text` with the spans they were read from. Documents
+    /// keep their original text; only these terms are normalized.
+    pub(crate) fn tokenize_with_spans(&self, text: &str) -> Vec<Token> {
+        let tokens = normalize::tokens(text)
+            .into_iter()
+            .filter(|token| !self.settings.stop_words || !STOP_WORDS.contains(token.term.as_str()))
+            .collect();
+        self.synonyms.expand(tokens)
+    }
+
+    fn calculate_tfidf(&self, tokens: &[String]) -> Array1<f32> {
+        tfidf_vector(tokens, &self.vocabulary, &self.idf_values)
+    }
+
+    fn update_idf_values(&mut self) {
+        self.term_revision += 1;
+        self.ensure_doc_freqs();
+        let doc_freqs = self.doc_freqs.as_ref().expect("counted above");
+        let doc_count = self.documents.len() as f32;
+        
+        for term in self.vocabulary.keys() {
+            // Terms of removed documents keep their vocabulary slot until
+            // `rebuild()`, but no longer weigh anything
+            let Some(&doc_freq) = doc_freqs.get(term) else {
+                self.idf_values.remove(term);
+                continue;
+            };
+            
+            self.idf_values.insert(term.clone(), idf(doc_count, doc_freq));
+        }
+    }
+
+    /// Counts document frequencies from scratch if they aren't tracked yet
+    fn ensure_doc_freqs(&mut self) {
+        if self.doc_freqs.is_none() {
+            let tokenized: Vec<Vec<String>> = self.documents.values().map(|doc| self.tokenize(&doc.content)).collect();
+            self.doc_freqs = Some(count_doc_freqs(tokenized.iter().map(Vec::as_slice)));
+        }
+    }
+
+    /// Adds a document's terms to the tracked frequencies, or removes them
+    /// when `added` is false
+    fn count_terms(&mut self, content: &str, added: bool) {
+        if self.doc_freqs.is_some() {
+            let tokens = self.tokenize(content);
+            self.count_tokens(&tokens, added);
+        }
+    }
+
+    fn count_tokens(&mut self, tokens: &[String], added: bool) {
+        let Some(doc_freqs) = &mut self.doc_freqs else {
+            return;
+        };
+        let terms: FxHashSet<&String> = tokens.iter().collect();
+        for term in terms {
+            if added {
+                *doc_freqs.entry(term.clone()).or_insert(0) += 1;
+            } else if let Some(count) = doc_freqs.get_mut(term) {
+                *count -= 1;
+                if *count == 0 {
+                    doc_freqs.remove(term);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn cosine_similarity(&self, a: &Array1<f32>, b: &Array1<f32>) -> f32 {
+        cosine_similarity(a, b)
+    }
+}
+
+/// Number of token lists each term appears in
+fn count_doc_freqs<'a>(documents: impl Iterator<Item = &'a [String]>) -> FxHashMap<String, usize> {
+    let mut doc_freqs = FxHashMap::default();
+    for tokens in documents {
+        let terms: FxHashSet<&String> = tokens.iter().collect();
+        for term in terms {
+            *doc_freqs.entry(term.clone()).or_insert(0) += 1;
+        }
+    }
+    doc_freqs
+}
+
+/// See `VectorDB::fingerprint`; `documents` may come in any order
+fn fingerprint_of<'a>(
+    model_id: &str,
+    synonyms: &Synonyms,
+    settings: &CollectionSettings,
+    documents: impl Iterator<Item = &'a Document>,
+    vocabulary: &FxHashMap<String, usize>,
+    idf_values: &FxHashMap<String, f32>,
+) -> String {
+    let mut hasher = Sha256Hasher(Sha256::new());
+    model_id.hash(&mut hasher);
+    synonyms.hash(&mut hasher);
+    settings.hash(&mut hasher);
+
+    let mut documents: Vec<&Document> = documents.collect();
+    documents.sort_by(|a, b| a.id.cmp(&b.id));
+    for doc in documents {
+        doc.id.hash(&mut hasher);
+        doc.content.hash(&mut hasher);
+        doc.metadata.hash(&mut hasher);
+        for value in &doc.embedding {
+            value.to_bits().hash(&mut hasher);
+        }
+        for (model_id, embedding) in &doc.variants {
+            model_id.hash(&mut hasher);
+            for value in embedding {
+                value.to_bits().hash(&mut hasher);
+            }
+        }
+    }
+
+    let mut vocabulary: Vec<(&String, &usize)> = vocabulary.iter().collect();
+    vocabulary.sort();
+    vocabulary.hash(&mut hasher);
+
+    let mut idf_values: Vec<(&String, u32)> = idf_values.iter().map(|(term, idf)| (term, idf.to_bits())).collect();
+    idf_values.sort();
+    idf_values.hash(&mut hasher);
+
+    hasher.0.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Feeds `Hash` impls into SHA256, with integers in little-endian and
+/// `usize` widened to 64 bits so the digest doesn't depend on the platform
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.0.update(value.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.0.update(value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.0.update(value.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.0.update((value as u64).to_le_bytes());
+    }
+
+    fn write_i64(&mut self, value: i64) {
+        self.0.update(value.to_le_bytes());
+    }
+
+    fn write_isize(&mut self, value: isize) {
+        self.0.update((value as i64).to_le_bytes());
+    }
+
+    /// Only the digest is used, see `fingerprint_of`
+    fn finish(&self) -> u64 {
+        unreachable!("Sha256Hasher is read with finalize")
+    }
+}
+
+/// UUID-formatted ID from the SHA256 of a document's position and content
+/// TF-IDF embedding of `tokens` against a vocabulary and IDF table
+/// Inverse document frequency of a term in `doc_freq` of `doc_count` documents
+fn idf(doc_count: f32, doc_freq: usize) -> f32 {
+    (1.0 + doc_count / (1.0 + doc_freq as f32)).ln()
+}
+
+/// HNSW graph over the embeddings of `documents`, `None` for indexes small
+/// enough to scan
+fn build_ann(documents: &HashMap<String, Document>, params: HnswParams) -> Option<Hnsw> {
+    if documents.len() < ANN_MIN_DOCUMENTS {
+        return None;
+    }
+    let mut ids: Vec<&String> = documents.keys().collect();
+    // Same graph for the same index on every load
+    ids.sort();
+    let mut ann = Hnsw::new(params);
+    for id in ids {
+        ann.insert(id.clone(), documents);
+    }
+    Some(ann)
+}
+
+fn tfidf_vector(tokens: &[String], vocabulary: &FxHashMap<String, usize>, idf_values: &FxHashMap<String, f32>) -> Array1<f32> {
+    let mut term_freq = FxHashMap::default();
+    
+    // Calculate term frequencies
+    for token in tokens {
+        *term_freq.entry(token.clone()).or_insert(0.0) += 1.0;
+    }
+    
+    // Normalize term frequencies
+    let tokens_count = tokens.len() as f32;
+    for freq in term_freq.values_mut() {
+        *freq /= tokens_count;
+    }
+    
+    // Calculate TF-IDF vector
+    let mut tfidf = vec![0.0; vocabulary.len()];
+    
+    for (term, tf) in &term_freq {
+        if let (Some(&i), Some(idf)) = (vocabulary.get(term), idf_values.get(term)) {
+            tfidf[i] = tf * idf;
+        }
+    }
+    
+    Array1::from(tfidf)
+}
+
+fn content_id(position: usize, content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update((position as u64).to_le_bytes());
+    hasher.update(content.as_bytes());
+    let digest = hasher.finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Uuid::from_bytes(bytes).to_string()
+}
+
+/// Identifies a document by its source file (or import `source`) and
+/// content, so the same text from two files stays two documents
+fn dedup_key(content: &str, metadata: &BTreeMap<String, String>) -> String {
+    let source = source_of(metadata);
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_bytes());
+    hasher.update([0]);
+    hasher.update(content.as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The file a document was loaded from, or an import's `source`. Paths
+/// are made absolute with `.` and `..` resolved, so `a.txt`, `./a.txt` and
+/// `/docs/a.txt` loaded from `/docs` are one source.
+pub(crate) fn source_of(metadata: &BTreeMap<String, String>) -> String {
+    match metadata.get("path") {
+        Some(path) => normalize_path(path),
+        None => metadata.get("source").cloned().unwrap_or_default(),
+    }
+}
+
+/// `path` made absolute against the working directory, resolving `.` and
+/// `..` without touching the file system
+pub(crate) fn normalize_path(path: &str) -> String {
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_string();
+    };
+    let mut normal = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            other => normal.push(other),
+        }
+    }
+    normal.display().to_string()
+}
+
+/// Equal apart from the `ingested` time, which differs on every ingest
+fn same_metadata(a: &BTreeMap<String, String>, b: &BTreeMap<String, String>) -> bool {
+    a.iter().filter(|(key, _)| *key != "ingested").eq(b.iter().filter(|(key, _)| *key != "ingested"))
+}
+
+fn cosine_similarity(a: &Array1<f32>, b: &Array1<f32>) -> f32 {
+    // Embeddings from before a vocabulary grew are shorter; the missing
+    // trailing dimensions are zero, so only the shared prefix contributes
+    let shared = a.len().min(b.len());
+    let dot_product = a.slice(s![..shared]).dot(&b.slice(s![..shared]));
+    let norm_a = (a.dot(a)).sqrt();
+    let norm_b = (b.dot(b)).sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot_product / (norm_a * norm_b)
+    }
+}
+
+/// Difference between two versions of an index, see `VectorDB::diff`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexDelta {
+    base_fingerprint: String,
+    target_fingerprint: String,
+    upserted: Vec<Document>,
+    removed: Vec<String>,
+    /// Unchanged documents whose TF-IDF vectors the receiver recomputes
+    /// against `vocabulary` and `idf_values`
+    reembedded: Vec<String>,
+    vocabulary: FxHashMap<String, usize>,
+    idf_values: FxHashMap<String, f32>,
+    model_id: String,
+    synonyms: Synonyms,
+    settings: CollectionSettings,
+}
+
+impl IndexDelta {
+    pub fn upserted_count(&self) -> usize {
+        self.upserted.len()
+    }
+
+    pub fn removed_count(&self) -> usize {
+        self.removed.len()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_with_header(path.as_ref(), DELTA_MAGIC, DELTA_FORMAT_VERSION, self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let payload = strip_header(&bytes, DELTA_MAGIC, DELTA_FORMAT_VERSION, "index delta")?;
+        Ok(bincode::deserialize(payload)?)
+    }
+}
+
+/// Documents embedded by an ingestion worker, waiting in the write-ahead
+/// log to be applied to the shared index, see `VectorDB::apply_segment`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IngestSegment {
+    model_id: String,
+    documents: Vec<Document>,
+}
+
+impl IngestSegment {
+    pub fn document_count(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_with_header(path.as_ref(), SEGMENT_MAGIC, SEGMENT_FORMAT_VERSION, self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let payload = strip_header(&bytes, SEGMENT_MAGIC, SEGMENT_FORMAT_VERSION, "ingest segment")?;
+        Ok(bincode::deserialize(payload)?)
+    }
+}
+
+/// First line of a JSONL export, see `VectorDB::export_jsonl`
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportHeader {
+    /// Always `EXPORT_FORMAT`, so other JSONL files are told apart
+    format: String,
+    version: u32,
+    model_id: String,
+    synonyms: Synonyms,
+    settings: CollectionSettings,
+}
+
+/// One document line of a JSONL export. Embeddings are plain arrays
+/// rather than ndarray's serde form, for tools like `jq`.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedDocument {
+    id: String,
+    content: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, String>,
+    embedding: Vec<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    table: Option<TableInfo>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    variants: BTreeMap<String, Vec<f32>>,
+}
+
+/// Whether `line`, the first line of a file, is the header of a JSONL
+/// export written by `VectorDB::export_jsonl`
+pub fn is_export_header(line: &str) -> bool {
+    serde_json::from_str::<ExportHeader>(line).is_ok_and(|header| header.format == EXPORT_FORMAT)
+}
+
+/// Writes magic + version + bincode payload via a temp file and rename
+fn write_with_header<T: Serialize>(path: &Path, magic: &[u8; 8], version: u32, payload: &T) -> Result<()> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(magic);
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bincode::serialize_into(&mut bytes, payload)?;
+
+    let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp_path, &bytes)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Validates the magic + version header and returns the payload after it
+fn strip_header<'a>(bytes: &'a [u8], magic: &[u8; 8], version: u32, kind: &str) -> Result<&'a [u8]> {
+    let header_len = magic.len() + 4;
+    if bytes.len() < header_len || &bytes[..magic.len()] != magic {
+        return Err(anyhow!("Not a tapssp {} file", kind));
+    }
+    let found = u32::from_le_bytes(bytes[magic.len()..header_len].try_into()?);
+    if found != version {
+        return Err(anyhow!("Unsupported {} format version {}", kind, found));
+    }
+    Ok(&bytes[header_len..])
+}
+
+/// Orders documents by reciprocal rank fusion of several rankings. The
+/// returned score is each document's best cosine similarity in any space,
+/// so thresholds on it mean the same as for a single ranking.
+fn fuse_rankings(rankings: Vec<Vec<(f32, &Document)>>) -> Vec<(f32, &Document)> {
+    let mut fused: HashMap<&str, (f32, f32, &Document)> = HashMap::new();
+    for ranking in rankings {
+        for (rank, (similarity, doc)) in ranking.into_iter().enumerate() {
+            let entry = fused.entry(doc.id.as_str()).or_insert((0.0, similarity, doc));
+            entry.0 += 1.0 / (RRF_K + rank as f32 + 1.0);
+            entry.1 = entry.1.max(similarity);
+        }
+    }
+
+    let mut fused: Vec<(f32, f32, &Document)> = fused.into_values().collect();
+    fused.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    fused.into_iter().map(|(_, similarity, doc)| (similarity, doc)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_after_vocabulary_growth() -> Result<()> {
+        let mut db = VectorDB::new();
+        db.add_document("Rust ownership rules prevent data races".to_string())?;
+        db.add_document("Bread needs flour water yeast and salt".to_string())?;
+        assert!(db.is_stale());
+
+        // Older embeddings are shorter than the query vector
+        let results = db.search_similar("flour yeast", 1);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].content.contains("flour"));
+
+        db.rebuild();
+        assert!(!db.is_stale());
+        let results = db.search_similar("ownership", 1);
+        assert!(results[0].content.contains("ownership"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepared_rebuild_is_dropped_after_the_index_changed() -> Result<()> {
+        let mut db = VectorDB::new();
+        db.add_document("Rust ownership rules prevent data races".to_string())?;
+        db.add_document("Bread needs flour water yeast and salt".to_string())?;
+
+        let rebuild = db.prepare_rebuild().expect("TF-IDF index");
+        db.add_document("Tea is brewed with hot water".to_string())?;
+        assert!(!db.finish_rebuild(rebuild));
+        assert!(db.is_stale());
+
+        // Prepared again, it matches a rebuild in place
+        let dir = tempfile::tempdir()?;
+        db.save(dir.path().join("index.bin"))?;
+        let mut in_place = VectorDB::load(dir.path().join("index.bin"))?;
+        in_place.rebuild();
+        let rebuild = db.prepare_rebuild().expect("TF-IDF index");
+        assert!(db.finish_rebuild(rebuild));
+        assert!(!db.is_stale());
+        assert_eq!(db.fingerprint(), in_place.fingerprint());
+        let results = db.search_similar("ownership", 1);
+        assert!(results[0].content.contains("ownership"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_and_open_read_only() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("index.bin");
+
+        let mut db = VectorDB::new();
+        db.add_document("Tokio is an async runtime".to_string())?;
+        db.add_document("Serde serializes data structures".to_string())?;
+        db.save(&path)?;
+
+        let mut shared = VectorDB::open_read_only(&path)?;
+        let other = VectorDB::open_read_only(&path)?;
+        assert_eq!(shared.len(), 2);
+        assert_eq!(other.len(), 2);
+        assert!(shared.search_similar("async runtime", 1)[0].content.contains("Tokio"));
+        assert!(shared.add_document("more".to_string()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delta_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let base_path = dir.path().join("base.bin");
+
+        let mut base = VectorDB::new();
+        base.add_document("Cargo builds Rust crates".to_string())?;
+        base.add_document("Clippy lints Rust code".to_string())?;
+        base.save(&base_path)?;
+
+        let mut target = VectorDB::load(&base_path)?;
+        target.add_document("Rustfmt formats Rust code".to_string())?;
+        // Re-embeds the other documents against the new IDF table
+        target.rebuild();
+
+        let delta_path = dir.path().join("update.delta");
+        target.diff(&base).save(&delta_path)?;
+        let delta = IndexDelta::load(&delta_path)?;
+        assert_eq!((delta.upserted_count(), delta.removed_count()), (1, 0));
+        assert_eq!(delta.reembedded.len(), 2);
+
+        let mut replica = VectorDB::load(&base_path)?;
+        replica.apply_delta(delta)?;
+        assert_eq!(replica.fingerprint(), target.fingerprint());
+
+        // A delta only applies to the base it was created from
+        assert!(replica.apply_delta(target.diff(&base)).is_err());
+
+        // A delta that doesn't check out changes nothing
+        let mut untouched = VectorDB::load(&base_path)?;
+        let mut wrong_target = target.diff(&base);
+        wrong_target.target_fingerprint = base.fingerprint();
+        assert!(untouched.apply_delta(wrong_target).is_err());
+        let mut wrong_model = target.diff(&base);
+        wrong_model.model_id = "other-model".to_string();
+        assert!(untouched.apply_delta(wrong_model).is_err());
+        assert_eq!((untouched.fingerprint(), untouched.len()), (base.fingerprint(), 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebuilt_index_keeps_ids_of_unchanged_documents() -> Result<()> {
+        let source = |path: &str| BTreeMap::from([("path".to_string(), path.to_string())]);
+        let mut previous = VectorDB::new();
+        let kept = previous.add_documents(vec!["Cargo builds Rust crates".to_string()], source("/docs/cargo.txt"))?;
+        previous.add_documents(vec!["Clippy lints Rust code".to_string()], source("/docs/clippy.txt"))?;
+
+        let mut rebuilt = VectorDB::new();
+        rebuilt.reuse_ids_from(&previous);
+        rebuilt.add_documents(vec!["Cargo builds Rust crates".to_string()], source("/docs/cargo.txt"))?;
+        rebuilt.add_documents(vec!["Clippy lints Rust code, pedantically".to_string()], source("/docs/clippy.txt"))?;
+        rebuilt.add_documents(vec!["Rustfmt formats Rust code".to_string()], source("/docs/rustfmt.txt"))?;
+        rebuilt.rebuild();
+
+        assert!(rebuilt.contains(&kept[0]));
+        let delta = rebuilt.diff(&previous);
+        assert_eq!((delta.upserted_count(), delta.removed_count(), delta.reembedded.len()), (2, 1, 1));
+        previous.apply_delta(delta)?;
+        assert_eq!(previous.fingerprint(), rebuilt.fingerprint());
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_idf_matches_full_recount() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let base_path = dir.path().join("base.bin");
+
+        let mut db = VectorDB::new();
+        db.add_document("Cargo builds Rust crates".to_string())?;
+        db.add_document("Clippy lints Rust code".to_string())?;
+        db.save(&base_path)?;
+        let mut target = VectorDB::load(&base_path)?;
+        target.add_document("Rustfmt formats code".to_string())?;
+
+        // Counts start from the loaded documents, then follow the delta
+        db.apply_delta(target.diff(&VectorDB::load(&base_path)?))?;
+        db.add_document("Miri checks unsafe Rust code".to_string())?;
+
+        let incremental = db.idf_values.clone();
+        db.doc_freqs = None;
+        db.update_idf_values();
+        assert_eq!(db.idf_values, incremental);
+        assert_eq!(db.doc_freqs.as_ref().unwrap()["rust"], 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_and_update_documents() -> Result<()> {
+        let mut db = VectorDB::new();
+        let cargo = db.add_document("Cargo builds Rust crates".to_string())?;
+        let clippy = db.add_document("Clippy lints Rust code".to_string())?;
+        db.add_document("Bread needs flour".to_string())?;
+
+        assert!(db.update_document(&cargo, "Rustfmt formats Rust code".to_string())?);
+        assert!(db.remove_document(&clippy)?);
+        assert!(!db.remove_document(&clippy)?);
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.documents[&cargo].content, "Rustfmt formats Rust code");
+
+        // Removed terms no longer weigh anything, and the counts match a recount
+        assert!(!db.idf_values.contains_key("clippy") && !db.idf_values.contains_key("cargo"));
+        assert_eq!(db.doc_freqs.as_ref().unwrap()["code"], 1);
+        let incremental = db.idf_values.clone();
+        db.doc_freqs = None;
+        db.update_idf_values();
+        assert_eq!(db.idf_values, incremental);
+
+        db.rebuild();
+        assert!(!db.vocabulary.contains_key("clippy"));
+        assert_eq!(db.search_similar("formats code", 1)[0].id, cargo);
+        Ok(())
+    }
+
+    /// Embeds text as counts of a few fixed words
+    struct KeywordEmbedder;
+
+    impl Embedder for KeywordEmbedder {
+        fn model_id(&self) -> &str {
+            "keywords@1"
+        }
+
+        fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            let text = text.to_lowercase();
+            Ok(["rust", "bread", "async"].iter().map(|word| text.matches(word).count() as f32).collect())
+        }
+    }
+
+    #[test]
+    fn test_large_index_searches_hnsw_graph() -> Result<()> {
+        let mut db = VectorDB::new();
+        db.use_embedding_model(Some(Arc::new(KeywordEmbedder)))?;
+        for i in 0..ANN_MIN_DOCUMENTS {
+            db.add_document(format!("{}{}", "rust ".repeat(i % 30), "bread ".repeat(i / 30 + 1)))?;
+        }
+        db.add_document("async".to_string())?;
+        assert!(db.uses_ann());
+
+        let results = db.search_scored("bread", 3);
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|(score, doc)| *score > 0.99 && !doc.content.contains("rust")));
+        // Too selective for the graph's neighbourhood; falls back to a scan
+        let filtered = db.search_similar_filtered("bread", 1, |doc| doc.content == "async");
+        assert_eq!(filtered[0].content, "async");
+
+        // A batch is dropped from the graph in one rebuild
+        let without_rust: Vec<String> = db.documents().filter(|doc| !doc.content.contains("rust")).map(|doc| doc.id.clone()).collect();
+        assert_eq!(db.remove_documents(&without_rust)?, without_rust.len());
+        assert!(db.search_scored("bread", 3).iter().all(|(_, doc)| doc.content.contains("rust")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hnsw_graph_is_saved_and_extended_by_deltas() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut db = VectorDB::new();
+        db.use_embedding_model(Some(Arc::new(KeywordEmbedder)))?;
+        for i in 0..ANN_MIN_DOCUMENTS {
+            db.add_document(format!("{}{}", "rust ".repeat(i % 30), "bread ".repeat(i / 30 + 1)))?;
+        }
+        // Inserted one by one, unlike the graph a rebuild would make
+        let graph = |db: &VectorDB| bincode::serialize(&db.ann).unwrap();
+        let base_path = dir.path().join("base.bin");
+        db.save(&base_path)?;
+        let mut replica = VectorDB::load(&base_path)?;
+        assert!(replica.uses_ann());
+        assert_eq!(graph(&replica), graph(&db));
+
+        // New documents in a delta are linked into the loaded graph
+        let base = VectorDB::load(&base_path)?;
+        db.add_document("async rust".to_string())?;
+        replica.apply_delta(db.diff(&base))?;
+        assert_eq!(graph(&replica), graph(&db));
+        replica.use_embedding_model(Some(Arc::new(KeywordEmbedder)))?;
+        let ids = |db: &VectorDB| db.search_similar("bread async", 5).iter().map(|doc| doc.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&replica), ids(&db));
+        Ok(())
+    }
+
+    #[test]
+    fn test_embedding_model_is_recorded_and_enforced() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("index.bin");
+
+        let mut db = VectorDB::new();
+        db.use_embedding_model(Some(Arc::new(KeywordEmbedder)))?;
+        db.add_document("Rust and async Rust".to_string())?;
+        db.add_document("Bread recipes".to_string())?;
+        assert!(db.search_similar("bread", 1)[0].content.starts_with("Bread"));
+        db.save(&path)?;
+
+        let mut loaded = VectorDB::load(&path)?;
+        assert_eq!(loaded.model_id(), "keywords@1");
+        assert!(loaded.use_embedding_model(None).is_err());
+        loaded.use_embedding_model(Some(Arc::new(KeywordEmbedder)))?;
+        assert!(loaded.search_similar("async", 1)[0].content.starts_with("Rust"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_modes_with_embedding_variant() -> Result<()> {
+        let mut db = VectorDB::new();
+        db.add_embedding_variant(Arc::new(KeywordEmbedder))?;
+        db.add_document("Rust ownership and async Rust".to_string())?;
+        db.add_document("Sourdough bread needs a starter".to_string())?;
+        db.rebuild();
+        assert_eq!(db.variant_coverage("keywords@1"), 2);
+        assert!(db.add_embedding_variant(Arc::new(KeywordEmbedder)).is_err());
+        assert!(db.set_search_mode(SearchMode::parse("unknown@1")).is_err());
+
+        for mode in ["primary", "keywords@1", "fused"] {
+            db.set_search_mode(SearchMode::parse(mode))?;
+            let results = db.search_scored("bread", 2);
+            assert!(results[0].1.content.starts_with("Sourdough"), "mode {}", mode);
+            assert!(results[0].0 > 0.0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_synonyms_apply_to_documents_and_queries() -> Result<()> {
+        let mut db = VectorDB::new();
+        db.add_document("Deploying services on k8s clusters".to_string())?;
+        db.add_document("Approving a purchase order".to_string())?;
+        db.add_document("Baking sourdough bread".to_string())?;
+        db.rebuild();
+
+        assert!(db.set_synonyms(Synonyms::parse("k8s = kubernetes\nPO = purchase order")?)?);
+        assert!(db.search_similar("kubernetes", 1)[0].content.starts_with("Deploying"));
+        assert!(db.search_similar("who signs a PO?", 1)[0].content.starts_with("Approving"));
+        assert!(db.term_weights().iter().any(|(term, _)| term == "k8s"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_add_matches_one_by_one() -> Result<()> {
+        let texts: Vec<String> = (0..40)
+            .map(|i| format!("Document {} about {} and {}", i, ["rust", "bread", "tokio"][i % 3], ["ownership", "yeast"][i % 2]))
+            .collect();
+        let mut one_by_one = VectorDB::new();
+        for text in &texts {
+            one_by_one.add_document(text.clone())?;
+        }
+        one_by_one.rebuild();
+
+        // Without a rebuild: every embedding already sees the whole batch
+        let mut bulk = VectorDB::new();
+        let metadata = BTreeMap::from([("source".to_string(), "docs.txt".to_string())]);
+        let ids = bulk.add_documents(texts.clone(), metadata)?;
+        assert_eq!(ids.len(), texts.len());
+        assert!(!bulk.is_stale());
+
+        // Many documents tie, so compare the scores rather than the order
+        for query in ["rust ownership", "bread yeast", "document 7 tokio"] {
+            let expected: Vec<f32> = one_by_one.search_scored(query, 10).into_iter().map(|(score, _)| score).collect();
+            let actual: Vec<f32> = bulk.search_scored(query, 10).into_iter().map(|(score, _)| score).collect();
+            assert!(expected.iter().zip(&actual).all(|(a, b)| (a - b).abs() < 1e-5), "{}: {:?} vs {:?}", query, expected, actual);
+        }
+        assert_eq!(bulk.search_similar("document 7 tokio", 1)[0].content, "Document 7 about bread and yeast");
+        assert_eq!(bulk.documents().filter(|doc| doc.metadata["source"] == "docs.txt").count(), texts.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reingesting_a_file_skips_or_updates_its_chunks() -> Result<()> {
+        let file = |modified: &str| {
+            BTreeMap::from([("path".to_string(), "docs/a.md".to_string()), ("modified".to_string(), modified.to_string())])
+        };
+        let chunks = vec!["Refunds take thirty days.".to_string(), "Shipping is free.".to_string()];
+        let mut db = VectorDB::new();
+        let ids = db.add_documents(chunks.clone(), file("2024-01-01T00:00:00Z"))?;
+        assert_eq!(db.take_ingest_report(), IngestReport { added: 2, ..IngestReport::default() });
+
+        // The same file again, then touched, then a repeat within a batch
+        assert_eq!(db.add_documents(chunks.clone(), file("2024-01-01T00:00:00Z"))?, ids);
+        assert_eq!(db.add_documents(chunks.clone(), file("2024-02-01T00:00:00Z"))?, ids);
+        db.add_documents(vec!["New.".to_string(), "New.".to_string()], file("2024-02-01T00:00:00Z"))?;
+        assert_eq!(db.take_ingest_report(), IngestReport { added: 1, updated: 2, skipped: 3, removed: 0 });
+        assert_eq!(db.len(), 3);
+        assert!(db.documents().all(|doc| doc.metadata["modified"] == "2024-02-01T00:00:00Z"));
+
+        // The same text from another file is a document of its own
+        let other = BTreeMap::from([("path".to_string(), "docs/b.md".to_string())]);
+        db.add_documents(vec!["Shipping is free.".to_string()], other)?;
+        db.remove_document(&ids[0])?;
+        db.add_document("Refunds take thirty days.".to_string())?;
+        assert_eq!(db.take_ingest_report(), IngestReport { added: 2, ..IngestReport::default() });
+        Ok(())
+    }
+
+    #[test]
+    fn test_jsonl_export_rebuilds_the_index() -> Result<()> {
+        let mut db = VectorDB::new();
+        db.use_embedding_model(Some(Arc::new(KeywordEmbedder)))?;
+        let source = BTreeMap::from([("source".to_string(), "kb.md".to_string())]);
+        db.add_documents(vec!["Rust and async Rust".to_string(), "Bread recipes".to_string()], source)?;
+        let mut dump = Vec::new();
+        assert_eq!(db.export_jsonl(&mut dump)?, 2);
+        let text = String::from_utf8(dump.clone())?;
+        assert!(is_export_header(text.lines().next().unwrap_or_default()));
+        assert!(text.lines().nth(1).is_some_and(|line| line.contains(r#""metadata":{"source":"kb.md"}"#)));
+
+        let mut imported = VectorDB::import_jsonl(dump.as_slice())?;
+        assert_eq!(imported.model_id(), "keywords@1");
+        let ids = |db: &VectorDB| {
+            let mut ids: Vec<String> = db.documents().map(|doc| doc.id.clone()).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(&imported), ids(&db));
+        imported.use_embedding_model(Some(Arc::new(KeywordEmbedder)))?;
+        assert!(imported.search_similar("bread", 1)[0].content.starts_with("Bread"));
+
+        // TF-IDF indexes get their vocabulary back from the content
+        let mut tfidf = VectorDB::new();
+        tfidf.add_document("Tokio is an async runtime".to_string())?;
+        tfidf.add_document("Serde serializes data structures".to_string())?;
+        let mut dump = Vec::new();
+        tfidf.export_jsonl(&mut dump)?;
+        let imported = VectorDB::import_jsonl(dump.as_slice())?;
+        assert!(imported.search_similar("async runtime", 1)[0].content.contains("Tokio"));
+        assert!(VectorDB::import_jsonl(&b"{\"page_content\": \"x\"}\n"[..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_documents_keeps_the_embedding_model() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("index.bin");
+        let mut db = VectorDB::new();
+        db.use_embedding_model(Some(Arc::new(KeywordEmbedder)))?;
+        db.add_document("Rust and async Rust".to_string())?;
+        db.save(&path)?;
+        db.add_document("Bread recipes".to_string())?;
+
+        db.replace_documents(VectorDB::load(&path)?)?;
+        assert_eq!(db.len(), 1);
+        // Still embeds with the model, so new documents and queries match
+        db.add_document("Bread recipes".to_string())?;
+        assert!(db.search_similar("bread", 1)[0].content.starts_with("Bread"));
+        assert!(db.replace_documents(VectorDB::new()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use rustc_hash::FxHashMap;
+
+/// Term frequency saturation: further occurrences of a term add less
+const K1: f32 = 1.2;
+/// How strongly scores are normalized by document length
+const B: f32 = 0.75;
+
+/// Inverted index scoring documents by Okapi BM25, which rewards exact
+/// term matches (error codes, identifiers) that embeddings tend to blur.
+/// Kept in memory next to the embeddings and built from the index's own
+/// tokenization, so synonyms and stop-word settings apply to it as well.
+#[derive(Default)]
+pub(super) struct Bm25 {
+    /// Term -> document ID -> occurrences
+    postings: FxHashMap<String, FxHashMap<String, u32>>,
+    /// Document ID -> number of terms
+    lengths: FxHashMap<String, usize>,
+    total_length: usize,
+}
+
+impl Bm25 {
+    pub(super) fn insert(&mut self, id: &str, tokens: &[String]) {
+        self.remove(id);
+        for token in tokens {
+            *self.postings.entry(token.clone()).or_default().entry(id.to_string()).or_insert(0) += 1;
+        }
+        self.lengths.insert(id.to_string(), tokens.len());
+        self.total_length += tokens.len();
+    }
+
+    pub(super) fn remove(&mut self, id: &str) {
+        let Some(length) = self.lengths.remove(id) else {
+            return;
+        };
+        self.total_length -= length;
+        self.postings.retain(|_, docs| {
+            docs.remove(id);
+            !docs.is_empty()
+        });
+    }
+
+    /// BM25 score of every document containing a query term, best first
+    pub(super) fn score(&self, query: &[String]) -> Vec<(f32, &str)> {
+        let documents = self.lengths.len() as f32;
+        if documents == 0.0 {
+            return Vec::new();
+        }
+        let average_length = self.total_length as f32 / documents;
+        let mut scores: FxHashMap<&str, f32> = FxHashMap::default();
+        let mut seen = Vec::new();
+        for term in query {
+            // Repeating a word in the query doesn't count it twice
+            if seen.contains(&term) {
+                continue;
+            }
+            seen.push(term);
+            let Some(docs) = self.postings.get(term) else {
+                continue;
+            };
+            let frequency = docs.len() as f32;
+            let idf = (1.0 + (documents - frequency + 0.5) / (frequency + 0.5)).ln();
+            for (id, &count) in docs {
+                let tf = count as f32;
+                let length = self.lengths[id] as f32;
+                let norm = K1 * (1.0 - B + B * length / average_length.max(1.0));
+                *scores.entry(id.as_str()).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+        let mut ranked: Vec<(f32, &str)> = scores.into_iter().map(|(id, score)| (score, id)).collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(b.1)));
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_lowercase).collect()
+    }
+
+    #[test]
+    fn test_exact_terms_rank_first_and_removal_unindexes() {
+        let mut index = Bm25::default();
+        index.insert("a", &tokens("error E1234 means the disk is full"));
+        index.insert("b", &tokens("errors are logged when the disk is slow"));
+        index.insert("c", &tokens("refunds take thirty days"));
+
+        let ranked = index.score(&tokens("E1234 error"));
+        assert_eq!(ranked[0].1, "a");
+        assert!(ranked.iter().all(|(_, id)| *id != "c"));
+
+        index.remove("a");
+        assert!(index.score(&tokens("E1234")).is_empty());
+        index.insert("b", &tokens("refunds"));
+        assert_eq!(index.score(&tokens("refunds")).len(), 2);
+        assert_eq!(index.total_length, 5);
+    }
+}
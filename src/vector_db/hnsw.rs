@@ -0,0 +1,244 @@
+use ndarray::Array1;
+use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+use super::{Document, cosine_similarity};
+
+/// Tuning knobs of the HNSW graph
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HnswParams {
+    /// Links per node on the upper layers (twice as many on layer 0);
+    /// more links improve recall at the cost of memory and build time
+    pub m: usize,
+    /// Candidate list size while inserting; higher builds a better graph
+    pub ef_construction: usize,
+    /// Candidate list size while searching; higher trades speed for recall
+    pub ef_search: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams { m: 16, ef_construction: 200, ef_search: 64 }
+    }
+}
+
+/// Hierarchical navigable small world graph over document embeddings
+/// (Malkov & Yashunin, 2016). Nodes refer to documents by ID; embeddings
+/// are looked up in the document map passed to each call, so the graph
+/// holds no copies of them and is saved with the index at little cost.
+#[derive(Serialize, Deserialize)]
+pub(super) struct Hnsw {
+    params: HnswParams,
+    /// Document ID of each node
+    ids: Vec<String>,
+    /// Neighbours of each node on every layer it is on, layer 0 first
+    links: Vec<Vec<Vec<usize>>>,
+    /// Node on the highest layer, where searches start
+    entry: Option<usize>,
+    /// xorshift state for drawing node levels; fixed seed so equal inputs
+    /// build equal graphs
+    rng: u64,
+}
+
+/// A node and its similarity to the current target, ordered by similarity
+#[derive(Clone, Copy, PartialEq)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl Hnsw {
+    pub fn new(params: HnswParams) -> Self {
+        Hnsw { params, ids: Vec::new(), links: Vec::new(), entry: None, rng: 0x9E37_79B9_7F4A_7C15 }
+    }
+
+    pub fn params(&self) -> HnswParams {
+        self.params
+    }
+
+    /// Whether the graph has a node for each of `documents` and no others,
+    /// e.g. after loading both from a file
+    pub fn covers(&self, documents: &HashMap<String, Document>) -> bool {
+        self.ids.len() == documents.len()
+            && self.links.len() == self.ids.len()
+            && self.entry.is_none_or(|entry| entry < self.ids.len())
+            && self.links.iter().flatten().flatten().all(|&node| node < self.ids.len())
+            && self.ids.iter().all(|id| documents.contains_key(id))
+    }
+
+    /// Adds document `id`, which must be in `documents`
+    pub fn insert(&mut self, id: String, documents: &HashMap<String, Document>) {
+        let node = self.ids.len();
+        let level = self.random_level();
+        self.ids.push(id);
+        self.links.push(vec![Vec::new(); level + 1]);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+        let target = self.embedding(node, documents).clone();
+        let top = self.links[entry].len() - 1;
+
+        let mut nearest = vec![Scored(self.similarity(&target, entry, documents), entry)];
+        for layer in (level + 1..=top).rev() {
+            nearest = self.search_layer(&target, nearest, 1, layer, documents);
+        }
+        for layer in (0..=level.min(top)).rev() {
+            nearest = self.search_layer(&target, nearest, self.params.ef_construction, layer, documents);
+            let neighbours: Vec<usize> = nearest.iter().take(self.max_links(layer)).map(|s| s.1).collect();
+            for &neighbour in &neighbours {
+                self.links[neighbour][layer].push(node);
+                self.prune(neighbour, layer, documents);
+            }
+            self.links[node][layer] = neighbours;
+        }
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    /// IDs of up to `k` documents most similar to `query`, best first
+    pub fn search<'a>(
+        &'a self,
+        query: &Array1<f32>,
+        k: usize,
+        documents: &HashMap<String, Document>,
+    ) -> Vec<(f32, &'a str)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let mut nearest = vec![Scored(self.similarity(query, entry, documents), entry)];
+        for layer in (1..self.links[entry].len()).rev() {
+            nearest = self.search_layer(query, nearest, 1, layer, documents);
+        }
+        let mut found = self.search_layer(query, nearest, self.params.ef_search.max(k), 0, documents);
+        found.truncate(k);
+        found.into_iter().map(|Scored(similarity, node)| (similarity, self.ids[node].as_str())).collect()
+    }
+
+    /// Best-first search of one layer from `entry_points`, keeping the `ef`
+    /// most similar nodes seen; returns them best first
+    fn search_layer(
+        &self,
+        target: &Array1<f32>,
+        entry_points: Vec<Scored>,
+        ef: usize,
+        layer: usize,
+        documents: &HashMap<String, Document>,
+    ) -> Vec<Scored> {
+        let mut visited: FxHashSet<usize> = entry_points.iter().map(|s| s.1).collect();
+        let mut candidates: BinaryHeap<Scored> = entry_points.iter().copied().collect();
+        let mut results: BinaryHeap<Reverse<Scored>> = entry_points.into_iter().map(Reverse).collect();
+        while results.len() > ef {
+            results.pop();
+        }
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = results.peek().map_or(f32::NEG_INFINITY, |Reverse(s)| s.0);
+            if candidate.0 < worst && results.len() >= ef {
+                break;
+            }
+            for &neighbour in &self.links[candidate.1][layer] {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let scored = Scored(self.similarity(target, neighbour, documents), neighbour);
+                let worst = results.peek().map_or(f32::NEG_INFINITY, |Reverse(s)| s.0);
+                if results.len() < ef || scored.0 > worst {
+                    candidates.push(scored);
+                    results.push(Reverse(scored));
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        let mut results: Vec<Scored> = results.into_iter().map(|Reverse(s)| s).collect();
+        results.sort_by(|a, b| b.cmp(a));
+        results
+    }
+
+    /// Keeps only the closest `max_links` neighbours of `node` on `layer`
+    fn prune(&mut self, node: usize, layer: usize, documents: &HashMap<String, Document>) {
+        let max = self.max_links(layer);
+        if self.links[node][layer].len() <= max {
+            return;
+        }
+        let embedding = self.embedding(node, documents);
+        let mut scored: Vec<Scored> = self.links[node][layer]
+            .iter()
+            .map(|&neighbour| Scored(self.similarity(embedding, neighbour, documents), neighbour))
+            .collect();
+        scored.sort_by(|a, b| b.cmp(a));
+        self.links[node][layer] = scored.into_iter().take(max).map(|s| s.1).collect();
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 { 2 * self.params.m } else { self.params.m }
+    }
+
+    /// Level drawn from the exponential distribution with scale 1/ln(M)
+    fn random_level(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let uniform = ((self.rng >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        let scale = 1.0 / (self.params.m.max(2) as f64).ln();
+        (-uniform.ln() * scale) as usize
+    }
+
+    fn embedding<'a>(&self, node: usize, documents: &'a HashMap<String, Document>) -> &'a Array1<f32> {
+        &documents[&self.ids[node]].embedding
+    }
+
+    fn similarity(&self, target: &Array1<f32>, node: usize, documents: &HashMap<String, Document>) -> f32 {
+        cosine_similarity(target, self.embedding(node, documents))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_finds_nearest_neighbours() {
+        // Points on a circle; the nearest neighbours of an angle are the
+        // points next to it
+        let mut documents = HashMap::new();
+        let mut graph = Hnsw::new(HnswParams { m: 4, ef_construction: 32, ef_search: 16 });
+        for i in 0..500 {
+            let angle = i as f32 * std::f32::consts::TAU / 500.0;
+            let id = format!("doc-{}", i);
+            documents.insert(id.clone(), Document {
+                id: id.clone(),
+                content: String::new(),
+                embedding: Array1::from(vec![angle.cos(), angle.sin()]),
+                table: None,
+                variants: BTreeMap::new(),
+                metadata: BTreeMap::new(),
+            });
+            graph.insert(id, &documents);
+        }
+
+        let angle = 100.2 * std::f32::consts::TAU / 500.0;
+        let found = graph.search(&Array1::from(vec![angle.cos(), angle.sin()]), 3, &documents);
+        let ids: Vec<&str> = found.iter().map(|(_, id)| *id).collect();
+        assert_eq!(ids, ["doc-100", "doc-101", "doc-99"]);
+    }
+}
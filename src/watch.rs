@@ -0,0 +1,157 @@
+use anyhow::Result;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::retriever::Retriever;
+
+/// How often the watch thread checks whether it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Quiet time after a change before files are re-indexed, so an editor's
+/// write-rename-delete sequence or a bulk copy is handled once
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+/// Background thread that keeps the index in step with a docs directory:
+/// the chunks of a file that is added, modified or deleted are dropped and,
+/// if it still exists, it is loaded again with `load`. The index is saved
+/// to `index_path` after each batch. The thread is stopped and joined when
+/// the worker is dropped.
+pub struct WatchWorker {
+    // Dropping the watcher ends its notifications
+    _watcher: RecommendedWatcher,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl WatchWorker {
+    pub fn spawn<F>(dir: &Path, retriever: Arc<RwLock<Retriever>>, index_path: Option<PathBuf>, load: F) -> Result<Self>
+    where
+        F: Fn(&mut Retriever, &Path) -> Result<()> + Send + 'static,
+    {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        // Like the initial load, files in subdirectories are indexed too;
+        // `load` decides which of them
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_flag = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            while !stop_flag.load(Ordering::Relaxed) {
+                let first = match events.recv_timeout(POLL_INTERVAL) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                let mut batch = vec![first];
+                while let Ok(event) = events.recv_timeout(SETTLE_TIME) {
+                    batch.push(event);
+                }
+                let batch: Vec<Event> = batch
+                    .into_iter()
+                    .filter_map(|event| event.map_err(|e| tracing::warn!(error = %e, "file watch error")).ok())
+                    .collect();
+                let paths = changed_paths(&batch);
+                if paths.is_empty() {
+                    continue;
+                }
+
+                let _span = tracing::info_span!("watch_reindex", files = paths.len()).entered();
+                let Ok(mut retriever) = retriever.write() else {
+                    break;
+                };
+                for path in &paths {
+                    match reindex(&mut retriever, path, &load) {
+                        Ok((removed, true)) => tracing::info!(path = %path.display(), removed, chunks = retriever.len(), "re-indexed changed file"),
+                        Ok((removed, false)) => tracing::info!(path = %path.display(), removed, "dropped deleted file from the index"),
+                        Err(e) => tracing::warn!(path = %path.display(), error = %e, "failed to re-index changed file"),
+                    }
+                }
+                if let Some(index_path) = &index_path
+                    && let Err(e) = retriever.save(index_path)
+                {
+                    tracing::warn!(error = %e, "failed to save the updated index");
+                }
+            }
+        });
+
+        Ok(WatchWorker { _watcher: watcher, stop, handle: Some(handle) })
+    }
+}
+
+impl Drop for WatchWorker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Drops the chunks of `path` and loads it again if it still exists,
+/// returning how many chunks were dropped and whether it was loaded
+fn reindex<F>(retriever: &mut Retriever, path: &Path, load: &F) -> Result<(usize, bool)>
+where
+    F: Fn(&mut Retriever, &Path) -> Result<()>,
+{
+    let removed = retriever.remove_source(path)?;
+    if !path.is_file() {
+        return Ok((removed, false));
+    }
+    load(retriever, path)?;
+    Ok((removed, true))
+}
+
+/// Files created, modified, renamed or deleted by `events`, each once;
+/// reads and metadata-only changes don't count
+fn changed_paths(events: &[Event]) -> BTreeSet<PathBuf> {
+    events
+        .iter()
+        .filter(|event| matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)))
+        .filter(|event| !matches!(event.kind, EventKind::Modify(notify::event::ModifyKind::Metadata(_))))
+        .flat_map(|event| event.paths.iter().cloned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, MetadataKind, ModifyKind, RemoveKind, RenameMode};
+
+    #[test]
+    fn test_changes_are_batched_and_reindexed() -> Result<()> {
+        let event = |kind, paths: &[&str]| paths.iter().fold(Event::new(kind), |event, path| event.add_path(PathBuf::from(path)));
+        let events = [
+            event(EventKind::Create(CreateKind::File), &["docs/new.md"]),
+            event(EventKind::Modify(ModifyKind::Any), &["docs/new.md"]),
+            event(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &["docs/a.txt", "docs/b.txt"]),
+            event(EventKind::Remove(RemoveKind::File), &["docs/old.txt"]),
+            event(EventKind::Access(AccessKind::Any), &["docs/read.txt"]),
+            event(EventKind::Modify(ModifyKind::Metadata(MetadataKind::Permissions)), &["docs/chmod.txt"]),
+        ];
+        let paths: Vec<PathBuf> = changed_paths(&events).into_iter().collect();
+        assert_eq!(paths, ["docs/a.txt", "docs/b.txt", "docs/new.md", "docs/old.txt"].map(PathBuf::from));
+
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("refunds.txt");
+        std::fs::write(&file, "Refunds take thirty days.")?;
+        let load = |retriever: &mut Retriever, path: &Path| {
+            let metadata = crate::metadata::of_file(path)?;
+            retriever.add_to_knowledge_base(std::fs::read_to_string(path)?, metadata).map(|_| ())
+        };
+        let mut retriever = Retriever::new();
+        assert_eq!(reindex(&mut retriever, &file, &load)?, (0, true));
+        std::fs::write(&file, "Refunds take fourteen days.")?;
+        assert_eq!(reindex(&mut retriever, &file, &load)?, (1, true));
+        assert_eq!(retriever.retrieve("refunds", 5), vec!["Refunds take fourteen days."]);
+        std::fs::remove_file(&file)?;
+        assert_eq!(reindex(&mut retriever, &file, &load)?, (1, false));
+        assert!(retriever.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Result, anyhow};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Header carrying `sha256=<hex HMAC of the body>` when a secret is set
+pub const SIGNATURE_HEADER: &str = "X-Tapssp-Signature";
+/// Header carrying the event name, e.g. `ingest_completed`
+pub const EVENT_HEADER: &str = "X-Tapssp-Event";
+
+/// Something worth telling external systems about
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// Documents were loaded into the index
+    IngestCompleted { documents: usize, index_path: Option<String> },
+    /// The index couldn't be loaded, built or saved
+    IndexError { message: String },
+    /// An answer ended as "no answer" because retrieval was weak
+    LowConfidence { query: String, confidence: f32 },
+}
+
+impl WebhookEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::IngestCompleted { .. } => "ingest_completed",
+            WebhookEvent::IndexError { .. } => "index_error",
+            WebhookEvent::LowConfidence { .. } => "low_confidence",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+}
+
+/// Endpoints notified of `WebhookEvent`s. With a secret, every body is
+/// signed with HMAC-SHA256 so receivers can verify it came from us.
+#[derive(Debug, Clone, Default)]
+pub struct Webhooks {
+    urls: Vec<String>,
+    secret: Option<String>,
+}
+
+impl Webhooks {
+    pub fn new(urls: Vec<String>, secret: Option<String>) -> Self {
+        Webhooks { urls, secret }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty()
+    }
+
+    /// Delivers `event` to every endpoint, reporting the first failure
+    pub fn send(&self, event: &WebhookEvent) -> Result<()> {
+        if self.urls.is_empty() {
+            return Ok(());
+        }
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let body = serde_json::to_vec(&Payload { event, timestamp })?;
+
+        let client = reqwest::blocking::Client::new();
+        let mut first_error = None;
+        for url in &self.urls {
+            let mut request = client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .header(EVENT_HEADER, event.name())
+                .body(body.clone());
+            if let Some(secret) = &self.secret {
+                request = request.header(SIGNATURE_HEADER, format!("sha256={}", sign(secret, &body)));
+            }
+            let result = request
+                .send()
+                .map_err(anyhow::Error::from)
+                .and_then(|response| match response.status().is_success() {
+                    true => Ok(()),
+                    false => Err(anyhow!("{} returned {}", url, response.status())),
+                });
+            if let Err(e) = result {
+                tracing::warn!(url = %url, event = event.name(), error = %e, "webhook delivery failed");
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// `send` on a background thread, for events raised while serving queries
+    pub fn send_in_background(&self, event: WebhookEvent) {
+        if self.urls.is_empty() {
+            return;
+        }
+        let webhooks = self.clone();
+        std::thread::spawn(move || {
+            let _ = webhooks.send(&event);
+        });
+    }
+}
+
+/// Hex-encoded HMAC-SHA256 of `body` keyed with `secret`
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_matches_rfc4231_vector() {
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_payload_is_tagged_with_event_name() -> Result<()> {
+        let event = WebhookEvent::IngestCompleted { documents: 12, index_path: None };
+        let payload = serde_json::to_value(Payload { event: &event, timestamp: 1 })?;
+        assert_eq!(payload["event"], event.name());
+        assert_eq!(payload["documents"], 12);
+        Ok(())
+    }
+}